@@ -0,0 +1,152 @@
+// Yearly archives of old treatment records, expenses and drug purchases.
+// Settings moves everything before a chosen year out of clinic_data.json into
+// archives/archive_<year>.json (see src/archive.rs), so what is loaded at every
+// start stays the same size however many years the clinic has been open. Each
+// archive is a cut-down clinic_data.json document with only those collections
+// and its schema version, sealed like the data file when encryption is on.
+//
+// Backups carry the archives inside `data` under "clinic_archives", keyed by
+// year, so the checksum covers them and a restore brings them back. The data
+// file itself never holds that key.
+//
+// Audit log entries past the frontend's limit are archived too, by the year
+// they were logged, under the same key the log uses in a backup.
+
+use serde_json::{Map, Value};
+use crate::auditlog;
+
+pub const KEY_ARCHIVES: &str = "clinic_archives";
+/// The collections that are archived, keyed like clinic_data.json
+pub const COLLECTIONS: [&str; 3] = ["clinic_records", "clinic_expenses", "clinic_drug_purchases"];
+const KEY_SCHEMA_VERSION: &str = "clinic_schema_version";
+
+pub fn file_name(year: i32) -> String {
+    format!("archive_{}.json", year)
+}
+
+/// The year of an archive file, None for any other file
+pub fn year_of(name: &str) -> Option<i32> {
+    name.strip_prefix("archive_")?.strip_suffix(".json")?.parse().ok()
+}
+
+fn parse_object(json: &str, what: &str) -> Result<Map<String, Value>, String> {
+    match serde_json::from_str(json).map_err(|e| format!("Invalid {}: {}", what, e))? {
+        Value::Object(doc) => Ok(doc),
+        _ => Err(format!("Invalid {}: not a JSON object", what)),
+    }
+}
+
+/// A clinic_data.json document with the archives added, for a backup
+pub fn bundle(document: &str, archives: &[(i32, String)]) -> Result<String, String> {
+    if archives.is_empty() {
+        return Ok(document.to_string());
+    }
+    let mut doc = parse_object(document, "clinic data")?;
+    let mut bundled = Map::new();
+    for (year, archive) in archives {
+        bundled.insert(year.to_string(), Value::Object(parse_object(archive, "archive")?));
+    }
+    doc.insert(KEY_ARCHIVES.to_string(), Value::Object(bundled));
+    Ok(Value::Object(doc).to_string())
+}
+
+/// A restored document split into the data file and the archives it carried
+pub fn unbundle(document: &str) -> Result<(String, Vec<(i32, String)>), String> {
+    let mut doc = parse_object(document, "clinic data")?;
+    let Some(bundled) = doc.remove(KEY_ARCHIVES) else {
+        return Ok((document.to_string(), Vec::new()));
+    };
+    let archives = bundled
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(year, archive)| Some((year.parse().ok()?, archive.to_string())))
+        .collect();
+    Ok((Value::Object(doc).to_string(), archives))
+}
+
+fn id_of(item: &Value) -> &str {
+    item.get("id").and_then(Value::as_str).unwrap_or("")
+}
+
+/// `incoming` added to `existing` by id. An item that is already archived stays
+/// as it is: archives are read-only, so both copies are the same item anyway.
+pub fn merge(existing: &str, incoming: &str) -> Result<String, String> {
+    let mut merged = parse_object(existing, "archive")?;
+    let incoming = parse_object(incoming, "archive")?;
+
+    for key in COLLECTIONS.into_iter().chain([auditlog::KEY]) {
+        let Some(items) = incoming.get(key).and_then(Value::as_array) else {
+            continue;
+        };
+        let list = merged.entry(key).or_insert_with(|| Value::Array(Vec::new()));
+        let Some(list) = list.as_array_mut() else {
+            continue;
+        };
+        let known: std::collections::HashSet<String> = list.iter().map(|item| id_of(item).to_string()).collect();
+        list.extend(items.iter().filter(|item| !known.contains(id_of(item))).cloned());
+    }
+
+    // Migrations only fill in what is missing, so the older of the two versions
+    // is the safe one: the frontend upgrades whichever items need it on load
+    let version = |doc: &Map<String, Value>| doc.get(KEY_SCHEMA_VERSION).and_then(Value::as_u64).unwrap_or(1);
+    let lowest = version(&merged).min(version(&incoming));
+    merged.insert(KEY_SCHEMA_VERSION.to_string(), Value::from(lowest));
+    Ok(Value::Object(merged).to_string())
+}
+
+/// How many items an archive holds, for clinic-cli
+pub fn count(archive: &str) -> Result<usize, String> {
+    let doc = parse_object(archive, "archive")?;
+    Ok(COLLECTIONS
+        .iter()
+        .filter_map(|key| doc.get(*key).and_then(Value::as_array))
+        .map(Vec::len)
+        .sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(file_name(2022), "archive_2022.json");
+        assert_eq!(year_of("archive_2022.json"), Some(2022));
+        assert_eq!(year_of("backup_20240101_120000.json"), None);
+        assert_eq!(year_of("archive_x.json"), None);
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let document = json!({ "clinic_records": [{ "id": "r9" }] }).to_string();
+        let archive = json!({ "clinic_schema_version": 3, "clinic_records": [{ "id": "r1" }] }).to_string();
+
+        assert_eq!(bundle(&document, &[]).unwrap(), document);
+        let bundled = bundle(&document, &[(2022, archive.clone())]).unwrap();
+        let (plain, archives) = unbundle(&bundled).unwrap();
+        assert_eq!(plain, document);
+        assert_eq!(archives, vec![(2022, archive)]);
+        assert_eq!(unbundle(&document).unwrap(), (document, Vec::new()));
+    }
+
+    #[test]
+    fn test_merge_adds_by_id() {
+        let existing = json!({ "clinic_schema_version": 3, "clinic_records": [{ "id": "r1", "price": 100 }] }).to_string();
+        let incoming = json!({
+            "clinic_schema_version": 2,
+            "clinic_records": [{ "id": "r1", "price": 999 }, { "id": "r2" }],
+            "clinic_expenses": [{ "id": "e1" }],
+            "clinic_audit_log": [{ "id": "log1" }]
+        })
+        .to_string();
+
+        let merged: Value = serde_json::from_str(&merge(&existing, &incoming).unwrap()).unwrap();
+        assert_eq!(merged["clinic_records"], json!([{ "id": "r1", "price": 100 }, { "id": "r2" }]));
+        assert_eq!(merged["clinic_expenses"], json!([{ "id": "e1" }]));
+        assert_eq!(merged["clinic_audit_log"], json!([{ "id": "log1" }]));
+        assert_eq!(merged["clinic_schema_version"], 2);
+        assert_eq!(count(&merged.to_string()).unwrap(), 3);
+    }
+}
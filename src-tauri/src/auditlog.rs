@@ -0,0 +1,91 @@
+// The audit log, kept in audit_log.jsonl next to clinic_data.json rather than
+// inside it (see src/storage.rs). One entry per line, each sealed on its own when
+// encryption is on, so a save only appends the new entries instead of rewriting
+// everything logged so far. A last line cut short by a crash is skipped on reading.
+//
+// Backups and sync still see the log under "clinic_audit_log" in the document:
+// `insert` puts it back for them and `split` takes it out of a restored one.
+
+use serde_json::{Map, Value};
+
+pub const FILE: &str = "audit_log.jsonl";
+pub const KEY: &str = "clinic_audit_log";
+
+/// `entries` as lines for the file, each passed through `seal`
+pub fn to_lines(entries: &[Value], seal: impl Fn(&str) -> Result<String, String>) -> Result<String, String> {
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&seal(&entry.to_string())?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+/// The entries in the file, each line passed through `open`
+pub fn from_lines(contents: &str, open: impl Fn(&str) -> Result<String, String>) -> Result<Vec<Value>, String> {
+    let lines: Vec<&str> = contents.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut entries = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        match open(line).and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string())) {
+            Ok(entry) => entries.push(entry),
+            Err(e) if i + 1 == lines.len() => log::warn!("Skipped an unfinished audit log entry: {}", e),
+            Err(e) => return Err(format!("Invalid audit log entry on line {}: {}", i + 1, e)),
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_object(document: &str) -> Result<Map<String, Value>, String> {
+    match serde_json::from_str(document).map_err(|e| format!("Invalid clinic data: {}", e))? {
+        Value::Object(doc) => Ok(doc),
+        _ => Err("Invalid clinic data: not a JSON object".to_string()),
+    }
+}
+
+/// A clinic_data.json document with the log added, for a backup or sync
+pub fn insert(document: &str, entries: Vec<Value>) -> Result<String, String> {
+    let mut doc = parse_object(document)?;
+    doc.insert(KEY.to_string(), Value::Array(entries));
+    Ok(Value::Object(doc).to_string())
+}
+
+/// A restored document split into the data file and the log it carried, if any
+pub fn split(document: &str) -> Result<(String, Option<Vec<Value>>), String> {
+    let mut doc = parse_object(document)?;
+    match doc.remove(KEY) {
+        Some(Value::Array(entries)) => Ok((Value::Object(doc).to_string(), Some(entries))),
+        Some(_) => Ok((Value::Object(doc).to_string(), None)),
+        None => Ok((document.to_string(), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_lines_round_trip_and_torn_tail() {
+        let entries = vec![json!({"id": "log1"}), json!({"id": "log2"})];
+        let lines = to_lines(&entries, |s| Ok(s.to_string())).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        assert_eq!(from_lines(&lines, |s| Ok(s.to_string())).unwrap(), entries);
+
+        // A crash while appending leaves half a line at the end
+        let torn = format!("{}{{\"id\": \"lo", lines);
+        assert_eq!(from_lines(&torn, |s| Ok(s.to_string())).unwrap(), entries);
+        // Anywhere else it is real damage
+        let damaged = format!("{{\"id\": \"lo\n{}", lines);
+        assert!(from_lines(&damaged, |s| Ok(s.to_string())).is_err());
+    }
+
+    #[test]
+    fn test_insert_and_split() {
+        let document = r#"{"clinic_last_hn":3}"#;
+        let bundled = insert(document, vec![json!({"id": "log1"})]).unwrap();
+        let (data, entries) = split(&bundled).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&data).unwrap(), json!({"clinic_last_hn": 3}));
+        assert_eq!(entries, Some(vec![json!({"id": "log1"})]));
+        assert_eq!(split(document).unwrap(), (document.to_string(), None));
+    }
+}
@@ -0,0 +1,419 @@
+// Backup files, in the same format as the browser download (src/backup.rs):
+//   { "backup_format": 1, "created_at": "...", "checksum": "sha256:...", "data": { clinic_data.json } }
+// The checksum covers `data` as serde_json writes it (object keys sorted), so a
+// backup made on either side can be checked on the other. Backups made before
+// this format, plain copies of clinic_data.json or the browser's old download,
+// are still accepted.
+//
+// Backups are named `backup_<YYYYmmdd_HHMMSS>[_<reason>].json`. Automatic ones
+// follow `ClinicSettings.backup_schedule`, and old ones are thinned out to so
+// many daily, weekly and monthly backups. When a second folder is set (a USB
+// drive or network share), the backups are mirrored there after every backup and
+// each copy is read back and compared with the original.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use crate::datafile;
+
+/// Bump together with the frontend when the layout of the backup file changes
+pub const BACKUP_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    backup_format: u32,
+    created_at: DateTime<Utc>,
+    checksum: String,
+    data: Value,
+}
+
+fn checksum(data: &Value) -> String {
+    let hash = Sha256::digest(data.to_string().as_bytes());
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Wrap a clinic_data.json document in a backup
+pub fn create(document: &str) -> Result<String, String> {
+    let data: Value = serde_json::from_str(document).map_err(|e| format!("Invalid clinic data: {}", e))?;
+    if !data.is_object() {
+        return Err("Invalid clinic data: not a JSON object".to_string());
+    }
+    let backup = Backup { backup_format: BACKUP_FORMAT, created_at: Utc::now(), checksum: checksum(&data), data };
+    serde_json::to_string_pretty(&backup).map_err(|e| format!("Failed to encode backup: {}", e))
+}
+
+/// The clinic_data.json document inside a backup, after checking its checksum
+pub fn unpack(json: &str) -> Result<String, String> {
+    let raw: Map<String, Value> = serde_json::from_str(json).map_err(|e| format!("Invalid backup: {}", e))?;
+
+    let document = if raw.contains_key("backup_format") {
+        let backup: Backup = serde_json::from_value(Value::Object(raw)).map_err(|e| format!("Invalid backup: {}", e))?;
+        if backup.backup_format > BACKUP_FORMAT {
+            return Err("ไฟล์สำรองนี้มาจากโปรแกรมรุ่นใหม่กว่า กรุณาอัปเดตโปรแกรมก่อนกู้คืน".to_string());
+        }
+        if checksum(&backup.data) != backup.checksum {
+            return Err("ไฟล์สำรองเสียหายหรือถูกแก้ไข (checksum ไม่ตรงกัน)".to_string());
+        }
+        if !backup.data.is_object() {
+            return Err("Invalid backup: no clinic data".to_string());
+        }
+        backup.data
+    } else if raw.contains_key("backup_date") {
+        // The browser's old download, with its own field names
+        let mut doc = Map::new();
+        for (field, key) in [
+            ("version", "clinic_schema_version"),
+            ("patients", "clinic_patients"),
+            ("records", "clinic_records"),
+            ("drugs", "clinic_drugs"),
+            ("settings", "clinic_settings"),
+        ] {
+            if let Some(value) = raw.get(field) {
+                doc.insert(key.to_string(), value.clone());
+            }
+        }
+        Value::Object(doc)
+    } else {
+        Value::Object(raw)
+    };
+    Ok(document.to_string())
+}
+
+// ========== Schedule ==========
+
+/// `ClinicSettings.backup_schedule` in the frontend
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Schedule {
+    pub on_close: bool,
+    pub daily: bool,
+    pub before_restore: bool,
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    pub keep_monthly: u32,
+    /// Second folder the backups are copied to; empty when there is none
+    pub mirror_dir: String,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            on_close: true,
+            daily: true,
+            before_restore: true,
+            keep_daily: 7,
+            keep_weekly: 4,
+            keep_monthly: 12,
+            mirror_dir: String::new(),
+        }
+    }
+}
+
+impl Schedule {
+    /// The schedule in a clinic_data.json document, or the defaults
+    pub fn from_document(document: &Value) -> Self {
+        document
+            .get("clinic_settings")
+            .and_then(|settings| settings.get("backup_schedule"))
+            .and_then(|schedule| serde_json::from_value(schedule.clone()).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Why a backup was taken
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reason {
+    Manual,
+    Close,
+    Daily,
+    Restore,
+    Update,
+}
+
+impl Reason {
+    /// Added to the file name so the backup list can tell them apart
+    pub fn tag(self) -> Option<&'static str> {
+        match self {
+            Reason::Manual => None,
+            Reason::Close => Some("close"),
+            Reason::Daily => Some("daily"),
+            Reason::Restore => Some("restore"),
+            Reason::Update => Some("update"),
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        [Reason::Close, Reason::Daily, Reason::Restore, Reason::Update]
+            .into_iter()
+            .find(|reason| reason.tag() == Some(tag))
+    }
+
+    pub fn is_enabled(self, schedule: &Schedule) -> bool {
+        match self {
+            Reason::Manual => true,
+            Reason::Close => schedule.on_close,
+            Reason::Daily => schedule.daily,
+            Reason::Restore | Reason::Update => schedule.before_restore,
+        }
+    }
+}
+
+const TIME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+pub fn file_name(taken_at: NaiveDateTime, reason: Reason) -> String {
+    match reason.tag() {
+        Some(tag) => format!("backup_{}_{}.json", taken_at.format(TIME_FORMAT), tag),
+        None => format!("backup_{}.json", taken_at.format(TIME_FORMAT)),
+    }
+}
+
+/// When a backup was taken, from its file name. None for files we didn't name.
+pub fn taken_at(name: &str) -> Option<NaiveDateTime> {
+    let stamp = name.strip_prefix("backup_")?.get(..15)?;
+    NaiveDateTime::parse_from_str(stamp, TIME_FORMAT).ok()
+}
+
+/// Why a backup was taken, from its file name
+pub fn reason_of(name: &str) -> Option<Reason> {
+    let tag = name.strip_prefix("backup_")?.get(15..)?.strip_suffix(".json")?.strip_prefix('_')?;
+    Reason::from_tag(tag)
+}
+
+/// How many items each collection of a clinic_data.json document holds
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Counts {
+    pub patients: usize,
+    pub records: usize,
+    pub drugs: usize,
+    pub expenses: usize,
+    pub drug_purchases: usize,
+    pub appointments: usize,
+}
+
+pub fn counts(document: &Value) -> Counts {
+    let len = |key: &str| document.get(key).and_then(Value::as_array).map_or(0, Vec::len);
+    Counts {
+        patients: len("clinic_patients"),
+        records: len("clinic_records"),
+        drugs: len("clinic_drugs"),
+        expenses: len("clinic_expenses"),
+        drug_purchases: len("clinic_drug_purchases"),
+        appointments: len("clinic_appointments"),
+    }
+}
+
+/// Whether today's daily backup is still to be taken. Any backup taken today counts.
+pub fn daily_due(names: &[String], today: NaiveDate) -> bool {
+    names.iter().filter_map(|name| taken_at(name)).all(|at| at.date() < today)
+}
+
+/// The backups to delete so that only the newest of each of the last
+/// `keep_daily` days, `keep_weekly` weeks and `keep_monthly` months remain.
+/// Files not named by us are never touched, and all limits at 0 keeps everything.
+pub fn to_prune(names: &[String], schedule: &Schedule) -> Vec<String> {
+    if schedule.keep_daily == 0 && schedule.keep_weekly == 0 && schedule.keep_monthly == 0 {
+        return Vec::new();
+    }
+    let mut backups: Vec<(NaiveDateTime, &String)> =
+        names.iter().filter_map(|name| taken_at(name).map(|at| (at, name))).collect();
+    backups.sort_by(|a, b| b.cmp(a));
+
+    let mut keep: HashSet<&String> = HashSet::new();
+    let mut newest_per = |limit: u32, period: &dyn Fn(NaiveDate) -> (i32, u32)| {
+        let mut seen = HashSet::new();
+        for (at, name) in &backups {
+            if seen.len() >= limit as usize {
+                break;
+            }
+            if seen.insert(period(at.date())) {
+                keep.insert(*name);
+            }
+        }
+    };
+    newest_per(schedule.keep_daily, &|d| (d.year(), d.ordinal()));
+    newest_per(schedule.keep_weekly, &|d| (d.iso_week().year(), d.iso_week().week()));
+    newest_per(schedule.keep_monthly, &|d| (d.year(), d.month()));
+
+    backups.into_iter().filter(|(_, name)| !keep.contains(name)).map(|(_, name)| name.clone()).collect()
+}
+
+// ========== Mirror ==========
+
+fn file_hash(path: &Path) -> Option<Vec<u8>> {
+    fs::read(path).ok().map(|bytes| Sha256::digest(&bytes).to_vec())
+}
+
+fn missing_folder(dir: &Path) -> String {
+    format!("ไม่พบโฟลเดอร์สำรองภายนอก {} กรุณาเสียบ USB หรือเชื่อมต่อเครือข่าย", dir.display())
+}
+
+/// Copy the backups `names` in `from` that `to` has no identical copy of, read
+/// each copy back to check it matches, then thin out `to` like `from`.
+/// Returns how many files were copied.
+pub fn mirror(from: &Path, names: &[String], to: &Path, schedule: &Schedule) -> Result<usize, String> {
+    if !to.is_dir() {
+        return Err(missing_folder(to));
+    }
+    let mut copied = 0;
+    for name in names {
+        let source = from.join(name);
+        let target = to.join(name);
+        let expected = file_hash(&source).ok_or_else(|| format!("อ่านไฟล์สำรอง {} ไม่ได้", name))?;
+        if file_hash(&target).as_ref() == Some(&expected) {
+            continue;
+        }
+        let contents = fs::read_to_string(&source).map_err(|e| format!("อ่านไฟล์สำรอง {} ไม่ได้: {}", name, e))?;
+        datafile::replace_atomic(&target, &contents).map_err(|e| format!("คัดลอก {} ไม่สำเร็จ: {}", name, e))?;
+        if file_hash(&target).as_ref() != Some(&expected) {
+            let _ = fs::remove_file(&target);
+            return Err(format!("สำเนาของ {} ที่ {} ไม่ตรงกับต้นฉบับ", name, to.display()));
+        }
+        copied += 1;
+    }
+
+    let mirrored: Vec<String> = fs::read_dir(to)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    for name in to_prune(&mirrored, schedule) {
+        let _ = fs::remove_file(to.join(name));
+    }
+    Ok(copied)
+}
+
+/// What is wrong with the mirror, if anything: the folder is missing or the
+/// newest backup has no matching copy there
+pub fn mirror_warning(from: &Path, newest: Option<&String>, to: &Path) -> Option<String> {
+    if !to.is_dir() {
+        return Some(missing_folder(to));
+    }
+    let newest = newest?;
+    (file_hash(&to.join(newest)) != file_hash(&from.join(newest)))
+        .then(|| format!("ไฟล์สำรองล่าสุดยังไม่ได้คัดลอกไปที่ {}", to.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"{"clinic_patients":[],"clinic_expenses":[{"id":"e1","amount":1200.5}],
+        "clinic_drug_purchases":[{"id":"dp1"}],"clinic_appointments":[{"id":"a1"}],
+        "clinic_audit_log":[{"id":"log1"}],"clinic_last_hn":7}"#;
+
+    #[test]
+    fn test_round_trip_keeps_every_collection() {
+        let backup = create(DOCUMENT).unwrap();
+        let restored: Value = serde_json::from_str(&unpack(&backup).unwrap()).unwrap();
+        assert_eq!(restored, serde_json::from_str::<Value>(DOCUMENT).unwrap());
+
+        let counts = counts(&restored);
+        assert_eq!((counts.patients, counts.expenses, counts.drug_purchases, counts.appointments), (0, 1, 1, 1));
+    }
+
+    #[test]
+    fn test_checksum_catches_changes() {
+        let backup = create(DOCUMENT).unwrap();
+        let tampered = backup.replace("1200.5", "9999.0");
+        assert!(unpack(&tampered).unwrap_err().contains("checksum"));
+    }
+
+    #[test]
+    fn test_checksum_matches_frontend() {
+        // Same value as the frontend's test in src/backup.rs
+        let data = serde_json::json!({ "b": [1, "ก"], "a": { "y": null, "x": 1.5 } });
+        assert_eq!(checksum(&data), "sha256:973403c34a5d207d79adc42bee534b785c35efe98466c05d16943328bcda9cfa");
+    }
+
+    #[test]
+    fn test_reads_legacy_backups() {
+        let legacy = r#"{"version":1,"backup_date":"2024-01-01 10:00:00","drugs":[{"id":"d1"}]}"#;
+        let doc: Value = serde_json::from_str(&unpack(legacy).unwrap()).unwrap();
+        assert_eq!(doc["clinic_drugs"][0]["id"], "d1");
+        assert_eq!(doc["clinic_schema_version"], 1);
+
+        // A plain copy of clinic_data.json
+        assert_eq!(unpack(DOCUMENT).unwrap(), serde_json::from_str::<Value>(DOCUMENT).unwrap().to_string());
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_file_names() {
+        let name = file_name(at(2024, 3, 5, 14), Reason::Close);
+        assert_eq!(name, "backup_20240305_140000_close.json");
+        assert_eq!(taken_at(&name), Some(at(2024, 3, 5, 14)));
+        assert_eq!(taken_at("backup_20240305_140000.json"), Some(at(2024, 3, 5, 14)));
+        assert_eq!(taken_at("my_copy.json"), None);
+        assert_eq!(reason_of(&name), Some(Reason::Close));
+        assert_eq!(reason_of("backup_20240305_140000.json"), None);
+
+        let names = vec![name];
+        assert!(!daily_due(&names, NaiveDate::from_ymd_opt(2024, 3, 5).unwrap()));
+        assert!(daily_due(&names, NaiveDate::from_ymd_opt(2024, 3, 6).unwrap()));
+    }
+
+    #[test]
+    fn test_retention_keeps_daily_weekly_monthly() {
+        // Two backups a day for a year, plus a file someone copied in
+        let first = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut names: Vec<String> = (0..366)
+            .flat_map(|day| {
+                let date = first + chrono::Duration::days(day);
+                [9, 18].map(|h| file_name(date.and_hms_opt(h, 0, 0).unwrap(), Reason::Daily))
+            })
+            .collect();
+        names.push("clinic_data_copy.json".to_string());
+
+        let pruned = to_prune(&names, &Schedule::default());
+        let kept: Vec<&String> = names.iter().filter(|name| !pruned.contains(name)).collect();
+
+        assert!(kept.contains(&&"clinic_data_copy.json".to_string()));
+        // The newest backup of each day is the evening one
+        assert!(kept.contains(&&file_name(at(2024, 12, 31, 18), Reason::Daily)));
+        assert!(!kept.contains(&&file_name(at(2024, 12, 31, 9), Reason::Daily)));
+        // 7 days, plus 4 weeks and 12 months that partly overlap with them
+        let backups = kept.len() - 1;
+        assert!((12..=7 + 4 + 12).contains(&backups));
+        assert!(kept.contains(&&file_name(at(2024, 1, 31, 18), Reason::Daily)));
+
+        let keep_all = Schedule { keep_daily: 0, keep_weekly: 0, keep_monthly: 0, ..Schedule::default() };
+        assert!(to_prune(&names, &keep_all).is_empty());
+    }
+
+    #[test]
+    fn test_mirror_copies_and_verifies() {
+        let root = std::env::temp_dir().join(format!("clinic_mirror_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (local, usb) = (root.join("backups"), root.join("usb"));
+        fs::create_dir_all(&local).unwrap();
+
+        let names: Vec<String> = (1..=3).map(|d| file_name(at(2024, 3, d, 9), Reason::Daily)).collect();
+        for name in &names {
+            fs::write(local.join(name), create(DOCUMENT).unwrap()).unwrap();
+        }
+        let schedule = Schedule::default();
+
+        // The drive isn't plugged in
+        assert!(mirror(&local, &names, &usb, &schedule).unwrap_err().contains("ไม่พบโฟลเดอร์"));
+        assert!(mirror_warning(&local, names.last(), &usb).is_some());
+
+        fs::create_dir_all(&usb).unwrap();
+        assert_eq!(mirror(&local, &names, &usb, &schedule).unwrap(), 3);
+        assert_eq!(mirror(&local, &names, &usb, &schedule).unwrap(), 0);
+        assert_eq!(mirror_warning(&local, names.last(), &usb), None);
+
+        // A damaged copy is noticed and copied again
+        fs::write(usb.join(&names[2]), "{}").unwrap();
+        assert!(mirror_warning(&local, names.last(), &usb).is_some());
+        assert_eq!(mirror(&local, &names, &usb, &schedule).unwrap(), 1);
+        assert_eq!(fs::read(usb.join(&names[2])).unwrap(), fs::read(local.join(&names[2])).unwrap());
+    }
+}
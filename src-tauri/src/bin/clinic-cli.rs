@@ -0,0 +1,8 @@
+// Command-line maintenance for the clinic's data; see `clinic-cli --help`
+
+fn main() {
+    if let Err(err) = app_lib::cli::run(std::env::args().skip(1).collect()) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
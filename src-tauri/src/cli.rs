@@ -0,0 +1,473 @@
+// clinic-cli: look after clinic_data.json and its backups without the window,
+// e.g. for a nightly export or to recover data on a machine where the app won't start.
+// It works on the same files as the app, so close the app before changing data.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde_json::Value;
+use crate::archive;
+use crate::auditlog;
+use crate::backup::{self, Counts, Reason};
+use crate::commands::{self, Crypto, CryptoState};
+use crate::crypto::{self, Session};
+use crate::datafile;
+use crate::integrity;
+use crate::models::ClinicData;
+
+/// Environment variable holding the passphrase of an encrypted data file
+pub const PASSPHRASE_VAR: &str = "CLINICCARE_PASSPHRASE";
+
+const USAGE: &str = "\
+Usage: clinic-cli [--data-dir DIR] <command>
+
+Commands:
+  export --format csv|json [--out PATH]
+                   json: the whole data file, to PATH or standard output
+                   csv: one file per collection, into the folder PATH (default: .)
+                   Archived years are included either way.
+  import FILE      replace the data with a data file or a backup, backing up first
+  backup           take a backup now
+  restore [NAME]   restore a backup from the backups folder; lists them without NAME
+  verify           check the data file, every backup and every archive; exits with 1
+                   if any is damaged
+  stats            how much data there is and how the backups stand
+  check            look for records or appointments without a patient, duplicate HNs
+                   and prescribed drugs missing from the inventory; exits with 1 if
+                   any are found. Fix them in the app under Settings.
+
+The data folder is the app's unless --data-dir or CLINICCARE_DATA_DIR says otherwise.
+An encrypted data file is opened with the passphrase in CLINICCARE_PASSPHRASE,
+or one typed in when asked.";
+
+/// The collections exported to CSV, by key in clinic_data.json and file name
+const COLLECTIONS: [(&str, &str); 8] = [
+    ("clinic_patients", "patients"),
+    ("clinic_records", "records"),
+    ("clinic_drugs", "drugs"),
+    ("clinic_expenses", "expenses"),
+    ("clinic_drug_purchases", "drug_purchases"),
+    ("clinic_appointments", "appointments"),
+    ("clinic_interactions", "interactions"),
+    ("clinic_audit_log", "audit_log"),
+];
+
+/// A parsed command line
+#[derive(Debug, PartialEq)]
+enum Command {
+    Export { format: String, out: Option<PathBuf> },
+    Import(PathBuf),
+    Backup,
+    Restore(Option<String>),
+    Verify,
+    Stats,
+    Check,
+    Help,
+}
+
+/// Split off `--data-dir DIR` and parse the rest of the arguments
+fn parse(args: Vec<String>) -> Result<(Option<PathBuf>, Command), String> {
+    let mut data_dir = None;
+    let mut format = None;
+    let mut out = None;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--data-dir" => data_dir = Some(PathBuf::from(value("--data-dir")?)),
+            "--format" => format = Some(value("--format")?),
+            "--out" => out = Some(PathBuf::from(value("--out")?)),
+            "-h" | "--help" => return Ok((data_dir, Command::Help)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("export") => Command::Export {
+            format: format.ok_or("export needs --format csv or --format json")?,
+            out,
+        },
+        Some("import") => Command::Import(PathBuf::from(positional.next().ok_or("import needs a file")?)),
+        Some("backup") => Command::Backup,
+        Some("restore") => Command::Restore(positional.next()),
+        Some("verify") => Command::Verify,
+        Some("stats") => Command::Stats,
+        Some("check") => Command::Check,
+        Some("help") | None => Command::Help,
+        Some(other) => return Err(format!("Unknown command '{}'\n\n{}", other, USAGE)),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("Unexpected argument '{}'", extra));
+    }
+    Ok((data_dir, command))
+}
+
+/// Run clinic-cli with the arguments after the program name
+pub fn run(args: Vec<String>) -> Result<(), String> {
+    let (data_dir, command) = parse(args)?;
+    if let Some(dir) = data_dir {
+        std::env::set_var(commands::DATA_DIR_VAR, dir);
+    }
+    if command == Command::Help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    // Only import and restore make sense in a folder without data yet
+    let data_file = commands::get_data_file_path();
+    if !data_file.exists() && !matches!(command, Command::Import(_) | Command::Restore(_)) {
+        return Err(format!("No data file at {}", data_file.display()));
+    }
+
+    let state = unlock()?;
+    match command {
+        Command::Export { format, out } => export(&format, out, &state),
+        Command::Import(file) => import(&file, &state),
+        Command::Backup => {
+            match commands::take_backup(&state, Reason::Manual)? {
+                Some(path) => println!("Backup written to {}", path.display()),
+                None => println!("Backups are turned off"),
+            }
+            Ok(())
+        }
+        Command::Restore(None) => list_backups(),
+        Command::Restore(Some(name)) => restore(&name, &state),
+        Command::Verify => verify(&state),
+        Command::Stats => stats(&state),
+        Command::Check => check(&state),
+        Command::Help => Ok(()),
+    }
+}
+
+/// The key for an encrypted data file, checked against the file
+fn unlock() -> Result<CryptoState, String> {
+    let path = commands::get_data_file_path();
+    let crypto = match Crypto::detect(&path) {
+        Crypto::Locked => {
+            let contents = datafile::read_with_fallback(&path)?.data;
+            let params = crypto::envelope_params(&contents).ok_or("Data file is not encrypted")?;
+            let session = Session::with_params(&passphrase()?, params)?;
+            session.decrypt(&contents)?;
+            Crypto::Unlocked(Box::new(session))
+        }
+        crypto => crypto,
+    };
+    Ok(CryptoState(Mutex::new(crypto)))
+}
+
+fn passphrase() -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    eprint!("Passphrase: ");
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// The data file's JSON, from the previous save if the live file is damaged,
+/// with the audit log put back in
+fn read_data(state: &CryptoState) -> Result<Value, String> {
+    let loaded = datafile::read_with_fallback(&commands::get_data_file_path())?;
+    if let Some(warning) = loaded.warning {
+        eprintln!("warning: {}", warning);
+    }
+    let crypto = commands::lock_crypto(state)?;
+    let document = auditlog::insert(&crypto.open(&loaded.data)?, commands::read_audit_log(&crypto)?)?;
+    serde_json::from_str(&document).map_err(|e| format!("Invalid data file: {}", e))
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// ========== export ==========
+
+fn export(format: &str, out: Option<PathBuf>, state: &CryptoState) -> Result<(), String> {
+    let document = read_data(state)?;
+    let archives = commands::read_archives(&*commands::lock_crypto(state)?);
+    match format {
+        "json" => {
+            // The same layout as the data inside a backup, so `import` takes it back
+            let bundled = archive::bundle(&document.to_string(), &archives)?;
+            let bundled: Value = serde_json::from_str(&bundled).map_err(|e| e.to_string())?;
+            let json = serde_json::to_string_pretty(&bundled).map_err(|e| e.to_string())?;
+            match out {
+                Some(path) => write_file(&path, &json),
+                None => writeln!(io::stdout().lock(), "{}", json).map_err(|e| e.to_string()),
+            }
+        }
+        "csv" => {
+            let dir = out.unwrap_or_else(|| PathBuf::from("."));
+            fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            let archived: Vec<Value> = archives
+                .iter()
+                .filter_map(|(_, archive)| serde_json::from_str(archive).ok())
+                .collect();
+            for (key, name) in COLLECTIONS {
+                let mut items = document.get(key).and_then(Value::as_array).cloned().unwrap_or_default();
+                for archive in &archived {
+                    items.extend(archive.get(key).and_then(Value::as_array).into_iter().flatten().cloned());
+                }
+                let path = dir.join(format!("{}.csv", name));
+                write_file(&path, &to_csv(&items))?;
+                println!("{:>6} rows  {}", items.len(), path.display());
+            }
+            Ok(())
+        }
+        other => Err(format!("Unknown export format '{}': use csv or json", other)),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per item and one column per field, `id` first. Nested values are
+/// written as JSON. Starts with a BOM so Excel reads the Thai text as UTF-8.
+fn to_csv(items: &[Value]) -> String {
+    let mut columns = vec!["id".to_string()];
+    for key in items.iter().filter_map(Value::as_object).flat_map(|item| item.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+
+    let mut csv = String::from("\u{feff}");
+    csv.push_str(&columns.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(","));
+    csv.push_str("\r\n");
+    for item in items {
+        let row: Vec<String> = columns
+            .iter()
+            .map(|column| match item.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(text)) => csv_field(text),
+                Some(other) => csv_field(&other.to_string()),
+            })
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+// ========== import / restore ==========
+
+/// Make `document` the clinic's data, like restoring in the app: back up
+/// what is there now and write the data file and the archives it carries
+fn replace_data(document: &str, state: &CryptoState) -> Result<(), String> {
+    let (document, archives) = archive::unbundle(document)?;
+    let (document, log) = auditlog::split(&document)?;
+    serde_json::from_str::<ClinicData>(&document).map_err(|e| format!("Invalid clinic data: {}", e))?;
+    if commands::get_data_file_path().exists() {
+        if let Some(path) = commands::take_backup(state, Reason::Restore)? {
+            println!("Previous data backed up to {}", path.display());
+        }
+    }
+    commands::write_archives(&archives, &*commands::lock_crypto(state)?)?;
+    if let Some(log) = log {
+        commands::write_audit_log(&log, &*commands::lock_crypto(state)?)?;
+    }
+    commands::write_data_file(&document, state)
+}
+
+fn import(file: &Path, state: &CryptoState) -> Result<(), String> {
+    let contents = fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    let document = backup::unpack(&commands::lock_crypto(state)?.open(&contents)?)?;
+    replace_data(&document, state)?;
+    println!("Imported {}: {}", file.display(), describe(&counts_of(&document)?));
+    Ok(())
+}
+
+fn restore(name: &str, state: &CryptoState) -> Result<(), String> {
+    let json = commands::read_backup_file(&commands::backup_file(name)?, &*commands::lock_crypto(state)?)?;
+    let document = backup::unpack(&json)?;
+    replace_data(&document, state)?;
+    println!("Restored {}: {}", name, describe(&counts_of(&document)?));
+    Ok(())
+}
+
+fn list_backups() -> Result<(), String> {
+    let backup_dir = commands::get_backup_dir();
+    for name in commands::backup_names()? {
+        let size = fs::metadata(backup_dir.join(&name)).map(|m| m.len()).unwrap_or(0);
+        println!("{:>10}  {}", size, name);
+    }
+    Ok(())
+}
+
+// ========== verify / stats ==========
+
+fn counts_of(document: &str) -> Result<Counts, String> {
+    serde_json::from_str::<ClinicData>(document).map_err(|e| format!("Invalid clinic data: {}", e))?;
+    let value: Value = serde_json::from_str(document).map_err(|e| e.to_string())?;
+    Ok(backup::counts(&value))
+}
+
+fn describe(counts: &Counts) -> String {
+    format!(
+        "{} patients, {} records, {} drugs, {} expenses, {} drug purchases, {} appointments",
+        counts.patients, counts.records, counts.drugs, counts.expenses, counts.drug_purchases, counts.appointments
+    )
+}
+
+fn report(label: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(contents) => {
+            println!("OK    {}  ({})", label, contents);
+            true
+        }
+        Err(err) => {
+            println!("FAIL  {}: {}", label, err);
+            false
+        }
+    }
+}
+
+/// Open the data file, its previous generation and every backup the way a
+/// restore would, including the backups' checksums
+fn verify(state: &CryptoState) -> Result<(), String> {
+    let crypto = commands::lock_crypto(state)?;
+    let data_file = commands::get_data_file_path();
+    let mut failed = 0;
+
+    for path in [data_file.clone(), datafile::backup_path(&data_file)] {
+        if !path.exists() && path != data_file {
+            continue;
+        }
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| crypto.open(&contents))
+            .and_then(|document| counts_of(&document));
+        failed += usize::from(!report(&path.display().to_string(), result.map(|c| describe(&c))));
+    }
+
+    let result = commands::read_audit_log(&crypto).map(|entries| format!("{} audit log entries", entries.len()));
+    failed += usize::from(!report(auditlog::FILE, result));
+
+    let backup_dir = commands::get_backup_dir();
+    for name in commands::backup_names()? {
+        let result = commands::read_backup_file(&backup_dir.join(&name), &crypto)
+            .and_then(|json| backup::unpack(&json))
+            .and_then(|document| counts_of(&document));
+        failed += usize::from(!report(&name, result.map(|c| describe(&c))));
+    }
+
+    for year in commands::archive_years()? {
+        let result = commands::read_archive(year, &crypto)
+            .and_then(|archive| archive.ok_or_else(|| "missing".to_string()))
+            .and_then(|archive| archive::count(&archive))
+            .map(|items| format!("{} archived items", items));
+        failed += usize::from(!report(&archive::file_name(year), result));
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{} file(s) failed verification", n)),
+    }
+}
+
+fn stats(state: &CryptoState) -> Result<(), String> {
+    let data_file = commands::get_data_file_path();
+    let document = read_data(state)?;
+    let size = fs::metadata(&data_file).map(|m| m.len()).unwrap_or(0);
+    println!("Data file:   {} ({} bytes)", data_file.display(), size);
+    println!("Encrypted:   {}", if commands::lock_crypto(state)?.is_enabled() { "yes" } else { "no" });
+    println!("Last HN:     {}", document.get("clinic_last_hn").and_then(Value::as_u64).unwrap_or(0));
+    println!();
+
+    for (key, name) in COLLECTIONS {
+        let items = document.get(key).and_then(Value::as_array).map_or(&[][..], Vec::as_slice);
+        let in_trash = items.iter().filter(|item| item.get("deleted_at").is_some_and(|at| !at.is_null())).count();
+        println!("{:<16}{:>7}   ({} in the Recycle Bin)", name, items.len(), in_trash);
+    }
+    println!();
+
+    let years = commands::archive_years()?;
+    if !years.is_empty() {
+        let years: Vec<String> = years.iter().map(i32::to_string).collect();
+        println!("Archived:    {}", years.join(", "));
+        println!();
+    }
+
+    let names = commands::backup_names()?;
+    let newest = names.iter().filter_map(|name| backup::taken_at(name)).max();
+    println!("Backups:     {} in {}", names.len(), commands::get_backup_dir().display());
+    match newest {
+        Some(at) => println!("Newest:      {}", at.format("%Y-%m-%d %H:%M:%S")),
+        None => println!("Newest:      none"),
+    }
+    let schedule = commands::read_schedule(&document.to_string());
+    if !schedule.mirror_dir.is_empty() {
+        let mirror = PathBuf::from(&schedule.mirror_dir);
+        match backup::mirror_warning(&commands::get_backup_dir(), names.first(), &mirror) {
+            Some(warning) => println!("Mirror:      {}", warning),
+            None => println!("Mirror:      {} is up to date", mirror.display()),
+        }
+    }
+    Ok(())
+}
+
+fn check(state: &CryptoState) -> Result<(), String> {
+    let mut found = 0;
+    for category in integrity::scan(&read_data(state)?) {
+        println!("{} ({})", category.title, category.problems.len());
+        for problem in &category.problems {
+            println!("  {}", problem);
+        }
+        found += category.problems.len();
+    }
+    match found {
+        0 => Ok(()),
+        n => Err(format!("{} problem(s) found; fix them in Settings", n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse(args("--data-dir /mnt/old export --format csv --out out")).unwrap(),
+            (Some(PathBuf::from("/mnt/old")), Command::Export { format: "csv".into(), out: Some(PathBuf::from("out")) })
+        );
+        assert_eq!(parse(args("restore")).unwrap().1, Command::Restore(None));
+        assert_eq!(parse(args("check")).unwrap().1, Command::Check);
+        assert_eq!(parse(args("import a.json")).unwrap().1, Command::Import(PathBuf::from("a.json")));
+        assert_eq!(parse(args("")).unwrap().1, Command::Help);
+        assert!(parse(args("export")).is_err());
+        assert!(parse(args("backup now")).is_err());
+        assert!(parse(args("stats --out")).is_err());
+        assert!(parse(args("frobnicate")).is_err());
+    }
+
+    #[test]
+    fn test_csv_columns_and_escaping() {
+        let items = vec![
+            json!({"name": "สมชาย, ใจดี", "id": "p1", "allergies": ["penicillin"]}),
+            json!({"id": "p2", "note": "say \"hi\"", "deleted_at": null}),
+        ];
+        let csv = to_csv(&items);
+        let lines: Vec<&str> = csv.trim_start_matches('\u{feff}').lines().collect();
+        assert_eq!(lines[0], "id,allergies,name,deleted_at,note");
+        assert_eq!(lines[1], "p1,\"[\"\"penicillin\"\"]\",\"สมชาย, ใจดี\",,");
+        assert_eq!(lines[2], "p2,,,,\"say \"\"hi\"\"\"");
+    }
+}
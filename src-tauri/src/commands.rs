@@ -1,12 +1,87 @@
 use std::fs;
+use std::io::Write;
+use std::net::TcpListener;
 use std::path::PathBuf;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Manager, State};
 use tauri_plugin_updater::UpdaterExt;
+use crate::archive;
+use crate::auditlog;
+use crate::backup::{self, Counts, Reason, Schedule};
+use crate::crypto::{self, Session};
+use crate::datafile::{self, LoadedData};
+use crate::models::ClinicData;
+use crate::sync;
+
+/// Whether clinic_data.json and the backups are encrypted, and the key once unlocked
+pub enum Crypto {
+    Off,
+    Locked,
+    Unlocked(Box<Session>),
+}
+
+pub struct CryptoState(pub Mutex<Crypto>);
+
+const LOCKED_MESSAGE: &str = "ข้อมูลถูกเข้ารหัสอยู่ กรุณาใส่รหัสผ่านเพื่อปลดล็อกก่อน";
+
+impl Crypto {
+    /// At startup the data file itself tells whether encryption is on
+    pub fn detect(path: &Path) -> Self {
+        match datafile::read_with_fallback(path) {
+            Ok(loaded) if crypto::is_encrypted(&loaded.data) => Crypto::Locked,
+            _ => Crypto::Off,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Crypto::Off)
+    }
+
+    /// What to write to disk for `plaintext`
+    pub(crate) fn seal(&self, plaintext: &str) -> Result<String, String> {
+        match self {
+            Crypto::Off => Ok(plaintext.to_string()),
+            Crypto::Locked => Err(LOCKED_MESSAGE.to_string()),
+            Crypto::Unlocked(session) => session.encrypt(plaintext),
+        }
+    }
+
+    /// Plain JSON from a file on disk, whether it was encrypted or not
+    pub(crate) fn open(&self, contents: &str) -> Result<String, String> {
+        if !crypto::is_encrypted(contents) {
+            return Ok(contents.to_string());
+        }
+        match self {
+            Crypto::Unlocked(session) => session.decrypt(contents),
+            Crypto::Locked => Err(LOCKED_MESSAGE.to_string()),
+            Crypto::Off => Err("ไฟล์นี้ถูกเข้ารหัส กรุณาเปิดการเข้ารหัสด้วยรหัสผ่านของไฟล์ก่อน".to_string()),
+        }
+    }
+}
+
+pub(crate) fn lock_crypto(crypto: &CryptoState) -> Result<std::sync::MutexGuard<'_, Crypto>, String> {
+    crypto.0.lock().map_err(|e| e.to_string())
+}
+
+/// Environment variable that points the app and clinic-cli at another data folder
+pub const DATA_DIR_VAR: &str = "CLINICCARE_DATA_DIR";
 
 // Data file path helper
 fn get_data_dir() -> PathBuf {
-    let data_dir = dirs::data_local_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("ClinicCare");
+    let data_dir = std::env::var_os(DATA_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            dirs::data_local_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("ClinicCare")
+        });
     
     // Create directory if it doesn't exist
     if !data_dir.exists() {
@@ -16,12 +91,21 @@ fn get_data_dir() -> PathBuf {
     data_dir
 }
 
-fn get_data_file_path() -> PathBuf {
+pub fn get_data_file_path() -> PathBuf {
     get_data_dir().join("clinic_data.json")
 }
 
+fn get_audit_log_path() -> PathBuf {
+    get_data_dir().join(auditlog::FILE)
+}
+
+/// Where older versions kept a SQLite copy of the data
+fn get_database_path() -> PathBuf {
+    get_data_dir().join("clinic.db")
+}
+
 // Backup directory
-fn get_backup_dir() -> PathBuf {
+pub(crate) fn get_backup_dir() -> PathBuf {
     let backup_dir = get_data_dir().join("backups");
     if !backup_dir.exists() {
         let _ = fs::create_dir_all(&backup_dir);
@@ -29,61 +113,228 @@ fn get_backup_dir() -> PathBuf {
     backup_dir
 }
 
+// Yearly archives, see archive.rs
+fn get_archive_dir() -> PathBuf {
+    let archive_dir = get_data_dir().join("archives");
+    if !archive_dir.exists() {
+        let _ = fs::create_dir_all(&archive_dir);
+    }
+    archive_dir
+}
+
 // ============ Tauri Commands ============
 
-/// Load all clinic data from file
+/// Load all clinic data from file, or from the previous save if the file is damaged
 #[tauri::command]
-pub fn load_clinic_data() -> Result<String, String> {
-    let file_path = get_data_file_path();
-    
-    if file_path.exists() {
-        fs::read_to_string(&file_path)
-            .map_err(|e| format!("Failed to read data file: {}", e))
-    } else {
-        // Return empty object if file doesn't exist yet
-        Ok("{}".to_string())
-    }
+pub fn load_clinic_data(crypto: State<CryptoState>) -> Result<LoadedData, String> {
+    let mut loaded = datafile::read_with_fallback(&get_data_file_path())?;
+    loaded.data = lock_crypto(&crypto)?.open(&loaded.data)?;
+    Ok(loaded)
 }
 
 /// Save all clinic data to file
 #[tauri::command]
-pub fn save_clinic_data(data: String) -> Result<(), String> {
+pub fn save_clinic_data(data: String, crypto: State<CryptoState>) -> Result<(), String> {
+    serde_json::from_str::<ClinicData>(&data).map_err(|e| format!("Invalid clinic data: {}", e))?;
+    write_data_file(&data, &crypto)
+}
+
+pub(crate) fn write_data_file(data: &str, crypto: &CryptoState) -> Result<(), String> {
     let file_path = get_data_file_path();
     
-    fs::write(&file_path, &data)
-        .map_err(|e| format!("Failed to save data file: {}", e))?;
+    datafile::write_atomic(&file_path, &lock_crypto(crypto)?.seal(data)?)?;
     
     log::info!("Data saved to {:?}", file_path);
     Ok(())
 }
 
+/// The audit log entries in audit_log.jsonl, oldest first
+pub(crate) fn read_audit_log(crypto: &Crypto) -> Result<Vec<Value>, String> {
+    let path = get_audit_log_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read audit log: {}", e))?;
+    auditlog::from_lines(&contents, |line| crypto.open(line))
+}
+
+/// Replace audit_log.jsonl with `entries`
+pub(crate) fn write_audit_log(entries: &[Value], crypto: &Crypto) -> Result<(), String> {
+    datafile::replace_atomic(&get_audit_log_path(), &auditlog::to_lines(entries, |entry| crypto.seal(entry))?)
+        .map_err(|e| format!("Failed to save audit log: {}", e))
+}
+
+fn parse_entries(entries: &str) -> Result<Vec<Value>, String> {
+    serde_json::from_str(entries).map_err(|e| format!("Invalid audit log entries: {}", e))
+}
+
+/// The audit log as a JSON array
+#[tauri::command]
+pub fn load_audit_log(crypto: State<CryptoState>) -> Result<String, String> {
+    Ok(Value::Array(read_audit_log(&*lock_crypto(&crypto)?)?).to_string())
+}
+
+/// Add the entries of one save to the end of the audit log
+#[tauri::command]
+pub fn append_audit_log(entries: String, crypto: State<CryptoState>) -> Result<(), String> {
+    let entries = parse_entries(&entries)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let lines = auditlog::to_lines(&entries, |entry| lock_crypto(&crypto)?.seal(entry))?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_audit_log_path())
+        .map_err(|e| format!("Failed to open audit log: {}", e))?;
+    file.write_all(lines.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to save audit log: {}", e))
+}
+
+/// Replace the whole audit log, after old entries moved to the archives or a restore
+#[tauri::command]
+pub fn save_audit_log(entries: String, crypto: State<CryptoState>) -> Result<(), String> {
+    write_audit_log(&parse_entries(&entries)?, &*lock_crypto(&crypto)?)
+}
+
 /// Get the data file path for display
 #[tauri::command]
 pub fn get_data_path() -> String {
     get_data_file_path().to_string_lossy().to_string()
 }
 
-/// Create a backup with timestamp
-#[tauri::command]
-pub fn create_backup() -> Result<String, String> {
+/// The data file's JSON, decrypted
+fn read_document(crypto: &Crypto) -> Result<String, String> {
     let source = get_data_file_path();
     
     if !source.exists() {
         return Err("No data file to backup".to_string());
     }
     
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-    let backup_path = get_backup_dir().join(format!("backup_{}.json", timestamp));
+    let contents = fs::read_to_string(&source)
+        .map_err(|e| format!("Failed to read data file: {}", e))?;
+    crypto.open(&contents)
+}
+
+/// The data file's JSON with the audit log put back in, as backups and sync expect it
+fn read_full_document(crypto: &Crypto) -> Result<String, String> {
+    auditlog::insert(&read_document(crypto)?, read_audit_log(crypto)?)
+}
+
+/// The backup schedule saved in the settings
+pub(crate) fn read_schedule(document: &str) -> Schedule {
+    serde_json::from_str(document)
+        .map(|doc| Schedule::from_document(&doc))
+        .unwrap_or_default()
+}
+
+/// Back up the data file, in the same format as the browser download, unless
+/// the schedule in the settings turns off backups for `reason`. Old backups are
+/// then thinned out by the same schedule, and everything is copied to the
+/// mirror folder if one is set. Returns the new backup's path.
+pub fn take_backup(crypto: &CryptoState, reason: Reason) -> Result<Option<PathBuf>, String> {
+    let crypto = lock_crypto(crypto)?;
+    let document = read_full_document(&crypto)?;
+    let schedule = read_schedule(&document);
+    if !reason.is_enabled(&schedule) {
+        return Ok(None);
+    }
+    let backup = backup::create(&archive::bundle(&document, &read_archives(&crypto))?)?;
+    
+    let backup_dir = get_backup_dir();
+    let backup_path = backup_dir.join(backup::file_name(chrono::Local::now().naive_local(), reason));
     
-    fs::copy(&source, &backup_path)
+    datafile::replace_atomic(&backup_path, &crypto.seal(&backup)?)
         .map_err(|e| format!("Failed to create backup: {}", e))?;
     
-    Ok(backup_path.to_string_lossy().to_string())
+    for name in backup::to_prune(&backup_names()?, &schedule) {
+        if let Err(e) = fs::remove_file(backup_dir.join(&name)) {
+            log::error!("Could not remove old backup {}: {}", name, e);
+        }
+    }
+    
+    // A missing USB drive shouldn't lose the backup that was just taken;
+    // get_backup_status reports it instead
+    if !schedule.mirror_dir.is_empty() {
+        match backup::mirror(&backup_dir, &backup_names()?, Path::new(&schedule.mirror_dir), &schedule) {
+            Ok(copied) => log::info!("Copied {} backup(s) to {}", copied, schedule.mirror_dir),
+            Err(e) => log::warn!("Could not mirror backups: {}", e),
+        }
+    }
+    
+    Ok(Some(backup_path))
+}
+
+/// The automatic backups that don't come from a command. Failures are only
+/// logged: nobody is waiting on them, and the data file itself is unharmed.
+pub fn scheduled_backup(crypto: &CryptoState, reason: Reason) {
+    if reason == Reason::Daily {
+        let today = chrono::Local::now().date_naive();
+        if !backup_names().is_ok_and(|names| backup::daily_due(&names, today)) {
+            return;
+        }
+    }
+    match take_backup(crypto, reason) {
+        Ok(Some(path)) => log::info!("Backup taken: {:?}", path),
+        Ok(None) => {}
+        Err(e) => log::error!("Automatic {:?} backup failed: {}", reason, e),
+    }
+}
+
+/// Create a backup with timestamp
+#[tauri::command]
+pub fn create_backup(crypto: State<CryptoState>) -> Result<String, String> {
+    let path = take_backup(&crypto, Reason::Manual)?.ok_or_else(|| "Failed to create backup".to_string())?;
+    Ok(path.to_string_lossy().to_string())
 }
 
-/// List available backups
+/// Take the automatic backup that goes before restoring data in the frontend.
+/// Returns the path, or None when the schedule has it turned off.
 #[tauri::command]
-pub fn list_backups() -> Result<Vec<String>, String> {
+pub fn backup_before_restore(crypto: State<CryptoState>) -> Result<Option<String>, String> {
+    Ok(take_backup(&crypto, Reason::Restore)?.map(|path| path.to_string_lossy().to_string()))
+}
+
+/// How the backups stand, for the warning on the home page
+#[derive(Serialize)]
+pub struct BackupStatus {
+    /// When the newest backup was taken, going by its file name
+    pub last_backup: Option<NaiveDateTime>,
+    /// Why the mirror folder doesn't hold a good copy of the newest backup
+    pub mirror_warning: Option<String>,
+}
+
+#[tauri::command]
+pub fn get_backup_status(crypto: State<CryptoState>) -> Result<BackupStatus, String> {
+    let names = backup_names()?;
+    let last_backup = names.iter().filter_map(|name| backup::taken_at(name)).max();
+    
+    let schedule = read_document(&*lock_crypto(&crypto)?)
+        .map(|document| read_schedule(&document))
+        .unwrap_or_default();
+    let mirror_warning = if schedule.mirror_dir.is_empty() {
+        None
+    } else {
+        backup::mirror_warning(&get_backup_dir(), names.first(), Path::new(&schedule.mirror_dir))
+    };
+    
+    Ok(BackupStatus { last_backup, mirror_warning })
+}
+
+/// Copy the backups to `mirror_dir` now, checking every copy, so the folder
+/// can be tried out before it is saved in the settings. Returns how many
+/// files were copied.
+#[tauri::command]
+pub fn mirror_backups(crypto: State<CryptoState>, mirror_dir: String) -> Result<usize, String> {
+    let schedule = read_document(&*lock_crypto(&crypto)?)
+        .map(|document| read_schedule(&document))
+        .unwrap_or_default();
+    backup::mirror(&get_backup_dir(), &backup_names()?, Path::new(mirror_dir.trim()), &schedule)
+}
+
+/// Names of the backup files, most recent first
+pub(crate) fn backup_names() -> Result<Vec<String>, String> {
     let backup_dir = get_backup_dir();
     
     let mut backups: Vec<String> = fs::read_dir(&backup_dir)
@@ -106,24 +357,521 @@ pub fn list_backups() -> Result<Vec<String>, String> {
     Ok(backups)
 }
 
-/// Restore from a backup file
-#[tauri::command]
-pub fn restore_backup(backup_name: String) -> Result<String, String> {
-    let backup_path = get_backup_dir().join(&backup_name);
-    
-    if !backup_path.exists() {
+/// A backup file as the backup list shows it
+#[derive(Serialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub taken_at: Option<NaiveDateTime>,
+    /// `Reason::tag`, None for backups made by hand or by an older version
+    pub reason: Option<&'static str>,
+    pub size: u64,
+    pub counts: Option<Counts>,
+    /// Why the contents could not be read, e.g. a damaged file
+    pub error: Option<String>,
+}
+
+/// A file in the backups folder, by name only so nothing outside it can be reached
+pub(crate) fn backup_file(name: &str) -> Result<PathBuf, String> {
+    let path = get_backup_dir().join(name);
+    if name.contains(['/', '\\']) || name.starts_with('.') || !name.ends_with(".json") || !path.is_file() {
         return Err("Backup file not found".to_string());
     }
-    
-    let data = fs::read_to_string(&backup_path)
+    Ok(path)
+}
+
+/// A backup as plain JSON, in the backup format if it was made by this version
+pub(crate) fn read_backup_file(path: &Path, crypto: &Crypto) -> Result<String, String> {
+    let contents = fs::read_to_string(path)
         .map_err(|e| format!("Failed to read backup: {}", e))?;
+    crypto.open(&contents)
+}
+
+/// List available backups with what is in them
+#[tauri::command]
+pub fn list_backups(crypto: State<CryptoState>) -> Result<Vec<BackupInfo>, String> {
+    let backup_dir = get_backup_dir();
+    let crypto = lock_crypto(&crypto)?;
+    
+    let backups = backup_names()?
+        .into_iter()
+        .map(|name| {
+            let path = backup_dir.join(&name);
+            let contents = read_backup_file(&path, &crypto)
+                .and_then(|json| backup::unpack(&json))
+                .and_then(|doc| serde_json::from_str(&doc).map_err(|e| e.to_string()));
+            let (counts, error) = match contents {
+                Ok(doc) => (Some(backup::counts(&doc)), None),
+                Err(e) => (None, Some(e)),
+            };
+            BackupInfo {
+                taken_at: backup::taken_at(&name),
+                reason: backup::reason_of(&name).and_then(Reason::tag),
+                size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                counts,
+                error,
+                name,
+            }
+        })
+        .collect();
+    
+    Ok(backups)
+}
+
+/// A backup's plain JSON, for the frontend to preview, restore or export
+#[tauri::command]
+pub fn read_backup(backup_name: String, crypto: State<CryptoState>) -> Result<String, String> {
+    read_backup_file(&backup_file(&backup_name)?, &*lock_crypto(&crypto)?)
+}
+
+#[tauri::command]
+pub fn delete_backup(backup_name: String) -> Result<(), String> {
+    fs::remove_file(backup_file(&backup_name)?)
+        .map_err(|e| format!("Failed to delete backup: {}", e))
+}
+
+/// Restore from a backup file
+#[tauri::command]
+pub fn restore_backup(backup_name: String, crypto: State<CryptoState>) -> Result<String, String> {
+    let document = backup::unpack(&read_backup_file(&backup_file(&backup_name)?, &*lock_crypto(&crypto)?)?)?;
+    let (data, archives) = archive::unbundle(&document)?;
+    take_backup(&crypto, Reason::Restore)?;
     
     // Save as current data
-    save_clinic_data(data.clone())?;
+    write_archives(&archives, &*lock_crypto(&crypto)?)?;
+    let (file_data, log) = auditlog::split(&data)?;
+    if let Some(log) = log {
+        write_audit_log(&log, &*lock_crypto(&crypto)?)?;
+    }
+    save_clinic_data(file_data, crypto)?;
     
     Ok(data)
 }
 
+// ============ Archive Commands ============
+
+/// Years of the archive files, oldest first
+pub(crate) fn archive_years() -> Result<Vec<i32>, String> {
+    let mut years: Vec<i32> = fs::read_dir(get_archive_dir())
+        .map_err(|e| format!("Failed to read archive directory: {}", e))?
+        .flatten()
+        .filter_map(|entry| archive::year_of(&entry.file_name().to_string_lossy()))
+        .collect();
+    years.sort();
+    Ok(years)
+}
+
+pub(crate) fn read_archive(year: i32, crypto: &Crypto) -> Result<Option<String>, String> {
+    let path = get_archive_dir().join(archive::file_name(year));
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read archive {}: {}", year, e))?;
+    crypto.open(&contents).map(Some)
+}
+
+/// Every archive that can be read, for a backup. One that can't is left out
+/// and logged rather than costing the whole backup.
+pub(crate) fn read_archives(crypto: &Crypto) -> Vec<(i32, String)> {
+    archive_years()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|year| match read_archive(year, crypto) {
+            Ok(archive) => archive.map(|archive| (year, archive)),
+            Err(e) => {
+                log::error!("Archive {} left out of the backup: {}", year, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn write_archive(year: i32, archive: &str, crypto: &Crypto) -> Result<(), String> {
+    datafile::replace_atomic(&get_archive_dir().join(archive::file_name(year)), &crypto.seal(archive)?)
+        .map_err(|e| format!("Failed to save archive {}: {}", year, e))
+}
+
+/// Restored archives merged into the ones on disk
+pub(crate) fn write_archives(archives: &[(i32, String)], crypto: &Crypto) -> Result<(), String> {
+    for (year, incoming) in archives {
+        let merged = match read_archive(*year, crypto)? {
+            Some(existing) => archive::merge(&existing, incoming)?,
+            None => incoming.clone(),
+        };
+        write_archive(*year, &merged, crypto)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_archives() -> Result<Vec<i32>, String> {
+    archive_years()
+}
+
+/// An archive's plain JSON, or None when there is no archive for `year`
+#[tauri::command]
+pub fn load_archive(year: i32, crypto: State<CryptoState>) -> Result<Option<String>, String> {
+    read_archive(year, &*lock_crypto(&crypto)?)
+}
+
+/// Write an archive as the frontend built it, replacing the file for `year`
+#[tauri::command]
+pub fn save_archive(year: i32, data: String, crypto: State<CryptoState>) -> Result<(), String> {
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&data)
+        .map_err(|e| format!("Invalid archive: {}", e))?;
+    write_archive(year, &data, &*lock_crypto(&crypto)?)
+}
+
+// ============ Sync Commands ============
+
+/// The LAN sync server and timer (see sync.rs), started again whenever sync.json is saved
+#[derive(Default)]
+pub struct SyncState {
+    threads: Mutex<Option<SyncThreads>>,
+    /// Held for one exchange, so the timer and "sync now" never overlap
+    exchanging: Mutex<()>,
+}
+
+struct SyncThreads {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+// How often the sync timer checks whether it should stop or sync
+const SYNC_TICK: Duration = Duration::from_secs(1);
+
+/// sync.json, or the defaults (sync off) when there is none yet or it can't be
+/// opened. It holds the sync key, so it is sealed like the data file; while the
+/// data is locked sync stays off, and unlocking starts it.
+fn read_sync_config(app: &AppHandle) -> sync::Config {
+    let state = app.state::<CryptoState>();
+    let Ok(crypto) = lock_crypto(&state) else {
+        return sync::Config::default();
+    };
+    fs::read_to_string(get_data_dir().join(sync::CONFIG_FILE))
+        .ok()
+        .and_then(|contents| crypto.open(&contents).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn write_sync_config(app: &AppHandle, config: &sync::Config) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    let sealed = lock_crypto(&app.state::<CryptoState>())?.seal(&json)?;
+    datafile::replace_atomic(&get_data_dir().join(sync::CONFIG_FILE), &sealed)
+        .map_err(|e| format!("Failed to save sync settings: {}", e))
+}
+
+/// Changes from the peer that the frontend hasn't applied yet, oldest first.
+/// Sealed like the data file, since they are clinic data too.
+fn read_inbox(crypto: &Crypto) -> Result<Vec<Value>, String> {
+    let path = get_data_dir().join(sync::INBOX_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read sync inbox: {}", e))?;
+    serde_json::from_str(&crypto.open(&contents)?).map_err(|e| format!("Invalid sync inbox: {}", e))
+}
+
+fn write_inbox(inbox: &[Value], crypto: &Crypto) -> Result<(), String> {
+    let json = serde_json::to_string(inbox).map_err(|e| e.to_string())?;
+    datafile::replace_atomic(&get_data_dir().join(sync::INBOX_FILE), &crypto.seal(&json)?)
+        .map_err(|e| format!("Failed to save sync inbox: {}", e))
+}
+
+fn push_inbox(changes: Value, crypto: &CryptoState) -> Result<(), String> {
+    if sync::is_empty(&changes) {
+        return Ok(());
+    }
+    let crypto = lock_crypto(crypto)?;
+    let mut inbox = read_inbox(&crypto)?;
+    inbox.push(changes);
+    write_inbox(&inbox, &crypto)
+}
+
+/// A request from the peer: keep its changes for the frontend and answer with ours
+fn answer_peer(app: &AppHandle, request: sync::Request) -> Result<sync::Response, String> {
+    let until = Utc::now();
+    let crypto = app.state::<CryptoState>();
+    let document = read_full_document(&*lock_crypto(&crypto)?)?;
+    push_inbox(request.changes, &crypto)?;
+    Ok(sync::Response { until, changes: sync::changes_since(&document, request.since)? })
+}
+
+/// Send our changes to the peer and keep its answer. Returns the peer's time
+/// and how many items it sent.
+fn exchange_with_peer(app: &AppHandle, config: &sync::Config) -> Result<(DateTime<Utc>, usize), String> {
+    let (send_since, ask_since) = config.next_since();
+    let crypto = app.state::<CryptoState>();
+    let document = read_full_document(&*lock_crypto(&crypto)?)?;
+    let request = sync::Request { since: ask_since, changes: sync::changes_since(&document, send_since)? };
+    let key = crypto::Key::derive(&config.key, crypto::KdfParams::generate())?;
+    let response = sync::exchange(config.peer.trim(), &key, &request)?;
+    let received = sync::count(&response.changes);
+    push_inbox(response.changes, &crypto)?;
+    Ok((response.until, received))
+}
+
+/// One exchange with the peer in sync.json, with the outcome saved for Settings
+fn run_sync(app: &AppHandle) -> Result<usize, String> {
+    let state = app.state::<SyncState>();
+    let Ok(_exchanging) = state.exchanging.try_lock() else {
+        return Err("กำลังซิงก์อยู่ กรุณารอสักครู่".to_string());
+    };
+    let config = read_sync_config(app);
+    if !config.enabled || config.peer.trim().is_empty() || config.key.is_empty() {
+        return Err("ยังไม่ได้ตั้งค่าการซิงก์กับเครื่องอื่น".to_string());
+    }
+
+    let started = Utc::now();
+    let result = exchange_with_peer(app, &config);
+
+    // Settings may have been saved meanwhile; only the status is ours to change
+    let mut latest = read_sync_config(app);
+    if latest.peer == config.peer {
+        match &result {
+            Ok((until, _)) => {
+                latest.status.sent = Some(started);
+                latest.status.received = Some(*until);
+                latest.status.last_sync = Some(Utc::now());
+                latest.status.last_error = None;
+            }
+            Err(e) => latest.status.last_error = Some(e.clone()),
+        }
+        write_sync_config(app, &latest)?;
+    }
+    result.map(|(_, received)| received)
+}
+
+/// Stop the sync threads and start them again as sync.json says
+pub fn restart_sync(app: &AppHandle) -> Result<(), String> {
+    let state = app.state::<SyncState>();
+    let mut threads = state.threads.lock().map_err(|e| e.to_string())?;
+    if let Some(old) = threads.take() {
+        old.stop.store(true, Ordering::Relaxed);
+        for handle in old.handles {
+            let _ = handle.join();
+        }
+    }
+
+    let config = read_sync_config(app);
+    if !config.enabled || config.key.is_empty() {
+        return Ok(());
+    }
+    // Without an address set this listens on every network the computer is on,
+    // so sync works without knowing which one the peer is on; the sync key
+    // still keeps out anyone who doesn't have it
+    let host = match config.bind.trim() {
+        "" => "0.0.0.0",
+        address => address,
+    };
+    let listener = TcpListener::bind((host, config.port))
+        .map_err(|e| format!("เปิดพอร์ต {} ที่ {} สำหรับซิงก์ไม่ได้: {}", config.port, host, e))?;
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let server = {
+        let (app, stop, key) = (app.clone(), stop.clone(), config.key.clone());
+        std::thread::spawn(move || sync::serve(listener, &stop, &key, |request| answer_peer(&app, request)))
+    };
+    let timer = {
+        let (app, stop) = (app.clone(), stop.clone());
+        let every = Duration::from_secs(60 * u64::from(config.interval_minutes));
+        let automatic = config.interval_minutes > 0 && !config.peer.trim().is_empty();
+        std::thread::spawn(move || {
+            let mut waited = Duration::ZERO;
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(SYNC_TICK);
+                waited += SYNC_TICK;
+                if automatic && waited >= every {
+                    waited = Duration::ZERO;
+                    if let Err(e) = run_sync(&app) {
+                        log::warn!("Automatic sync failed: {}", e);
+                    }
+                }
+            }
+        })
+    };
+    *threads = Some(SyncThreads { stop, handles: vec![server, timer] });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_sync_config(app: AppHandle) -> sync::Config {
+    read_sync_config(&app)
+}
+
+/// Save the sync settings and restart the sync server with them. The status
+/// of the last sync is kept unless the peer changed.
+#[tauri::command]
+pub async fn save_sync_config(config: sync::Config, app: AppHandle) -> Result<(), String> {
+    let current = read_sync_config(&app);
+    let status = if current.peer == config.peer { current.status } else { sync::Status::default() };
+    write_sync_config(&app, &sync::Config { status, ..config })?;
+    restart_sync(&app)
+}
+
+/// Sync with the peer now. Returns how many items it sent; they wait in the
+/// inbox until the frontend applies them.
+#[tauri::command]
+pub async fn sync_now(app: AppHandle) -> Result<usize, String> {
+    run_sync(&app)
+}
+
+/// The inbox as clinic_data.json documents, for the frontend to apply
+#[tauri::command]
+pub fn read_sync_inbox(crypto: State<CryptoState>) -> Result<Vec<String>, String> {
+    Ok(read_inbox(&*lock_crypto(&crypto)?)?.iter().map(Value::to_string).collect())
+}
+
+/// Drop the first `count` inbox entries, once the frontend has saved them
+#[tauri::command]
+pub fn clear_sync_inbox(count: usize, crypto: State<CryptoState>) -> Result<(), String> {
+    let crypto = lock_crypto(&crypto)?;
+    let mut inbox = read_inbox(&crypto)?;
+    inbox.drain(..count.min(inbox.len()));
+    write_inbox(&inbox, &crypto)
+}
+
+// ============ Encryption Commands ============
+
+#[derive(Serialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+#[tauri::command]
+pub fn get_encryption_status(crypto: State<CryptoState>) -> Result<EncryptionStatus, String> {
+    let crypto = lock_crypto(&crypto)?;
+    Ok(EncryptionStatus {
+        enabled: crypto.is_enabled(),
+        unlocked: matches!(*crypto, Crypto::Unlocked(_)),
+    })
+}
+
+/// Check the passphrase against the data file and start sync, whose settings
+/// could not be read before
+#[tauri::command]
+pub fn unlock_data(passphrase: String, app: AppHandle, crypto: State<CryptoState>) -> Result<(), String> {
+    unlock(&passphrase, &crypto)?;
+    if let Err(err) = restart_sync(&app) {
+        log::error!("LAN sync not started: {}", err);
+    }
+    Ok(())
+}
+
+fn unlock(passphrase: &str, crypto: &CryptoState) -> Result<(), String> {
+    let mut crypto = lock_crypto(crypto)?;
+    if !matches!(*crypto, Crypto::Locked) {
+        return Ok(());
+    }
+
+    let contents = datafile::read_with_fallback(&get_data_file_path())?.data;
+    let params = crypto::envelope_params(&contents).ok_or_else(|| "Data file is not encrypted".to_string())?;
+    let session = Session::with_params(passphrase, params)?;
+    session.decrypt(&contents)?;
+    *crypto = Crypto::Unlocked(Box::new(session));
+    Ok(())
+}
+
+/// Turn encryption on, change the passphrase, or turn it off (`new_passphrase` empty).
+/// The data file, its .bak, the audit log, the sync inbox and settings, every backup (the mirror folder's copies included)
+/// and every archive are rewritten with the new setting, and an unreadable data file set aside as .corrupt is deleted.
+/// Returns the files that could not be opened and were left as they were.
+#[tauri::command]
+pub fn set_passphrase(
+    current_passphrase: String,
+    new_passphrase: String,
+    crypto: State<CryptoState>,
+) -> Result<Vec<String>, String> {
+    let mut crypto = lock_crypto(&crypto)?;
+    match &*crypto {
+        Crypto::Locked => return Err(LOCKED_MESSAGE.to_string()),
+        Crypto::Unlocked(session) if session.passphrase() != current_passphrase => {
+            return Err("รหัสผ่านเดิมไม่ถูกต้อง".to_string());
+        }
+        _ => {}
+    }
+
+    let next = if new_passphrase.is_empty() {
+        Crypto::Off
+    } else {
+        Crypto::Unlocked(Box::new(Session::new(&new_passphrase)?))
+    };
+
+    let data_file = get_data_file_path();
+    let mut skipped = Vec::new();
+    let mut files = vec![
+        data_file.clone(),
+        datafile::backup_path(&data_file),
+        get_data_dir().join(sync::INBOX_FILE),
+        get_data_dir().join(sync::CONFIG_FILE),
+    ];
+    for dir in [get_backup_dir(), get_archive_dir()] {
+        if let Ok(entries) = fs::read_dir(dir) {
+            files.extend(entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|x| x == "json")));
+        }
+    }
+    // The copies of the backups in the mirror folder too, when it is plugged in.
+    // Only our backups: the folder may be a USB drive with other files on it.
+    let mirror_dir = read_document(&crypto).map(|document| read_schedule(&document).mirror_dir).unwrap_or_default();
+    if !mirror_dir.is_empty() {
+        match fs::read_dir(&mirror_dir) {
+            Ok(entries) => files.extend(
+                entries.flatten().filter(|e| backup::taken_at(&e.file_name().to_string_lossy()).is_some()).map(|e| e.path()),
+            ),
+            Err(err) => {
+                log::error!("Could not read the mirror folder {}: {}", mirror_dir, err);
+                skipped.push(mirror_dir);
+            }
+        }
+    }
+
+    // A data file that was set aside unreadable can't be opened to seal again,
+    // and may hold plain data from before encryption was turned on
+    let corrupt = datafile::corrupt_path(&data_file);
+    if corrupt.exists() {
+        if let Err(err) = fs::remove_file(&corrupt) {
+            log::error!("Could not remove {:?}: {}", corrupt, err);
+            skipped.push(corrupt.file_name().unwrap_or_default().to_string_lossy().to_string());
+        }
+    }
+
+    for path in files.iter().filter(|p| p.exists()) {
+        let rewritten = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| crypto.open(&contents))
+            .and_then(|plain| next.seal(&plain))
+            .and_then(|sealed| datafile::replace_atomic(path, &sealed));
+        if let Err(err) = rewritten {
+            log::error!("Could not re-encrypt {:?}: {}", path, err);
+            skipped.push(path.file_name().unwrap_or_default().to_string_lossy().to_string());
+        }
+    }
+
+    // The audit log is sealed line by line
+    if get_audit_log_path().exists() {
+        if let Err(err) = read_audit_log(&crypto).and_then(|entries| write_audit_log(&entries, &next)) {
+            log::error!("Could not re-encrypt the audit log: {}", err);
+            skipped.push(auditlog::FILE.to_string());
+        }
+    }
+
+    *crypto = next;
+    Ok(skipped)
+}
+
+/// Delete clinic.db and its WAL files, left by versions that kept a SQLite
+/// copy of the data beside clinic_data.json
+pub fn remove_database_files() {
+    let path = get_database_path();
+    for suffix in ["", "-wal", "-shm"] {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        let _ = fs::remove_file(PathBuf::from(name));
+    }
+}
+
 /// Open data folder in file explorer
 #[tauri::command]
 pub fn open_data_folder() -> Result<(), String> {
@@ -157,6 +905,7 @@ pub async fn check_for_updates(app: tauri::AppHandle) -> Result<String, String>
         Ok(Some(update)) => {
             let version = update.version.to_string();
             log::info!("Update found: {}", version);
+            scheduled_backup(&app.state::<CryptoState>(), Reason::Update);
             
             // Download and install
             if let Err(e) = update.download_and_install(|_, _| {}, || {}).await {
@@ -0,0 +1,180 @@
+// Passphrase encryption for clinic_data.json and backups.
+// An encrypted file is still one JSON object, so the atomic writer, the .bak
+// fallback and the backup list handle it like any other data file:
+//   { "clinic_encrypted": 1, "kdf": { "m_cost", "t_cost", "p_cost", "salt" }, "nonce": "...", "data": "..." }
+// The key is Argon2id(passphrase, salt) and the data is sealed with AES-256-GCM,
+// so a wrong passphrase or a tampered file fails to open instead of giving garbage.
+// The frontend compiles this same file into src/crypto.rs, so both read and
+// write exactly the same format.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const ENVELOPE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: String,
+}
+
+impl KdfParams {
+    /// OWASP's Argon2id baseline (19 MiB, 2 passes) with a fresh salt
+    pub fn generate() -> Self {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self { m_cost: 19 * 1024, t_cost: 2, p_cost: 1, salt: BASE64.encode(salt) }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    clinic_encrypted: u32,
+    kdf: KdfParams,
+    nonce: String,
+    data: String,
+}
+
+/// A key derived from a passphrase, with the parameters that produced it
+pub struct Key {
+    params: KdfParams,
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    pub fn derive(passphrase: &str, params: KdfParams) -> Result<Self, String> {
+        let salt = BASE64.decode(&params.salt).map_err(|_| "Invalid salt".to_string())?;
+        let argon_params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+            .map_err(|e| format!("Invalid key parameters: {}", e))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, argon_params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| e.to_string())?;
+        Ok(Self { params, cipher })
+    }
+
+    pub fn params(&self) -> &KdfParams {
+        &self.params
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let data = self.cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| "Failed to encrypt data".to_string())?;
+        let envelope = Envelope {
+            clinic_encrypted: ENVELOPE_VERSION,
+            kdf: self.params.clone(),
+            nonce: BASE64.encode(nonce),
+            data: BASE64.encode(data),
+        };
+        serde_json::to_string(&envelope).map_err(|e| e.to_string())
+    }
+
+    /// Open an envelope sealed with this key
+    pub fn decrypt(&self, json: &str) -> Result<String, String> {
+        let envelope: Envelope = serde_json::from_str(json).map_err(|e| format!("Invalid encrypted file: {}", e))?;
+        let nonce = BASE64.decode(&envelope.nonce).map_err(|_| "Invalid encrypted file".to_string())?;
+        let data = BASE64.decode(&envelope.data).map_err(|_| "Invalid encrypted file".to_string())?;
+        if nonce.len() != 12 {
+            return Err("Invalid encrypted file".to_string());
+        }
+        let plaintext = self.cipher
+            .decrypt(Nonce::from_slice(&nonce), data.as_ref())
+            .map_err(|_| "รหัสผ่านไม่ถูกต้อง หรือไฟล์ถูกแก้ไข".to_string())?;
+        String::from_utf8(plaintext).map_err(|_| "Invalid encrypted file".to_string())
+    }
+}
+
+/// The KDF parameters of an encrypted file, or None for plain JSON
+pub fn envelope_params(json: &str) -> Option<KdfParams> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    value.get("clinic_encrypted")?;
+    serde_json::from_value(value.get("kdf")?.clone()).ok()
+}
+
+pub fn is_encrypted(json: &str) -> bool {
+    envelope_params(json).is_some()
+}
+
+/// The unlocked passphrase for this run of the app. Files sealed with other
+/// parameters (e.g. an imported backup) are opened by deriving their key again.
+pub struct Session {
+    passphrase: String,
+    key: Key,
+}
+
+impl Session {
+    pub fn new(passphrase: &str) -> Result<Self, String> {
+        Self::with_params(passphrase, KdfParams::generate())
+    }
+
+    pub fn with_params(passphrase: &str, params: KdfParams) -> Result<Self, String> {
+        Ok(Self { passphrase: passphrase.to_string(), key: Key::derive(passphrase, params)? })
+    }
+
+    pub fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        self.key.encrypt(plaintext)
+    }
+
+    pub fn decrypt(&self, json: &str) -> Result<String, String> {
+        match envelope_params(json) {
+            Some(params) if &params == self.key.params() => self.key.decrypt(json),
+            Some(params) => Key::derive(&self.passphrase, params)?.decrypt(json),
+            None => Ok(json.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast; the format is the same
+    fn params() -> KdfParams {
+        KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let session = Session::with_params("รหัสผ่าน", params()).unwrap();
+        let sealed = session.encrypt(r#"{"clinic_patients":[]}"#).unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("clinic_patients"));
+        assert_eq!(session.decrypt(&sealed).unwrap(), r#"{"clinic_patients":[]}"#);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_and_tampering_fail() {
+        let p = params();
+        let sealed = Key::derive("right", p.clone()).unwrap().encrypt("{}").unwrap();
+        assert!(Key::derive("wrong", p.clone()).unwrap().decrypt(&sealed).is_err());
+
+        let mut envelope: Value = serde_json::from_str(&sealed).unwrap();
+        envelope["data"] = Value::from(BASE64.encode(b"not the original ciphertext"));
+        assert!(Key::derive("right", p).unwrap().decrypt(&envelope.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_other_salt_uses_passphrase() {
+        let old = Session::with_params("same", params()).unwrap();
+        let sealed = old.encrypt("{}").unwrap();
+        let current = Session::with_params("same", params()).unwrap();
+        assert_eq!(current.decrypt(&sealed).unwrap(), "{}");
+        assert_eq!(current.decrypt("{\"plain\":1}").unwrap(), "{\"plain\":1}");
+    }
+}
@@ -0,0 +1,189 @@
+// Crash-safe reads and writes of clinic_data.json.
+// A save goes to `<file>.tmp`, is fsynced, and then renamed over the live file.
+// The generation it replaces is kept as `<file>.bak`, so a torn write or a bad
+// disk sector costs at most the last save, never the whole clinic.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_json::Value;
+
+/// What `load_clinic_data` hands the frontend
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LoadedData {
+    pub data: String,
+    /// Set when the live file was unusable and the previous generation was loaded instead
+    pub warning: Option<String>,
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".tmp")
+}
+
+/// Where an unreadable data file is moved aside instead of being rotated over a good `.bak`
+pub(crate) fn corrupt_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".corrupt")
+}
+
+/// A complete clinic_data.json is always one JSON object
+fn is_valid(json: &str) -> bool {
+    matches!(serde_json::from_str::<Value>(json), Ok(Value::Object(_)))
+}
+
+fn read_valid(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().filter(|json| is_valid(json))
+}
+
+/// Make the renames themselves durable (directories can't be opened on Windows)
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Replace `path` with `data` so that a crash at any point leaves either the
+/// old or the new contents on disk, plus the previous good generation in `.bak`.
+pub fn write_atomic(path: &Path, data: &str) -> Result<(), String> {
+    let tmp = write_temp(path, data)?;
+
+    // Only a readable file is worth keeping as the fallback generation
+    if read_valid(path).is_some() {
+        fs::rename(path, backup_path(path)).map_err(|e| format!("Failed to keep previous data file: {}", e))?;
+    } else if path.exists() {
+        let _ = fs::rename(path, corrupt_path(path));
+    }
+    fs::rename(&tmp, path).map_err(|e| format!("Failed to replace data file: {}", e))?;
+    sync_dir(path);
+    Ok(())
+}
+
+/// Replace `path` in one step without keeping the old contents, e.g. when
+/// re-encrypting a backup
+pub fn replace_atomic(path: &Path, data: &str) -> Result<(), String> {
+    let tmp = write_temp(path, data)?;
+    fs::rename(&tmp, path).map_err(|e| format!("Failed to replace file: {}", e))?;
+    sync_dir(path);
+    Ok(())
+}
+
+/// Write `data` next to `path` and flush it to disk
+fn write_temp(path: &Path, data: &str) -> Result<PathBuf, String> {
+    let tmp = temp_path(path);
+    let mut file = File::create(&tmp).map_err(|e| format!("Failed to create temp file: {}", e))?;
+    file.write_all(data.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    Ok(tmp)
+}
+
+/// Read the data file, falling back to `.bak` when the live file is missing,
+/// truncated or not JSON. Errors only when neither generation can be used.
+pub fn read_with_fallback(path: &Path) -> Result<LoadedData, String> {
+    let backup = backup_path(path);
+
+    if !path.exists() {
+        // A crash between the two renames leaves only the backup behind
+        return Ok(match read_valid(&backup) {
+            Some(data) => LoadedData { data, warning: None },
+            None => LoadedData { data: "{}".to_string(), warning: None },
+        });
+    }
+
+    if let Some(data) = read_valid(path) {
+        return Ok(LoadedData { data, warning: None });
+    }
+
+    log::error!("{:?} is damaged, trying {:?}", path, backup);
+    let data = read_valid(&backup).ok_or_else(|| {
+        "ไฟล์ข้อมูลเสียหายและไม่มีไฟล์สำรองที่ใช้ได้ กรุณากู้คืนจากไฟล์สำรอง (Backup)".to_string()
+    })?;
+    Ok(LoadedData {
+        data,
+        warning: Some("ไฟล์ข้อมูลเสียหาย ระบบได้โหลดข้อมูลจากการบันทึกครั้งก่อนแทน กรุณาตรวจสอบข้อมูลล่าสุด".to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clinic_datafile_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("clinic_data.json")
+    }
+
+    #[test]
+    fn test_write_keeps_previous_generation() {
+        let path = temp_file("rotate");
+        write_atomic(&path, r#"{"v":1}"#).unwrap();
+        write_atomic(&path, r#"{"v":2}"#).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"v":2}"#);
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), r#"{"v":1}"#);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_truncated_file_falls_back_to_backup() {
+        let path = temp_file("truncated");
+        write_atomic(&path, r#"{"v":1}"#).unwrap();
+        write_atomic(&path, r#"{"v":2}"#).unwrap();
+        fs::write(&path, r#"{"v":"#).unwrap();
+
+        let loaded = read_with_fallback(&path).unwrap();
+        assert_eq!(loaded.data, r#"{"v":1}"#);
+        assert!(loaded.warning.is_some());
+
+        // The next save must not rotate the damaged file over the good backup
+        write_atomic(&path, r#"{"v":3}"#).unwrap();
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), r#"{"v":1}"#);
+        assert_eq!(fs::read_to_string(corrupt_path(&path)).unwrap(), r#"{"v":"#);
+    }
+
+    #[test]
+    fn test_missing_file_uses_backup_after_crash() {
+        let path = temp_file("missing");
+        fs::write(backup_path(&path), r#"{"v":1}"#).unwrap();
+        assert_eq!(read_with_fallback(&path).unwrap().data, r#"{"v":1}"#);
+
+        let fresh = temp_file("fresh");
+        assert_eq!(read_with_fallback(&fresh).unwrap(), LoadedData { data: "{}".to_string(), warning: None });
+    }
+
+    #[test]
+    fn test_replace_leaves_backup_alone() {
+        let path = temp_file("replace");
+        write_atomic(&path, r#"{"v":1}"#).unwrap();
+        write_atomic(&path, r#"{"v":2}"#).unwrap();
+        replace_atomic(&path, r#"{"v":3}"#).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"v":3}"#);
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), r#"{"v":1}"#);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_no_usable_generation_is_an_error() {
+        let path = temp_file("broken");
+        fs::write(&path, "").unwrap();
+        assert!(read_with_fallback(&path).is_err());
+    }
+}
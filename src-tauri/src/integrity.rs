@@ -0,0 +1,149 @@
+// Integrity scan of a clinic_data.json document, for `clinic-cli check`.
+// The same checks as src/integrity.rs in the frontend, which also offers the
+// fixes in Settings; this side only reports. Works on the JSON itself so a file
+// the models can't fully read can still be checked.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde_json::Value;
+
+/// One kind of problem and every place it was found
+#[derive(Debug, PartialEq)]
+pub struct Category {
+    pub title: &'static str,
+    pub problems: Vec<String>,
+}
+
+fn text<'a>(item: &'a Value, field: &str) -> &'a str {
+    item.get(field).and_then(Value::as_str).unwrap_or("")
+}
+
+/// The Thai mod-11 check digit (src/thai_id.rs): the first 12 digits weighted
+/// 13 down to 2, and the 13th is (11 - sum mod 11) mod 10
+fn is_citizen_id(id: &str) -> bool {
+    let digits: Vec<u32> = id.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != 13 {
+        return false;
+    }
+    let sum: u32 = digits[..12].iter().zip((2..=13).rev()).map(|(d, weight)| d * weight).sum();
+    (11 - sum % 11) % 10 == digits[12]
+}
+
+fn in_trash(item: &Value) -> bool {
+    item.get("deleted_at").is_some_and(|at| !at.is_null())
+}
+
+/// The items of a collection that are not in the Recycle Bin
+fn live<'a>(document: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    document
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|item| !in_trash(item))
+}
+
+pub fn scan(document: &Value) -> Vec<Category> {
+    let patients: HashMap<&str, &Value> = document
+        .get("clinic_patients")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|p| (text(p, "id"), p))
+        .collect();
+    let missing = |id: &str| match patients.get(id) {
+        None => Some(format!("no patient {}", id)),
+        Some(p) if in_trash(p) => Some(format!("patient {} is in the Recycle Bin", text(p, "hn"))),
+        Some(_) => None,
+    };
+
+    let orphan_records = live(document, "clinic_records")
+        .filter_map(|r| {
+            missing(text(r, "patient_id")).map(|why| format!("record {} of {}: {}", text(r, "id"), text(r, "date"), why))
+        })
+        .collect();
+
+    let mut by_hn: BTreeMap<&str, Vec<&Value>> = BTreeMap::new();
+    for p in live(document, "clinic_patients").filter(|p| !text(p, "hn").trim().is_empty()) {
+        by_hn.entry(text(p, "hn").trim()).or_default().push(p);
+    }
+    let duplicate_hns = by_hn
+        .into_iter()
+        .filter(|(_, patients)| patients.len() > 1)
+        .map(|(hn, patients)| {
+            let names: Vec<String> = patients
+                .iter()
+                .map(|p| format!("{}{} {}", text(p, "title"), text(p, "first_name"), text(p, "last_name")))
+                .collect();
+            format!("{}: {}", hn, names.join(", "))
+        })
+        .collect();
+
+    let invalid_citizen_ids = live(document, "clinic_patients")
+        .filter(|p| !text(p, "citizen_id").trim().is_empty() && !is_citizen_id(text(p, "citizen_id")))
+        .map(|p| format!("{} {}{} {}: {}", text(p, "hn"), text(p, "title"), text(p, "first_name"), text(p, "last_name"), text(p, "citizen_id")))
+        .collect();
+
+    let orphan_appointments = live(document, "clinic_appointments")
+        .filter_map(|a| {
+            missing(text(a, "patient_id")).map(|why| {
+                format!("appointment {} on {} for {}: {}", text(a, "id"), text(a, "date"), text(a, "patient_name"), why)
+            })
+        })
+        .collect();
+
+    let known: HashSet<&str> = live(document, "clinic_drugs").map(|d| text(d, "name")).collect();
+    let mut unknown: BTreeMap<&str, usize> = BTreeMap::new();
+    for r in live(document, "clinic_records") {
+        let names: HashSet<&str> = r
+            .get("prescriptions")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|rx| text(rx, "name"))
+            .collect();
+        for name in names.into_iter().filter(|name| !name.trim().is_empty() && !known.contains(name)) {
+            *unknown.entry(name).or_default() += 1;
+        }
+    }
+    let unknown_drugs = unknown
+        .into_iter()
+        .map(|(name, records)| format!("{} (in {} records)", name, records))
+        .collect();
+
+    vec![
+        Category { title: "Records without a patient", problems: orphan_records },
+        Category { title: "Duplicate HNs", problems: duplicate_hns },
+        Category { title: "Invalid citizen IDs", problems: invalid_citizen_ids },
+        Category { title: "Appointments without a patient", problems: orphan_appointments },
+        Category { title: "Prescribed drugs not in the inventory", problems: unknown_drugs },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_scan_finds_each_category() {
+        let document = json!({
+            "clinic_patients": [
+                {"id": "p1", "hn": "HN-00001", "first_name": "A", "citizen_id": "1101700207030"},
+                {"id": "p2", "hn": "HN-00001", "first_name": "B", "citizen_id": "1101700207031"},
+                {"id": "p3", "hn": "HN-00003", "deleted_at": "2024-03-01T00:00:00Z"}
+            ],
+            "clinic_records": [
+                {"id": "r1", "patient_id": "p1", "prescriptions": [{"name": "Para"}, {"name": "Para"}]},
+                {"id": "r2", "patient_id": "gone", "prescriptions": []},
+                {"id": "r3", "patient_id": "p3", "prescriptions": [], "deleted_at": "2024-03-01T00:00:00Z"}
+            ],
+            "clinic_drugs": [{"id": "d1", "name": "Paracetamol"}],
+            "clinic_appointments": [{"id": "a1", "patient_id": "p3"}]
+        });
+
+        let counts: Vec<usize> = scan(&document).iter().map(|c| c.problems.len()).collect();
+        assert_eq!(counts, vec![1, 1, 1, 1, 1]);
+        assert_eq!(scan(&document)[4].problems, vec!["Para (in 1 records)"]);
+        assert!(scan(&json!({})).iter().all(|c| c.problems.is_empty()));
+    }
+}
@@ -1,7 +1,23 @@
+mod archive;
+mod auditlog;
+mod backup;
+pub mod cli;
 mod commands;
+mod crypto;
+mod datafile;
+mod integrity;
+mod models;
+mod sync;
 
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::Manager;
+use backup::Reason;
 use commands::*;
 
+// How often to check whether today's backup has been taken
+const DAILY_BACKUP_CHECK: Duration = Duration::from_secs(60 * 60);
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -11,16 +27,56 @@ pub fn run() {
                 .build(),
         )
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .setup(|app| {
+            remove_database_files();
+            let crypto = Crypto::detect(&get_data_file_path());
+            app.manage(CryptoState(Mutex::new(crypto)));
+            app.manage(SyncState::default());
+            if let Err(err) = restart_sync(app.handle()) {
+                log::error!("LAN sync not started: {}", err);
+            }
+
+            let handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                scheduled_backup(&handle.state::<CryptoState>(), Reason::Daily);
+                std::thread::sleep(DAILY_BACKUP_CHECK);
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             load_clinic_data,
             save_clinic_data,
+            load_audit_log,
+            append_audit_log,
+            save_audit_log,
             get_data_path,
             create_backup,
+            backup_before_restore,
+            get_backup_status,
+            mirror_backups,
             list_backups,
+            read_backup,
+            delete_backup,
             restore_backup,
+            list_archives,
+            load_archive,
+            save_archive,
+            get_sync_config,
+            save_sync_config,
+            sync_now,
+            read_sync_inbox,
+            clear_sync_inbox,
             open_data_folder,
-            check_for_updates
+            check_for_updates,
+            get_encryption_status,
+            unlock_data,
+            set_passphrase
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                scheduled_backup(&app.state::<CryptoState>(), Reason::Close);
+            }
+        });
 }
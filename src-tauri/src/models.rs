@@ -0,0 +1,166 @@
+// Backend copies of the frontend models in src/models.rs, used to check that a
+// document really is clinic data before it replaces clinic_data.json.
+// Fields the backend doesn't know about yet are kept in `extra`, so a newer
+// frontend's data passes the check.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use chrono::{DateTime, Utc, NaiveDate};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Patient {
+    pub id: String,
+    pub hn: String,
+    pub citizen_id: String,
+    pub title: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub birth_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub age: Option<u32>,
+    #[serde(default)]
+    pub blood_group: String,
+    #[serde(default)]
+    pub underlying_disease: String,
+    /// Free text up to schema 3; newer data keeps a list in `allergies` instead
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub drug_allergy: String,
+    pub phone: String,
+    pub address: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PrescriptionItem {
+    pub name: String,
+    pub amount: String,
+    pub usage: String,
+    pub duration_days: Option<u32>,
+    #[serde(default)]
+    pub morning: f64,
+    #[serde(default)]
+    pub noon: f64,
+    #[serde(default)]
+    pub evening: f64,
+    #[serde(default)]
+    pub before_bed: f64,
+    #[serde(default)]
+    pub timing: String,
+    #[serde(default)]
+    pub warning: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct InjectionItem {
+    pub name: String,
+    pub dose: String,
+    pub site: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct TreatmentRecord {
+    pub id: String,
+    pub patient_id: String,
+    pub date: DateTime<Utc>,
+    pub symptoms: String,
+    pub diagnosis: String,
+    pub weight: Option<f32>,
+    pub pressure: String,
+    pub prescriptions: Vec<PrescriptionItem>,
+    pub injections: Vec<InjectionItem>,
+    pub doctor_note: String,
+    pub price: f64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct DrugItem {
+    pub id: String,
+    pub name: String,
+    pub unit: String,
+    pub stock: u32,
+    pub min_stock: u32,
+    pub cost_price: f64,
+    pub sell_price: f64,
+    pub expiry_date: Option<NaiveDate>,
+    pub category: String,
+    pub description: String,
+    pub default_usage: String,
+    pub warning: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Expense {
+    pub id: String,
+    pub date: DateTime<Utc>,
+    pub category: String,
+    pub description: String,
+    pub amount: f64,
+    pub note: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct DrugPurchase {
+    pub id: String,
+    pub drug_id: String,
+    pub drug_name: String,
+    pub date: DateTime<Utc>,
+    pub quantity: u32,
+    pub cost_per_unit: f64,
+    pub total_cost: f64,
+    pub lot_number: String,
+    pub expiry_date: Option<NaiveDate>,
+    pub supplier: String,
+    pub note: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Appointment {
+    pub id: String,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub date: NaiveDate,
+    pub time: String,
+    pub reason: String,
+    pub status: String,
+    pub note: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The whole of clinic_data.json. Keys match the frontend's LocalStorage keys.
+/// Settings are passed through as-is; the backend never edits them.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ClinicData {
+    #[serde(rename = "clinic_patients", default)]
+    pub patients: Vec<Patient>,
+    #[serde(rename = "clinic_records", default)]
+    pub records: Vec<TreatmentRecord>,
+    #[serde(rename = "clinic_drugs", default)]
+    pub drugs: Vec<DrugItem>,
+    #[serde(rename = "clinic_settings", default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+    #[serde(rename = "clinic_expenses", default)]
+    pub expenses: Vec<Expense>,
+    #[serde(rename = "clinic_drug_purchases", default)]
+    pub drug_purchases: Vec<DrugPurchase>,
+    #[serde(rename = "clinic_appointments", default)]
+    pub appointments: Vec<Appointment>,
+    #[serde(rename = "clinic_last_hn", default)]
+    pub last_hn: u32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
@@ -0,0 +1,374 @@
+// LAN sync between two clinic computers.
+// Each computer answers POST /sync on a port of its own (a bare HTTP/1.1 server,
+// one request per connection) and, when it has a peer set, calls the other one
+// every few minutes or when asked. A request carries this computer's changes
+// since it last sent any and asks for the peer's changes since it last got any;
+// the answer carries those. Both sides only put what they receive in the sync
+// inbox: the frontend applies it to clinic_data.json, keeping whichever copy of
+// an item has the later `updated_at` (see src/sync.rs), so only the frontend
+// ever writes the data file.
+//
+// Bodies are sealed with the crypto.rs envelope under a key derived from the
+// sync key both computers share, so a computer without it can neither read
+// the clinic's data nor send it changes. sync.json keeps that key, and is
+// sealed with the data file's passphrase when encryption is on.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::crypto::{self, Key};
+
+pub const DEFAULT_PORT: u16 = 47825;
+pub const CONFIG_FILE: &str = "sync.json";
+pub const INBOX_FILE: &str = "sync_inbox.json";
+/// The synced collections, keyed like clinic_data.json. Settings stay per computer.
+const COLLECTIONS: [&str; 7] = [
+    "clinic_patients", "clinic_records", "clinic_drugs",
+    "clinic_expenses", "clinic_drug_purchases", "clinic_appointments", "clinic_interactions",
+];
+const KEY_TOMBSTONES: &str = "clinic_tombstones";
+const KEY_AUDIT_LOG: &str = "clinic_audit_log";
+const KEY_LAST_HN: &str = "clinic_last_hn";
+const KEY_SCHEMA_VERSION: &str = "clinic_schema_version";
+/// A save can reach the data file a moment after it was stamped, so every
+/// exchange also resends this much from before the last one. Applying a
+/// change twice does nothing.
+const OVERLAP: chrono::Duration = chrono::Duration::minutes(1);
+const TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BODY: usize = 256 * 1024 * 1024;
+/// Bodies are read this much at a time, so memory follows what the other side
+/// actually sends rather than the length it claims
+const CHUNK: usize = 64 * 1024;
+/// How every sealed body starts; crypto.rs writes the envelope version first
+const SEALED_START: &[u8] = br#"{"clinic_encrypted":"#;
+const MAX_KDF_MEMORY: u32 = 64 * 1024;
+
+/// How sync with the peer went the last time
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Status {
+    /// When the last successful exchange started, by this computer's clock
+    pub sent: Option<DateTime<Utc>>,
+    /// The peer's time when it answered, by its clock
+    pub received: Option<DateTime<Utc>>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// sync.json in the data folder
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub enabled: bool,
+    /// This computer's address to listen on, e.g. its LAN address; empty for
+    /// every network it is on
+    pub bind: String,
+    pub port: u16,
+    /// "host:port" of the other computer; empty to only answer it
+    pub peer: String,
+    pub key: String,
+    /// Minutes between automatic syncs, 0 for only on demand
+    pub interval_minutes: u32,
+    pub status: Status,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: String::new(),
+            port: DEFAULT_PORT,
+            peer: String::new(),
+            key: String::new(),
+            interval_minutes: 5,
+            status: Status::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The next exchange: what to send, and from when to ask for the peer's changes
+    pub fn next_since(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        (self.status.sent.map(|at| at - OVERLAP), self.status.received.map(|at| at - OVERLAP))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Request {
+    /// Send the changes since then, everything when None
+    pub since: Option<DateTime<Utc>>,
+    pub changes: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Response {
+    /// The answering computer's time before it gathered its changes
+    pub until: DateTime<Utc>,
+    pub changes: Value,
+}
+
+fn changed_since(item: &Value, field: &str, since: Option<DateTime<Utc>>) -> bool {
+    let Some(since) = since else {
+        return true;
+    };
+    item.get(field)
+        .and_then(|at| serde_json::from_value::<DateTime<Utc>>(at.clone()).ok())
+        .is_some_and(|at| at >= since)
+}
+
+/// What changed in a clinic_data.json document since `since`, as a document of
+/// the same shape, or all of it when `since` is None
+pub fn changes_since(document: &str, since: Option<DateTime<Utc>>) -> Result<Value, String> {
+    let doc: Map<String, Value> = serde_json::from_str(document).map_err(|e| format!("Invalid clinic data: {}", e))?;
+    let mut changes = Map::new();
+    let lists = COLLECTIONS
+        .iter()
+        .map(|key| (*key, "updated_at"))
+        .chain([(KEY_TOMBSTONES, "deleted_at"), (KEY_AUDIT_LOG, "timestamp")]);
+    for (key, field) in lists {
+        let items: Vec<Value> = doc
+            .get(key)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter(|item| changed_since(item, field, since))
+            .cloned()
+            .collect();
+        changes.insert(key.to_string(), Value::Array(items));
+    }
+    for key in [KEY_LAST_HN, KEY_SCHEMA_VERSION] {
+        if let Some(value) = doc.get(key) {
+            changes.insert(key.to_string(), value.clone());
+        }
+    }
+    Ok(Value::Object(changes))
+}
+
+fn len(changes: &Value, key: &str) -> usize {
+    changes.get(key).and_then(Value::as_array).map_or(0, Vec::len)
+}
+
+/// How many items `changes` adds, changes or removes; the audit log doesn't count
+pub fn count(changes: &Value) -> usize {
+    COLLECTIONS.iter().chain([&KEY_TOMBSTONES]).map(|key| len(changes, key)).sum()
+}
+
+/// Nothing worth keeping for the frontend, not even audit entries
+pub fn is_empty(changes: &Value) -> bool {
+    count(changes) == 0 && len(changes, KEY_AUDIT_LOG) == 0
+}
+
+fn seal(key: &Key, message: &impl Serialize) -> Result<String, String> {
+    key.encrypt(&serde_json::to_string(message).map_err(|e| e.to_string())?)
+}
+
+fn open<T: DeserializeOwned>(key: &Key, body: &str) -> Result<T, String> {
+    let plain = key.decrypt(body).map_err(|_| "รหัสซิงก์ของสองเครื่องไม่ตรงกัน".to_string())?;
+    serde_json::from_str(&plain).map_err(|e| format!("Invalid sync message: {}", e))
+}
+
+/// The start line of one HTTP message and the length of the body after it
+fn read_head(reader: &mut impl BufRead) -> Result<(String, usize), String> {
+    let mut start = String::new();
+    reader.read_line(&mut start).map_err(|e| e.to_string())?;
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| "Invalid Content-Length".to_string())?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err("Sync message too large".to_string());
+    }
+    Ok((start.trim_end().to_string(), length))
+}
+
+/// Read the body on until it is `length` bytes long
+fn read_body(reader: &mut impl Read, body: &mut Vec<u8>, length: usize) -> Result<(), String> {
+    let mut chunk = vec![0; CHUNK.min(length)];
+    while body.len() < length {
+        let part = &mut chunk[..CHUNK.min(length - body.len())];
+        reader.read_exact(part).map_err(|e| e.to_string())?;
+        body.extend_from_slice(part);
+    }
+    Ok(())
+}
+
+fn into_text(body: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(body).map_err(|_| "Sync message is not UTF-8".to_string())
+}
+
+/// The start line and body of one HTTP message
+fn read_message(stream: &TcpStream) -> Result<(String, String), String> {
+    let mut reader = BufReader::new(stream);
+    let (start, length) = read_head(&mut reader)?;
+    let mut body = Vec::new();
+    read_body(&mut reader, &mut body, length)?;
+    Ok((start, into_text(body)?))
+}
+
+fn write_message(mut stream: &TcpStream, start: &str, body: &str) -> Result<(), String> {
+    let message = format!(
+        "{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        start,
+        body.len(),
+        body
+    );
+    stream.write_all(message.as_bytes()).and_then(|_| stream.flush()).map_err(|e| e.to_string())
+}
+
+fn answer(stream: &TcpStream, secret: &str, handle: &impl Fn(Request) -> Result<Response, String>) -> Result<(), String> {
+    let mut reader = BufReader::new(stream);
+    let (start, length) = read_head(&mut reader)?;
+    if !start.starts_with("POST /sync ") {
+        return write_message(stream, "HTTP/1.1 404 Not Found", "");
+    }
+    // Only sealed requests are taken, and the rest of one is only read once it
+    // starts like an envelope. The envelope says how its key was derived;
+    // anything costlier than crypto.rs ever asks for is refused before deriving it.
+    let mut body = Vec::new();
+    read_body(&mut reader, &mut body, length.min(SEALED_START.len()))?;
+    if !body.starts_with(SEALED_START) {
+        return write_message(stream, "HTTP/1.1 403 Forbidden", "Sync requests must be sealed");
+    }
+    read_body(&mut reader, &mut body, length)?;
+    let body = into_text(body)?;
+    let Some(params) = crypto::envelope_params(&body).filter(|p| p.m_cost <= MAX_KDF_MEMORY && p.t_cost <= 4) else {
+        return write_message(stream, "HTTP/1.1 403 Forbidden", "Sync requests must be sealed");
+    };
+    let key = Key::derive(secret, params)?;
+    let request = match open(&key, &body) {
+        Ok(request) => request,
+        Err(e) => return write_message(stream, "HTTP/1.1 403 Forbidden", &e),
+    };
+    match handle(request).and_then(|response| seal(&key, &response)) {
+        Ok(sealed) => write_message(stream, "HTTP/1.1 200 OK", &sealed),
+        Err(e) => write_message(stream, "HTTP/1.1 500 Internal Server Error", &e),
+    }
+}
+
+/// Answer sync requests on `listener` until `stop` is set
+pub fn serve(listener: TcpListener, stop: &AtomicBool, secret: &str, handle: impl Fn(Request) -> Result<Response, String>) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        log::error!("Sync server could not start: {}", e);
+        return;
+    }
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, from)) => {
+                let answered = stream
+                    .set_nonblocking(false)
+                    .and_then(|_| stream.set_read_timeout(Some(TIMEOUT)))
+                    .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| answer(&stream, secret, &handle));
+                if let Err(e) = answered {
+                    log::warn!("Sync request from {} failed: {}", from, e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(200)),
+            Err(e) => {
+                log::warn!("Sync server: {}", e);
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+/// Send `request` to the peer at `peer` ("host:port") and return its answer
+pub fn exchange(peer: &str, key: &Key, request: &Request) -> Result<Response, String> {
+    let address = peer
+        .to_socket_addrs()
+        .map_err(|e| format!("หาเครื่อง {} ไม่พบ: {}", peer, e))?
+        .next()
+        .ok_or_else(|| format!("หาเครื่อง {} ไม่พบ", peer))?;
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT)
+        .map_err(|e| format!("เชื่อมต่อเครื่อง {} ไม่ได้: {}", peer, e))?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+
+    write_message(&stream, &format!("POST /sync HTTP/1.1\r\nHost: {}", peer), &seal(key, request)?)?;
+    let (status, body) = read_message(&stream)?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("เครื่อง {} ปฏิเสธการซิงก์: {} {}", peer, status, body));
+    }
+    open(key, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KdfParams;
+    use serde_json::json;
+
+    fn key(secret: &str) -> Key {
+        Key::derive(secret, KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() }).unwrap()
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let document = json!({
+            "clinic_patients": [
+                { "id": "p1", "updated_at": "2024-03-01T08:00:00Z" },
+                { "id": "p2", "updated_at": "2024-03-01T10:00:00Z" },
+                { "id": "p3" }
+            ],
+            "clinic_tombstones": [{ "entity": "drug", "id": "d1", "deleted_at": "2024-03-01T11:00:00Z" }],
+            "clinic_settings": { "clinic_name": "stays here" },
+            "clinic_last_hn": 7
+        })
+        .to_string();
+
+        let since = "2024-03-01T09:00:00Z".parse().ok();
+        let changes = changes_since(&document, since).unwrap();
+        assert_eq!(changes["clinic_patients"], json!([{ "id": "p2", "updated_at": "2024-03-01T10:00:00Z" }]));
+        assert_eq!(changes["clinic_last_hn"], 7);
+        assert!(changes.get("clinic_settings").is_none());
+        assert_eq!(count(&changes), 2);
+        assert_eq!(count(&changes_since(&document, None).unwrap()), 4);
+    }
+
+    #[test]
+    fn test_exchange_over_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = listener.local_addr().unwrap().to_string();
+        let stop = AtomicBool::new(false);
+        let until: DateTime<Utc> = "2024-03-01T12:00:00Z".parse().unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                serve(listener, &stop, "shared", |request| {
+                    assert_eq!(request.changes, json!({ "clinic_patients": [{ "id": "p1" }] }));
+                    Ok(Response { until, changes: json!({ "clinic_drugs": [{ "id": "d1" }] }) })
+                })
+            });
+
+            let request = Request { since: None, changes: json!({ "clinic_patients": [{ "id": "p1" }] }) };
+            let response = exchange(&peer, &key("shared"), &request).unwrap();
+            assert_eq!(response, Response { until, changes: json!({ "clinic_drugs": [{ "id": "d1" }] }) });
+
+            // Another key gets nothing back
+            let err = exchange(&peer, &key("guess"), &request).unwrap_err();
+            assert!(err.contains("403"), "{}", err);
+
+            // A body that isn't sealed is turned away without waiting for the length it claims
+            let stream = TcpStream::connect(&peer).unwrap();
+            let mut raw = &stream;
+            raw.write_all(format!("POST /sync HTTP/1.1\r\nContent-Length: {}\r\n\r\n{{\"changes\": {{\"clinic_patients\": [", MAX_BODY).as_bytes()).unwrap();
+            let (status, _) = read_message(&stream).unwrap();
+            assert!(status.contains("403"), "{}", status);
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
+}
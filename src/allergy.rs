@@ -0,0 +1,247 @@
+// Drug allergies
+// A patient's allergies are entries naming a drug from the inventory, a group of
+// related drugs, or a name typed by hand, each with the reaction and how severe
+// it was. `conflicts` checks a prescription against them; the Treatment form
+// won't save a conflicting prescription without a reason, which is kept on the
+// record (TreatmentRecord::allergy_override).
+// Prescriptions are free text, so matching is by name: a drug matches a group
+// when its name or its inventory description mentions one of the group's drugs,
+// e.g. "Amoxicillin 500mg" for penicillins. A name typed by hand that names a
+// group counts as that group, so "แพ้ penicillin" also catches amoxicillin.
+
+use crate::models::{Allergen, Allergy, AllergySeverity, DrugItem, PrescriptionItem};
+
+/// Drugs that are commonly cross-allergic
+pub struct Group {
+    pub key: &'static str,
+    pub name: &'static str,
+    /// Generic and common brand names, lower case; matched anywhere in a drug name
+    pub members: &'static [&'static str],
+}
+
+pub const GROUPS: [Group; 9] = [
+    Group {
+        key: "penicillins",
+        name: "กลุ่มเพนิซิลลิน (Penicillins)",
+        members: &[
+            "penicillin", "amoxicillin", "amoxycillin", "amoxy", "ampicillin", "cloxacillin", "dicloxacillin",
+            "piperacillin", "augmentin", "amoxiclav", "เพนิซิลลิน", "เพนนิซิลลิน", "อะม็อกซี่", "อะมอกซี",
+        ],
+    },
+    Group {
+        key: "cephalosporins",
+        name: "กลุ่มเซฟาโลสปอริน (Cephalosporins)",
+        members: &[
+            "cephalosporin", "cephalexin", "cefalexin", "cefazolin", "cefadroxil", "cefaclor", "cefuroxime",
+            "cefixime", "cefdinir", "cefditoren", "ceftriaxone", "ceftazidime", "cefotaxime", "เซฟา",
+        ],
+    },
+    Group {
+        key: "sulfonamides",
+        name: "กลุ่มซัลฟา (Sulfonamides)",
+        members: &["sulfa", "sulfamethoxazole", "cotrimoxazole", "bactrim", "ซัลฟา"],
+    },
+    Group {
+        key: "nsaids",
+        name: "กลุ่มยาต้านการอักเสบ (NSAIDs)",
+        members: &[
+            "nsaid", "aspirin", "ibuprofen", "diclofenac", "naproxen", "mefenamic", "ponstan", "piroxicam",
+            "meloxicam", "indomethacin", "celecoxib", "etoricoxib", "arcoxia", "แอสไพริน", "ไอบูโพรเฟน",
+        ],
+    },
+    Group {
+        key: "macrolides",
+        name: "กลุ่มแมคโครไลด์ (Macrolides)",
+        members: &["macrolide", "erythromycin", "azithromycin", "clarithromycin", "roxithromycin"],
+    },
+    Group {
+        key: "quinolones",
+        name: "กลุ่มควิโนโลน (Quinolones)",
+        members: &["quinolone", "ciprofloxacin", "ofloxacin", "levofloxacin", "norfloxacin", "moxifloxacin"],
+    },
+    Group {
+        key: "tetracyclines",
+        name: "กลุ่มเตตราไซคลิน (Tetracyclines)",
+        members: &["tetracycline", "doxycycline", "minocycline"],
+    },
+    Group {
+        key: "anticonvulsants",
+        name: "ยากันชักกลุ่ม aromatic",
+        members: &["carbamazepine", "tegretol", "phenytoin", "dilantin", "phenobarbital", "oxcarbazepine", "lamotrigine"],
+    },
+    Group {
+        key: "allopurinol",
+        name: "อัลโลพูรินอล (Allopurinol)",
+        members: &["allopurinol", "zyloric", "อัลโลพูรินอล"],
+    },
+];
+
+/// Words that mean no known allergy in the old free-text field
+const NONE: [&str; 6] = ["", "-", "ไม่มี", "ไม่ทราบ", "none", "no"];
+
+pub fn group(key: &str) -> Option<&'static Group> {
+    GROUPS.iter().find(|g| g.key == key)
+}
+
+/// Lower case, without spaces and dashes, so "Co-Amoxiclav" finds "amoxiclav"
+pub fn normalized(name: &str) -> String {
+    name.chars().filter(|c| !c.is_whitespace() && *c != '-').flat_map(char::to_lowercase).collect()
+}
+
+/// The group a drug name belongs to, if any
+fn group_of(name: &str) -> Option<&'static Group> {
+    let name = normalized(name);
+    GROUPS.iter().find(|g| g.members.iter().any(|member| name.contains(member)))
+}
+
+/// The allergen as shown to staff
+pub fn allergen_name(allergen: &Allergen) -> String {
+    match allergen {
+        Allergen::Drug { name, .. } | Allergen::Other { name } => name.clone(),
+        Allergen::Group { group: key } => group(key).map(|g| g.name.to_string()).unwrap_or_else(|| key.clone()),
+    }
+}
+
+pub fn severity_name(severity: AllergySeverity) -> &'static str {
+    match severity {
+        AllergySeverity::Unknown => "ไม่ทราบความรุนแรง",
+        AllergySeverity::Mild => "เล็กน้อย",
+        AllergySeverity::Moderate => "ปานกลาง",
+        AllergySeverity::Severe => "รุนแรง",
+    }
+}
+
+/// One line per allergy for banners and printed documents, e.g. "Penicillin (ผื่น)"
+pub fn describe(allergy: &Allergy) -> String {
+    let name = allergen_name(&allergy.allergen);
+    match (allergy.reaction.trim(), allergy.severity) {
+        ("", AllergySeverity::Unknown) => name,
+        ("", severity) => format!("{} ({})", name, severity_name(severity)),
+        (reaction, AllergySeverity::Unknown) => format!("{} ({})", name, reaction),
+        (reaction, severity) => format!("{} ({}, {})", name, reaction, severity_name(severity)),
+    }
+}
+
+/// All of a patient's allergies on one line
+pub fn summary(allergies: &[Allergy]) -> String {
+    allergies.iter().map(describe).collect::<Vec<_>>().join(", ")
+}
+
+/// Entries for the free-text allergy field of schema 3 and before, one per
+/// name separated by commas, slashes or new lines. Names of a known group become
+/// that group; the rest are kept as typed.
+pub fn from_text(text: &str) -> Vec<Allergy> {
+    text.split([',', '/', ';', '\n'])
+        .map(str::trim)
+        .filter(|name| !NONE.contains(&name.to_lowercase().as_str()))
+        .map(|name| {
+            let allergen = match GROUPS.iter().find(|g| g.members.contains(&normalized(name).as_str())) {
+                Some(g) => Allergen::Group { group: g.key.to_string() },
+                None => Allergen::Other { name: name.to_string() },
+            };
+            Allergy { allergen, reaction: String::new(), severity: AllergySeverity::Unknown }
+        })
+        .collect()
+}
+
+/// A prescribed drug the patient is allergic to
+#[derive(Clone, PartialEq, Debug)]
+pub struct Conflict {
+    /// The name as written in the prescription
+    pub drug: String,
+    pub allergy: Allergy,
+}
+
+/// Whether the prescribed drug `name`, described in the inventory as
+/// `description`, is covered by `allergy`
+fn is_allergic(allergy: &Allergy, name: &str, description: &str, drugs: &[DrugItem]) -> bool {
+    let prescribed = normalized(name);
+    let same_drug = |allergen: &str| {
+        let allergen = normalized(allergen);
+        // Short names would match too much by accident
+        let long_enough = allergen.chars().count() >= 3 && prescribed.chars().count() >= 3;
+        long_enough && (prescribed.contains(&allergen) || allergen.contains(&prescribed))
+    };
+    let in_group = |g: &Group| {
+        let text = normalized(&format!("{} {}", name, description));
+        g.members.iter().any(|member| text.contains(member))
+    };
+    match &allergy.allergen {
+        Allergen::Drug { drug_id, name: saved } => {
+            // Follow a rename in the inventory
+            let current = drugs.iter().find(|d| &d.id == drug_id).map(|d| d.name.as_str());
+            same_drug(current.unwrap_or(saved)) || same_drug(saved)
+        }
+        Allergen::Group { group: key } => group(key).is_some_and(in_group),
+        Allergen::Other { name: typed } => same_drug(typed) || group_of(typed).is_some_and(in_group),
+    }
+}
+
+/// Every prescribed drug that matches one of `allergies`, with the allergy it
+/// matched. `drugs` is the inventory, for descriptions and renamed drugs.
+pub fn conflicts(allergies: &[Allergy], prescriptions: &[PrescriptionItem], drugs: &[DrugItem]) -> Vec<Conflict> {
+    prescriptions
+        .iter()
+        .filter(|rx| !rx.name.trim().is_empty())
+        .flat_map(|rx| {
+            let description = drugs.iter().find(|d| d.name == rx.name).map(|d| d.description.as_str()).unwrap_or("");
+            allergies
+                .iter()
+                .filter(move |allergy| is_allergic(allergy, &rx.name, description, drugs))
+                .map(|allergy| Conflict { drug: rx.name.clone(), allergy: allergy.clone() })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allergy(allergen: Allergen) -> Allergy {
+        Allergy { allergen, reaction: "ผื่น".to_string(), severity: AllergySeverity::Severe }
+    }
+
+    fn rx(name: &str) -> PrescriptionItem {
+        PrescriptionItem { name: name.to_string(), ..PrescriptionItem::default() }
+    }
+
+    #[test]
+    fn test_from_text() {
+        assert!(from_text("ไม่มี").is_empty());
+        assert!(from_text(" None ").is_empty());
+        let found = from_text("Penicillin, อาหารทะเล");
+        assert_eq!(found[0].allergen, Allergen::Group { group: "penicillins".to_string() });
+        assert_eq!(found[1].allergen, Allergen::Other { name: "อาหารทะเล".to_string() });
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let drugs = vec![
+            DrugItem { id: "d1".to_string(), name: "Amoxy 500".to_string(), description: "Amoxicillin".to_string(), ..DrugItem::default() },
+            DrugItem { id: "d2".to_string(), name: "Brufen 400".to_string(), ..DrugItem::default() },
+        ];
+        let prescriptions = vec![rx("Amoxy 500"), rx("Paracetamol 500mg"), rx("Brufen 400")];
+
+        let penicillin = allergy(Allergen::Group { group: "penicillins".to_string() });
+        let found = conflicts(std::slice::from_ref(&penicillin), &prescriptions, &drugs);
+        assert_eq!(found, vec![Conflict { drug: "Amoxy 500".to_string(), allergy: penicillin }]);
+
+        // Linked to the inventory drug, which was renamed since
+        let brufen = allergy(Allergen::Drug { drug_id: "d2".to_string(), name: "Brufen".to_string() });
+        assert_eq!(conflicts(&[brufen], &prescriptions, &drugs).len(), 1);
+
+        // Typed by hand: by name, and as the group it names
+        let typed = |name: &str| allergy(Allergen::Other { name: name.to_string() });
+        assert_eq!(conflicts(&[typed("paracetamol")], &prescriptions, &drugs)[0].drug, "Paracetamol 500mg");
+        assert_eq!(conflicts(&[typed("เพนิซิลลิน")], &prescriptions, &drugs)[0].drug, "Amoxy 500");
+        assert!(conflicts(&[typed("อาหารทะเล")], &prescriptions, &drugs).is_empty());
+    }
+
+    #[test]
+    fn test_describe() {
+        let penicillin = allergy(Allergen::Group { group: "penicillins".to_string() });
+        assert_eq!(describe(&penicillin), "กลุ่มเพนิซิลลิน (Penicillins) (ผื่น, รุนแรง)");
+        let plain = Allergy { reaction: String::new(), severity: AllergySeverity::Unknown, ..penicillin };
+        assert_eq!(summary(&[plain]), "กลุ่มเพนิซิลลิน (Penicillins)");
+    }
+}
@@ -0,0 +1,393 @@
+// Yearly archives
+// Treatment records, expenses and drug purchases from before a chosen year can
+// be moved out of the active data into one archive per year: a LocalStorage key
+// in the browser, archives/archive_<year>.json in the desktop app (see
+// src-tauri/src/archive.rs). Nothing reads an archive at startup, so loading
+// takes as long as the years still in use, however old the clinic is. History
+// and Report load an archive when asked and show it read-only.
+//
+// An archive is a cut-down clinic data document with just those collections and
+// its schema version, so it goes through the same migrations as stored data.
+// It also takes the year's audit entries once the active log grows past
+// `LOG_LIMIT`, so the log that is loaded and saved stays a bounded size.
+// Backups carry every archive under `KEY_ARCHIVES`, and restoring one merges
+// them back in by id. An archived item is never edited, so merging is only
+// ever adding what a year doesn't have yet.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use chrono::{DateTime, Datelike, Local, Utc};
+use gloo::storage::{LocalStorage, Storage};
+use serde_json::{Map, Value};
+use crate::audit::Audited;
+use crate::migrations::{CURRENT_SCHEMA_VERSION, KEY_SCHEMA_VERSION};
+use crate::models::{TreatmentRecord, Expense, DrugPurchase, AuditEntry, SoftDelete};
+use crate::storage::{ClinicData, Parsed, KEY_RECORDS, KEY_EXPENSES, KEY_DRUG_PURCHASES, KEY_AUDIT_LOG};
+use crate::store::Store;
+use crate::tauri_bridge;
+
+/// Where a backup keeps the archives, by year
+pub const KEY_ARCHIVES: &str = "clinic_archives";
+// The browser keeps each year under its own key
+const KEY_PREFIX: &str = "clinic_archive_";
+/// How many audit entries the active data keeps; older ones go to their year's archive
+pub const LOG_LIMIT: usize = 5000;
+
+thread_local! {
+    // Archives already read this session; they only change through `save`
+    static LOADED: RefCell<BTreeMap<i32, Archive>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Everything archived from one calendar year
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Archive {
+    pub year: i32,
+    pub records: Vec<TreatmentRecord>,
+    pub expenses: Vec<Expense>,
+    pub drug_purchases: Vec<DrugPurchase>,
+    /// Audit entries made during the year, moved out of the active log
+    pub audit_log: Vec<AuditEntry>,
+}
+
+fn add_missing<T: Audited + Clone>(items: &mut Vec<T>, more: &[T]) {
+    let known: HashSet<String> = items.iter().map(|item| item.id().to_string()).collect();
+    items.extend(more.iter().filter(|item| !known.contains(item.id())).cloned());
+}
+
+impl Archive {
+    pub fn len(&self) -> usize {
+        self.records.len() + self.expenses.len() + self.drug_purchases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the items of `other` this archive doesn't have yet
+    pub fn merge(&mut self, other: &Archive) {
+        add_missing(&mut self.records, &other.records);
+        add_missing(&mut self.expenses, &other.expenses);
+        add_missing(&mut self.drug_purchases, &other.drug_purchases);
+        let logged: HashSet<String> = self.audit_log.iter().map(|e| e.id.clone()).collect();
+        self.audit_log.extend(other.audit_log.iter().filter(|e| !logged.contains(&e.id)).cloned());
+    }
+
+    /// Ids of everything in the archive
+    pub fn ids(&self) -> HashSet<String> {
+        let records = self.records.iter().map(|r| r.id.clone());
+        let expenses = self.expenses.iter().map(|e| e.id.clone());
+        let purchases = self.drug_purchases.iter().map(|p| p.id.clone());
+        records.chain(expenses).chain(purchases).collect()
+    }
+
+    /// What the archive holds, for messages to the user
+    pub fn summary(&self) -> String {
+        format!(
+            "การรักษา {} รายการ, ค่าใช้จ่าย {} รายการ, ซื้อยาเข้า {} รายการ",
+            self.records.len(),
+            self.expenses.len(),
+            self.drug_purchases.len(),
+        )
+    }
+
+    fn to_document(&self) -> Result<Value, String> {
+        let mut doc = Map::new();
+        doc.insert(KEY_SCHEMA_VERSION.to_string(), Value::from(CURRENT_SCHEMA_VERSION));
+        let mut insert = |key: &str, items: Result<Value, serde_json::Error>| -> Result<(), String> {
+            doc.insert(key.to_string(), items.map_err(|e| e.to_string())?);
+            Ok(())
+        };
+        insert(KEY_RECORDS, serde_json::to_value(&self.records))?;
+        insert(KEY_EXPENSES, serde_json::to_value(&self.expenses))?;
+        insert(KEY_DRUG_PURCHASES, serde_json::to_value(&self.drug_purchases))?;
+        if !self.audit_log.is_empty() {
+            insert(KEY_AUDIT_LOG, serde_json::to_value(&self.audit_log))?;
+        }
+        Ok(Value::Object(doc))
+    }
+
+    /// Read an archive document the way stored data is read, migrations included.
+    /// An item that can't be read fails the whole archive, so saving the
+    /// archive again can't drop it.
+    pub fn from_document(year: i32, doc: Map<String, Value>) -> Result<Self, String> {
+        let Parsed { data, skipped, .. } = ClinicData::from_document(doc)?;
+        if skipped > 0 {
+            return Err(format!("ข้อมูลเก่าปี {} มี {} รายการที่อ่านไม่ได้", year, skipped));
+        }
+        Ok(Self {
+            year,
+            records: data.records,
+            expenses: data.expenses,
+            drug_purchases: data.drug_purchases,
+            audit_log: data.audit_log,
+        })
+    }
+}
+
+/// The calendar year an item belongs to, by the clinic's clock
+pub fn year_of(at: DateTime<Utc>) -> i32 {
+    at.with_timezone(&Local).year()
+}
+
+/// The items from before `before_year` that can be archived, one archive per
+/// year, oldest first. Items in the Recycle Bin stay so they can still be
+/// restored or purged there.
+pub fn split(data: &ClinicData, before_year: i32) -> Vec<Archive> {
+    fn of_year(years: &mut BTreeMap<i32, Archive>, at: DateTime<Utc>) -> &mut Archive {
+        let year = year_of(at);
+        years.entry(year).or_insert_with(|| Archive { year, ..Archive::default() })
+    }
+    let old = |at: &DateTime<Utc>| year_of(*at) < before_year;
+
+    let mut years = BTreeMap::new();
+    for record in data.records.iter().filter(|r| !r.is_deleted() && old(&r.date)) {
+        of_year(&mut years, record.date).records.push(record.clone());
+    }
+    for expense in data.expenses.iter().filter(|e| !e.is_deleted() && old(&e.date)) {
+        of_year(&mut years, expense.date).expenses.push(expense.clone());
+    }
+    for purchase in data.drug_purchases.iter().filter(|p| old(&p.date)) {
+        of_year(&mut years, purchase.date).drug_purchases.push(purchase.clone());
+    }
+    years.into_values().collect()
+}
+
+/// `data` without anything the archives already hold, so a backup from before
+/// archiving doesn't bring the same items back into the active data
+pub fn without_archived(mut data: ClinicData, archived: &HashSet<String>) -> ClinicData {
+    data.records.retain(|r| !archived.contains(&r.id));
+    data.expenses.retain(|e| !archived.contains(&e.id));
+    data.drug_purchases.retain(|p| !archived.contains(&p.id));
+    data
+}
+
+/// The archives in a backup's data, oldest first
+pub fn from_backup(doc: &mut Map<String, Value>) -> Result<Vec<Archive>, String> {
+    let Some(Value::Object(bundled)) = doc.remove(KEY_ARCHIVES) else {
+        return Ok(Vec::new());
+    };
+    let mut archives = Vec::new();
+    for (year, archive) in bundled {
+        let year = year.parse().map_err(|_| format!("ปีของข้อมูลเก่าไม่ถูกต้อง: {}", year))?;
+        let Value::Object(doc) = archive else {
+            return Err(format!("ข้อมูลเก่าปี {} ไม่ถูกต้อง", year));
+        };
+        archives.push(Archive::from_document(year, doc)?);
+    }
+    archives.sort_by_key(|a| a.year);
+    Ok(archives)
+}
+
+/// The archives as a backup keeps them, keyed by year
+pub fn to_backup(archives: &[Archive]) -> Result<Value, String> {
+    let mut bundled = Map::new();
+    for archive in archives {
+        bundled.insert(archive.year.to_string(), archive.to_document()?);
+    }
+    Ok(Value::Object(bundled))
+}
+
+// ========== Reading and writing ==========
+
+/// The years that have an archive, oldest first
+pub async fn years() -> Result<Vec<i32>, String> {
+    if tauri_bridge::is_tauri() {
+        return tauri_bridge::list_archives().await;
+    }
+    let storage = LocalStorage::raw();
+    let mut years: Vec<i32> = (0..storage.length().unwrap_or(0))
+        .filter_map(|i| storage.key(i).ok().flatten())
+        .filter_map(|key| key.strip_prefix(KEY_PREFIX)?.parse().ok())
+        .collect();
+    years.sort();
+    Ok(years)
+}
+
+/// The archive for `year`, empty when nothing from that year was archived
+pub async fn load(year: i32) -> Result<Archive, String> {
+    if let Some(archive) = LOADED.with(|l| l.borrow().get(&year).cloned()) {
+        return Ok(archive);
+    }
+    let json = if tauri_bridge::is_tauri() {
+        tauri_bridge::load_archive(year).await?
+    } else {
+        LocalStorage::raw().get_item(&format!("{}{}", KEY_PREFIX, year)).ok().flatten()
+    };
+    let archive = match json {
+        Some(json) => {
+            let doc = serde_json::from_str(&json).map_err(|e| format!("ข้อมูลเก่าปี {} อ่านไม่ได้: {}", year, e))?;
+            Archive::from_document(year, doc)?
+        }
+        None => Archive { year, ..Archive::default() },
+    };
+    LOADED.with(|l| l.borrow_mut().insert(year, archive.clone()));
+    Ok(archive)
+}
+
+/// Every archive, oldest first
+pub async fn load_all() -> Result<Vec<Archive>, String> {
+    let mut archives = Vec::new();
+    for year in years().await? {
+        archives.push(load(year).await?);
+    }
+    Ok(archives)
+}
+
+async fn save(archive: &Archive) -> Result<(), String> {
+    let json = archive.to_document()?.to_string();
+    if tauri_bridge::is_tauri() {
+        tauri_bridge::save_archive(archive.year, &json).await?;
+    } else {
+        LocalStorage::raw()
+            .set_item(&format!("{}{}", KEY_PREFIX, archive.year), &json)
+            .map_err(|_| "พื้นที่จัดเก็บข้อมูลของเบราว์เซอร์เต็ม ข้อมูลเก่ายังไม่ถูกเก็บ".to_string())?;
+    }
+    LOADED.with(|l| l.borrow_mut().insert(archive.year, archive.clone()));
+    Ok(())
+}
+
+/// A patient's archived records, newest first
+pub async fn records_of_patient(patient_id: &str) -> Result<Vec<TreatmentRecord>, String> {
+    let mut records: Vec<TreatmentRecord> = load_all()
+        .await?
+        .into_iter()
+        .flat_map(|archive| archive.records)
+        .filter(|r| r.patient_id == patient_id)
+        .collect();
+    records.sort_by_key(|r| std::cmp::Reverse(r.date));
+    Ok(records)
+}
+
+/// Add each archive's items to what is already archived for its year
+pub async fn restore(archives: &[Archive]) -> Result<(), String> {
+    for incoming in archives {
+        let mut archive = load(incoming.year).await?;
+        archive.merge(incoming);
+        save(&archive).await?;
+    }
+    Ok(())
+}
+
+/// Move everything from before `before_year` into the archives. Every archive
+/// is saved before anything leaves the active data, so a failure part way
+/// leaves copies at worst, never gaps. Returns what was archived.
+pub async fn archive_before(before_year: i32) -> Result<Vec<Archive>, String> {
+    let archives = Store::get_archivable(before_year);
+    restore(&archives).await?;
+    Store::remove_archived(&archives).await?;
+    Ok(archives)
+}
+
+/// Move the audit entries past `LOG_LIMIT`, oldest first, into the archive of
+/// the year they were made. As with `archive_before`, the archives are saved
+/// before anything leaves the log. Returns how many entries moved.
+pub async fn rotate_log() -> Result<usize, String> {
+    let old = Store::get_log_overflow(LOG_LIMIT);
+    if old.is_empty() {
+        return Ok(0);
+    }
+    let mut years = BTreeMap::new();
+    for entry in &old {
+        let year = year_of(entry.timestamp);
+        years.entry(year).or_insert_with(|| Archive { year, ..Archive::default() }).audit_log.push(entry.clone());
+    }
+    restore(&years.into_values().collect::<Vec<_>>()).await?;
+    Store::remove_logged(&old.iter().map(|e| e.id.clone()).collect()).await?;
+    Ok(old.len())
+}
+
+/// Audit entries of every archived year, oldest first
+pub async fn audit_log() -> Result<Vec<AuditEntry>, String> {
+    Ok(load_all().await?.into_iter().flat_map(|archive| archive.audit_log).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(year: i32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, 6, 15, 3, 0, 0).unwrap()
+    }
+
+    fn record(id: &str, year: i32) -> TreatmentRecord {
+        TreatmentRecord {
+            id: id.to_string(),
+            patient_id: "p1".to_string(),
+            date: at(year),
+            symptoms: String::new(),
+            diagnosis: "ไข้หวัด".to_string(),
+            weight: None,
+            pressure: String::new(),
+            prescriptions: vec![],
+            injections: vec![],
+            doctor_note: String::new(),
+            price: 100.0,
+            allergy_override: None,
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn test_split_by_year() {
+        let mut trashed = record("r3", 2021);
+        trashed.deleted_at = Some(at(2022));
+        let data = ClinicData {
+            records: vec![record("r1", 2021), record("r2", 2023), trashed, record("r4", 2022)],
+            expenses: vec![Expense { id: "e1".into(), date: at(2021), ..Expense::default() }],
+            drug_purchases: vec![DrugPurchase { id: "dp1".into(), date: at(2024), ..DrugPurchase::default() }],
+            ..ClinicData::default()
+        };
+
+        let archives = split(&data, 2023);
+        let years: Vec<(i32, usize)> = archives.iter().map(|a| (a.year, a.len())).collect();
+        assert_eq!(years, vec![(2021, 2), (2022, 1)]);
+        assert_eq!(archives[0].records[0].id, "r1");
+
+        let left = without_archived(data, &archives[0].ids());
+        let ids: Vec<&str> = left.records.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["r2", "r3", "r4"]);
+        assert!(left.expenses.is_empty());
+    }
+
+    #[test]
+    fn test_backup_round_trip_and_merge() {
+        let mut archive = Archive { year: 2021, records: vec![record("r1", 2021)], ..Archive::default() };
+        let mut doc = Map::new();
+        doc.insert(KEY_ARCHIVES.to_string(), to_backup(std::slice::from_ref(&archive)).unwrap());
+        assert_eq!(from_backup(&mut doc).unwrap(), vec![archive.clone()]);
+        assert!(doc.is_empty());
+
+        let mut changed = record("r1", 2021);
+        changed.price = 999.0;
+        archive.merge(&Archive { year: 2021, records: vec![changed, record("r2", 2021)], ..Archive::default() });
+        let prices: Vec<f64> = archive.records.iter().map(|r| r.price).collect();
+        assert_eq!(prices, vec![100.0, 100.0]);
+    }
+
+    #[test]
+    fn test_audit_entries_round_trip_and_merge() {
+        let entry = |id: &str| AuditEntry {
+            id: id.to_string(),
+            timestamp: at(2021),
+            actor: String::new(),
+            action: "update".to_string(),
+            entity: "drug".to_string(),
+            entity_id: "d1".to_string(),
+            label: String::new(),
+            before: None,
+            after: None,
+        };
+        let mut archive = Archive { year: 2021, audit_log: vec![entry("log1")], ..Archive::default() };
+        let doc = archive.to_document().unwrap();
+        let Value::Object(doc) = doc else { unreachable!() };
+        assert_eq!(Archive::from_document(2021, doc).unwrap(), archive);
+        // Entries alone don't count as archived items
+        assert!(archive.is_empty());
+
+        archive.merge(&Archive { year: 2021, audit_log: vec![entry("log1"), entry("log2")], ..Archive::default() });
+        let ids: Vec<&str> = archive.audit_log.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["log1", "log2"]);
+    }
+}
@@ -0,0 +1,324 @@
+// Audit trail of every change the Store saves.
+// Store::transaction compares the collections a transaction touched with what
+// was there before, and appends one entry per item created, updated or deleted.
+// A new item is kept whole in `after` and a purged one in `before`; otherwise
+// only the fields that changed are kept, so the log grows with the edits rather
+// than with the size of what was edited. Entries are only ever appended, under
+// their own storage key, and the oldest move to the yearly archives (archive.rs).
+
+use std::collections::HashMap;
+use chrono::{Local, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use crate::models::{Patient, TreatmentRecord, DrugItem, Expense, DrugPurchase, Appointment, InteractionRule, AuditEntry};
+use crate::storage::{
+    ClinicData, KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS, KEY_SETTINGS, KEY_EXPENSES, KEY_DRUG_PURCHASES, KEY_APPOINTMENTS,
+    KEY_INTERACTIONS,
+};
+
+pub const ACTION_CREATE: &str = "create";
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_DELETE: &str = "delete";
+pub const ACTION_RESTORE: &str = "restore";
+pub const ACTION_PURGE: &str = "purge";
+/// A year of old data moved out of the active data, see archive.rs
+pub const ACTION_ARCHIVE: &str = "archive";
+/// Patients found to be the same person folded into one, see Store::merge_patients
+pub const ACTION_MERGE: &str = "merge";
+/// Every action, in the order the Audit page lists them
+pub const ACTIONS: [&str; 7] =
+    [ACTION_CREATE, ACTION_UPDATE, ACTION_DELETE, ACTION_RESTORE, ACTION_PURGE, ACTION_ARCHIVE, ACTION_MERGE];
+
+/// Entity names used in `AuditEntry::entity`, in the order the Audit page lists them
+pub const ENTITIES: [&str; 9] =
+    ["patient", "record", "drug", "drug_purchase", "interaction", "expense", "appointment", "settings", "archive"];
+
+/// An item of a collection the audit trail follows
+pub trait Audited: Serialize + PartialEq {
+    const ENTITY: &'static str;
+    fn id(&self) -> &str;
+    fn label(&self) -> String;
+    /// In the Recycle Bin; moving in or out of it is logged as delete / restore
+    fn in_trash(&self) -> bool {
+        false
+    }
+}
+
+impl Audited for Patient {
+    const ENTITY: &'static str = "patient";
+    fn id(&self) -> &str { &self.id }
+    fn in_trash(&self) -> bool { self.deleted_at.is_some() }
+    fn label(&self) -> String {
+        format!("{} {}{} {}", self.hn, self.title, self.first_name, self.last_name)
+    }
+}
+
+impl Audited for TreatmentRecord {
+    const ENTITY: &'static str = "record";
+    fn id(&self) -> &str { &self.id }
+    fn in_trash(&self) -> bool { self.deleted_at.is_some() }
+    fn label(&self) -> String {
+        format!("{} {}", self.date.with_timezone(&Local).format("%d/%m/%Y %H:%M"), self.diagnosis)
+    }
+}
+
+impl Audited for DrugItem {
+    const ENTITY: &'static str = "drug";
+    fn id(&self) -> &str { &self.id }
+    fn in_trash(&self) -> bool { self.deleted_at.is_some() }
+    fn label(&self) -> String { self.name.clone() }
+}
+
+impl Audited for Expense {
+    const ENTITY: &'static str = "expense";
+    fn id(&self) -> &str { &self.id }
+    fn in_trash(&self) -> bool { self.deleted_at.is_some() }
+    fn label(&self) -> String {
+        format!("{} {} ({:.2} บาท)", self.category, self.description, self.amount)
+    }
+}
+
+impl Audited for DrugPurchase {
+    const ENTITY: &'static str = "drug_purchase";
+    fn id(&self) -> &str { &self.id }
+    fn label(&self) -> String {
+        format!("{} x{}", self.drug_name, self.quantity)
+    }
+}
+
+impl Audited for Appointment {
+    const ENTITY: &'static str = "appointment";
+    fn id(&self) -> &str { &self.id }
+    fn in_trash(&self) -> bool { self.deleted_at.is_some() }
+    fn label(&self) -> String {
+        format!("{} {} {}", self.patient_name, self.date.format("%d/%m/%Y"), self.time)
+    }
+}
+
+impl Audited for InteractionRule {
+    const ENTITY: &'static str = "interaction";
+    fn id(&self) -> &str { &self.id }
+    fn label(&self) -> String {
+        format!("{} + {}", self.a, self.b)
+    }
+}
+
+struct Recorder<'a> {
+    actor: &'a str,
+    entries: Vec<AuditEntry>,
+}
+
+/// The fields of two snapshots that differ, each side holding its own values.
+/// `updated_at` always changes along with the rest and is left out.
+fn changed_fields(before: Value, after: Value) -> (Value, Value) {
+    let (mut old, mut new) = match (before, after) {
+        (Value::Object(old), Value::Object(new)) => (old, new),
+        other => return other,
+    };
+    let keys: Vec<String> = old.keys().chain(new.keys()).cloned().collect();
+    let (mut was, mut now) = (Map::new(), Map::new());
+    for key in keys {
+        if key == "updated_at" || was.contains_key(&key) {
+            continue;
+        }
+        let (a, b) = (old.remove(&key).unwrap_or(Value::Null), new.remove(&key).unwrap_or(Value::Null));
+        if a != b {
+            was.insert(key.clone(), a);
+            now.insert(key, b);
+        }
+    }
+    (Value::Object(was), Value::Object(now))
+}
+
+impl Recorder<'_> {
+    fn push<T: Serialize>(&mut self, action: &str, entity: &str, entity_id: &str, label: String, before: Option<&T>, after: Option<&T>) {
+        let snapshot = |item: Option<&T>| item.and_then(|item| serde_json::to_value(item).ok());
+        let (before, after) = match (snapshot(before), snapshot(after)) {
+            (Some(before), Some(after)) => {
+                let (before, after) = changed_fields(before, after);
+                (Some(before), Some(after))
+            }
+            other => other,
+        };
+        self.entries.push(AuditEntry {
+            id: Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            actor: self.actor.to_string(),
+            action: action.to_string(),
+            entity: entity.to_string(),
+            entity_id: entity_id.to_string(),
+            label,
+            before,
+            after,
+        });
+    }
+
+    fn list<T: Audited>(&mut self, before: &[T], after: &[T]) {
+        let old: HashMap<&str, &T> = before.iter().rev().map(|item| (item.id(), item)).collect();
+        let new: HashMap<&str, &T> = after.iter().rev().map(|item| (item.id(), item)).collect();
+
+        for item in after {
+            match old.get(item.id()) {
+                None => self.push(ACTION_CREATE, T::ENTITY, item.id(), item.label(), None, Some(item)),
+                Some(prev) if *prev != item => {
+                    let action = match (prev.in_trash(), item.in_trash()) {
+                        (false, true) => ACTION_DELETE,
+                        (true, false) => ACTION_RESTORE,
+                        _ => ACTION_UPDATE,
+                    };
+                    self.push(action, T::ENTITY, item.id(), item.label(), Some(*prev), Some(item))
+                }
+                Some(_) => {}
+            }
+        }
+        for item in before.iter().filter(|item| !new.contains_key(item.id())) {
+            let action = if item.in_trash() { ACTION_PURGE } else { ACTION_DELETE };
+            self.push(action, T::ENTITY, item.id(), item.label(), Some(item), None);
+        }
+    }
+}
+
+/// The one entry for a year moved to its archive, with what it held as the label
+pub fn archived(year: i32, label: String, actor: &str) -> AuditEntry {
+    let mut recorder = Recorder { actor, entries: Vec::new() };
+    recorder.push::<()>(ACTION_ARCHIVE, "archive", &year.to_string(), label, None, None);
+    recorder.entries.remove(0)
+}
+
+/// The one entry for patients merged into `keep`, naming who was merged. The
+/// merged patients as they were are kept as the before snapshot; the moved
+/// records and appointments get their own update entries.
+pub fn merged(keep: &Patient, others: &[Patient], actor: &str) -> AuditEntry {
+    let mut recorder = Recorder { actor, entries: Vec::new() };
+    let names: Vec<String> = others.iter().map(Audited::label).collect();
+    let label = format!("{} ← {}", keep.label(), names.join(", "));
+    recorder.push(ACTION_MERGE, Patient::ENTITY, &keep.id, label, Some(&others.to_vec()), Some(&vec![keep.clone()]));
+    recorder.entries.remove(0)
+}
+
+/// Audit entries for everything that differs between `before` and `after` in
+/// the collections named by `keys`
+pub fn changes(before: &ClinicData, after: &ClinicData, keys: &[&str], actor: &str) -> Vec<AuditEntry> {
+    let mut recorder = Recorder { actor, entries: Vec::new() };
+    for key in keys {
+        match *key {
+            KEY_PATIENTS => recorder.list(&before.patients, &after.patients),
+            KEY_RECORDS => recorder.list(&before.records, &after.records),
+            KEY_DRUGS => recorder.list(&before.drugs, &after.drugs),
+            KEY_EXPENSES => recorder.list(&before.expenses, &after.expenses),
+            KEY_DRUG_PURCHASES => recorder.list(&before.drug_purchases, &after.drug_purchases),
+            KEY_APPOINTMENTS => recorder.list(&before.appointments, &after.appointments),
+            KEY_INTERACTIONS => recorder.list(&before.interactions, &after.interactions),
+            KEY_SETTINGS if before.settings != after.settings => recorder.push(
+                ACTION_UPDATE, "settings", "settings", "ตั้งค่าคลินิก".to_string(),
+                Some(&before.settings), Some(&after.settings),
+            ),
+            _ => {}
+        }
+    }
+    recorder.entries
+}
+
+pub fn action_name(action: &str) -> &str {
+    match action {
+        ACTION_CREATE => "เพิ่ม",
+        ACTION_UPDATE => "แก้ไข",
+        ACTION_DELETE => "ลบ",
+        ACTION_RESTORE => "กู้คืน",
+        ACTION_PURGE => "ลบถาวร",
+        ACTION_ARCHIVE => "เก็บถาวร",
+        ACTION_MERGE => "รวมผู้ป่วย",
+        other => other,
+    }
+}
+
+pub fn entity_name(entity: &str) -> &str {
+    match entity {
+        "patient" => "ผู้ป่วย",
+        "record" => "การรักษา",
+        "drug" => "ยา",
+        "drug_purchase" => "ซื้อยาเข้า",
+        "interaction" => "ปฏิกิริยาระหว่างยา",
+        "expense" => "ค่าใช้จ่าย",
+        "appointment" => "นัดหมาย",
+        "settings" => "ตั้งค่า",
+        "archive" => "ข้อมูลเก่า",
+        other => other,
+    }
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The entries as CSV, with a BOM so Excel reads the Thai text as UTF-8
+pub fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from("\u{feff}เวลา,ผู้แก้ไข,การกระทำ,ประเภท,รหัส,รายการ,ก่อนแก้ไข,หลังแก้ไข\r\n");
+    for entry in entries {
+        let json = |value: &Option<serde_json::Value>| value.as_ref().map(|v| v.to_string()).unwrap_or_default();
+        let fields = [
+            entry.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            entry.actor.clone(),
+            action_name(&entry.action).to_string(),
+            entity_name(&entry.entity).to_string(),
+            entry.entity_id.clone(),
+            entry.label.clone(),
+            json(&entry.before),
+            json(&entry.after),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drug(id: &str, name: &str, stock: u32) -> DrugItem {
+        DrugItem { id: id.to_string(), name: name.to_string(), stock, ..DrugItem::default() }
+    }
+
+    #[test]
+    fn test_changes_per_item() {
+        let before = ClinicData { drugs: vec![drug("d1", "พารา", 10), drug("d2", "ยาแก้ไอ", 5)], ..ClinicData::default() };
+        let after = ClinicData { drugs: vec![drug("d1", "พารา", 8), drug("d3", "ยาลดกรด", 20)], ..ClinicData::default() };
+
+        let entries = changes(&before, &after, &[KEY_DRUGS], "พยาบาล");
+        let summary: Vec<(&str, &str)> = entries.iter().map(|e| (e.action.as_str(), e.entity_id.as_str())).collect();
+        assert_eq!(summary, [(ACTION_UPDATE, "d1"), (ACTION_CREATE, "d3"), (ACTION_DELETE, "d2")]);
+
+        // An update keeps only what changed; a new or removed item is kept whole
+        let update = &entries[0];
+        assert_eq!(update.actor, "พยาบาล");
+        assert_eq!(update.before, Some(serde_json::json!({ "stock": 10 })));
+        assert_eq!(update.after, Some(serde_json::json!({ "stock": 8 })));
+        assert_eq!(entries[1].after.as_ref().unwrap()["name"], "ยาลดกรด");
+        assert_eq!(entries[2].before.as_ref().unwrap()["name"], "ยาแก้ไอ");
+        assert!(entries[2].after.is_none());
+
+        // Collections the transaction did not touch are not compared
+        assert!(changes(&before, &after, &[KEY_PATIENTS], "พยาบาล").is_empty());
+    }
+
+    #[test]
+    fn test_csv_escapes_fields() {
+        let before = ClinicData::default();
+        let after = ClinicData { drugs: vec![drug("d1", "ยา \"A\", 500mg", 1)], ..ClinicData::default() };
+        let csv = to_csv(&changes(&before, &after, &[KEY_DRUGS], ""));
+
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.contains(",เพิ่ม,ยา,d1,\"ยา \"\"A\"\", 500mg\","));
+        // Snapshots are JSON, quoted like any other field
+        assert!(row.contains(r#"""id"":""d1"""#));
+        assert!(row.contains(r#"""stock"":1,"#));
+        assert!(row.ends_with('"'));
+    }
+}
@@ -0,0 +1,428 @@
+// Backup files
+// The browser download and the desktop app's `create_backup` command
+// (src-tauri/src/backup.rs) write the same format:
+//
+//     { "backup_format": 1, "created_at": "...", "checksum": "sha256:...", "data": { ... } }
+//
+// `data` is the whole clinic_data.json document, so every Store collection is in
+// it under its storage key, and `checksum` covers it so a truncated or edited file
+// is caught before anything is restored. The checksum is taken over `data` as
+// serde_json writes it, which sorts object keys, so either side can check a file
+// the other one wrote. Older backups without `backup_format` are still read.
+// Yearly archives (archive.rs) ride along in `data` under "clinic_archives".
+//
+// A backup can replace the current data outright or be merged into it by id.
+// Items carry no "last modified" field, so the newer of two versions is the one
+// with the later audit entry; when neither side can show that, it's a conflict
+// and the current version is kept.
+
+use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use crate::archive::{self, Archive, KEY_ARCHIVES};
+use crate::audit::Audited;
+use crate::migrations::KEY_SCHEMA_VERSION;
+use crate::models::AuditEntry;
+use crate::storage::{ClinicData, KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS, KEY_SETTINGS};
+
+/// Bump when the layout of the backup file changes
+pub const BACKUP_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    backup_format: u32,
+    created_at: DateTime<Utc>,
+    checksum: String,
+    data: Value,
+}
+
+fn checksum(data: &Value) -> String {
+    let hash = Sha256::digest(data.to_string().as_bytes());
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// A backup of `data` and the archived years as pretty-printed JSON
+pub fn create(data: &ClinicData, archives: &[Archive]) -> Result<String, String> {
+    let mut data = serde_json::to_value(data).map_err(|e| e.to_string())?;
+    if let (Value::Object(doc), false) = (&mut data, archives.is_empty()) {
+        doc.insert(KEY_ARCHIVES.to_string(), archive::to_backup(archives)?);
+    }
+    let backup = Backup { backup_format: BACKUP_FORMAT, created_at: Utc::now(), checksum: checksum(&data), data };
+    serde_json::to_string_pretty(&backup).map_err(|e| e.to_string())
+}
+
+/// Read a backup file, checking its checksum. Backups from before the current
+/// format are accepted too and go through the same migrations as stored data.
+pub fn read(json: &str) -> Result<ClinicData, String> {
+    Ok(ClinicData::from_document(document(json)?)?.data)
+}
+
+/// The archived years a backup carries, oldest first
+pub fn read_archives(json: &str) -> Result<Vec<Archive>, String> {
+    archive::from_backup(&mut document(json)?)
+}
+
+/// The clinic data document inside a backup, after checking its checksum
+fn document(json: &str) -> Result<Map<String, Value>, String> {
+    let raw: Map<String, Value> = serde_json::from_str(json).map_err(|e| format!("ไฟล์ไม่ถูกต้อง: {}", e))?;
+
+    let doc = if raw.contains_key("backup_format") {
+        let backup: Backup = serde_json::from_value(Value::Object(raw)).map_err(|e| format!("ไฟล์ไม่ถูกต้อง: {}", e))?;
+        if backup.backup_format > BACKUP_FORMAT {
+            return Err("ไฟล์สำรองนี้มาจากโปรแกรมรุ่นใหม่กว่า กรุณาอัปเดตโปรแกรมก่อนกู้คืน".to_string());
+        }
+        if checksum(&backup.data) != backup.checksum {
+            return Err("ไฟล์สำรองเสียหายหรือถูกแก้ไข (checksum ไม่ตรงกัน)".to_string());
+        }
+        match backup.data {
+            Value::Object(doc) => doc,
+            _ => return Err("ไฟล์ไม่ถูกต้อง: ไม่พบข้อมูลในไฟล์สำรอง".to_string()),
+        }
+    } else if raw.contains_key("backup_date") {
+        legacy_document(raw)
+    } else {
+        // The desktop app used to copy clinic_data.json as it was
+        raw
+    };
+    Ok(doc)
+}
+
+// The browser download used to have its own field names and only four collections
+fn legacy_document(raw: Map<String, Value>) -> Map<String, Value> {
+    let mut doc = Map::new();
+    for (field, key) in [
+        ("version", KEY_SCHEMA_VERSION),
+        ("patients", KEY_PATIENTS),
+        ("records", KEY_RECORDS),
+        ("drugs", KEY_DRUGS),
+        ("settings", KEY_SETTINGS),
+    ] {
+        if let Some(value) = raw.get(field) {
+            doc.insert(key.to_string(), value.clone());
+        }
+    }
+    doc
+}
+
+/// What a backup holds, for messages to the user
+pub fn summary(data: &ClinicData) -> String {
+    format!(
+        "ผู้ป่วย {} คน, การรักษา {} รายการ, ยา {} รายการ, ค่าใช้จ่าย {} รายการ, ซื้อยาเข้า {} รายการ, นัดหมาย {} รายการ",
+        data.patients.len(),
+        data.records.len(),
+        data.drugs.len(),
+        data.expenses.len(),
+        data.drug_purchases.len(),
+        data.appointments.len(),
+    )
+}
+
+// ========== Restoring ==========
+
+/// How one collection of a backup compares with the current data
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tally {
+    /// Same names as `AuditEntry::entity`
+    pub entity: &'static str,
+    /// Only in the backup
+    pub added: usize,
+    /// Differs, and the backup has the newer version
+    pub updated: usize,
+    /// Differs, and the backup's version is not known to be newer
+    pub conflicts: usize,
+    /// Only in the current data
+    pub missing: usize,
+}
+
+impl Tally {
+    pub fn is_unchanged(&self) -> bool {
+        self.added == 0 && self.updated == 0 && self.conflicts == 0 && self.missing == 0
+    }
+}
+
+/// When each item was last changed according to an audit log
+fn last_changed(log: &[AuditEntry]) -> HashMap<(&str, &str), DateTime<Utc>> {
+    let mut last = HashMap::new();
+    for entry in log {
+        let at = last.entry((entry.entity.as_str(), entry.entity_id.as_str())).or_insert(entry.timestamp);
+        *at = (*at).max(entry.timestamp);
+    }
+    last
+}
+
+struct Merger<'a> {
+    current_log: HashMap<(&'a str, &'a str), DateTime<Utc>>,
+    backup_log: HashMap<(&'a str, &'a str), DateTime<Utc>>,
+    tallies: Vec<Tally>,
+}
+
+impl Merger<'_> {
+    fn backup_is_newer(&self, entity: &str, id: &str) -> bool {
+        self.backup_log.get(&(entity, id)) > self.current_log.get(&(entity, id))
+    }
+
+    fn list<T: Audited + Clone>(&mut self, current: &[T], backup: &[T]) -> Vec<T> {
+        let mut tally = Tally { entity: T::ENTITY, added: 0, updated: 0, conflicts: 0, missing: 0 };
+        let mut merged = current.to_vec();
+        let positions: HashMap<String, usize> =
+            current.iter().enumerate().rev().map(|(pos, item)| (item.id().to_string(), pos)).collect();
+
+        for item in backup {
+            match positions.get(item.id()) {
+                None => {
+                    merged.push(item.clone());
+                    tally.added += 1;
+                }
+                Some(&pos) if merged[pos] == *item => {}
+                Some(&pos) if self.backup_is_newer(T::ENTITY, item.id()) => {
+                    merged[pos] = item.clone();
+                    tally.updated += 1;
+                }
+                Some(_) => tally.conflicts += 1,
+            }
+        }
+        let in_backup: HashSet<&str> = backup.iter().map(|item| item.id()).collect();
+        tally.missing = current.iter().filter(|item| !in_backup.contains(item.id())).count();
+
+        self.tallies.push(tally);
+        merged
+    }
+}
+
+/// Both audit logs, each entry once, oldest first
+pub fn merge_logs(current: &[AuditEntry], backup: &[AuditEntry]) -> Vec<AuditEntry> {
+    let known: HashSet<&str> = current.iter().map(|e| e.id.as_str()).collect();
+    let mut log = current.to_vec();
+    log.extend(backup.iter().filter(|e| !known.contains(e.id.as_str())).cloned());
+    log.sort_by_key(|e| e.timestamp);
+    log
+}
+
+/// The current data with the backup merged in by id, and what changed per collection.
+/// Nothing is re-applied: a restored record doesn't take its drugs out of stock again.
+/// Settings stay as they are.
+pub fn merge(current: &ClinicData, backup: &ClinicData) -> (ClinicData, Vec<Tally>) {
+    let mut merger = Merger {
+        current_log: last_changed(&current.audit_log),
+        backup_log: last_changed(&backup.audit_log),
+        tallies: Vec::new(),
+    };
+    let merged = ClinicData {
+        patients: merger.list(&current.patients, &backup.patients),
+        records: merger.list(&current.records, &backup.records),
+        drugs: merger.list(&current.drugs, &backup.drugs),
+        drug_purchases: merger.list(&current.drug_purchases, &backup.drug_purchases),
+        expenses: merger.list(&current.expenses, &backup.expenses),
+        appointments: merger.list(&current.appointments, &backup.appointments),
+        interactions: merger.list(&current.interactions, &backup.interactions),
+        last_hn: current.last_hn.max(backup.last_hn),
+        audit_log: merge_logs(&current.audit_log, &backup.audit_log),
+        ..current.clone()
+    };
+    (merged, merger.tallies)
+}
+
+/// The backup in place of the current data. The audit log keeps its history,
+/// so the replacement itself shows up in it as changes.
+pub fn replace(current: &ClinicData, backup: ClinicData) -> ClinicData {
+    ClinicData {
+        audit_log: merge_logs(&current.audit_log, &backup.audit_log),
+        unreadable: current.unreadable.clone(),
+        ..backup
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::migrations::CURRENT_SCHEMA_VERSION;
+    use crate::models::{Patient, TreatmentRecord, DrugItem, Expense, DrugPurchase, Appointment, AuditEntry};
+
+    fn sample() -> ClinicData {
+        let now = "2024-05-01T03:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let patient = Patient {
+            id: "p1".into(),
+            hn: "HN000001".into(),
+            citizen_id: String::new(),
+            title: "นาย".into(),
+            first_name: "สมชาย".into(),
+            last_name: "ใจดี".into(),
+            birth_date: None,
+            age: Some(40),
+            blood_group: String::new(),
+            underlying_disease: String::new(),
+            allergies: vec![],
+            phone: String::new(),
+            address: String::new(),
+            created_at: now,
+            deleted_at: Some(now),
+            updated_at: None,
+        };
+        let record = TreatmentRecord {
+            id: "r1".into(),
+            patient_id: "p1".into(),
+            date: now,
+            symptoms: String::new(),
+            diagnosis: "ไข้หวัด".into(),
+            weight: Some(60.5),
+            pressure: String::new(),
+            prescriptions: Vec::new(),
+            injections: Vec::new(),
+            doctor_note: String::new(),
+            price: 150.0,
+            allergy_override: None,
+            deleted_at: None,
+            updated_at: None,
+        };
+        let entry = AuditEntry {
+            id: "log1".into(),
+            timestamp: now,
+            actor: String::new(),
+            action: "create".into(),
+            entity: "patient".into(),
+            entity_id: "p1".into(),
+            label: String::new(),
+            before: None,
+            after: Some(serde_json::json!({ "id": "p1" })),
+        };
+        let mut data = ClinicData {
+            patients: vec![patient],
+            records: vec![record],
+            drugs: vec![DrugItem { id: "d1".into(), name: "Paracetamol".into(), stock: 100, ..DrugItem::default() }],
+            expenses: vec![Expense { id: "e1".into(), date: now, description: "ค่าไฟ".into(), amount: 1200.5, ..Expense::default() }],
+            drug_purchases: vec![DrugPurchase { id: "dp1".into(), drug_id: "d1".into(), date: now, quantity: 50, ..DrugPurchase::default() }],
+            appointments: vec![Appointment {
+                id: "a1".into(),
+                patient_id: "p1".into(),
+                date: NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+                ..Appointment::default()
+            }],
+            audit_log: vec![entry],
+            last_hn: 1,
+            schema_version: CURRENT_SCHEMA_VERSION,
+            ..ClinicData::default()
+        };
+        data.settings.clinic_name = "คลินิกทดสอบ".into();
+        data
+    }
+
+    #[test]
+    fn test_round_trip_keeps_every_collection() {
+        let data = sample();
+        let json = create(&data, &[]).unwrap();
+        assert_eq!(read(&json).unwrap(), data);
+        assert!(read_archives(&json).unwrap().is_empty());
+
+        let archive = Archive { year: 2022, records: data.records.clone(), ..Archive::default() };
+        let json = create(&data, std::slice::from_ref(&archive)).unwrap();
+        assert_eq!(read(&json).unwrap(), data);
+        assert_eq!(read_archives(&json).unwrap(), vec![archive]);
+    }
+
+    #[test]
+    fn test_checksum_catches_changes() {
+        let json = create(&sample(), &[]).unwrap();
+        let tampered = json.replace("1200.5", "9999.0");
+        assert_ne!(json, tampered);
+        assert!(read(&tampered).unwrap_err().contains("checksum"));
+
+        let newer = json.replace("\"backup_format\": 1", "\"backup_format\": 2");
+        assert!(read(&newer).unwrap_err().contains("รุ่นใหม่กว่า"));
+    }
+
+    #[test]
+    fn test_checksum_matches_backend() {
+        // Same value as the backend's test in src-tauri/src/backup.rs
+        let data = serde_json::json!({ "b": [1, "ก"], "a": { "y": null, "x": 1.5 } });
+        assert_eq!(checksum(&data), "sha256:973403c34a5d207d79adc42bee534b785c35efe98466c05d16943328bcda9cfa");
+    }
+
+    #[test]
+    fn test_reads_legacy_backups() {
+        let legacy = r#"{"version":1,"backup_date":"2024-01-01 10:00:00",
+            "patients":[],"records":[],"drugs":[{"id":"d1","name":"Paracetamol","unit":"เม็ด","stock":5,"min_stock":10,"cost_price":1.0,
+            "sell_price":2.0,"expiry_date":null,"category":"","description":"","default_usage":"","warning":""}],
+            "settings":{}}"#;
+        let data = read(legacy).unwrap();
+        assert_eq!(data.drugs.len(), 1);
+        assert_eq!(data.drugs[0].stock, 5);
+
+        // A copy of clinic_data.json, as the desktop app used to make
+        let copied = r#"{"clinic_expenses":[{"id":"e1","date":"2024-01-01T00:00:00Z","category":"อื่นๆ",
+            "description":"","amount":10.0,"note":""}]}"#;
+        assert_eq!(read(copied).unwrap().expenses.len(), 1);
+    }
+
+    fn logged(entity: &str, id: &str, timestamp: DateTime<Utc>) -> AuditEntry {
+        AuditEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp,
+            actor: String::new(),
+            action: "update".into(),
+            entity: entity.into(),
+            entity_id: id.into(),
+            label: String::new(),
+            before: None,
+            after: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_twice_adds_nothing() {
+        let current = sample();
+        let (merged, tallies) = merge(&current, &sample());
+        assert_eq!(merged, current);
+        assert!(tallies.iter().all(Tally::is_unchanged));
+        assert_eq!(tallies.len(), 7);
+    }
+
+    #[test]
+    fn test_merge_keeps_newer_version() {
+        let t1 = Utc::now();
+        let t2 = t1 + chrono::Duration::minutes(5);
+        let mut current = sample();
+        let mut backup = sample();
+        backup.drugs[0].stock = 80;
+        backup.expenses[0].amount = 99.0;
+        backup.appointments.push(Appointment { id: "a2".into(), ..Appointment::default() });
+        current.records.clear();
+
+        // The backup's drug was changed after ours; nothing says which expense is newer
+        current.audit_log.push(logged("drug", "d1", t1));
+        backup.audit_log.push(logged("drug", "d1", t2));
+
+        let (merged, tallies) = merge(&current, &backup);
+        assert_eq!(merged.drugs[0].stock, 80);
+        assert_eq!(merged.expenses[0].amount, 1200.5);
+        assert_eq!(merged.appointments.len(), 2);
+        assert_eq!(merged.records.len(), 1);
+        assert_eq!(merged.audit_log.len(), 3);
+
+        let tally = |entity: &str| tallies.iter().find(|t| t.entity == entity).unwrap().clone();
+        assert_eq!((tally("drug").updated, tally("drug").conflicts), (1, 0));
+        assert_eq!((tally("expense").updated, tally("expense").conflicts), (0, 1));
+        assert_eq!(tally("appointment").added, 1);
+        assert_eq!(tally("record").added, 1);
+
+        // Our version is newer: kept, and counted as a conflict
+        let (merged, _) = merge(&backup, &current);
+        assert_eq!(merged.drugs[0].stock, 80);
+    }
+
+    #[test]
+    fn test_replace_keeps_audit_history() {
+        let mut current = sample();
+        current.audit_log.push(logged("drug", "d1", Utc::now()));
+        let backup = ClinicData { drugs: sample().drugs, audit_log: sample().audit_log, ..ClinicData::default() };
+
+        let replaced = replace(&current, backup.clone());
+        assert!(replaced.patients.is_empty());
+        assert_eq!(replaced.drugs, backup.drugs);
+        assert_eq!(replaced.settings, backup.settings);
+        assert_eq!(replaced.audit_log.len(), 2);
+    }
+}
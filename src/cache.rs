@@ -0,0 +1,195 @@
+// Lookup indexes over the in-memory data, so pages can find a patient, a visit
+// or a day's records without scanning every record.
+// Indexes hold positions into the vectors in ClinicData. After a transaction the
+// Store hands over the collections before and after it; only the positions whose
+// item changed are updated, unless items were taken out and everything after
+// them moved, in which case that collection's indexes are built again.
+// Items in the Recycle Bin are left out, so lookups never find them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use chrono::{Local, NaiveDate};
+use crate::models::{DrugItem, Patient, SoftDelete, TreatmentRecord};
+use crate::storage::{ClinicData, KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS};
+
+/// Positions of the items under each key, lowest first, so a lookup returns
+/// what `iter().find()` would
+type Positions<K> = HashMap<K, Vec<usize>>;
+
+#[derive(Default)]
+pub struct Indexes {
+    patient_by_id: Positions<String>,
+    patient_by_hn: Positions<String>,
+    record_by_id: Positions<String>,
+    records_by_patient: Positions<String>,
+    /// Keyed by the local calendar day of the visit
+    records_by_day: BTreeMap<NaiveDate, Vec<usize>>,
+    drug_by_id: Positions<String>,
+    drug_by_name: Positions<String>,
+}
+
+fn add(list: &mut Vec<usize>, pos: usize) {
+    if let Err(at) = list.binary_search(&pos) {
+        list.insert(at, pos);
+    }
+}
+
+/// Take `pos` out of the list under `key`, and the key with it once it is empty
+fn take<K: Eq + Hash>(map: &mut Positions<K>, key: &K, pos: usize) {
+    if let Some(list) = map.get_mut(key) {
+        if let Ok(at) = list.binary_search(&pos) {
+            list.remove(at);
+        }
+        if list.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+/// Positions whose item differs between `before` and `after`, or None when
+/// items were taken out and the positions after them no longer line up
+fn changed<T: PartialEq>(before: &[T], after: &[T]) -> Option<Vec<usize>> {
+    (after.len() >= before.len()).then(|| (0..after.len()).filter(|&pos| before.get(pos) != Some(&after[pos])).collect())
+}
+
+impl Indexes {
+    pub fn build(data: &ClinicData) -> Self {
+        let mut indexes = Self::default();
+        indexes.update(&ClinicData::default(), data, &[KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS]);
+        indexes
+    }
+
+    /// Bring the indexes of the collections named by `keys` from `before` to `after`
+    pub fn update(&mut self, before: &ClinicData, after: &ClinicData, keys: &[&str]) {
+        if keys.contains(&KEY_PATIENTS) {
+            self.follow(&before.patients, &after.patients, Self::clear_patients, Self::patient_entry);
+        }
+        if keys.contains(&KEY_RECORDS) {
+            self.follow(&before.records, &after.records, Self::clear_records, Self::record_entry);
+        }
+        if keys.contains(&KEY_DRUGS) {
+            self.follow(&before.drugs, &after.drugs, Self::clear_drugs, Self::drug_entry);
+        }
+    }
+
+    /// Apply one collection's changes. `entry` adds (true) or takes out (false)
+    /// the item at a position; items in the Recycle Bin are never added.
+    fn follow<T: PartialEq + SoftDelete>(
+        &mut self,
+        before: &[T],
+        after: &[T],
+        clear: fn(&mut Self),
+        entry: fn(&mut Self, usize, &T, bool),
+    ) {
+        match changed(before, after) {
+            Some(positions) => {
+                for pos in positions {
+                    if let Some(old) = before.get(pos).filter(|old| !old.is_deleted()) {
+                        entry(self, pos, old, false);
+                    }
+                    if !after[pos].is_deleted() {
+                        entry(self, pos, &after[pos], true);
+                    }
+                }
+            }
+            None => {
+                clear(self);
+                for (pos, item) in after.iter().enumerate().filter(|(_, item)| !item.is_deleted()) {
+                    entry(self, pos, item, true);
+                }
+            }
+        }
+    }
+
+    fn clear_patients(&mut self) {
+        self.patient_by_id.clear();
+        self.patient_by_hn.clear();
+    }
+
+    fn patient_entry(&mut self, pos: usize, p: &Patient, added: bool) {
+        if added {
+            add(self.patient_by_id.entry(p.id.clone()).or_default(), pos);
+            add(self.patient_by_hn.entry(p.hn.clone()).or_default(), pos);
+        } else {
+            take(&mut self.patient_by_id, &p.id, pos);
+            take(&mut self.patient_by_hn, &p.hn, pos);
+        }
+    }
+
+    fn clear_records(&mut self) {
+        self.record_by_id.clear();
+        self.records_by_patient.clear();
+        self.records_by_day.clear();
+    }
+
+    fn record_entry(&mut self, pos: usize, r: &TreatmentRecord, added: bool) {
+        let day = r.date.with_timezone(&Local).date_naive();
+        if added {
+            add(self.record_by_id.entry(r.id.clone()).or_default(), pos);
+            add(self.records_by_patient.entry(r.patient_id.clone()).or_default(), pos);
+            add(self.records_by_day.entry(day).or_default(), pos);
+        } else {
+            take(&mut self.record_by_id, &r.id, pos);
+            take(&mut self.records_by_patient, &r.patient_id, pos);
+            if let Some(list) = self.records_by_day.get_mut(&day) {
+                if let Ok(at) = list.binary_search(&pos) {
+                    list.remove(at);
+                }
+                if list.is_empty() {
+                    self.records_by_day.remove(&day);
+                }
+            }
+        }
+    }
+
+    fn clear_drugs(&mut self) {
+        self.drug_by_id.clear();
+        self.drug_by_name.clear();
+    }
+
+    fn drug_entry(&mut self, pos: usize, d: &DrugItem, added: bool) {
+        if added {
+            add(self.drug_by_id.entry(d.id.clone()).or_default(), pos);
+            add(self.drug_by_name.entry(d.name.clone()).or_default(), pos);
+        } else {
+            take(&mut self.drug_by_id, &d.id, pos);
+            take(&mut self.drug_by_name, &d.name, pos);
+        }
+    }
+
+    pub fn patient(&self, id: &str) -> Option<usize> {
+        self.patient_by_id.get(id).and_then(|list| list.first()).copied()
+    }
+
+    pub fn patient_by_hn(&self, hn: &str) -> Option<usize> {
+        self.patient_by_hn.get(hn).and_then(|list| list.first()).copied()
+    }
+
+    pub fn record(&self, id: &str) -> Option<usize> {
+        self.record_by_id.get(id).and_then(|list| list.first()).copied()
+    }
+
+    /// Positions of a patient's records, oldest saved first
+    pub fn records_of_patient(&self, patient_id: &str) -> &[usize] {
+        self.records_by_patient.get(patient_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Positions of records whose local day is within `start..=end`, in day order
+    pub fn records_between(&self, start: NaiveDate, end: NaiveDate) -> impl Iterator<Item = usize> + '_ {
+        let range = if start <= end { Some(self.records_by_day.range(start..=end)) } else { None };
+        range.into_iter().flatten().flat_map(|(_, positions)| positions.iter().copied())
+    }
+
+    /// Every day that has at least one record, earliest first
+    pub fn record_days(&self) -> impl Iterator<Item = NaiveDate> + '_ {
+        self.records_by_day.keys().copied()
+    }
+
+    pub fn drug(&self, id: &str) -> Option<usize> {
+        self.drug_by_id.get(id).and_then(|list| list.first()).copied()
+    }
+
+    pub fn drug_by_name(&self, name: &str) -> Option<usize> {
+        self.drug_by_name.get(name).and_then(|list| list.first()).copied()
+    }
+}
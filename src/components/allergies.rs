@@ -0,0 +1,136 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use crate::allergy::{self, GROUPS};
+use crate::models::{Allergen, Allergy, AllergySeverity};
+use crate::store::Store;
+
+const SEVERITIES: [AllergySeverity; 4] =
+    [AllergySeverity::Unknown, AllergySeverity::Mild, AllergySeverity::Moderate, AllergySeverity::Severe];
+
+// Values of the allergen drop-down: "group:<key>", "drug:<id>" or OTHER
+const OTHER: &str = "other";
+
+#[derive(Properties, PartialEq)]
+pub struct AllergyEditorProps {
+    pub allergies: Vec<Allergy>,
+    pub on_change: Callback<Vec<Allergy>>,
+}
+
+/// A patient's drug allergies: the list so far, and a row to add one
+#[function_component(AllergyEditor)]
+pub fn allergy_editor(props: &AllergyEditorProps) -> Html {
+    let drugs = Store::get_drugs();
+    let choice = use_state(String::new);
+    let other_name = use_state(String::new);
+    let reaction = use_state(String::new);
+    let severity = use_state(AllergySeverity::default);
+
+    let allergen = match choice.split_once(':') {
+        Some(("group", key)) => Some(Allergen::Group { group: key.to_string() }),
+        Some(("drug", id)) => drugs
+            .iter()
+            .find(|d| d.id == id)
+            .map(|d| Allergen::Drug { drug_id: d.id.clone(), name: d.name.clone() }),
+        _ if *choice == OTHER && !other_name.trim().is_empty() => Some(Allergen::Other { name: other_name.trim().to_string() }),
+        _ => None,
+    };
+
+    let on_add = {
+        let allergies = props.allergies.clone();
+        let on_change = props.on_change.clone();
+        let allergen = allergen.clone();
+        let choice = choice.clone();
+        let other_name = other_name.clone();
+        let reaction = reaction.clone();
+        let severity = severity.clone();
+        Callback::from(move |_: MouseEvent| {
+            let Some(allergen) = allergen.clone() else { return };
+            let mut allergies = allergies.clone();
+            allergies.push(Allergy { allergen, reaction: reaction.trim().to_string(), severity: *severity });
+            on_change.emit(allergies);
+            choice.set(String::new());
+            other_name.set(String::new());
+            reaction.set(String::new());
+            severity.set(AllergySeverity::default());
+        })
+    };
+
+    html! {
+        <div>
+            { if props.allergies.is_empty() {
+                html! { <p class="text-muted">{ "ไม่มีประวัติแพ้ยา" }</p> }
+            } else { html! {
+                <div class="flex gap-2 mb-4" style="flex-wrap: wrap;">
+                    { for props.allergies.iter().enumerate().map(|(idx, a)| {
+                        let on_remove = {
+                            let allergies = props.allergies.clone();
+                            let on_change = props.on_change.clone();
+                            Callback::from(move |_: MouseEvent| {
+                                let mut allergies = allergies.clone();
+                                allergies.remove(idx);
+                                on_change.emit(allergies);
+                            })
+                        };
+                        html! {
+                            <span class="badge badge-error">
+                                { allergy::describe(a) }
+                                <button type="button" class="btn btn-ghost btn-sm" style="padding: 0 0.25rem;" onclick={on_remove}>{ "✕" }</button>
+                            </span>
+                        }
+                    }) }
+                </div>
+            } } }
+            <div class="grid grid-cols-2 gap-4">
+                <select onchange={{
+                    let choice = choice.clone();
+                    Callback::from(move |e: Event| choice.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+                }}>
+                    <option value="" selected={choice.is_empty()}>{ "-- เลือกยาที่แพ้ --" }</option>
+                    <optgroup label="กลุ่มยา">
+                        { for GROUPS.iter().map(|g| {
+                            let value = format!("group:{}", g.key);
+                            let selected = *choice == value;
+                            html! { <option {selected} {value}>{ g.name }</option> }
+                        }) }
+                    </optgroup>
+                    <optgroup label="ยาในคลัง">
+                        { for drugs.iter().map(|d| {
+                            let value = format!("drug:{}", d.id);
+                            let selected = *choice == value;
+                            html! { <option {selected} {value}>{ &d.name }</option> }
+                        }) }
+                    </optgroup>
+                    <option value={OTHER} selected={*choice == OTHER}>{ "อื่นๆ (พิมพ์ชื่อเอง)" }</option>
+                </select>
+                { if *choice == OTHER { html! {
+                    <input type="text" value={(*other_name).clone()} placeholder="ชื่อยาที่แพ้"
+                        oninput={{
+                            let other_name = other_name.clone();
+                            Callback::from(move |e: InputEvent| other_name.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+                        }} />
+                } } else { html! { <div></div> } } }
+                <input type="text" value={(*reaction).clone()} placeholder="อาการที่แพ้ เช่น ผื่น ปากบวม"
+                    oninput={{
+                        let reaction = reaction.clone();
+                        Callback::from(move |e: InputEvent| reaction.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+                    }} />
+                <div class="flex gap-2">
+                    <select onchange={{
+                        let severity = severity.clone();
+                        Callback::from(move |e: Event| {
+                            let idx: usize = e.target_unchecked_into::<HtmlInputElement>().value().parse().unwrap_or(0);
+                            severity.set(SEVERITIES.get(idx).copied().unwrap_or_default());
+                        })
+                    }}>
+                        { for SEVERITIES.iter().enumerate().map(|(idx, s)| html! {
+                            <option value={idx.to_string()} selected={*severity == *s}>{ allergy::severity_name(*s) }</option>
+                        }) }
+                    </select>
+                    <button type="button" class="btn btn-secondary" onclick={on_add} disabled={allergen.is_none()}>
+                        { "➕ เพิ่ม" }
+                    </button>
+                </div>
+            </div>
+        </div>
+    }
+}
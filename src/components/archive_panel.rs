@@ -0,0 +1,133 @@
+use yew::prelude::*;
+use chrono::Datelike;
+use crate::archive::{self, Archive};
+use crate::store::Store;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+/// Move old years out of the active data, and the years archived so far
+#[function_component(ArchivePanel)]
+pub fn archive_panel() -> Html {
+    let toast = use_context::<ToastContext>();
+    let this_year = chrono::Local::now().year();
+    let before_year = use_state(|| this_year - 1);
+    let archives = use_state(|| None::<Vec<Archive>>);
+    let busy = use_state(|| false);
+    // Bumped to read the archives again
+    let generation = use_state(|| 0u32);
+
+    {
+        let archives = archives.clone();
+        let toast = toast.clone();
+        use_effect_with(*generation, move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::load_all().await {
+                    Ok(list) => archives.set(Some(list)),
+                    Err(err) => {
+                        archives.set(Some(Vec::new()));
+                        toast_error(&toast, format!("❌ อ่านข้อมูลเก่าที่เก็บถาวรไม่สำเร็จ: {}", err));
+                    }
+                }
+            });
+            || ()
+        });
+    }
+
+    // Every year with data older than this one can be the cut-off
+    let oldest = Store::get_archivable(this_year).first().map(|a| a.year);
+    let candidates = Store::get_archivable(*before_year);
+    let total: usize = candidates.iter().map(Archive::len).sum();
+
+    let on_year = {
+        let before_year = before_year.clone();
+        Callback::from(move |e: Event| {
+            let select: web_sys::HtmlSelectElement = e.target_unchecked_into();
+            if let Ok(year) = select.value().parse() {
+                before_year.set(year);
+            }
+        })
+    };
+
+    let on_archive = {
+        let toast = toast.clone();
+        let busy = busy.clone();
+        let generation = generation.clone();
+        let year = *before_year;
+        Callback::from(move |_: MouseEvent| {
+            let message = format!(
+                "ย้ายการรักษา ค่าใช้จ่าย และการซื้อยาเข้าก่อนปี พ.ศ. {} ไปเก็บถาวร?\nข้อมูลจะยังค้นดูได้ในหน้าประวัติและรายงาน แต่แก้ไขไม่ได้",
+                year + 543
+            );
+            if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                return;
+            }
+            busy.set(true);
+            let toast = toast.clone();
+            let busy = busy.clone();
+            let generation = generation.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::archive_before(year).await {
+                    Ok(archived) => {
+                        let count: usize = archived.iter().map(Archive::len).sum();
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(format!("📦 เก็บถาวรแล้ว {} รายการ", count), ToastType::Success));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, format!("❌ เก็บถาวรไม่สำเร็จ: {}", err)),
+                }
+                busy.set(false);
+                generation.set(*generation + 1);
+            });
+        })
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header">
+                <h3 class="card-title">{ "📦 เก็บถาวรข้อมูลเก่า" }</h3>
+                <p class="card-subtitle">{ "ย้ายข้อมูลปีเก่าออกจากข้อมูลที่ใช้งาน โปรแกรมจะเปิดได้เร็วเท่าเดิม ข้อมูลเก่ายังดูได้ในหน้าประวัติและรายงาน" }</p>
+            </div>
+
+            { match oldest {
+                None => html! { <p class="text-muted mb-4">{ "ยังไม่มีข้อมูลก่อนปีนี้ให้เก็บถาวร" }</p> },
+                Some(oldest) => html! {
+                    <div class="flex items-center gap-4 mb-4">
+                        <select class="form-select" style="width: auto;" onchange={on_year}>
+                            { for (oldest + 1..=this_year).rev().map(|year| html! {
+                                <option value={year.to_string()} selected={year == *before_year}>
+                                    { format!("ก่อนปี พ.ศ. {}", year + 543) }
+                                </option>
+                            })}
+                        </select>
+                        <span class="text-muted">{ format!("{} รายการ", total) }</span>
+                        <button class="btn btn-primary" onclick={on_archive} disabled={total == 0 || *busy}>
+                            { if *busy { "⏳ กำลังเก็บถาวร..." } else { "📦 เก็บถาวร" } }
+                        </button>
+                    </div>
+                },
+            } }
+
+            { match archives.as_ref() {
+                None => html! { <p class="text-muted">{ "กำลังโหลด..." }</p> },
+                Some(list) if list.is_empty() => html! {},
+                Some(list) => html! {
+                    <table class="data-table">
+                        <thead>
+                            <tr>
+                                <th>{ "ปี" }</th>
+                                <th>{ "ข้อมูลที่เก็บถาวร" }</th>
+                            </tr>
+                        </thead>
+                        <tbody>
+                            { for list.iter().map(|a| html! {
+                                <tr>
+                                    <td>{ format!("พ.ศ. {}", a.year + 543) }</td>
+                                    <td>{ a.summary() }</td>
+                                </tr>
+                            })}
+                        </tbody>
+                    </table>
+                },
+            } }
+        </div>
+    }
+}
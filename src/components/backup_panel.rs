@@ -0,0 +1,245 @@
+use yew::prelude::*;
+use web_sys::{Blob, Url, HtmlAnchorElement};
+use wasm_bindgen::JsCast;
+use crate::tauri_bridge;
+use crate::crypto;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+use super::restore_preview::{prompt, Restoring};
+
+/// Seal the backup with the unlocked passphrase, or ask for one.
+/// None when the user cancelled.
+pub fn seal_backup(data: String) -> Option<Result<String, String>> {
+    let passphrase = match crypto::session_passphrase() {
+        Some(passphrase) => passphrase,
+        None => prompt("ตั้งรหัสผ่านสำหรับไฟล์สำรอง (เว้นว่างไว้หากไม่ต้องการเข้ารหัส)")?,
+    };
+    if passphrase.is_empty() {
+        return Some(Ok(data));
+    }
+    Some(crypto::encrypt(&passphrase, &data))
+}
+
+pub fn download_backup(data: &str, filename: &str) {
+    let window = web_sys::window().unwrap();
+    let document = window.document().unwrap();
+    
+    // Create blob
+    let blob_parts = js_sys::Array::new();
+    blob_parts.push(&wasm_bindgen::JsValue::from_str(data));
+    
+    let blob_options = web_sys::BlobPropertyBag::new();
+    blob_options.set_type("application/json");
+    
+    if let Ok(blob) = Blob::new_with_str_sequence_and_options(&blob_parts, &blob_options) {
+        if let Ok(url) = Url::create_object_url_with_blob(&blob) {
+            // Create download link
+            let a: HtmlAnchorElement = document.create_element("a").unwrap().unchecked_into();
+            a.set_href(&url);
+            a.set_download(filename);
+            a.click();
+            let _ = Url::revoke_object_url(&url);
+        }
+    }
+}
+
+fn reason_name(reason: Option<&str>) -> &'static str {
+    match reason {
+        Some("close") => "ปิดโปรแกรม",
+        Some("daily") => "รายวัน",
+        Some("restore") => "ก่อนกู้คืน",
+        Some("update") => "ก่อนอัปเดต",
+        _ => "สำรองเอง",
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.0} KB", (bytes as f64 / 1024.0).ceil())
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct BackupPanelProps {
+    /// A backup the user chose to restore, to show in `RestorePreview`
+    pub on_restore: Callback<Restoring>,
+}
+
+/// The backups in the desktop app's backups folder
+#[function_component(BackupPanel)]
+pub fn backup_panel(props: &BackupPanelProps) -> Html {
+    let toast = use_context::<ToastContext>();
+    let backups = use_state(|| None::<Vec<tauri_bridge::BackupInfo>>);
+    let expanded = use_state(|| None::<String>);
+    // Bumped to read the folder again
+    let generation = use_state(|| 0u32);
+
+    {
+        let backups = backups.clone();
+        let toast = toast.clone();
+        use_effect_with(*generation, move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::list_backups().await {
+                    Ok(list) => backups.set(Some(list)),
+                    Err(err) => {
+                        backups.set(Some(Vec::new()));
+                        toast_error(&toast, format!("❌ อ่านรายการไฟล์สำรองไม่สำเร็จ: {}", err));
+                    }
+                }
+            });
+            || ()
+        });
+    }
+
+    let on_refresh = {
+        let generation = generation.clone();
+        Callback::from(move |_: MouseEvent| generation.set(*generation + 1))
+    };
+
+    let on_restore = |name: String| {
+        let toast = toast.clone();
+        let on_restore = props.on_restore.clone();
+        Callback::from(move |_: MouseEvent| {
+            let toast = toast.clone();
+            let on_restore = on_restore.clone();
+            let name = name.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let read = tauri_bridge::read_backup(&name).await.and_then(|json| Restoring::read(name, &json));
+                match read {
+                    Ok(backup) => on_restore.emit(backup),
+                    Err(err) => toast_error(&toast, format!("❌ เปิดไฟล์สำรองไม่สำเร็จ: {}", err)),
+                }
+            });
+        })
+    };
+
+    let on_export = |name: String| {
+        let toast = toast.clone();
+        Callback::from(move |_: MouseEvent| {
+            let toast = toast.clone();
+            let name = name.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::read_backup(&name).await.map(seal_backup) {
+                    Ok(Some(Ok(data))) => download_backup(&data, &name),
+                    Ok(Some(Err(err))) | Err(err) => toast_error(&toast, format!("❌ ส่งออกไม่สำเร็จ: {}", err)),
+                    Ok(None) => {}
+                }
+            });
+        })
+    };
+
+    let on_delete = |name: String| {
+        let toast = toast.clone();
+        let generation = generation.clone();
+        Callback::from(move |_: MouseEvent| {
+            let message = format!("ลบไฟล์สำรอง {} ถาวร?", name);
+            if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                return;
+            }
+            let toast = toast.clone();
+            let generation = generation.clone();
+            let name = name.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::delete_backup(&name).await {
+                    Ok(()) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add("🗑️ ลบไฟล์สำรองแล้ว".to_string(), ToastType::Success));
+                        }
+                        generation.set(*generation + 1);
+                    }
+                    Err(err) => toast_error(&toast, format!("❌ ลบไฟล์สำรองไม่สำเร็จ: {}", err)),
+                }
+            });
+        })
+    };
+
+    let rows = match &*backups {
+        None => html! { <p class="text-muted">{ "กำลังโหลด..." }</p> },
+        Some(list) if list.is_empty() => html! {
+            <div class="empty-state">
+                <div class="empty-state-icon">{ "🗂️" }</div>
+                <h3 class="empty-state-title">{ "ยังไม่มีไฟล์สำรอง" }</h3>
+            </div>
+        },
+        Some(list) => html! {
+            <table class="data-table">
+                <thead>
+                    <tr>
+                        <th>{ "วันที่" }</th>
+                        <th>{ "ประเภท" }</th>
+                        <th>{ "ขนาด" }</th>
+                        <th>{ "ข้อมูล" }</th>
+                        <th>{ "" }</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    { for list.iter().map(|info| {
+                        let is_open = expanded.as_deref() == Some(info.name.as_str());
+                        let on_toggle = {
+                            let expanded = expanded.clone();
+                            let name = info.name.clone();
+                            Callback::from(move |_: MouseEvent| expanded.set(if is_open { None } else { Some(name.clone()) }))
+                        };
+                        let contents = match (&info.counts, &info.error) {
+                            (Some(c), _) => html! { format!("ผู้ป่วย {} คน, การรักษา {} รายการ", c.patients, c.records) },
+                            (None, Some(err)) => html! { <span class="text-error">{ format!("⚠️ {}", err) }</span> },
+                            (None, None) => html! { "-" },
+                        };
+                        html! {
+                            <>
+                                <tr>
+                                    <td>{ info.taken_at.map(|t| t.format("%d/%m/%Y %H:%M").to_string()).unwrap_or_else(|| info.name.clone()) }</td>
+                                    <td>{ reason_name(info.reason.as_deref()) }</td>
+                                    <td>{ format_size(info.size) }</td>
+                                    <td>{ contents }</td>
+                                    <td>
+                                        <div class="flex gap-2">
+                                            <button class="btn btn-ghost btn-sm" onclick={on_toggle} disabled={info.counts.is_none()}>
+                                                { if is_open { "ซ่อน" } else { "รายละเอียด" } }
+                                            </button>
+                                            <button class="btn btn-primary btn-sm" onclick={on_restore(info.name.clone())}>{ "กู้คืน" }</button>
+                                            <button class="btn btn-secondary btn-sm" onclick={on_export(info.name.clone())}>{ "ส่งออก" }</button>
+                                            <button class="btn btn-danger btn-sm" onclick={on_delete(info.name.clone())}>{ "ลบ" }</button>
+                                        </div>
+                                    </td>
+                                </tr>
+                                { match (&info.counts, is_open) {
+                                    (Some(c), true) => html! {
+                                        <tr>
+                                            <td colspan="5">
+                                                <div class="grid grid-cols-3 gap-4">
+                                                    <div>{ format!("👥 ผู้ป่วย {} คน", c.patients) }</div>
+                                                    <div>{ format!("📋 การรักษา {} รายการ", c.records) }</div>
+                                                    <div>{ format!("💊 ยา {} รายการ", c.drugs) }</div>
+                                                    <div>{ format!("💸 ค่าใช้จ่าย {} รายการ", c.expenses) }</div>
+                                                    <div>{ format!("📦 ซื้อยาเข้า {} รายการ", c.drug_purchases) }</div>
+                                                    <div>{ format!("🗓️ นัดหมาย {} รายการ", c.appointments) }</div>
+                                                </div>
+                                                <p class="text-muted" style="margin-top: 0.5rem;">{ &info.name }</p>
+                                            </td>
+                                        </tr>
+                                    },
+                                    _ => html! {},
+                                }}
+                            </>
+                        }
+                    })}
+                </tbody>
+            </table>
+        },
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header flex justify-between items-center">
+                <div>
+                    <h3 class="card-title">{ "🗂️ ไฟล์สำรองในเครื่อง" }</h3>
+                    <p class="card-subtitle">{ "ไฟล์ในโฟลเดอร์ backups ทั้งที่สำรองเองและสำรองอัตโนมัติ" }</p>
+                </div>
+                <button class="btn btn-ghost btn-sm" onclick={on_refresh}>{ "🔄 รีเฟรช" }</button>
+            </div>
+            { rows }
+        </div>
+    }
+}
@@ -0,0 +1,126 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use crate::models::BackupSchedule;
+use crate::pages::settings::digits_max;
+use crate::tauri_bridge;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+#[derive(Properties, PartialEq)]
+pub struct BackupScheduleCardProps {
+    pub schedule: BackupSchedule,
+    pub on_change: Callback<BackupSchedule>,
+}
+
+/// When the desktop app backs up, how many backups it keeps, and the external
+/// folder it copies them to
+#[function_component(BackupScheduleCard)]
+pub fn backup_schedule_card(props: &BackupScheduleCardProps) -> Html {
+    let toast = use_context::<ToastContext>();
+    let schedule = &props.schedule;
+
+    // Copy the backups to the external folder now, to try out the drive
+    let on_mirror_now = {
+        let toast = toast.clone();
+        let mirror_dir = schedule.mirror_dir.trim().to_string();
+        Callback::from(move |_: MouseEvent| {
+            let toast = toast.clone();
+            let mirror_dir = mirror_dir.clone();
+            if mirror_dir.is_empty() {
+                toast_error(&toast, "⚠️ กรุณาระบุโฟลเดอร์สำรองภายนอก".to_string());
+                return;
+            }
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::mirror_backups(&mirror_dir).await {
+                    Ok(copied) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(
+                                format!("✅ คัดลอกและตรวจสอบแล้ว {} ไฟล์ ไฟล์สำรองที่ {} ครบถ้วน", copied, mirror_dir),
+                                ToastType::Success
+                            ));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, format!("❌ คัดลอกไปโฟลเดอร์ภายนอกไม่สำเร็จ: {}", err)),
+                }
+            });
+        })
+    };
+
+    let toggle = |label: &'static str, get: fn(&BackupSchedule) -> bool, set: fn(&mut BackupSchedule, bool)| {
+        let checked = get(schedule);
+        let onchange = {
+            let schedule = schedule.clone();
+            let on_change = props.on_change.clone();
+            Callback::from(move |e: Event| {
+                let mut schedule = schedule.clone();
+                set(&mut schedule, e.target_unchecked_into::<HtmlInputElement>().checked());
+                on_change.emit(schedule);
+            })
+        };
+        html! {
+            <label class="flex items-center gap-2" style="cursor: pointer;">
+                <input type="checkbox" {checked} {onchange} />
+                <span>{ label }</span>
+            </label>
+        }
+    };
+    let keep = |label: &'static str, get: fn(&BackupSchedule) -> u32, set: fn(&mut BackupSchedule, u32)| {
+        let value = get(schedule).to_string();
+        let oninput = {
+            let schedule = schedule.clone();
+            let on_change = props.on_change.clone();
+            Callback::from(move |e: InputEvent| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                let filtered = digits_max(&input.value(), 3);
+                let mut schedule = schedule.clone();
+                set(&mut schedule, filtered.parse().unwrap_or(0));
+                on_change.emit(schedule);
+                input.set_value(&filtered);
+            })
+        };
+        html! {
+            <div class="form-group">
+                <label class="form-label">{ label }</label>
+                <input type="text" inputmode="numeric" maxlength="3" {value} {oninput} />
+            </div>
+        }
+    };
+    let on_mirror_dir = {
+        let schedule = schedule.clone();
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: InputEvent| {
+            let mut schedule = schedule.clone();
+            schedule.mirror_dir = e.target_unchecked_into::<HtmlInputElement>().value();
+            on_change.emit(schedule);
+        })
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header">
+                <h3 class="card-title">{ "🕒 สำรองข้อมูลอัตโนมัติ" }</h3>
+                <p class="card-subtitle">{ "เก็บไว้ในโฟลเดอร์ backups ข้างไฟล์ข้อมูล ไฟล์เก่าจะถูกลบตามจำนวนที่กำหนด" }</p>
+            </div>
+            <div class="flex flex-col gap-2 mb-4">
+                { toggle("สำรองทุกครั้งที่ปิดโปรแกรม", |s| s.on_close, |s, v| s.on_close = v) }
+                { toggle("สำรองวันละครั้ง", |s| s.daily, |s, v| s.daily = v) }
+                { toggle("สำรองก่อนกู้คืนข้อมูลและก่อนอัปเดตโปรแกรม", |s| s.before_restore, |s, v| s.before_restore = v) }
+            </div>
+            <div class="grid grid-cols-3 gap-4">
+                { keep("เก็บรายวัน (วัน)", |s| s.keep_daily, |s, v| s.keep_daily = v) }
+                { keep("เก็บรายสัปดาห์ (สัปดาห์)", |s| s.keep_weekly, |s, v| s.keep_weekly = v) }
+                { keep("เก็บรายเดือน (เดือน)", |s| s.keep_monthly, |s, v| s.keep_monthly = v) }
+            </div>
+            <p class="text-muted">{ "ใส่ 0 ทั้งสามช่องหากไม่ต้องการให้ลบไฟล์สำรองเก่า" }</p>
+            <div class="form-group mt-4">
+                <label class="form-label">{ "โฟลเดอร์สำรองภายนอก (USB หรือโฟลเดอร์ในเครือข่าย)" }</label>
+                <div class="flex gap-2">
+                    <input type="text" placeholder="เช่น E:\\ClinicBackup" value={schedule.mirror_dir.clone()} oninput={on_mirror_dir} />
+                    <button type="button" class="btn btn-secondary" onclick={on_mirror_now}>
+                        { "📤 คัดลอกตอนนี้" }
+                    </button>
+                </div>
+                <p class="text-muted" style="margin-top: 0.5rem;">{ "ทุกครั้งที่สำรองข้อมูล จะคัดลอกไฟล์ไปที่นี่ด้วยและอ่านกลับมาตรวจสอบว่าตรงกับต้นฉบับ หากไม่พบโฟลเดอร์จะแจ้งเตือนที่หน้าแรก" }</p>
+            </div>
+        </div>
+    }
+}
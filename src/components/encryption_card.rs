@@ -0,0 +1,149 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use crate::tauri_bridge;
+use crate::crypto;
+use crate::storage::LocalStorageBackend;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+/// Passphrase for clinic_data.json and the backups folder (desktop app only)
+#[function_component(EncryptionCard)]
+pub fn encryption_card() -> Html {
+    let toast = use_context::<ToastContext>();
+    let enabled = use_state(|| false);
+    let busy = use_state(|| false);
+    let current = use_state(String::new);
+    let new = use_state(String::new);
+    let confirm = use_state(String::new);
+
+    {
+        let enabled = enabled.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Ok(status) = tauri_bridge::encryption_status().await {
+                    enabled.set(status.enabled);
+                }
+            });
+            || ()
+        });
+    }
+
+    // An empty `next` turns encryption off
+    let apply = {
+        let toast = toast.clone();
+        let enabled = enabled.clone();
+        let busy = busy.clone();
+        let current = current.clone();
+        let new = new.clone();
+        let confirm = confirm.clone();
+        move |next: String| {
+            let toast = toast.clone();
+            let enabled = enabled.clone();
+            let busy = busy.clone();
+            let current = current.clone();
+            let new = new.clone();
+            let confirm = confirm.clone();
+            busy.set(true);
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::set_passphrase(&current, &next).await {
+                    Ok(skipped) => {
+                        let on = !next.is_empty();
+                        if on {
+                            // The webview's copy from before the data file existed is plaintext
+                            LocalStorageBackend::clear();
+                        }
+                        crypto::set_session_passphrase(on.then_some(next));
+                        enabled.set(on);
+                        current.set(String::new());
+                        new.set(String::new());
+                        confirm.set(String::new());
+                        if let Some(ref t) = toast {
+                            let msg = if on { "🔒 เข้ารหัสข้อมูลเรียบร้อยแล้ว" } else { "🔓 ยกเลิกการเข้ารหัสข้อมูลแล้ว" };
+                            t.dispatch(ToastAction::Add(msg.to_string(), ToastType::Success));
+                        }
+                        if !skipped.is_empty() {
+                            toast_error(&toast, format!("ไฟล์หรือโฟลเดอร์ต่อไปนี้เปิดไม่ได้ จึงยังไม่ได้เปลี่ยนรหัสผ่าน: {}", skipped.join(", ")));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                busy.set(false);
+            });
+        }
+    };
+
+    let on_submit = {
+        let toast = toast.clone();
+        let new = new.clone();
+        let confirm = confirm.clone();
+        let apply = apply.clone();
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+            if new.chars().count() < 8 {
+                toast_error(&toast, "รหัสผ่านต้องมีอย่างน้อย 8 ตัวอักษร".to_string());
+            } else if *new != *confirm {
+                toast_error(&toast, "รหัสผ่านใหม่ทั้งสองช่องไม่ตรงกัน".to_string());
+            } else {
+                apply((*new).clone());
+            }
+        })
+    };
+
+    let on_disable = Callback::from(move |_: MouseEvent| apply(String::new()));
+
+    let input = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: InputEvent| state.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+
+    html! {
+        <form class="card mb-6" onsubmit={on_submit}>
+            <div class="card-header">
+                <h3 class="card-title">{ "🔒 เข้ารหัสข้อมูล" }</h3>
+                <p class="card-subtitle">
+                    { if *enabled {
+                        "ไฟล์ข้อมูลและไฟล์สำรองถูกเข้ารหัสอยู่ ต้องใส่รหัสผ่านทุกครั้งที่เปิดโปรแกรม"
+                    } else {
+                        "ป้องกันเลขบัตรประชาชน ผลการรักษา และประวัติแพ้ยา หากเครื่องหรือไฟล์สำรองหลุดไปถึงผู้อื่น"
+                    } }
+                </p>
+            </div>
+
+            <div class="alert alert-warning mb-4">
+                <span class="alert-icon">{ "⚠️" }</span>
+                <span>{ "หากลืมรหัสผ่าน จะไม่สามารถเปิดข้อมูลได้อีก กรุณาจดเก็บไว้ในที่ปลอดภัย" }</span>
+            </div>
+
+            <div class="grid grid-cols-2 gap-4">
+                { if *enabled {
+                    html! {
+                        <div class="form-group" style="grid-column: 1 / -1;">
+                            <label class="form-label">{ "รหัสผ่านปัจจุบัน" }</label>
+                            <input type="password" value={(*current).clone()} oninput={input(&current)} />
+                        </div>
+                    }
+                } else { html! {} } }
+                <div class="form-group">
+                    <label class="form-label">{ "รหัสผ่านใหม่" }</label>
+                    <input type="password" value={(*new).clone()} oninput={input(&new)} />
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "ยืนยันรหัสผ่านใหม่" }</label>
+                    <input type="password" value={(*confirm).clone()} oninput={input(&confirm)} />
+                </div>
+            </div>
+
+            <div class="flex gap-4">
+                <button type="submit" class="btn btn-primary" disabled={*busy}>
+                    { if *enabled { "🔑 เปลี่ยนรหัสผ่าน" } else { "🔒 เปิดการเข้ารหัส" } }
+                </button>
+                { if *enabled {
+                    html! {
+                        <button type="button" class="btn btn-danger" onclick={on_disable} disabled={*busy}>
+                            { "🔓 ยกเลิกการเข้ารหัส" }
+                        </button>
+                    }
+                } else { html! {} } }
+            </div>
+        </form>
+    }
+}
@@ -0,0 +1,74 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use crate::models::HnFormat;
+use crate::pages::settings::digits_max;
+use crate::store::Store;
+
+pub const MAX_HN_PADDING: u32 = 10;
+
+#[derive(Properties, PartialEq)]
+pub struct HnFormatCardProps {
+    pub format: HnFormat,
+    pub on_change: Callback<HnFormat>,
+}
+
+/// The HN format new patients get, with the next HN it would give
+#[function_component(HnFormatCard)]
+pub fn hn_format_card(props: &HnFormatCardProps) -> Html {
+    let format = &props.format;
+    let on_prefix = {
+        let format = format.clone();
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            on_change.emit(HnFormat { prefix: input.value(), ..format.clone() });
+        })
+    };
+    let on_padding = {
+        let format = format.clone();
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let filtered = digits_max(&input.value(), 2);
+            on_change.emit(HnFormat { padding: filtered.parse().unwrap_or(0), ..format.clone() });
+            input.set_value(&filtered);
+        })
+    };
+    let on_with_year = {
+        let format = format.clone();
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let with_year = e.target_unchecked_into::<HtmlInputElement>().checked();
+            on_change.emit(HnFormat { with_year, ..format.clone() });
+        })
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header">
+                <h3 class="card-title">{ "🔢 เลข HN" }</h3>
+                <p class="card-subtitle">{ "ผู้ป่วยใหม่ได้เลข HN ถัดไปอัตโนมัติตามรูปแบบนี้" }</p>
+            </div>
+            <div class="grid grid-cols-2 gap-4">
+                <div class="form-group">
+                    <label class="form-label">{ "ขึ้นต้นด้วย" }</label>
+                    <input type="text" maxlength="10" value={format.prefix.clone()} oninput={on_prefix} />
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "จำนวนหลักของเลขลำดับ" }</label>
+                    <input type="text" inputmode="numeric" maxlength="2" value={format.padding.to_string()} oninput={on_padding} />
+                </div>
+            </div>
+            <label class="flex items-center gap-2 mb-4" style="cursor: pointer;">
+                <input type="checkbox" checked={format.with_year} onchange={on_with_year} />
+                <span>{ "ใส่ปี พ.ศ. 2 หลัก และเริ่มนับ 1 ใหม่ทุกปี" }</span>
+            </label>
+            <p class="text-muted">
+                { "ผู้ป่วยคนถัดไปจะได้เลข " }
+                <span class="font-mono" style="font-weight: 700;">
+                    { Store::next_hn_in(&HnFormat { padding: format.padding.clamp(1, MAX_HN_PADDING), ..format.clone() }) }
+                </span>
+            </p>
+        </div>
+    }
+}
@@ -0,0 +1,116 @@
+use yew::prelude::*;
+use std::rc::Rc;
+use crate::models::Patient;
+use crate::hn;
+use crate::store::Store;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+// How many patients with an HN out of format the check lists
+const HN_LIST_LIMIT: usize = 50;
+
+/// Check existing HNs against the format, and renumber everyone once if needed
+#[function_component(HnPanel)]
+pub fn hn_panel() -> Html {
+    let toast = use_context::<ToastContext>();
+    // None until the first check
+    let found = use_state(|| None::<Rc<hn::Check>>);
+
+    let on_check = {
+        let found = found.clone();
+        Callback::from(move |_: MouseEvent| found.set(Some(Rc::new(Store::check_hns()))))
+    };
+
+    let on_renumber = {
+        let toast = toast.clone();
+        let found = found.clone();
+        Callback::from(move |_: MouseEvent| {
+            let message = "เปลี่ยนเลข HN ของผู้ป่วยทุกคน (รวมในถังขยะ) ตามลำดับการลงทะเบียนและรูปแบบที่บันทึกไว้\n\
+                           เลข HN บนบัตรและเอกสารที่พิมพ์ไปแล้วจะไม่ตรงกับในระบบ\n\nต้องการดำเนินการต่อหรือไม่?";
+            if !web_sys::window().unwrap().confirm_with_message(message).unwrap_or(false) {
+                return;
+            }
+            let toast = toast.clone();
+            let found = found.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match Store::renumber_hns().await {
+                    Ok(changed) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(format!("🔢 เปลี่ยนเลข HN แล้ว {} คน", changed), ToastType::Success));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                found.set(Some(Rc::new(Store::check_hns())));
+            });
+        })
+    };
+
+    let name = |p: &Patient| {
+        let trash = if p.deleted_at.is_some() { " (ในถังขยะ)" } else { "" };
+        format!("{}{} {}{}", p.title, p.first_name, p.last_name, trash)
+    };
+
+    let body = match found.as_deref() {
+        None => html! {
+            <p class="text-muted">{ "ตรวจว่าเลข HN ของผู้ป่วยทุกคนตรงกับรูปแบบที่ตั้งไว้และไม่ซ้ำกัน" }</p>
+        },
+        Some(found) if found.is_clean() => html! {
+            <p class="text-success">{ "✅ เลข HN ทุกคนตรงรูปแบบและไม่ซ้ำกัน" }</p>
+        },
+        Some(found) => html! {
+            <>
+                { if found.duplicates.is_empty() { html! {} } else { html! {
+                    <>
+                        <h4 style="margin: 1rem 0 0.5rem;">{ format!("🔁 HN ซ้ำกัน ({})", found.duplicates.len()) }</h4>
+                        <table class="data-table">
+                            <tbody>
+                                { for found.duplicates.iter().map(|(hn, patients)| html! {
+                                    <tr>
+                                        <td class="font-mono">{ hn }</td>
+                                        <td>{ patients.iter().map(name).collect::<Vec<_>>().join(", ") }</td>
+                                    </tr>
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                } } }
+                { if found.other_format.is_empty() { html! {} } else { html! {
+                    <>
+                        <h4 style="margin: 1rem 0 0.5rem;">{ format!("✏️ HN ไม่ตรงรูปแบบ ({})", found.other_format.len()) }</h4>
+                        <table class="data-table">
+                            <tbody>
+                                { for found.other_format.iter().take(HN_LIST_LIMIT).map(|p| html! {
+                                    <tr>
+                                        <td class="font-mono">{ if p.hn.trim().is_empty() { "(ว่าง)".to_string() } else { p.hn.clone() } }</td>
+                                        <td>{ name(p) }</td>
+                                    </tr>
+                                }) }
+                            </tbody>
+                        </table>
+                        { if found.other_format.len() > HN_LIST_LIMIT {
+                            html! { <p class="text-muted">{ format!("และอีก {} คน", found.other_format.len() - HN_LIST_LIMIT) }</p> }
+                        } else { html! {} } }
+                    </>
+                } } }
+                <div class="flex gap-4" style="margin-top: 1rem;">
+                    <button type="button" class="btn btn-danger" onclick={on_renumber}>{ "🔢 เรียงเลข HN ใหม่ทั้งหมด" }</button>
+                </div>
+            </>
+        },
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header flex justify-between items-center">
+                <div>
+                    <h3 class="card-title">{ "🔢 ตรวจสอบเลข HN" }</h3>
+                    <p class="card-subtitle">{ "สำหรับเลข HN เดิมที่กรอกเองก่อนเปิดการออกเลขอัตโนมัติ" }</p>
+                </div>
+                <button type="button" class="btn btn-secondary btn-sm" onclick={on_check}>
+                    { if found.is_some() { "🔄 ตรวจอีกครั้ง" } else { "🔍 ตรวจสอบ" } }
+                </button>
+            </div>
+            { body }
+        </div>
+    }
+}
@@ -0,0 +1,350 @@
+use yew::prelude::*;
+use std::rc::Rc;
+use web_sys::HtmlInputElement;
+use yew_router::prelude::Link;
+use crate::thai_id;
+use crate::integrity::{self, Missing};
+use crate::store::Store;
+use crate::Route;
+use crate::storage::Saving;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+#[derive(Properties, PartialEq)]
+struct PickerProps {
+    /// (value, label) pairs
+    options: Rc<Vec<(String, String)>>,
+    #[prop_or_default]
+    selected: Option<String>,
+    button: AttrValue,
+    on_pick: Callback<String>,
+}
+
+/// A drop-down with a button that hands on the chosen value
+#[function_component(Picker)]
+fn picker(props: &PickerProps) -> Html {
+    let choice = use_state(|| props.selected.clone().unwrap_or_default());
+    let onchange = {
+        let choice = choice.clone();
+        Callback::from(move |e: Event| choice.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let onclick = {
+        let choice = choice.clone();
+        let on_pick = props.on_pick.clone();
+        Callback::from(move |_: MouseEvent| {
+            if !choice.is_empty() {
+                on_pick.emit((*choice).clone());
+            }
+        })
+    };
+    html! {
+        <div class="flex gap-2">
+            <select {onchange}>
+                <option value="" selected={choice.is_empty()}>{ "-- เลือก --" }</option>
+                { for props.options.iter().map(|(value, label)| html! {
+                    <option value={value.clone()} selected={*choice == *value}>{ label }</option>
+                }) }
+            </select>
+            <button type="button" class="btn btn-secondary btn-sm" {onclick} disabled={choice.is_empty()}>{ props.button.clone() }</button>
+        </div>
+    }
+}
+
+fn missing_text(missing: Missing, patient_id: &str) -> String {
+    match missing {
+        Missing::Gone => format!("ไม่พบผู้ป่วย (รหัส {})", patient_id),
+        Missing::InTrash => "ผู้ป่วยอยู่ในถังขยะ".to_string(),
+    }
+}
+
+/// Problems across collections, each with the fixes that make sense for it
+#[function_component(IntegrityPanel)]
+pub fn integrity_panel() -> Html {
+    let toast = use_context::<ToastContext>();
+    // None until the first scan
+    let report = use_state(|| None::<Rc<integrity::Report>>);
+
+    let rescan = {
+        let report = report.clone();
+        Callback::from(move |_: ()| report.set(Some(Rc::new(Store::check_integrity()))))
+    };
+    let on_scan = {
+        let rescan = rescan.clone();
+        Callback::from(move |_: MouseEvent| rescan.emit(()))
+    };
+
+    // Run a fix, then scan again once it is saved so the fixed problem drops off the list
+    let apply = {
+        let toast = toast.clone();
+        let rescan = rescan.clone();
+        Callback::from(move |(fix, done): (Saving, &'static str)| {
+            let toast = toast.clone();
+            let rescan = rescan.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match fix.await {
+                    Ok(()) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(done.to_string(), ToastType::Success));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                rescan.emit(());
+            });
+        })
+    };
+
+    let body = match report.as_deref() {
+        None => html! {
+            <p class="text-muted">{ "ตรวจหาประวัติการรักษาหรือนัดหมายที่ไม่มีผู้ป่วย HN ซ้ำ เลขบัตรประชาชนที่ไม่ถูกต้อง และชื่อยาในใบสั่งยาที่ไม่มีในคลังยา" }</p>
+        },
+        Some(report) if report.is_clean() => html! {
+            <p class="text-success">{ "✅ ไม่พบปัญหา ข้อมูลถูกต้องครบถ้วน" }</p>
+        },
+        Some(report) => {
+            let patients: Rc<Vec<(String, String)>> = Rc::new(
+                Store::get_patients()
+                    .into_iter()
+                    .map(|p| (p.id.clone(), format!("{} {}{} {}", p.hn, p.title, p.first_name, p.last_name)))
+                    .collect(),
+            );
+            let drugs: Rc<Vec<(String, String)>> =
+                Rc::new(Store::get_drugs().into_iter().map(|d| (d.name.clone(), d.name)).collect());
+            let heading = |title: &str, count: usize| html! {
+                <h4 style="margin: 1rem 0 0.5rem;">{ format!("{} ({})", title, count) }</h4>
+            };
+
+            let orphan_records = if report.orphan_records.is_empty() { html! {} } else {
+                html! {
+                    <>
+                        { heading("📋 ประวัติการรักษาที่ไม่มีผู้ป่วย", report.orphan_records.len()) }
+                        <table class="data-table">
+                            <tbody>
+                                { for report.orphan_records.iter().map(|o| {
+                                    let relink = {
+                                        let apply = apply.clone();
+                                        let record_id = o.record_id.clone();
+                                        Callback::from(move |patient_id: String| {
+                                            let record_id = record_id.clone();
+                                            let fix = async move { Store::relink_records(std::slice::from_ref(&record_id), &patient_id).await };
+                                            apply.emit((Box::pin(fix), "🔗 ย้ายประวัติการรักษาแล้ว"))
+                                        })
+                                    };
+                                    let restore_patient = {
+                                        let apply = apply.clone();
+                                        let patient_id = o.patient_id.clone();
+                                        Callback::from(move |_: MouseEvent| {
+                                            let patient_id = patient_id.clone();
+                                            let fix = async move { Store::restore("patient", &patient_id).await };
+                                            apply.emit((Box::pin(fix), "♻️ กู้คืนผู้ป่วยแล้ว"))
+                                        })
+                                    };
+                                    let archive = {
+                                        let apply = apply.clone();
+                                        let record_id = o.record_id.clone();
+                                        Callback::from(move |_: MouseEvent| {
+                                            let record_id = record_id.clone();
+                                            let fix = async move { Store::delete_record(&record_id).await };
+                                            apply.emit((Box::pin(fix), "🗑️ ย้ายไปถังขยะแล้ว"))
+                                        })
+                                    };
+                                    html! {
+                                        <tr key={o.record_id.clone()}>
+                                            <td>{ o.date.with_timezone(&chrono::Local).format("%d/%m/%Y").to_string() }</td>
+                                            <td>{ &o.diagnosis }</td>
+                                            <td class="text-error">{ missing_text(o.missing, &o.patient_id) }</td>
+                                            <td>
+                                                <div class="flex gap-2">
+                                                    <Picker options={patients.clone()} button="ย้ายไปผู้ป่วยนี้" on_pick={relink} />
+                                                    { if o.missing == Missing::InTrash {
+                                                        html! { <button class="btn btn-secondary btn-sm" onclick={restore_patient}>{ "กู้คืนผู้ป่วย" }</button> }
+                                                    } else { html! {} } }
+                                                    <button class="btn btn-ghost btn-sm" onclick={archive}>{ "ย้ายไปถังขยะ" }</button>
+                                                </div>
+                                            </td>
+                                        </tr>
+                                    }
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                }
+            };
+
+            let duplicate_hns = if report.duplicate_hns.is_empty() { html! {} } else {
+                html! {
+                    <>
+                        { heading("🔁 HN ซ้ำ", report.duplicate_hns.len()) }
+                        <p class="text-muted">{ "ถ้าเป็นคนเดียวกัน ให้เลือกคนที่จะเก็บไว้แล้วรวมประวัติเข้าด้วยกัน ถ้าเป็นคนละคน ให้แก้ HN ของคนใดคนหนึ่ง" }</p>
+                        <table class="data-table">
+                            <tbody>
+                                { for report.duplicate_hns.iter().flat_map(|dup| dup.patients.iter().map(move |p| (dup, p))).map(|(dup, p)| {
+                                    let merge = {
+                                        let apply = apply.clone();
+                                        let keep = p.clone();
+                                        let others: Vec<String> = dup.patients.iter().filter(|o| o.id != p.id).map(|o| o.id.clone()).collect();
+                                        Callback::from(move |_: MouseEvent| {
+                                            let message = format!(
+                                                "รวมผู้ป่วย HN {} อีก {} คนเข้ากับ {}{} {}?\nประวัติการรักษาและนัดหมายจะย้ายมาที่คนนี้ และคนอื่นจะถูกย้ายไปถังขยะ",
+                                                keep.hn, others.len(), keep.title, keep.first_name, keep.last_name
+                                            );
+                                            if web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                                                let (keep_id, others) = (keep.id.clone(), others.clone());
+                                                let fix = async move { Store::merge_patients(&keep_id, &others).await };
+                                                apply.emit((Box::pin(fix), "🔗 รวมผู้ป่วยแล้ว"));
+                                            }
+                                        })
+                                    };
+                                    html! {
+                                        <tr key={p.id.clone()}>
+                                            <td>{ &dup.hn }</td>
+                                            <td>{ format!("{}{} {}", p.title, p.first_name, p.last_name) }</td>
+                                            <td>{ format!("ลงทะเบียน {}", p.created_at.with_timezone(&chrono::Local).format("%d/%m/%Y")) }</td>
+                                            <td>{ format!("การรักษา {} รายการ", Store::get_records_by_patient(&p.id).len()) }</td>
+                                            <td>
+                                                <div class="flex gap-2">
+                                                    <button class="btn btn-secondary btn-sm" onclick={merge}>{ "เก็บคนนี้ รวมคนอื่นเข้ามา" }</button>
+                                                    <Link<Route> to={Route::EditPatient { id: p.id.clone() }} classes="btn btn-ghost btn-sm">{ "แก้ HN" }</Link<Route>>
+                                                </div>
+                                            </td>
+                                        </tr>
+                                    }
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                }
+            };
+
+            let invalid_citizen_ids = if report.invalid_citizen_ids.is_empty() { html! {} } else {
+                html! {
+                    <>
+                        { heading("🪪 เลขบัตรประชาชนไม่ถูกต้อง", report.invalid_citizen_ids.len()) }
+                        <p class="text-muted">{ "เลขหลักสุดท้ายไม่ตรงกับเลขที่เหลือ มักเกิดจากพิมพ์ผิด ให้ตรวจกับบัตรของผู้ป่วยแล้วแก้ไข" }</p>
+                        <table class="data-table">
+                            <tbody>
+                                { for report.invalid_citizen_ids.iter().map(|p| html! {
+                                    <tr key={p.id.clone()}>
+                                        <td>{ &p.hn }</td>
+                                        <td>{ format!("{}{} {}", p.title, p.first_name, p.last_name) }</td>
+                                        <td class="font-mono">{ &p.citizen_id }</td>
+                                        <td class="text-error">{ thai_id::problem(&p.citizen_id).unwrap_or_default() }</td>
+                                        <td>
+                                            <Link<Route> to={Route::EditPatient { id: p.id.clone() }} classes="btn btn-ghost btn-sm">{ "แก้เลขบัตร" }</Link<Route>>
+                                        </td>
+                                    </tr>
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                }
+            };
+
+            let orphan_appointments = if report.orphan_appointments.is_empty() { html! {} } else {
+                html! {
+                    <>
+                        { heading("🗓️ นัดหมายที่ไม่มีผู้ป่วย", report.orphan_appointments.len()) }
+                        <table class="data-table">
+                            <tbody>
+                                { for report.orphan_appointments.iter().map(|o| {
+                                    let relink = {
+                                        let apply = apply.clone();
+                                        let appointment_id = o.appointment_id.clone();
+                                        Callback::from(move |patient_id: String| {
+                                            let appointment_id = appointment_id.clone();
+                                            let fix = async move {
+                                                let patient = Store::get_patient(&patient_id).ok_or_else(|| "ไม่พบผู้ป่วย".to_string())?;
+                                                Store::relink_appointment(&appointment_id, &patient).await
+                                            };
+                                            apply.emit((Box::pin(fix), "🔗 ย้ายนัดหมายแล้ว"))
+                                        })
+                                    };
+                                    let archive = {
+                                        let apply = apply.clone();
+                                        let appointment_id = o.appointment_id.clone();
+                                        Callback::from(move |_: MouseEvent| {
+                                            let appointment_id = appointment_id.clone();
+                                            let fix = async move { Store::delete_appointment(&appointment_id).await };
+                                            apply.emit((Box::pin(fix), "🗑️ ย้ายไปถังขยะแล้ว"))
+                                        })
+                                    };
+                                    html! {
+                                        <tr key={o.appointment_id.clone()}>
+                                            <td>{ o.date.format("%d/%m/%Y").to_string() }</td>
+                                            <td>{ &o.patient_name }</td>
+                                            <td class="text-error">{ missing_text(o.missing, &o.patient_id) }</td>
+                                            <td>
+                                                <div class="flex gap-2">
+                                                    <Picker options={patients.clone()} button="ย้ายไปผู้ป่วยนี้" on_pick={relink} />
+                                                    <button class="btn btn-ghost btn-sm" onclick={archive}>{ "ย้ายไปถังขยะ" }</button>
+                                                </div>
+                                            </td>
+                                        </tr>
+                                    }
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                }
+            };
+
+            let unknown_drugs = if report.unknown_drugs.is_empty() { html! {} } else {
+                html! {
+                    <>
+                        { heading("💊 ชื่อยาในใบสั่งยาที่ไม่มีในคลังยา", report.unknown_drugs.len()) }
+                        <p class="text-muted">{ "ถ้ายาถูกเปลี่ยนชื่อในคลังยา ให้เลือกชื่อใหม่เพื่อแก้ทุกใบสั่งยา ยาที่จ่ายจากภายนอกคลังปล่อยไว้ได้" }</p>
+                        <table class="data-table">
+                            <tbody>
+                                { for report.unknown_drugs.iter().map(|u| {
+                                    let rename = {
+                                        let apply = apply.clone();
+                                        let from = u.name.clone();
+                                        Callback::from(move |to: String| {
+                                            let from = from.clone();
+                                            let fix = async move { Store::rename_prescribed_drug(&from, &to).await };
+                                            apply.emit((Box::pin(fix), "💊 เปลี่ยนชื่อยาในใบสั่งยาแล้ว"))
+                                        })
+                                    };
+                                    html! {
+                                        <tr key={u.name.clone()}>
+                                            <td>{ &u.name }</td>
+                                            <td>{ format!("ใช้ใน {} ครั้งการรักษา", u.records) }</td>
+                                            <td>
+                                                <Picker options={drugs.clone()} selected={u.suggestion.clone()} button="เปลี่ยนเป็นยานี้" on_pick={rename} />
+                                            </td>
+                                        </tr>
+                                    }
+                                }) }
+                            </tbody>
+                        </table>
+                    </>
+                }
+            };
+
+            html! {
+                <>
+                    <p class="text-error">{ format!("⚠️ พบปัญหา {} รายการ", report.total()) }</p>
+                    { orphan_records }
+                    { duplicate_hns }
+                    { invalid_citizen_ids }
+                    { orphan_appointments }
+                    { unknown_drugs }
+                </>
+            }
+        }
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header flex justify-between items-center">
+                <div>
+                    <h3 class="card-title">{ "🩺 ตรวจสอบความถูกต้องของข้อมูล" }</h3>
+                    <p class="card-subtitle">{ "หาข้อมูลที่เชื่อมโยงกันไม่ถูกต้องและแก้ไขทีละรายการ" }</p>
+                </div>
+                <button type="button" class="btn btn-secondary btn-sm" onclick={on_scan}>
+                    { if report.is_some() { "🔄 ตรวจอีกครั้ง" } else { "🔍 ตรวจสอบ" } }
+                </button>
+            </div>
+            { body }
+        </div>
+    }
+}
@@ -0,0 +1,249 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use wasm_bindgen::JsCast;
+use uuid::Uuid;
+use crate::allergy::GROUPS;
+use crate::interactions::{self, CLASSES, SEVERITIES};
+use crate::models::{InteractionRule, InteractionSeverity};
+use crate::pages::audit::download_csv;
+use crate::store::Store;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+pub fn severity_badge(severity: InteractionSeverity) -> Html {
+    let class = match severity {
+        InteractionSeverity::Minor => "badge badge-accent",
+        InteractionSeverity::Moderate => "badge badge-warning",
+        InteractionSeverity::Major | InteractionSeverity::Contraindicated => "badge badge-error",
+    };
+    html! { <span {class}>{ interactions::severity_name(severity) }</span> }
+}
+
+/// The clinic's drug interaction rules: the list, a row to add or edit one,
+/// and CSV import and export
+#[function_component(InteractionRules)]
+pub fn interaction_rules() -> Html {
+    let toast = use_context::<ToastContext>();
+    let rules = use_state(Store::get_interaction_rules);
+    let editing = use_state(|| None::<String>);
+    let a = use_state(String::new);
+    let b = use_state(String::new);
+    let severity = use_state(InteractionSeverity::default);
+    let advice = use_state(String::new);
+
+    let clear = {
+        let (editing, a, b, severity, advice) = (editing.clone(), a.clone(), b.clone(), severity.clone(), advice.clone());
+        Callback::from(move |_: ()| {
+            editing.set(None);
+            a.set(String::new());
+            b.set(String::new());
+            severity.set(InteractionSeverity::default());
+            advice.set(String::new());
+        })
+    };
+
+    let on_save = {
+        let toast = toast.clone();
+        let rules = rules.clone();
+        let clear = clear.clone();
+        let rule = InteractionRule {
+            id: (*editing).clone().unwrap_or_else(|| Uuid::new_v4().to_string()),
+            a: a.trim().to_string(),
+            b: b.trim().to_string(),
+            severity: *severity,
+            advice: advice.trim().to_string(),
+            updated_at: None,
+        };
+        Callback::from(move |_: MouseEvent| {
+            let toast = toast.clone();
+            let rules = rules.clone();
+            let clear = clear.clone();
+            let rule = rule.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::save_interaction_rule(rule.clone()).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                rules.set(Store::get_interaction_rules());
+                clear.emit(());
+            });
+        })
+    };
+
+    let on_import = {
+        let toast = toast.clone();
+        let rules = rules.clone();
+        Callback::from(move |e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            if let Some(file) = input.files().and_then(|files| files.get(0)) {
+                let reader = web_sys::FileReader::new().unwrap();
+                let reader_clone = reader.clone();
+                let toast = toast.clone();
+                let rules = rules.clone();
+                let onload = wasm_bindgen::closure::Closure::wrap(Box::new(move |_: web_sys::Event| {
+                    let Some(text) = reader_clone.result().ok().and_then(|r| r.as_string()) else { return };
+                    let toast = toast.clone();
+                    let rules = rules.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let imported = match interactions::from_csv(&text) {
+                            Ok(parsed) => Store::import_interaction_rules(parsed).await,
+                            Err(err) => Err(err),
+                        };
+                        match imported {
+                            Ok((added, replaced)) => {
+                                rules.set(Store::get_interaction_rules());
+                                if let Some(t) = &toast {
+                                    t.dispatch(ToastAction::Add(
+                                        format!("✅ นำเข้ากฎใหม่ {} รายการ แทนที่ของเดิม {} รายการ", added, replaced),
+                                        ToastType::Success,
+                                    ));
+                                }
+                            }
+                            Err(err) => toast_error(&toast, format!("❌ นำเข้าไม่สำเร็จ: {}", err)),
+                        }
+                    });
+                }) as Box<dyn FnMut(_)>);
+                reader.set_onload(Some(onload.as_ref().unchecked_ref()));
+                onload.forget();
+                let _ = reader.read_as_text(&file);
+            }
+            // Clear the input so the same file can be picked again
+            input.set_value("");
+        })
+    };
+
+    let on_export = {
+        let rules = rules.clone();
+        Callback::from(move |_: MouseEvent| download_csv(&interactions::to_csv(&rules), "clinic_interactions"))
+    };
+
+    let text_input = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: InputEvent| state.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let drugs = Store::get_drugs();
+
+    html! {
+        <div class="card mt-6">
+            <div class="card-header flex justify-between items-center">
+                <div>
+                    <h3 class="card-title">{ "🔀 ปฏิกิริยาระหว่างยา" }</h3>
+                    <p class="card-subtitle">
+                        { "หน้าบันทึกการรักษาจะเตือนเมื่อสั่งยาคู่ที่ระบุไว้ด้วยกัน หรือสั่งยาที่ตีกับยาที่ผู้ป่วยยังกินอยู่จากครั้งก่อน" }
+                    </p>
+                </div>
+                <div class="flex gap-2">
+                    <label class="btn btn-secondary btn-sm" style="cursor: pointer;">
+                        { "📤 นำเข้า CSV" }
+                        <input type="file" accept=".csv,text/csv" onchange={on_import} style="display: none;" />
+                    </label>
+                    <button type="button" class="btn btn-secondary btn-sm" onclick={on_export} disabled={rules.is_empty()}>
+                        { "📥 ส่งออก CSV" }
+                    </button>
+                </div>
+            </div>
+
+            <p class="text-muted">
+                { "ใส่ชื่อยา หรือรหัสกลุ่มยาจากรายการ เช่น nsaids, anticoagulants ไฟล์ CSV มี 4 คอลัมน์: ยา/กลุ่มยา 1, ยา/กลุ่มยา 2, ความรุนแรง, คำแนะนำ" }
+            </p>
+            <datalist id="interaction-sides">
+                { for CLASSES.iter().chain(GROUPS.iter()).map(|g| html! { <option value={g.key}>{ g.name }</option> }) }
+                { for drugs.iter().map(|d| html! { <option value={d.name.clone()} /> }) }
+            </datalist>
+            <div class="grid grid-cols-2 gap-4 mb-4">
+                <input type="text" list="interaction-sides" value={(*a).clone()} placeholder="ยาหรือกลุ่มยา 1" oninput={text_input(&a)} />
+                <input type="text" list="interaction-sides" value={(*b).clone()} placeholder="ยาหรือกลุ่มยา 2" oninput={text_input(&b)} />
+                <input type="text" value={(*advice).clone()} placeholder="ผลที่เกิดและคำแนะนำ เช่น เสี่ยงเลือดออก หลีกเลี่ยง" oninput={text_input(&advice)} />
+                <div class="flex gap-2">
+                    <select onchange={{
+                        let severity = severity.clone();
+                        Callback::from(move |e: Event| {
+                            let idx: usize = e.target_unchecked_into::<HtmlInputElement>().value().parse().unwrap_or(1);
+                            severity.set(SEVERITIES.get(idx).copied().unwrap_or_default());
+                        })
+                    }}>
+                        { for SEVERITIES.iter().enumerate().map(|(idx, s)| html! {
+                            <option value={idx.to_string()} selected={*severity == *s}>{ interactions::severity_name(*s) }</option>
+                        }) }
+                    </select>
+                    <button type="button" class="btn btn-primary" onclick={on_save}
+                        disabled={a.trim().is_empty() || b.trim().is_empty()}>
+                        { if editing.is_some() { "💾 บันทึก" } else { "➕ เพิ่ม" } }
+                    </button>
+                    { if editing.is_some() { html! {
+                        <button type="button" class="btn btn-ghost" onclick={let clear = clear.clone(); move |_| clear.emit(())}>{ "ยกเลิก" }</button>
+                    } } else { html! {} } }
+                </div>
+            </div>
+
+            { if rules.is_empty() {
+                html! { <p class="text-muted">{ "ยังไม่มีกฎ เพิ่มทีละคู่หรือนำเข้าจากไฟล์ CSV" }</p> }
+            } else { html! {
+                <table class="data-table">
+                    <thead>
+                        <tr>
+                            <th>{ "ยาหรือกลุ่มยา" }</th>
+                            <th>{ "ความรุนแรง" }</th>
+                            <th>{ "คำแนะนำ" }</th>
+                            <th>{ "จัดการ" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for rules.iter().map(|rule| {
+                            let on_edit = {
+                                let rule = rule.clone();
+                                let (editing, a, b, severity, advice) = (editing.clone(), a.clone(), b.clone(), severity.clone(), advice.clone());
+                                Callback::from(move |_: MouseEvent| {
+                                    editing.set(Some(rule.id.clone()));
+                                    a.set(rule.a.clone());
+                                    b.set(rule.b.clone());
+                                    severity.set(rule.severity);
+                                    advice.set(rule.advice.clone());
+                                })
+                            };
+                            let on_delete = {
+                                let toast = toast.clone();
+                                let rules = rules.clone();
+                                let clear = clear.clone();
+                                let id = rule.id.clone();
+                                let message = format!("ลบกฎ {} กับ {}?", rule.a, rule.b);
+                                Callback::from(move |_: MouseEvent| {
+                                    if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                                        return;
+                                    }
+                                    let toast = toast.clone();
+                                    let rules = rules.clone();
+                                    let clear = clear.clone();
+                                    let id = id.clone();
+                                    wasm_bindgen_futures::spawn_local(async move {
+                                        if let Err(err) = Store::delete_interaction_rule(&id).await {
+                                            toast_error(&toast, err);
+                                            return;
+                                        }
+                                        rules.set(Store::get_interaction_rules());
+                                        clear.emit(());
+                                    });
+                                })
+                            };
+                            html! {
+                                <tr key={rule.id.clone()}>
+                                    <td>
+                                        <div class="font-bold">{ interactions::side_name(&rule.a) }</div>
+                                        <div>{ format!("+ {}", interactions::side_name(&rule.b)) }</div>
+                                    </td>
+                                    <td>{ severity_badge(rule.severity) }</td>
+                                    <td>{ &rule.advice }</td>
+                                    <td>
+                                        <div class="flex gap-2">
+                                            <button class="btn btn-secondary btn-sm" onclick={on_edit}>{ "✏️ แก้ไข" }</button>
+                                            <button class="btn btn-danger btn-sm" onclick={on_delete}>{ "🗑️" }</button>
+                                        </div>
+                                    </td>
+                                </tr>
+                            }
+                        }) }
+                    </tbody>
+                </table>
+            } } }
+        </div>
+    }
+}
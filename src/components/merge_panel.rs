@@ -0,0 +1,189 @@
+use yew::prelude::*;
+use std::rc::Rc;
+use web_sys::HtmlInputElement;
+use crate::models::Patient;
+use crate::duplicates;
+use crate::store::Store;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+// How many likely duplicate pairs the search lists at once
+const DUPLICATE_LIST_LIMIT: usize = 50;
+
+/// Patients registered more than once, and merging them into one
+#[function_component(MergePanel)]
+pub fn merge_panel() -> Html {
+    let toast = use_context::<ToastContext>();
+    // None until the first search
+    let pairs = use_state(|| None::<Rc<Vec<duplicates::Pair>>>);
+    // The two patients picked by hand for a merge the search didn't find
+    let keep_id = use_state(String::new);
+    let other_id = use_state(String::new);
+
+    let rescan = {
+        let pairs = pairs.clone();
+        Callback::from(move |_: ()| pairs.set(Some(Rc::new(Store::duplicate_pairs()))))
+    };
+    let on_scan = {
+        let rescan = rescan.clone();
+        Callback::from(move |_: MouseEvent| rescan.emit(()))
+    };
+
+    // Ask, merge `other` into `keep`, then search again if a search was shown
+    let merge = {
+        let toast = toast.clone();
+        let pairs = pairs.clone();
+        let rescan = rescan.clone();
+        Callback::from(move |(keep, other): (Patient, Patient)| {
+            let message = format!(
+                "รวม {} {}{} {} เข้ากับ {} {}{} {}?\nประวัติการรักษาและนัดหมายจะย้ายมาที่ {} และ {} จะถูกย้ายไปถังขยะ",
+                other.hn, other.title, other.first_name, other.last_name,
+                keep.hn, keep.title, keep.first_name, keep.last_name,
+                keep.hn, other.hn
+            );
+            if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                return;
+            }
+            let toast = toast.clone();
+            let pairs = pairs.clone();
+            let rescan = rescan.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match Store::merge_patients(&keep.id, std::slice::from_ref(&other.id)).await {
+                    Ok(()) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(format!("🔗 รวมผู้ป่วยเข้ากับ {} แล้ว", keep.hn), ToastType::Success));
+                        }
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                if pairs.is_some() {
+                    rescan.emit(());
+                }
+            });
+        })
+    };
+
+    let describe = |p: &Patient| html! {
+        <>
+            <div class="font-semibold">{ format!("{} {}{} {}", p.hn, p.title, p.first_name, p.last_name) }</div>
+            <div class="text-muted" style="font-size: 0.85rem;">
+                { format!(
+                    "ลงทะเบียน {} • การรักษา {} ครั้ง",
+                    p.created_at.with_timezone(&chrono::Local).format("%d/%m/%Y"),
+                    Store::get_records_by_patient(&p.id).len()
+                ) }
+            </div>
+        </>
+    };
+
+    let found = match pairs.as_deref() {
+        None => html! {
+            <p class="text-muted">{ "ค้นหาผู้ป่วยที่มีเลขบัตรประชาชน เบอร์โทร หรือชื่อเดียวกัน หรือชื่อคล้ายกันและวันเกิดตรงกัน" }</p>
+        },
+        Some(pairs) if pairs.is_empty() => html! {
+            <p class="text-success">{ "✅ ไม่พบผู้ป่วยที่น่าจะซ้ำกัน" }</p>
+        },
+        Some(pairs) => html! {
+            <>
+                <p class="text-warning">{ format!("⚠️ พบ {} คู่ที่อาจเป็นคนเดียวกัน เลือกคนที่จะเก็บไว้", pairs.len()) }</p>
+                <table class="data-table">
+                    <tbody>
+                        { for pairs.iter().take(DUPLICATE_LIST_LIMIT).map(|pair| {
+                            let keep_first = {
+                                let merge = merge.clone();
+                                let (keep, other) = (pair.first.clone(), pair.second.clone());
+                                Callback::from(move |_: MouseEvent| merge.emit((keep.clone(), other.clone())))
+                            };
+                            let keep_second = {
+                                let merge = merge.clone();
+                                let (keep, other) = (pair.second.clone(), pair.first.clone());
+                                Callback::from(move |_: MouseEvent| merge.emit((keep.clone(), other.clone())))
+                            };
+                            html! {
+                                <tr key={format!("{}-{}", pair.first.id, pair.second.id)}>
+                                    <td>{ describe(&pair.first) }</td>
+                                    <td>{ describe(&pair.second) }</td>
+                                    <td>
+                                        { for pair.reasons.iter().map(|r| html! {
+                                            <span class="badge badge-warning" style="margin-right: 5px;">{ r.describe() }</span>
+                                        }) }
+                                    </td>
+                                    <td>
+                                        <div class="flex gap-2">
+                                            <button type="button" class="btn btn-secondary btn-sm" onclick={keep_first}>{ format!("เก็บ {}", pair.first.hn) }</button>
+                                            <button type="button" class="btn btn-secondary btn-sm" onclick={keep_second}>{ format!("เก็บ {}", pair.second.hn) }</button>
+                                        </div>
+                                    </td>
+                                </tr>
+                            }
+                        }) }
+                    </tbody>
+                </table>
+                { if pairs.len() > DUPLICATE_LIST_LIMIT {
+                    html! { <p class="text-muted">{ format!("และอีก {} คู่ รวมคู่ที่แสดงแล้วค้นหาอีกครั้ง", pairs.len() - DUPLICATE_LIST_LIMIT) }</p> }
+                } else { html! {} } }
+            </>
+        },
+    };
+
+    let patients = Store::get_patients();
+    let select = |chosen: &UseStateHandle<String>| {
+        let onchange = {
+            let chosen = chosen.clone();
+            Callback::from(move |e: Event| chosen.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+        };
+        html! {
+            <select {onchange}>
+                <option value="" selected={chosen.is_empty()}>{ "-- เลือก --" }</option>
+                { for patients.iter().map(|p| html! {
+                    <option value={p.id.clone()} selected={**chosen == p.id}>
+                        { format!("{} {}{} {}", p.hn, p.title, p.first_name, p.last_name) }
+                    </option>
+                }) }
+            </select>
+        }
+    };
+    let picked = patients.iter().find(|p| p.id == *keep_id).zip(patients.iter().find(|p| p.id == *other_id));
+    let on_merge_picked = {
+        let merge = merge.clone();
+        let picked = picked.map(|(keep, other)| (keep.clone(), other.clone()));
+        let keep_id = keep_id.clone();
+        let other_id = other_id.clone();
+        Callback::from(move |_: MouseEvent| {
+            if let Some(picked) = picked.clone() {
+                merge.emit(picked);
+                keep_id.set(String::new());
+                other_id.set(String::new());
+            }
+        })
+    };
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header flex justify-between items-center">
+                <div>
+                    <h3 class="card-title">{ "👥 ผู้ป่วยซ้ำ" }</h3>
+                    <p class="card-subtitle">{ "รวมผู้ป่วยที่ลงทะเบียนซ้ำ ประวัติการรักษาและนัดหมายจะย้ายไปอยู่กับคนที่เก็บไว้" }</p>
+                </div>
+                <button type="button" class="btn btn-secondary btn-sm" onclick={on_scan}>
+                    { if pairs.is_some() { "🔄 ค้นหาอีกครั้ง" } else { "🔍 ค้นหาผู้ป่วยซ้ำ" } }
+                </button>
+            </div>
+            { found }
+            <h4 style="margin: 1rem 0 0.5rem;">{ "รวมผู้ป่วยเอง" }</h4>
+            <div class="grid grid-cols-2 gap-4">
+                <div class="form-group">
+                    <label class="form-label">{ "เก็บผู้ป่วยคนนี้ไว้" }</label>
+                    { select(&keep_id) }
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "รวมคนนี้เข้าไป (ย้ายไปถังขยะ)" }</label>
+                    { select(&other_id) }
+                </div>
+            </div>
+            <button type="button" class="btn btn-secondary" onclick={on_merge_picked}
+                disabled={picked.is_none() || *keep_id == *other_id}>
+                { "🔗 รวมผู้ป่วย" }
+            </button>
+        </div>
+    }
+}
@@ -1,7 +1,33 @@
 pub mod toast;
+pub mod allergies;
+pub mod interactions;
 pub mod sidebar;
+pub mod restore_preview;
+pub mod backup_panel;
+pub mod encryption_card;
+pub mod sync_card;
+pub mod integrity_panel;
+pub mod hn_panel;
+pub mod merge_panel;
+pub mod archive_panel;
+pub mod hn_format_card;
+pub mod backup_schedule_card;
+#[allow(dead_code)]
 pub mod animations;
 
-pub use toast::{ToastProvider, ToastType, ToastContext, ToastAction};
+pub use toast::{ToastProvider, ToastType, ToastContext, ToastAction, toast_error};
 pub use sidebar::Sidebar;
+pub use allergies::AllergyEditor;
+pub use interactions::{InteractionRules, severity_badge};
+pub use restore_preview::{RestorePreview, Restoring, open_backup};
+pub use backup_panel::{BackupPanel, seal_backup, download_backup};
+pub use encryption_card::EncryptionCard;
+pub use sync_card::SyncCard;
+pub use integrity_panel::IntegrityPanel;
+pub use hn_panel::HnPanel;
+pub use merge_panel::MergePanel;
+pub use archive_panel::ArchivePanel;
+pub use hn_format_card::{HnFormatCard, MAX_HN_PADDING};
+pub use backup_schedule_card::BackupScheduleCard;
+#[allow(unused_imports)]
 pub use animations::{SuccessAnimation, EmptyState};
@@ -0,0 +1,221 @@
+use yew::prelude::*;
+use std::collections::HashSet;
+use std::rc::Rc;
+use crate::archive::{self, Archive};
+use crate::audit;
+use crate::store::Store;
+use crate::tauri_bridge;
+use crate::crypto;
+use crate::backup;
+use crate::storage::ClinicData;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+pub fn prompt(message: &str) -> Option<String> {
+    web_sys::window()?.prompt_with_message(message).ok().flatten()
+}
+
+/// The backup's JSON, asking for the passphrase if the file is encrypted
+pub fn open_backup(text: &str) -> Result<String, String> {
+    if !crypto::is_encrypted(text) {
+        return Ok(text.to_string());
+    }
+    if let Some(plain) = crypto::session_passphrase().and_then(|p| crypto::decrypt(&p, text).ok()) {
+        return Ok(plain);
+    }
+    let passphrase = prompt("ไฟล์สำรองนี้ถูกเข้ารหัส กรุณาใส่รหัสผ่าน")
+        .ok_or_else(|| "ยกเลิกการกู้คืน".to_string())?;
+    crypto::decrypt(&passphrase, text)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RestoreMode {
+    /// Add what's missing and take the backup's version where it is newer
+    Merge,
+    /// Make the data exactly what is in the backup
+    Replace,
+}
+
+/// A backup file read back for `RestorePreview`
+#[derive(Clone, PartialEq)]
+pub struct Restoring {
+    file_name: String,
+    data: Rc<ClinicData>,
+    archives: Rc<Vec<Archive>>,
+}
+
+impl Restoring {
+    pub fn read(file_name: String, json: &str) -> Result<Self, String> {
+        Ok(Self {
+            file_name,
+            data: Rc::new(backup::read(json)?),
+            archives: Rc::new(backup::read_archives(json)?),
+        })
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct RestorePreviewProps {
+    pub backup: Restoring,
+    pub on_close: Callback<()>,
+}
+
+/// What restoring a backup would change, before anything is written
+#[function_component(RestorePreview)]
+pub fn restore_preview(props: &RestorePreviewProps) -> Html {
+    let toast = use_context::<ToastContext>();
+    let mode = use_state(|| RestoreMode::Merge);
+    // What is archived already, here or in the backup, doesn't go back into the active data
+    let archived = use_state(|| None::<Rc<HashSet<String>>>);
+    {
+        let archived = archived.clone();
+        let toast = toast.clone();
+        let incoming = props.backup.archives.clone();
+        use_effect_with(incoming, move |incoming| {
+            let incoming = incoming.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::load_all().await {
+                    Ok(archives) => {
+                        let ids = archives.iter().chain(incoming.iter()).flat_map(Archive::ids).collect();
+                        archived.set(Some(Rc::new(ids)));
+                    }
+                    Err(err) => toast_error(&toast, format!("❌ อ่านข้อมูลเก่าที่เก็บถาวรไม่สำเร็จ: {}", err)),
+                }
+            });
+            || ()
+        });
+    }
+    let Some(archived) = (*archived).clone() else {
+        return html! {};
+    };
+    let backup = Rc::new(archive::without_archived((*props.backup.data).clone(), &archived));
+    let (_, tallies) = backup::merge(&Store::snapshot(), &backup);
+
+    let on_confirm = {
+        let toast = toast.clone();
+        let archives = props.backup.archives.clone();
+        let mode = *mode;
+        Callback::from(move |_: MouseEvent| {
+            let current = Store::snapshot();
+            let restored = match mode {
+                RestoreMode::Merge => backup::merge(&current, &backup).0,
+                RestoreMode::Replace => {
+                    let message = "ข้อมูลปัจจุบันทั้งหมดจะถูกแทนที่ด้วยข้อมูลในไฟล์สำรอง ยืนยันหรือไม่?";
+                    if !web_sys::window().unwrap().confirm_with_message(message).unwrap_or(false) {
+                        return;
+                    }
+                    backup::replace(&current, (*backup).clone())
+                }
+            };
+            let toast = toast.clone();
+            let summary = backup::summary(&backup);
+            let archives = archives.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                // The desktop app keeps a copy of what is about to be overwritten
+                if tauri_bridge::is_tauri() {
+                    if let Err(err) = tauri_bridge::backup_before_restore().await {
+                        toast_error(&toast, format!("❌ สำรองข้อมูลก่อนกู้คืนไม่สำเร็จ จึงยังไม่กู้คืน: {}", err));
+                        return;
+                    }
+                }
+                if let Err(err) = archive::restore(&archives).await {
+                    toast_error(&toast, format!("❌ กู้คืนข้อมูลเก่าที่เก็บถาวรไม่สำเร็จ จึงยังไม่กู้คืน: {}", err));
+                    return;
+                }
+                match Store::replace_data(restored).await {
+                    Ok(()) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add(
+                                format!("✅ กู้คืนข้อมูลเรียบร้อย! {}", summary),
+                                ToastType::Success
+                            ));
+                        }
+                        // Reload page to show restored data
+                        let _ = web_sys::window().unwrap().location().reload();
+                    }
+                    Err(err) => toast_error(&toast, format!("❌ กู้คืนล้มเหลว: {}", err)),
+                }
+            });
+        })
+    };
+
+    let on_cancel = {
+        let on_close = props.on_close.clone();
+        Callback::from(move |_: MouseEvent| on_close.emit(()))
+    };
+
+    let mode_option = |value: RestoreMode, label: &'static str, hint: &'static str| {
+        let onchange = {
+            let mode = mode.clone();
+            Callback::from(move |_: Event| mode.set(value))
+        };
+        html! {
+            <label class="flex items-center gap-2" style="cursor: pointer;">
+                <input type="radio" name="restore-mode" checked={*mode == value} {onchange} />
+                <span><strong>{ label }</strong>{ " — " }{ hint }</span>
+            </label>
+        }
+    };
+
+    let unchanged = tallies.iter().all(backup::Tally::is_unchanged);
+
+    html! {
+        <div class="card mb-6">
+            <div class="card-header">
+                <h3 class="card-title">{ "🔍 ตรวจสอบก่อนกู้คืน" }</h3>
+                <p class="card-subtitle">{ format!("ไฟล์ {} ยังไม่มีการบันทึกข้อมูลใดๆ จนกว่าจะกดยืนยัน", props.backup.file_name) }</p>
+            </div>
+
+            { if props.backup.archives.is_empty() { html! {} } else {
+                let years: Vec<String> = props.backup.archives.iter().map(|a| (a.year + 543).to_string()).collect();
+                html! {
+                    <div class="alert alert-warning mb-4">
+                        <span class="alert-icon">{ "📦" }</span>
+                        <span>{ format!("มีข้อมูลเก่าที่เก็บถาวรปี {} จะรวมเข้ากับข้อมูลเก่าที่มีอยู่ทั้งสองแบบ", years.join(", ")) }</span>
+                    </div>
+                }
+            } }
+
+            <table class="data-table mb-4">
+                <thead>
+                    <tr>
+                        <th>{ "ประเภท" }</th>
+                        <th>{ "ใหม่" }</th>
+                        <th>{ "ไฟล์สำรองใหม่กว่า" }</th>
+                        <th>{ "ขัดแย้ง" }</th>
+                        <th>{ "ไม่มีในไฟล์สำรอง" }</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    { for tallies.iter().map(|t| html! {
+                        <tr>
+                            <td>{ audit::entity_name(t.entity) }</td>
+                            <td>{ t.added }</td>
+                            <td>{ t.updated }</td>
+                            <td>{ if t.conflicts > 0 { html! { <span class="badge badge-warning">{ t.conflicts }</span> } } else { html! { 0 } } }</td>
+                            <td>{ t.missing }</td>
+                        </tr>
+                    })}
+                </tbody>
+            </table>
+
+            { if unchanged {
+                html! {
+                    <div class="alert alert-success mb-4">
+                        <span class="alert-icon">{ "✅" }</span>
+                        <span>{ "ข้อมูลในไฟล์สำรองตรงกับข้อมูลปัจจุบันทุกรายการ" }</span>
+                    </div>
+                }
+            } else { html! {} } }
+
+            <div class="flex flex-col gap-2 mb-4">
+                { mode_option(RestoreMode::Merge, "รวมข้อมูล", "เพิ่มรายการที่ยังไม่มี และใช้รุ่นที่ใหม่กว่า รายการที่ขัดแย้งจะเก็บข้อมูลปัจจุบันไว้ ไม่ตัดสต็อกยาซ้ำ") }
+                { mode_option(RestoreMode::Replace, "แทนที่ทั้งหมด", "ลบข้อมูลปัจจุบันแล้วใช้ข้อมูลในไฟล์สำรองแทน รวมถึงการตั้งค่า") }
+            </div>
+
+            <div class="flex gap-4">
+                <button class="btn btn-primary" onclick={on_confirm}>{ "✅ ยืนยันกู้คืน" }</button>
+                <button class="btn btn-secondary" onclick={on_cancel}>{ "ยกเลิก" }</button>
+            </div>
+        </div>
+    }
+}
@@ -100,6 +100,14 @@ pub fn sidebar() -> Html {
                         <span class="nav-link-icon">{ "⚙️" }</span>
                         { "ตั้งค่าคลินิก" }
                     </Link<Route>>
+                    <Link<Route> to={Route::Audit} classes={nav_class(&Route::Audit)}>
+                        <span class="nav-link-icon">{ "📜" }</span>
+                        { "ประวัติการแก้ไข" }
+                    </Link<Route>>
+                    <Link<Route> to={Route::RecycleBin} classes={nav_class(&Route::RecycleBin)}>
+                        <span class="nav-link-icon">{ "🗑️" }</span>
+                        { "ถังขยะ" }
+                    </Link<Route>>
                 </div>
             </nav>
             
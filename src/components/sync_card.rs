@@ -0,0 +1,190 @@
+use yew::prelude::*;
+use web_sys::HtmlInputElement;
+use crate::sync;
+use crate::tauri_bridge::{self, SyncConfig};
+use crate::pages::settings::digits_max;
+use super::{ToastContext, ToastAction, ToastType, toast_error};
+
+/// LAN sync with a second clinic computer (desktop app only, see sync.rs)
+#[function_component(SyncCard)]
+pub fn sync_card() -> Html {
+    let toast = use_context::<ToastContext>();
+    let config = use_state(SyncConfig::default);
+    let busy = use_state(|| false);
+
+    let reload = {
+        let config = config.clone();
+        move || {
+            let config = config.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Ok(saved) = tauri_bridge::get_sync_config().await {
+                    config.set(saved);
+                }
+            });
+        }
+    };
+    {
+        let reload = reload.clone();
+        use_effect_with((), move |_| {
+            reload();
+            || ()
+        });
+    }
+
+    let on_submit = {
+        let toast = toast.clone();
+        let config = config.clone();
+        let busy = busy.clone();
+        let reload = reload.clone();
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+            if config.enabled && config.key.chars().count() < 8 {
+                toast_error(&toast, "รหัสซิงก์ต้องมีอย่างน้อย 8 ตัวอักษร".to_string());
+                return;
+            }
+            if config.port == 0 {
+                toast_error(&toast, "กรุณาระบุพอร์ต".to_string());
+                return;
+            }
+            if !config.bind.is_empty() && config.bind.parse::<std::net::IpAddr>().is_err() {
+                toast_error(&toast, "ที่อยู่ IP ของเครื่องนี้ไม่ถูกต้อง".to_string());
+                return;
+            }
+            let toast = toast.clone();
+            let config = (*config).clone();
+            let busy = busy.clone();
+            let reload = reload.clone();
+            busy.set(true);
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::save_sync_config(&config).await {
+                    Ok(()) => {
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add("💾 บันทึกการตั้งค่าซิงก์เรียบร้อยแล้ว".to_string(), ToastType::Success));
+                        }
+                        reload();
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                busy.set(false);
+            });
+        })
+    };
+
+    let on_sync = {
+        let toast = toast.clone();
+        let busy = busy.clone();
+        Callback::from(move |_: MouseEvent| {
+            let toast = toast.clone();
+            let busy = busy.clone();
+            let reload = reload.clone();
+            busy.set(true);
+            wasm_bindgen_futures::spawn_local(async move {
+                // What came back waits in the inbox; apply it now rather than at the next poll
+                let synced = match tauri_bridge::sync_now().await {
+                    Ok(_) => sync::receive().await,
+                    Err(err) => Err(err),
+                };
+                match synced {
+                    Ok(applied) => {
+                        if let Some(ref t) = toast {
+                            let msg = format!("🔄 ซิงก์เรียบร้อย ข้อมูลเปลี่ยน {} รายการ", applied.changed);
+                            t.dispatch(ToastAction::Add(msg, ToastType::Success));
+                        }
+                        if let Some(msg) = applied.clash_message() {
+                            toast_error(&toast, msg);
+                        }
+                    }
+                    Err(err) => toast_error(&toast, err),
+                }
+                reload();
+                busy.set(false);
+            });
+        })
+    };
+
+    let edit = |set: fn(&mut SyncConfig, String)| {
+        let config = config.clone();
+        Callback::from(move |e: InputEvent| {
+            let mut next = (*config).clone();
+            set(&mut next, e.target_unchecked_into::<HtmlInputElement>().value());
+            config.set(next);
+        })
+    };
+    let on_enabled = {
+        let config = config.clone();
+        Callback::from(move |e: Event| {
+            let mut next = (*config).clone();
+            next.enabled = e.target_unchecked_into::<HtmlInputElement>().checked();
+            config.set(next);
+        })
+    };
+
+    let status = &config.status;
+    html! {
+        <form class="card mb-6" onsubmit={on_submit}>
+            <div class="card-header">
+                <h3 class="card-title">{ "🔄 ซิงก์ข้อมูลระหว่างเครื่อง" }</h3>
+                <p class="card-subtitle">
+                    { "ใช้ข้อมูลชุดเดียวกันบนสองเครื่องในเครือข่ายเดียวกัน เช่น เครื่องหน้าห้องตรวจและเครื่องห้องยา ตั้งรหัสซิงก์ให้ตรงกันทั้งสองเครื่อง" }
+                </p>
+            </div>
+
+            <label class="flex items-center gap-2 mb-4" style="cursor: pointer;">
+                <input type="checkbox" checked={config.enabled} onchange={on_enabled} />
+                <span>{ "เปิดการซิงก์บนเครื่องนี้" }</span>
+            </label>
+
+            <div class="grid grid-cols-2 gap-4">
+                <div class="form-group">
+                    <label class="form-label">{ "IP ของเครื่องนี้ที่รับการซิงก์" }</label>
+                    <input type="text" placeholder="192.168.1.10" value={config.bind.clone()}
+                        oninput={edit(|c, v| c.bind = v.trim().to_string())} />
+                    <p class="text-muted" style="margin-top: 0.5rem;">{ "เว้นว่างไว้เพื่อรับจากทุกเครือข่ายที่เครื่องนี้ต่ออยู่" }</p>
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "พอร์ตของเครื่องนี้" }</label>
+                    <input type="text" inputmode="numeric" maxlength="5" value={config.port.to_string()}
+                        oninput={edit(|c, v| c.port = digits_max(&v, 5).parse().unwrap_or(0))} />
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "เครื่องที่จะซิงก์ด้วย (IP:พอร์ต)" }</label>
+                    <input type="text" placeholder="192.168.1.20:47825" value={config.peer.clone()}
+                        oninput={edit(|c, v| c.peer = v.trim().to_string())} />
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "รหัสซิงก์" }</label>
+                    <input type="password" value={config.key.clone()} oninput={edit(|c, v| c.key = v)} />
+                </div>
+                <div class="form-group">
+                    <label class="form-label">{ "ซิงก์อัตโนมัติทุก (นาที)" }</label>
+                    <input type="text" inputmode="numeric" maxlength="3" value={config.interval_minutes.to_string()}
+                        oninput={edit(|c, v| c.interval_minutes = digits_max(&v, 3).parse().unwrap_or(0))} />
+                    <p class="text-muted" style="margin-top: 0.5rem;">{ "ใส่ 0 หากต้องการซิงก์เมื่อกดปุ่มเท่านั้น" }</p>
+                </div>
+            </div>
+
+            <p class="text-muted mb-4">
+                { match status.last_sync {
+                    Some(at) => format!("ซิงก์ล่าสุด {}", at.with_timezone(&chrono::Local).format("%d/%m/%Y %H:%M")),
+                    None => "ยังไม่เคยซิงก์".to_string(),
+                } }
+            </p>
+            { if let Some(err) = &status.last_error {
+                html! {
+                    <div class="alert alert-warning mb-4">
+                        <span class="alert-icon">{ "⚠️" }</span>
+                        <span>{ format!("ซิงก์ครั้งล่าสุดไม่สำเร็จ: {}", err) }</span>
+                    </div>
+                }
+            } else { html! {} } }
+
+            <div class="flex gap-4">
+                <button type="submit" class="btn btn-primary" disabled={*busy}>{ "💾 บันทึกการตั้งค่าซิงก์" }</button>
+                <button type="button" class="btn btn-secondary" onclick={on_sync}
+                    disabled={*busy || !config.enabled || config.peer.is_empty()}>
+                    { if *busy { "⏳ กำลังซิงก์..." } else { "🔄 ซิงก์ตอนนี้" } }
+                </button>
+            </div>
+        </form>
+    }
+}
@@ -136,6 +136,13 @@ pub fn toast_provider(props: &ToastProviderProps) -> Html {
     }
 }
 
+/// Show an error from the Store, e.g. a save that didn't go through
+pub fn toast_error(toast: &Option<ToastContext>, err: String) {
+    if let Some(t) = toast {
+        t.dispatch(ToastAction::Add(err, ToastType::Error));
+    }
+}
+
 // Hook to use toast
 #[hook]
 pub fn use_toast() -> Callback<(String, ToastType)> {
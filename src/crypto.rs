@@ -0,0 +1,63 @@
+// Passphrase encryption for backup files, in the same envelope format that
+// src-tauri/src/crypto.rs uses for clinic_data.json and the backups folder:
+//   { "clinic_encrypted": 1, "kdf": { "m_cost", "t_cost", "p_cost", "salt" }, "nonce": "...", "data": "..." }
+// Argon2id derives the key, AES-256-GCM seals the data, so either side can open
+// a file the other wrote.
+
+use std::cell::RefCell;
+
+// The envelope is the desktop app's own module, compiled in here too so the two
+// sides can't drift apart. Its `Session` is only used by the desktop app.
+#[allow(dead_code)]
+#[path = "../src-tauri/src/crypto.rs"]
+mod envelope;
+
+use envelope::{Key, KdfParams};
+
+thread_local! {
+    /// The passphrase the desktop app was unlocked with, so downloaded backups
+    /// are sealed the same way as the files on disk
+    static SESSION_PASSPHRASE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_session_passphrase(passphrase: Option<String>) {
+    SESSION_PASSPHRASE.with(|p| *p.borrow_mut() = passphrase);
+}
+
+pub fn session_passphrase() -> Option<String> {
+    SESSION_PASSPHRASE.with(|p| p.borrow().clone())
+}
+
+pub fn is_encrypted(json: &str) -> bool {
+    envelope::is_encrypted(json)
+}
+
+pub fn encrypt(passphrase: &str, plaintext: &str) -> Result<String, String> {
+    encrypt_with(passphrase, plaintext, KdfParams::generate())
+}
+
+fn encrypt_with(passphrase: &str, plaintext: &str, kdf: KdfParams) -> Result<String, String> {
+    Key::derive(passphrase, kdf)?.encrypt(plaintext)
+}
+
+pub fn decrypt(passphrase: &str, json: &str) -> Result<String, String> {
+    let kdf = envelope::envelope_params(json).ok_or_else(|| "Invalid encrypted file".to_string())?;
+    Key::derive(passphrase, kdf)?.decrypt(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_wrong_passphrase() {
+        // Small parameters keep the test fast; the format is the same
+        let kdf = KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() };
+        let sealed = encrypt_with("รหัสผ่าน", r#"{"patients":[]}"#, kdf).unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(!is_encrypted(r#"{"patients":[]}"#));
+        assert_eq!(decrypt("รหัสผ่าน", &sealed).unwrap(), r#"{"patients":[]}"#);
+        assert!(decrypt("อื่น", &sealed).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Possible duplicate patients
+// The same person registered twice usually gets a new HN, so an HN check never
+// sees it. Two patients look like the same person when they share a citizen ID
+// or a phone number, have the same full name, or have names that differ by a
+// typo and the same birth date. Names are compared without spaces and Thai
+// tone marks, which are the parts most often typed differently.
+// Registration warns about matches for the patient being entered; Settings
+// lists every likely pair so they can be merged (Store::merge_patients).
+
+use std::collections::{BTreeMap, HashMap};
+use crate::models::{Patient, SoftDelete};
+
+/// Why two patients look like the same person, strongest first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Reason {
+    CitizenId,
+    Phone,
+    SameName,
+    SimilarNameAndBirthDate,
+}
+
+impl Reason {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Reason::CitizenId => "เลขบัตรประชาชนเดียวกัน",
+            Reason::Phone => "เบอร์โทรเดียวกัน",
+            Reason::SameName => "ชื่อ-นามสกุลเดียวกัน",
+            Reason::SimilarNameAndBirthDate => "ชื่อคล้ายกันและวันเกิดเดียวกัน",
+        }
+    }
+}
+
+/// A patient who may be the one being compared against, and why
+#[derive(Clone, PartialEq, Debug)]
+pub struct Match {
+    pub patient: Patient,
+    pub reasons: Vec<Reason>,
+}
+
+/// Two registered patients who may be the same person, registered first to last
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pair {
+    pub first: Patient,
+    pub second: Patient,
+    pub reasons: Vec<Reason>,
+}
+
+fn digits(s: &str) -> String {
+    s.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+/// Thai tone marks, the thanthakhat and the maitaikhu (U+0E47 to U+0E4E)
+fn is_mark(c: char) -> bool {
+    ('\u{0E47}'..='\u{0E4E}').contains(&c)
+}
+
+/// First and last name as compared: no title, spaces or marks, lower case
+fn name_key(p: &Patient) -> String {
+    p.first_name
+        .chars()
+        .chain(p.last_name.chars())
+        .filter(|c| !c.is_whitespace() && !is_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Edit distance between two strings, counted in characters
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != *cb)).min(row[j] + 1).min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Names a typo or two apart: one edit per 6 characters of the shorter name
+fn similar(a: &str, b: &str) -> bool {
+    let shorter = a.chars().count().min(b.chars().count());
+    shorter > 0 && distance(a, b) <= (shorter / 6).max(1)
+}
+
+/// Every reason `a` and `b` may be the same person
+pub fn reasons(a: &Patient, b: &Patient) -> Vec<Reason> {
+    let mut found = Vec::new();
+    let citizen_id = digits(&a.citizen_id);
+    if !citizen_id.is_empty() && citizen_id == digits(&b.citizen_id) {
+        found.push(Reason::CitizenId);
+    }
+    let phone = digits(&a.phone);
+    if phone.len() >= 9 && phone == digits(&b.phone) {
+        found.push(Reason::Phone);
+    }
+    let (name_a, name_b) = (name_key(a), name_key(b));
+    if !name_a.is_empty() && name_a == name_b {
+        found.push(Reason::SameName);
+    } else if a.birth_date.is_some() && a.birth_date == b.birth_date && similar(&name_a, &name_b) {
+        found.push(Reason::SimilarNameAndBirthDate);
+    }
+    found
+}
+
+/// Patients outside the Recycle Bin who may be `patient`, strongest match first
+pub fn matches(patient: &Patient, patients: &[Patient]) -> Vec<Match> {
+    let mut found: Vec<Match> = patients
+        .iter()
+        .filter(|p| !p.is_deleted() && p.id != patient.id)
+        .filter_map(|p| {
+            let reasons = reasons(patient, p);
+            (!reasons.is_empty()).then(|| Match { patient: p.clone(), reasons })
+        })
+        .collect();
+    found.sort_by(|a, b| (a.reasons[0], b.reasons.len()).cmp(&(b.reasons[0], a.reasons.len())));
+    found
+}
+
+/// Every pair of patients outside the Recycle Bin who may be the same person.
+/// Patients are only compared when they share a citizen ID, phone, name or birth
+/// date, so this stays quick with thousands of patients.
+pub fn pairs(patients: &[Patient]) -> Vec<Pair> {
+    let live: Vec<&Patient> = patients.iter().filter(|p| !p.is_deleted()).collect();
+    let mut groups: HashMap<(u8, String), Vec<usize>> = HashMap::new();
+    for (i, p) in live.iter().enumerate() {
+        let keys = [
+            (0, digits(&p.citizen_id)),
+            (1, Some(digits(&p.phone)).filter(|phone| phone.len() >= 9).unwrap_or_default()),
+            (2, name_key(p)),
+            (3, p.birth_date.map(|d| d.to_string()).unwrap_or_default()),
+        ];
+        for key in keys.into_iter().filter(|(_, value)| !value.is_empty()) {
+            groups.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut found: BTreeMap<(usize, usize), Vec<Reason>> = BTreeMap::new();
+    for members in groups.values().filter(|members| members.len() > 1) {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                let key = (i.min(j), i.max(j));
+                if found.contains_key(&key) {
+                    continue;
+                }
+                let reasons = reasons(live[key.0], live[key.1]);
+                if !reasons.is_empty() {
+                    found.insert(key, reasons);
+                }
+            }
+        }
+    }
+
+    let mut pairs: Vec<Pair> = found
+        .into_iter()
+        .map(|((i, j), reasons)| {
+            let (first, second) = if live[i].created_at <= live[j].created_at { (live[i], live[j]) } else { (live[j], live[i]) };
+            Pair { first: first.clone(), second: second.clone(), reasons }
+        })
+        .collect();
+    pairs.sort_by(|a, b| (a.reasons[0], a.first.created_at).cmp(&(b.reasons[0], b.first.created_at)));
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    fn patient(id: &str, first_name: &str, last_name: &str) -> Patient {
+        Patient {
+            id: id.to_string(),
+            hn: format!("HN-{}", id),
+            citizen_id: String::new(),
+            title: "นาย".to_string(),
+            first_name: first_name.to_string(),
+            last_name: last_name.to_string(),
+            birth_date: None,
+            age: None,
+            blood_group: String::new(),
+            underlying_disease: String::new(),
+            allergies: vec![],
+            phone: String::new(),
+            address: String::new(),
+            created_at: Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap(),
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn born(mut p: Patient, day: u32) -> Patient {
+        p.birth_date = NaiveDate::from_ymd_opt(1980, 1, day);
+        p
+    }
+
+    #[test]
+    fn test_reasons() {
+        let somchai = patient("p1", "สมชาย", "ใจดี");
+        // Tone marks and spacing don't make a different name
+        assert_eq!(reasons(&somchai, &patient("p2", "สมชาย ", "ใจดี่")), vec![Reason::SameName]);
+        // A typo only counts with the same birth date
+        assert!(reasons(&somchai, &patient("p2", "สมชาญ", "ใจดี")).is_empty());
+        assert_eq!(
+            reasons(&born(somchai.clone(), 5), &born(patient("p2", "สมชาญ", "ใจดี"), 5)),
+            vec![Reason::SimilarNameAndBirthDate]
+        );
+        assert!(reasons(&born(somchai.clone(), 5), &born(patient("p2", "สมศรี", "มีสุข"), 5)).is_empty());
+
+        let mut a = patient("p1", "A", "B");
+        let mut b = patient("p2", "C", "D");
+        (a.citizen_id, b.citizen_id) = ("1101700207030".to_string(), "1-1017-00207-03-0".to_string());
+        (a.phone, b.phone) = ("081-234-5678".to_string(), "0812345678".to_string());
+        assert_eq!(reasons(&a, &b), vec![Reason::CitizenId, Reason::Phone]);
+    }
+
+    #[test]
+    fn test_matches_and_pairs() {
+        let mut trashed = patient("p3", "สมชาย", "ใจดี");
+        trashed.deleted_at = Some(Utc::now());
+        let mut phone = patient("p4", "มานี", "มีนา");
+        phone.phone = "0812345678".to_string();
+        let mut same_phone = patient("p5", "ปิติ", "ชูใจ");
+        same_phone.phone = "0812345678".to_string();
+        same_phone.created_at = Utc.with_ymd_and_hms(2023, 1, 1, 9, 0, 0).unwrap();
+        let patients = vec![patient("p1", "สมชาย", "ใจดี"), patient("p2", "สมชาย", "ใจดี"), trashed, phone, same_phone];
+
+        let found = matches(&patient("new", "สมชาย", "ใจดี"), &patients);
+        assert_eq!(found.iter().map(|m| m.patient.id.as_str()).collect::<Vec<_>>(), vec!["p1", "p2"]);
+
+        let pairs = pairs(&patients);
+        let ids: Vec<(&str, &str)> = pairs.iter().map(|p| (p.first.id.as_str(), p.second.id.as_str())).collect();
+        assert_eq!(ids, vec![("p5", "p4"), ("p1", "p2")]);
+    }
+}
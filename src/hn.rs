@@ -0,0 +1,188 @@
+// Hospital numbers
+// New patients get the next HN in the format set in Settings: a prefix and the
+// running number padded to a fixed width, optionally with the two-digit Buddhist
+// year in between (HN-67-00016), in which case numbering starts again at 1 every
+// year. HNs typed by hand from old paper cards are kept as they are; `check`
+// lists the ones that don't fit the format or are shared, and `renumber` gives
+// every patient a new HN in the order they registered.
+
+use std::collections::{BTreeMap, HashMap};
+use chrono::{Datelike, Local};
+use crate::models::{HnFormat, Patient};
+
+/// Two-digit Buddhist year, 2024 (2567) → 67
+fn year_code(year: i32) -> i32 {
+    (year + 543) % 100
+}
+
+fn is_number(digits: &str) -> bool {
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The HN with running number `number`, issued in `year` (Christian era)
+pub fn format(format: &HnFormat, year: i32, number: u32) -> String {
+    let width = format.padding as usize;
+    if format.with_year {
+        format!("{}{:02}-{:0width$}", format.prefix, year_code(year), number)
+    } else {
+        format!("{}{:0width$}", format.prefix, number)
+    }
+}
+
+/// The running number of `hn` if it is in the format and, when the format has
+/// the year, was issued in `year`
+pub fn number_in(format: &HnFormat, year: i32, hn: &str) -> Option<u32> {
+    let rest = hn.trim().strip_prefix(format.prefix.as_str())?;
+    let digits = if format.with_year {
+        rest.strip_prefix(&format!("{:02}-", year_code(year)))?
+    } else {
+        rest
+    };
+    is_number(digits).then(|| digits.parse().ok()).flatten()
+}
+
+/// Whether `hn` is in the format, whichever year it was issued in
+pub fn matches(format: &HnFormat, hn: &str) -> bool {
+    let Some(rest) = hn.trim().strip_prefix(format.prefix.as_str()) else {
+        return false;
+    };
+    if !format.with_year {
+        return is_number(rest);
+    }
+    rest.split_once('-').is_some_and(|(year, number)| year.len() == 2 && is_number(year) && is_number(number))
+}
+
+/// The running number for the next patient registered in `year`: one past the
+/// highest in use. Without the year in the format it never goes below the saved
+/// counter either, so a number isn't handed out again after its patient was purged.
+pub fn next_number(format: &HnFormat, year: i32, patients: &[Patient], last_hn: u32) -> u32 {
+    let highest = patients.iter().filter_map(|p| number_in(format, year, &p.hn)).max().unwrap_or(0);
+    if format.with_year {
+        highest + 1
+    } else {
+        highest.max(last_hn) + 1
+    }
+}
+
+/// HNs that need a look, Recycle Bin included since a restored patient brings theirs back
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Check {
+    /// HNs shared by more than one patient, with those patients
+    pub duplicates: Vec<(String, Vec<Patient>)>,
+    /// Patients whose HN is empty or not in the format
+    pub other_format: Vec<Patient>,
+}
+
+impl Check {
+    pub fn is_clean(&self) -> bool {
+        self.duplicates.is_empty() && self.other_format.is_empty()
+    }
+}
+
+pub fn check(format: &HnFormat, patients: &[Patient]) -> Check {
+    let mut by_hn: BTreeMap<&str, Vec<Patient>> = BTreeMap::new();
+    for p in patients.iter().filter(|p| !p.hn.trim().is_empty()) {
+        by_hn.entry(p.hn.trim()).or_default().push(p.clone());
+    }
+    let duplicates = by_hn
+        .into_iter()
+        .filter(|(_, shared)| shared.len() > 1)
+        .map(|(hn, shared)| (hn.to_string(), shared))
+        .collect();
+    let other_format = patients.iter().filter(|p| !matches(format, &p.hn)).cloned().collect();
+    Check { duplicates, other_format }
+}
+
+/// A new HN in the format for every patient, in the order they registered, as
+/// patient id → HN. With the year in the format each patient gets the year they
+/// registered in.
+pub fn renumber(format: &HnFormat, patients: &[Patient]) -> HashMap<String, String> {
+    let mut ordered: Vec<&Patient> = patients.iter().collect();
+    ordered.sort_by_key(|p| p.created_at);
+    let mut counters: BTreeMap<i32, u32> = BTreeMap::new();
+    ordered
+        .into_iter()
+        .map(|p| {
+            let year = p.created_at.with_timezone(&Local).year();
+            let counter = counters.entry(if format.with_year { year } else { 0 }).or_default();
+            *counter += 1;
+            (p.id.clone(), self::format(format, year, *counter))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn with_year() -> HnFormat {
+        HnFormat { with_year: true, ..HnFormat::default() }
+    }
+
+    fn patient(id: &str, hn: &str, registered: DateTime<Utc>) -> Patient {
+        Patient {
+            id: id.to_string(),
+            hn: hn.to_string(),
+            citizen_id: String::new(),
+            title: "นาย".to_string(),
+            first_name: "สมชาย".to_string(),
+            last_name: "ใจดี".to_string(),
+            birth_date: None,
+            age: None,
+            blood_group: String::new(),
+            underlying_disease: String::new(),
+            allergies: vec![],
+            phone: String::new(),
+            address: String::new(),
+            created_at: registered,
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn test_format_and_parse() {
+        assert_eq!(format(&HnFormat::default(), 2024, 16), "HN-00016");
+        assert_eq!(format(&with_year(), 2024, 16), "HN-67-00016");
+        assert_eq!(format(&HnFormat { prefix: String::new(), padding: 3, with_year: false }, 2024, 7), "007");
+
+        assert_eq!(number_in(&HnFormat::default(), 2024, " HN-00016 "), Some(16));
+        assert_eq!(number_in(&with_year(), 2024, "HN-67-00016"), Some(16));
+        assert_eq!(number_in(&with_year(), 2025, "HN-67-00016"), None);
+        assert_eq!(number_in(&HnFormat::default(), 2024, "66001"), None);
+
+        assert!(matches(&with_year(), "HN-66-00001"));
+        assert!(!matches(&with_year(), "HN-00001"));
+        assert!(!matches(&HnFormat::default(), ""));
+    }
+
+    #[test]
+    fn test_next_number() {
+        let at = Utc.with_ymd_and_hms(2024, 6, 1, 3, 0, 0).unwrap();
+        let patients = vec![patient("p1", "HN-00003", at), patient("p2", "66001", at), patient("p3", "HN-67-00009", at)];
+        assert_eq!(next_number(&HnFormat::default(), 2024, &patients, 0), 4);
+        assert_eq!(next_number(&HnFormat::default(), 2024, &patients, 10), 11);
+        assert_eq!(next_number(&with_year(), 2024, &patients, 10), 10);
+        assert_eq!(next_number(&with_year(), 2025, &patients, 10), 1);
+    }
+
+    #[test]
+    fn test_check_and_renumber() {
+        let day = |y, m| Utc.with_ymd_and_hms(y, m, 1, 3, 0, 0).unwrap();
+        let patients = vec![
+            patient("p1", "66001", day(2023, 5)),
+            patient("p2", "HN-00001", day(2024, 1)),
+            patient("p3", "HN-00001", day(2023, 1)),
+        ];
+        let found = check(&HnFormat::default(), &patients);
+        assert_eq!(found.duplicates.len(), 1);
+        assert_eq!(found.duplicates[0].1.len(), 2);
+        assert_eq!(found.other_format.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), vec!["p1"]);
+
+        let plain = renumber(&HnFormat::default(), &patients);
+        assert_eq!((plain["p3"].as_str(), plain["p1"].as_str(), plain["p2"].as_str()), ("HN-00001", "HN-00002", "HN-00003"));
+        let yearly = renumber(&with_year(), &patients);
+        assert_eq!((yearly["p3"].as_str(), yearly["p1"].as_str(), yearly["p2"].as_str()), ("HN-66-00001", "HN-66-00002", "HN-67-00001"));
+    }
+}
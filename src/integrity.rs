@@ -0,0 +1,294 @@
+// Integrity scan
+// The forms keep new data consistent, but imports, restores, older versions and
+// renames can still leave a record pointing at a patient who is gone, two patients
+// sharing an HN, a citizen ID that fails its check digit, or prescriptions naming
+// a drug the inventory no longer has.
+// `scan` finds these by category; the fixes are Store methods (relink, merge,
+// move to the Recycle Bin) offered next to each problem in Settings.
+// clinic-cli runs the same checks on the data file (src-tauri/src/integrity.rs).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::models::{Patient, SoftDelete};
+use crate::storage::ClinicData;
+use crate::thai_id;
+
+/// Why a record or appointment has no patient to show
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Missing {
+    /// There is no patient with that id at all
+    Gone,
+    /// The patient is in the Recycle Bin but the item is not
+    InTrash,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct OrphanRecord {
+    pub record_id: String,
+    pub patient_id: String,
+    pub date: DateTime<Utc>,
+    pub diagnosis: String,
+    pub missing: Missing,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct OrphanAppointment {
+    pub appointment_id: String,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub date: NaiveDate,
+    pub missing: Missing,
+}
+
+/// Patients that share one HN, registered first to last
+#[derive(Clone, PartialEq, Debug)]
+pub struct DuplicateHn {
+    pub hn: String,
+    pub patients: Vec<Patient>,
+}
+
+/// A prescribed drug name that no drug in the inventory has
+#[derive(Clone, PartialEq, Debug)]
+pub struct UnknownDrug {
+    pub name: String,
+    /// How many records prescribe it
+    pub records: usize,
+    /// The inventory drug it was most likely renamed to
+    pub suggestion: Option<String>,
+}
+
+/// Everything `scan` found, by category
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Report {
+    pub orphan_records: Vec<OrphanRecord>,
+    pub duplicate_hns: Vec<DuplicateHn>,
+    /// Patients whose citizen ID is filled in but not a valid one
+    pub invalid_citizen_ids: Vec<Patient>,
+    pub orphan_appointments: Vec<OrphanAppointment>,
+    pub unknown_drugs: Vec<UnknownDrug>,
+}
+
+impl Report {
+    pub fn total(&self) -> usize {
+        self.orphan_records.len()
+            + self.duplicate_hns.len()
+            + self.invalid_citizen_ids.len()
+            + self.orphan_appointments.len()
+            + self.unknown_drugs.len()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.total() == 0
+    }
+}
+
+/// Spaces and case don't make a different drug
+fn normalized(name: &str) -> String {
+    name.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+/// The live drug whose name matches `name` but for spacing and case, or that
+/// one name starts with the other (e.g. "Paracetamol" and "Paracetamol 500mg")
+fn suggest_drug(name: &str, drug_names: &[&str]) -> Option<String> {
+    let wanted = normalized(name);
+    drug_names
+        .iter()
+        .find(|drug| normalized(drug) == wanted)
+        .or_else(|| {
+            drug_names.iter().find(|drug| {
+                let drug = normalized(drug);
+                drug.starts_with(&wanted) || wanted.starts_with(&drug)
+            })
+        })
+        .map(|drug| drug.to_string())
+}
+
+/// Check the data across collections. Only items outside the Recycle Bin are
+/// looked at, since those are what the rest of the app shows.
+pub fn scan(data: &ClinicData) -> Report {
+    let patients: HashMap<&str, &Patient> = data.patients.iter().map(|p| (p.id.as_str(), p)).collect();
+    let missing = |id: &str| match patients.get(id) {
+        None => Some(Missing::Gone),
+        Some(p) if p.is_deleted() => Some(Missing::InTrash),
+        Some(_) => None,
+    };
+
+    let orphan_records = data
+        .records
+        .iter()
+        .filter(|r| !r.is_deleted())
+        .filter_map(|r| {
+            missing(&r.patient_id).map(|missing| OrphanRecord {
+                record_id: r.id.clone(),
+                patient_id: r.patient_id.clone(),
+                date: r.date,
+                diagnosis: r.diagnosis.clone(),
+                missing,
+            })
+        })
+        .collect();
+
+    let orphan_appointments = data
+        .appointments
+        .iter()
+        .filter(|a| !a.is_deleted())
+        .filter_map(|a| {
+            missing(&a.patient_id).map(|missing| OrphanAppointment {
+                appointment_id: a.id.clone(),
+                patient_id: a.patient_id.clone(),
+                patient_name: a.patient_name.clone(),
+                date: a.date,
+                missing,
+            })
+        })
+        .collect();
+
+    let mut by_hn: BTreeMap<&str, Vec<Patient>> = BTreeMap::new();
+    for p in data.patients.iter().filter(|p| !p.is_deleted() && !p.hn.trim().is_empty()) {
+        by_hn.entry(p.hn.trim()).or_default().push(p.clone());
+    }
+    let duplicate_hns = by_hn
+        .into_iter()
+        .filter(|(_, patients)| patients.len() > 1)
+        .map(|(hn, mut patients)| {
+            patients.sort_by_key(|p| p.created_at);
+            DuplicateHn { hn: hn.to_string(), patients }
+        })
+        .collect();
+
+    let invalid_citizen_ids = data
+        .patients
+        .iter()
+        .filter(|p| !p.is_deleted() && !p.citizen_id.trim().is_empty() && !thai_id::is_valid(&p.citizen_id))
+        .cloned()
+        .collect();
+
+    let drug_names: Vec<&str> = data.drugs.iter().filter(|d| !d.is_deleted()).map(|d| d.name.as_str()).collect();
+    let known: HashSet<&str> = drug_names.iter().copied().collect();
+    let mut unknown: BTreeMap<&str, usize> = BTreeMap::new();
+    for r in data.records.iter().filter(|r| !r.is_deleted()) {
+        let names: HashSet<&str> = r.prescriptions.iter().map(|rx| rx.name.as_str()).collect();
+        for name in names.into_iter().filter(|name| !name.trim().is_empty() && !known.contains(name)) {
+            *unknown.entry(name).or_default() += 1;
+        }
+    }
+    let unknown_drugs = unknown
+        .into_iter()
+        .map(|(name, records)| UnknownDrug { name: name.to_string(), records, suggestion: suggest_drug(name, &drug_names) })
+        .collect();
+
+    Report { orphan_records, duplicate_hns, invalid_citizen_ids, orphan_appointments, unknown_drugs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::models::{Appointment, DrugItem, PrescriptionItem, TreatmentRecord};
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, 9, 0, 0).unwrap()
+    }
+
+    fn patient(id: &str, hn: &str, day: u32) -> Patient {
+        Patient {
+            id: id.to_string(),
+            hn: hn.to_string(),
+            citizen_id: String::new(),
+            title: "นาย".to_string(),
+            first_name: "สมชาย".to_string(),
+            last_name: "ใจดี".to_string(),
+            birth_date: None,
+            age: None,
+            blood_group: String::new(),
+            underlying_disease: String::new(),
+            allergies: vec![],
+            phone: String::new(),
+            address: String::new(),
+            created_at: at(day),
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn record(id: &str, patient_id: &str, drugs: &[&str]) -> TreatmentRecord {
+        TreatmentRecord {
+            id: id.to_string(),
+            patient_id: patient_id.to_string(),
+            date: at(10),
+            symptoms: String::new(),
+            diagnosis: "ไข้หวัด".to_string(),
+            weight: None,
+            pressure: String::new(),
+            prescriptions: drugs
+                .iter()
+                .map(|name| PrescriptionItem { name: name.to_string(), ..PrescriptionItem::default() })
+                .collect(),
+            injections: vec![],
+            doctor_note: String::new(),
+            price: 100.0,
+            allergy_override: None,
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn test_clean_data_has_no_issues() {
+        let data = ClinicData {
+            patients: vec![patient("p1", "HN-00001", 1)],
+            records: vec![record("r1", "p1", &["Paracetamol 500mg"])],
+            drugs: vec![DrugItem { name: "Paracetamol 500mg".to_string(), ..DrugItem::default() }],
+            ..ClinicData::default()
+        };
+        assert!(scan(&data).is_clean());
+    }
+
+    #[test]
+    fn test_scan_finds_each_category() {
+        let mut trashed = patient("p3", "HN-00003", 3);
+        trashed.deleted_at = Some(at(20));
+        let mut mistyped = patient("p4", "HN-00004", 4);
+        mistyped.citizen_id = "1101700207031".to_string();
+        let mut formatted = patient("p5", "HN-00005", 5);
+        formatted.citizen_id = "1-1017-00207-03-0".to_string();
+        let data = ClinicData {
+            patients: vec![patient("p2", "HN-00001", 2), patient("p1", "HN-00001", 1), trashed, mistyped, formatted],
+            records: vec![
+                record("r1", "p1", &["Paracetamol", "Paracetamol"]),
+                record("r2", "gone", &[]),
+                record("r3", "p3", &["Amoxy"]),
+            ],
+            drugs: vec![DrugItem { name: "Paracetamol 500mg".to_string(), ..DrugItem::default() }],
+            appointments: vec![Appointment { id: "a1".to_string(), patient_id: "p3".to_string(), ..Appointment::default() }],
+            ..ClinicData::default()
+        };
+
+        let report = scan(&data);
+        let orphans: Vec<(&str, Missing)> =
+            report.orphan_records.iter().map(|o| (o.record_id.as_str(), o.missing)).collect();
+        assert_eq!(orphans, vec![("r2", Missing::Gone), ("r3", Missing::InTrash)]);
+
+        assert_eq!(report.duplicate_hns.len(), 1);
+        let ids: Vec<&str> = report.duplicate_hns[0].patients.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["p1", "p2"]);
+
+        let invalid: Vec<&str> = report.invalid_citizen_ids.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(invalid, vec!["p4"]);
+
+        assert_eq!(report.orphan_appointments[0].missing, Missing::InTrash);
+
+        // Counted once per record, with the renamed drug suggested
+        assert_eq!(report.unknown_drugs[0], UnknownDrug {
+            name: "Amoxy".to_string(),
+            records: 1,
+            suggestion: None,
+        });
+        assert_eq!(report.unknown_drugs[1], UnknownDrug {
+            name: "Paracetamol".to_string(),
+            records: 1,
+            suggestion: Some("Paracetamol 500mg".to_string()),
+        });
+        assert_eq!(report.total(), 7);
+    }
+}
@@ -0,0 +1,389 @@
+// Drug–drug interactions
+// The clinic keeps its own list of interaction rules (ClinicData::interactions),
+// entered on the Drugs page or imported from a CSV file. Each side of a rule is
+// a drug name or the key of a drug class, one of CLASSES or of the allergy
+// groups (allergy::GROUPS), e.g. "warfarin" + "nsaids".
+// Prescriptions are free text, so matching is by name like allergies are: a
+// class covers a drug whose name or inventory description mentions one of its
+// members, and a name covers any drug whose name contains it.
+// The Treatment form checks a prescription against itself and against what the
+// patient is still taking from recent records (`active`). Interactions are a
+// warning only; nothing is refused.
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::allergy::{self, normalized, Group};
+use crate::audit::csv_field;
+use crate::models::{DrugItem, InteractionRule, InteractionSeverity, PrescriptionItem, SoftDelete, TreatmentRecord};
+
+/// Drug classes that commonly interact, on top of the allergy groups
+pub const CLASSES: [Group; 12] = [
+    Group {
+        key: "anticoagulants",
+        name: "ยาต้านการแข็งตัวของเลือด (Anticoagulants)",
+        members: &["warfarin", "orfarin", "coumadin", "rivaroxaban", "apixaban", "dabigatran", "วาร์ฟาริน"],
+    },
+    Group {
+        key: "antiplatelets",
+        name: "ยาต้านเกล็ดเลือด (Antiplatelets)",
+        members: &["clopidogrel", "plavix", "ticagrelor", "prasugrel", "cilostazol"],
+    },
+    Group {
+        key: "statins",
+        name: "ยาลดไขมันกลุ่มสแตติน (Statins)",
+        members: &["statin", "simvastatin", "atorvastatin", "lovastatin", "rosuvastatin", "pravastatin"],
+    },
+    Group {
+        key: "antacids",
+        name: "ยาลดกรดและแร่ธาตุ (Antacids)",
+        members: &[
+            "antacid", "aluminium", "aluminum", "magnesium", "calciumcarbonate", "simethicone", "ยาลดกรด",
+        ],
+    },
+    Group {
+        key: "iron",
+        name: "ธาตุเหล็ก (Iron)",
+        members: &["ferrous", "ferric", "เฟอรัส", "ธาตุเหล็ก"],
+    },
+    Group {
+        key: "ace_inhibitors",
+        name: "ยาลดความดันกลุ่ม ACE inhibitors",
+        members: &["enalapril", "captopril", "lisinopril", "ramipril", "perindopril"],
+    },
+    Group {
+        key: "arbs",
+        name: "ยาลดความดันกลุ่ม ARBs",
+        members: &["losartan", "valsartan", "irbesartan", "telmisartan", "candesartan"],
+    },
+    Group {
+        key: "sulfonylureas",
+        name: "ยาเบาหวานกลุ่มซัลโฟนิลยูเรีย (Sulfonylureas)",
+        members: &["glipizide", "glibenclamide", "gliclazide", "glimepiride"],
+    },
+    Group {
+        key: "nitrates",
+        name: "ยาขยายหลอดเลือดหัวใจ (Nitrates)",
+        members: &["isosorbide", "isordil", "nitroglycerin", "glyceryltrinitrate"],
+    },
+    Group {
+        key: "pde5_inhibitors",
+        name: "ยากลุ่ม PDE5 inhibitors",
+        members: &["sildenafil", "tadalafil", "vardenafil", "viagra", "cialis"],
+    },
+    Group {
+        key: "sedatives",
+        name: "ยานอนหลับและยากดประสาท (Sedatives)",
+        members: &[
+            "diazepam", "lorazepam", "alprazolam", "clonazepam", "midazolam", "tramadol", "codeine", "morphine",
+            "chlorpheniramine", "hydroxyzine", "diphenhydramine",
+        ],
+    },
+    Group {
+        key: "azole_antifungals",
+        name: "ยาต้านเชื้อรากลุ่มอะโซล (Azoles)",
+        members: &["ketoconazole", "fluconazole", "itraconazole", "voriconazole"],
+    },
+];
+
+pub const SEVERITIES: [InteractionSeverity; 4] = [
+    InteractionSeverity::Minor,
+    InteractionSeverity::Moderate,
+    InteractionSeverity::Major,
+    InteractionSeverity::Contraindicated,
+];
+
+/// A drug given without a number of days counts as still taken for this long
+pub const DEFAULT_COURSE_DAYS: i64 = 7;
+
+const CSV_HEADER: &str = "ยา/กลุ่มยา 1,ยา/กลุ่มยา 2,ความรุนแรง,คำแนะนำ";
+
+pub fn severity_name(severity: InteractionSeverity) -> &'static str {
+    match severity {
+        InteractionSeverity::Minor => "เล็กน้อย",
+        InteractionSeverity::Moderate => "ปานกลาง",
+        InteractionSeverity::Major => "รุนแรง",
+        InteractionSeverity::Contraindicated => "ห้ามใช้ร่วมกัน",
+    }
+}
+
+/// A severity written by hand, in Thai as shown or as its English key
+pub fn parse_severity(text: &str) -> Option<InteractionSeverity> {
+    let text = text.trim().to_lowercase();
+    SEVERITIES.into_iter().find(|s| {
+        let key = serde_json::to_value(s).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        text == key || text == severity_name(*s)
+    })
+}
+
+/// The class a rule side names by its key, e.g. "nsaids" or "NSAIDs"
+pub fn class(side: &str) -> Option<&'static Group> {
+    let key = normalized(side);
+    CLASSES.iter().chain(allergy::GROUPS.iter()).find(|g| normalized(g.key) == key)
+}
+
+/// A rule side as shown to staff: the class name, or the drug name as entered
+pub fn side_name(side: &str) -> String {
+    class(side).map(|g| g.name.to_string()).unwrap_or_else(|| side.trim().to_string())
+}
+
+/// Whether rule side `side` covers the drug `name`, described in the inventory as `description`
+fn covers(side: &str, name: &str, description: &str) -> bool {
+    let text = normalized(&format!("{} {}", name, description));
+    match class(side) {
+        Some(g) => g.members.iter().any(|member| text.contains(member)),
+        None => {
+            // Short names would match too much by accident
+            let side = normalized(side);
+            side.chars().count() >= 3 && text.contains(&side)
+        }
+    }
+}
+
+/// Both rules name the same two drugs or classes, in either order
+pub fn same_pair(a: &InteractionRule, b: &InteractionRule) -> bool {
+    let pair = |r: &InteractionRule| {
+        let (x, y) = (normalized(&r.a), normalized(&r.b));
+        if x <= y { (x, y) } else { (y, x) }
+    };
+    pair(a) == pair(b)
+}
+
+/// Why `rule` can't be saved, or None if it can
+pub fn problem(rule: &InteractionRule) -> Option<String> {
+    if rule.a.trim().is_empty() || rule.b.trim().is_empty() {
+        return Some("กรุณาระบุยาหรือกลุ่มยาทั้งสองฝั่ง".to_string());
+    }
+    [&rule.a, &rule.b]
+        .into_iter()
+        .find(|side| class(side).is_none() && normalized(side).chars().count() < 3)
+        .map(|side| format!("ชื่อยา \"{}\" สั้นเกินไป ต้องมีอย่างน้อย 3 ตัวอักษร", side.trim()))
+}
+
+/// A drug the patient is taking: on the prescription being written, or from a
+/// recent record, given at `given`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Medication {
+    pub name: String,
+    pub given: Option<DateTime<Utc>>,
+}
+
+/// Drugs from `records` whose course has not ended at `now`, most recently
+/// given first and each name once. Records in the Recycle Bin don't count.
+pub fn active(records: &[TreatmentRecord], now: DateTime<Utc>) -> Vec<Medication> {
+    let mut found: Vec<Medication> = Vec::new();
+    let mut records: Vec<&TreatmentRecord> = records.iter().filter(|r| !r.is_deleted() && r.date <= now).collect();
+    records.sort_by_key(|r| std::cmp::Reverse(r.date));
+    for record in records {
+        for rx in record.prescriptions.iter().filter(|rx| !rx.name.trim().is_empty()) {
+            let days = rx.duration_days.map_or(DEFAULT_COURSE_DAYS, i64::from);
+            let taken = record.date + Duration::days(days) >= now;
+            if taken && !found.iter().any(|m| normalized(&m.name) == normalized(&rx.name)) {
+                found.push(Medication { name: rx.name.clone(), given: Some(record.date) });
+            }
+        }
+    }
+    found
+}
+
+/// A pair of drugs a rule warns about
+#[derive(Clone, PartialEq, Debug)]
+pub struct Interaction {
+    pub rule: InteractionRule,
+    /// On the prescription being written
+    pub drug: String,
+    /// On the same prescription, or taken from an earlier record
+    pub other: Medication,
+}
+
+/// Every rule that applies between two drugs on `prescriptions`, or between one
+/// of them and a drug on `active`, most severe first. `drugs` is the inventory,
+/// for descriptions. The same drug given again is not checked against itself.
+pub fn check(
+    rules: &[InteractionRule],
+    prescriptions: &[PrescriptionItem],
+    active: &[Medication],
+    drugs: &[DrugItem],
+) -> Vec<Interaction> {
+    let description = |name: &str| drugs.iter().find(|d| d.name == name).map(|d| d.description.clone()).unwrap_or_default();
+    let prescribed: Vec<Medication> = prescriptions
+        .iter()
+        .filter(|rx| !rx.name.trim().is_empty())
+        .map(|rx| Medication { name: rx.name.clone(), given: None })
+        .collect();
+
+    let mut found = Vec::new();
+    for (i, drug) in prescribed.iter().enumerate() {
+        let others = prescribed[i + 1..].iter().chain(active);
+        for other in others.filter(|other| normalized(&other.name) != normalized(&drug.name)) {
+            let (d1, d2) = (description(&drug.name), description(&other.name));
+            for rule in rules {
+                let forward = covers(&rule.a, &drug.name, &d1) && covers(&rule.b, &other.name, &d2);
+                let backward = covers(&rule.b, &drug.name, &d1) && covers(&rule.a, &other.name, &d2);
+                if forward || backward {
+                    found.push(Interaction { rule: rule.clone(), drug: drug.name.clone(), other: other.clone() });
+                }
+            }
+        }
+    }
+    found.sort_by_key(|i| std::cmp::Reverse(i.rule.severity));
+    found
+}
+
+// ========== CSV ==========
+
+/// The rules as CSV, with a BOM so Excel reads the Thai text as UTF-8. `from_csv` reads it back.
+pub fn to_csv(rules: &[InteractionRule]) -> String {
+    let mut csv = format!("\u{feff}{}\r\n", CSV_HEADER);
+    for rule in rules {
+        let fields = [rule.a.as_str(), rule.b.as_str(), severity_name(rule.severity), rule.advice.as_str()];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Rows of `text`, with quoted fields as written by Excel
+fn rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Rules from a CSV file with the columns of `to_csv`: two drugs or classes,
+/// the severity and the advice. A header row and blank rows are skipped, and
+/// a blank severity is taken as moderate. Any bad row fails the whole file, so
+/// nothing is half imported.
+pub fn from_csv(text: &str) -> Result<Vec<InteractionRule>, String> {
+    let mut rules = Vec::new();
+    for (n, row) in rows(text).into_iter().enumerate() {
+        let cell = |i: usize| row.get(i).map(|c| c.trim().to_string()).unwrap_or_default();
+        if row.iter().all(|c| c.trim().is_empty()) || (n == 0 && row.join(",").trim() == CSV_HEADER) {
+            continue;
+        }
+        let severity = match cell(2).as_str() {
+            "" => InteractionSeverity::default(),
+            text => parse_severity(text).ok_or_else(|| format!("แถวที่ {}: ไม่รู้จักความรุนแรง \"{}\"", n + 1, text))?,
+        };
+        let rule = InteractionRule { id: Uuid::new_v4().to_string(), a: cell(0), b: cell(1), severity, advice: cell(3), updated_at: None };
+        if let Some(problem) = problem(&rule) {
+            return Err(format!("แถวที่ {}: {}", n + 1, problem));
+        }
+        rules.push(rule);
+    }
+    if rules.is_empty() {
+        return Err("ไม่พบกฎในไฟล์".to_string());
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn rule(a: &str, b: &str, severity: InteractionSeverity) -> InteractionRule {
+        InteractionRule { id: format!("{}+{}", a, b), a: a.to_string(), b: b.to_string(), severity, advice: String::new(), updated_at: None }
+    }
+
+    fn rx(name: &str) -> PrescriptionItem {
+        PrescriptionItem { name: name.to_string(), ..PrescriptionItem::default() }
+    }
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_check_within_prescription_and_active() {
+        let rules = vec![
+            rule("anticoagulants", "nsaids", InteractionSeverity::Major),
+            rule("Simvastatin", "clarithromycin", InteractionSeverity::Contraindicated),
+        ];
+        let drugs = vec![DrugItem { name: "Brufen 400".to_string(), description: "Ibuprofen".to_string(), ..DrugItem::default() }];
+
+        // Either order, and by the inventory description
+        let found = check(&rules, &[rx("Brufen 400"), rx("Orfarin 3mg")], &[], &drugs);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].drug.as_str(), found[0].other.name.as_str()), ("Brufen 400", "Orfarin 3mg"));
+
+        // Against what the patient is still taking, most severe first
+        let active = vec![Medication { name: "Simvastatin 20mg".to_string(), given: Some(at(1)) }];
+        let found = check(&rules, &[rx("Clarithromycin 500"), rx("Brufen 400"), rx("Warfarin")], &active, &drugs);
+        let severities: Vec<InteractionSeverity> = found.iter().map(|i| i.rule.severity).collect();
+        assert_eq!(severities, vec![InteractionSeverity::Contraindicated, InteractionSeverity::Major]);
+        assert_eq!(found[0].other.given, Some(at(1)));
+
+        // A refill is not an interaction with itself
+        let nsaids = [rule("nsaids", "nsaids", InteractionSeverity::Moderate)];
+        let ibuprofen = [Medication { name: "Brufen 400".to_string(), given: Some(at(1)) }];
+        assert!(check(&nsaids, &[rx("Brufen 400")], &ibuprofen, &drugs).is_empty());
+        assert_eq!(check(&nsaids, &[rx("Brufen 400"), rx("Naproxen")], &[], &drugs).len(), 1);
+    }
+
+    #[test]
+    fn test_active_courses() {
+        let record = |id: &str, day: u32, prescriptions: Vec<PrescriptionItem>| TreatmentRecord {
+            id: id.to_string(),
+            patient_id: "p1".to_string(),
+            date: at(day),
+            symptoms: String::new(),
+            diagnosis: String::new(),
+            weight: None,
+            pressure: String::new(),
+            prescriptions,
+            injections: vec![],
+            doctor_note: String::new(),
+            price: 0.0,
+            allergy_override: None,
+            deleted_at: None,
+            updated_at: None,
+        };
+        let month = PrescriptionItem { duration_days: Some(30), ..rx("Simvastatin") };
+        let mut trashed = record("r3", 9, vec![rx("Warfarin")]);
+        trashed.deleted_at = Some(at(9));
+        let records = vec![record("r1", 1, vec![month, rx("Paracetamol")]), record("r2", 8, vec![rx("paracetamol ")]), trashed];
+
+        let names: Vec<String> = active(&records, at(12)).into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["paracetamol ", "Simvastatin"]);
+        assert_eq!(active(&records, at(20)).len(), 1);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut rules = vec![rule("warfarin", "nsaids", InteractionSeverity::Major)];
+        rules[0].advice = "เลือดออกง่าย, หลีกเลี่ยง \"NSAIDs\"".to_string();
+        let read = from_csv(&to_csv(&rules)).unwrap();
+        assert_eq!((read[0].a.as_str(), read[0].severity, read[0].advice.as_str()), ("warfarin", rules[0].severity, rules[0].advice.as_str()));
+
+        let typed = from_csv("sildenafil,nitrates,contraindicated,ความดันตกรุนแรง\n\niron,antacids,,\n").unwrap();
+        assert_eq!(typed.len(), 2);
+        assert_eq!(typed[1].severity, InteractionSeverity::Moderate);
+        assert_eq!(from_csv("a,b,ปานกลาง,").unwrap_err(), "แถวที่ 1: ชื่อยา \"a\" สั้นเกินไป ต้องมีอย่างน้อย 3 ตัวอักษร");
+        assert_eq!(from_csv("iron,antacids,บ่อย,").unwrap_err(), "แถวที่ 1: ไม่รู้จักความรุนแรง \"บ่อย\"");
+        assert!(same_pair(&rule("NSAIDs", "Warfarin", InteractionSeverity::Minor), &rules[0]));
+    }
+}
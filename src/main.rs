@@ -1,14 +1,32 @@
+use std::cell::Cell;
+use std::rc::Rc;
 use yew::prelude::*;
 use yew_router::prelude::*;
 
 mod models;
+mod allergy;
+mod audit;
+mod archive;
+mod backup;
+mod cache;
+mod duplicates;
+mod hn;
+mod integrity;
+mod interactions;
+mod crypto;
+mod migrations;
 mod store;
+mod sync;
+mod thai_id;
+mod storage;
+mod tauri_bridge;
 mod pages;
 mod components;
 
-use pages::{Home, Register, Search, Treatment, History, Document, NotFound, Drugs, Sticker, Report, Settings, EditPatient, Expenses, Appointments};
-use components::{ToastProvider, Sidebar};
+use pages::{Home, Register, Search, Treatment, History, Document, NotFound, Drugs, Sticker, Report, Settings, EditPatient, Expenses, Appointments, Audit, RecycleBin};
+use components::{ToastProvider, Sidebar, ToastContext, ToastAction, ToastType};
 use store::Store;
+use storage::{LocalStorageBackend, LOCAL_STORAGE_QUOTA};
 
 #[derive(Clone, Routable, PartialEq)]
 pub enum Route {
@@ -38,6 +56,10 @@ pub enum Route {
     Appointments,
     #[at("/settings")]
     Settings,
+    #[at("/audit")]
+    Audit,
+    #[at("/trash")]
+    RecycleBin,
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -58,10 +80,80 @@ fn switch(routes: Route) -> Html {
         Route::Expenses => html! { <Expenses /> },
         Route::Appointments => html! { <Appointments /> },
         Route::Settings => html! { <Settings /> },
+        Route::Audit => html! { <Audit /> },
+        Route::RecycleBin => html! { <RecycleBin /> },
         Route::NotFound => html! { <NotFound /> },
     }
 }
 
+/// Shows storage problems that no save returns as toasts: anything found while
+/// loading, audit entries the desktop app couldn't add after a save, and a
+/// browser storage quota that is nearly used up
+#[function_component(StorageNotice)]
+fn storage_notice() -> Html {
+    let toast = use_context::<ToastContext>();
+    use_effect_with((), move |_| {
+        if let Some(toast) = toast {
+            if let Some(msg) = Store::take_load_notice() {
+                toast.dispatch(ToastAction::Add(msg, ToastType::Error));
+            }
+            if !tauri_bridge::is_tauri() && LocalStorageBackend::usage() * 100 / LOCAL_STORAGE_QUOTA >= 80 {
+                toast.dispatch(ToastAction::Add(
+                    "พื้นที่จัดเก็บในเบราว์เซอร์ใกล้เต็ม กรุณาสำรองข้อมูล (ดูที่หน้าตั้งค่า)".to_string(),
+                    ToastType::Error,
+                ));
+            }
+            storage::set_error_listener(move |err| toast.dispatch(ToastAction::Add(err, ToastType::Error)));
+        }
+        || ()
+    });
+    html! {}
+}
+
+// How often the desktop app looks for changes from the other computer
+const SYNC_INBOX_POLL_MS: u32 = 5_000;
+
+/// Applies changes from the other clinic computer as they arrive in the sync
+/// inbox (desktop app only, see sync.rs)
+#[function_component(SyncInbox)]
+fn sync_inbox() -> Html {
+    let toast = use_context::<ToastContext>();
+    use_effect_with((), move |_| {
+        let busy = Rc::new(Cell::new(false));
+        // A failure is shown once, not on every poll until it clears
+        let failing = Rc::new(Cell::new(false));
+        let interval = tauri_bridge::is_tauri().then(|| {
+            gloo::timers::callback::Interval::new(SYNC_INBOX_POLL_MS, move || {
+                if busy.replace(true) {
+                    return;
+                }
+                let busy = busy.clone();
+                let failing = failing.clone();
+                let toast = toast.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let messages = match sync::receive().await {
+                        Ok(applied) => {
+                            failing.set(false);
+                            let received = (applied.changed > 0)
+                                .then(|| (format!("🔄 ได้รับข้อมูลจากเครื่องอื่น {} รายการ", applied.changed), ToastType::Success));
+                            vec![received, applied.clash_message().map(|m| (m, ToastType::Error))]
+                        }
+                        Err(err) => vec![(!failing.replace(true)).then(|| (format!("ซิงก์ข้อมูลไม่สำเร็จ: {}", err), ToastType::Error))],
+                    };
+                    if let Some(toast) = toast {
+                        for (message, kind) in messages.into_iter().flatten() {
+                            toast.dispatch(ToastAction::Add(message, kind));
+                        }
+                    }
+                    busy.set(false);
+                });
+            })
+        });
+        move || drop(interval)
+    });
+    html! {}
+}
+
 #[function_component(App)]
 fn app() -> Html {
     // Apply font size on mount
@@ -73,6 +165,8 @@ fn app() -> Html {
     
     html! {
         <ToastProvider>
+            <StorageNotice />
+            <SyncInbox />
             <BrowserRouter>
                 <div class="app-layout">
                     <Sidebar />
@@ -88,6 +182,74 @@ fn app() -> Html {
     }
 }
 
+/// Asks for the passphrase when the desktop data file is encrypted, then loads
+/// the Store and shows the app
+#[function_component(Unlock)]
+fn unlock() -> Html {
+    let ready = use_state(|| false);
+    let busy = use_state(|| false);
+    let error = use_state(|| None::<String>);
+    let passphrase = use_node_ref();
+
+    let on_submit = {
+        let ready = ready.clone();
+        let busy = busy.clone();
+        let error = error.clone();
+        let passphrase = passphrase.clone();
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+            let Some(input) = passphrase.cast::<web_sys::HtmlInputElement>() else { return };
+            let value = input.value();
+            let ready = ready.clone();
+            let busy = busy.clone();
+            let error = error.clone();
+            busy.set(true);
+            wasm_bindgen_futures::spawn_local(async move {
+                match tauri_bridge::unlock_data(&value).await {
+                    Ok(()) => {
+                        crypto::set_session_passphrase(Some(value));
+                        Store::init().await;
+                        ready.set(true);
+                    }
+                    Err(err) => {
+                        error.set(Some(err));
+                        busy.set(false);
+                    }
+                }
+            });
+        })
+    };
+
+    if *ready {
+        return html! { <App /> };
+    }
+
+    html! {
+        <div class="modal-overlay">
+            <form class="modal-content" onsubmit={on_submit}>
+                <div class="modal-header">
+                    <div class="modal-icon warning">{ "🔒" }</div>
+                    <h3 class="modal-title">{ "ข้อมูลคลินิกถูกเข้ารหัส" }</h3>
+                </div>
+                <div class="modal-body">
+                    <div class="form-group">
+                        <label class="form-label">{ "รหัสผ่าน" }</label>
+                        <input type="password" ref={passphrase} autofocus=true disabled={*busy} />
+                        { if let Some(err) = (*error).clone() {
+                            html! { <div class="form-error">{ err }</div> }
+                        } else { html! {} } }
+                    </div>
+                </div>
+                <div class="modal-actions">
+                    <button type="submit" class="btn btn-primary" disabled={*busy}>
+                        { if *busy { "⏳ กำลังปลดล็อก..." } else { "🔓 ปลดล็อก" } }
+                    </button>
+                </div>
+            </form>
+        </div>
+    }
+}
+
 fn apply_font_size(size: &str) {
     if let Some(window) = web_sys::window() {
         if let Some(document) = window.document() {
@@ -114,5 +276,16 @@ pub fn set_font_size(size: &str) {
 }
 
 fn main() {
-    yew::Renderer::<App>::new().render();
+    // Data has to be in memory before the first page reads from the Store
+    wasm_bindgen_futures::spawn_local(async {
+        match tauri_bridge::encryption_status().await {
+            Ok(status) if status.enabled && !status.unlocked => {
+                yew::Renderer::<Unlock>::new().render();
+            }
+            _ => {
+                Store::init().await;
+                yew::Renderer::<App>::new().render();
+            }
+        }
+    });
 }
@@ -0,0 +1,237 @@
+// Data schema versions and the migrations between them.
+// Migrations work on the raw JSON document (keyed like LocalStorage) before it is
+// parsed into the structs in models.rs, so they can fix shapes serde would reject.
+//
+// History:
+//   1 - original format: no patient age/blood group/underlying disease, no sticker
+//       fields on prescriptions, no staff info in settings
+//   2 - sticker fields added, doses (morning/noon/evening/before_bed) stored as bools
+//   3 - doses are f64 so half doses work (e.g. 1.5 tsp)
+//   4 - patient allergies are a list of entries (allergy.rs) instead of the
+//       free-text drug_allergy
+//
+// Every step only fills in or converts what is missing or old, so running it over
+// data that is already newer is harmless. Old backups rely on that: they were all
+// written with version 1 whatever shape the data inside had.
+
+use serde_json::{Map, Value};
+use crate::allergy;
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 4;
+pub const KEY_SCHEMA_VERSION: &str = "clinic_schema_version";
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[i]` upgrades version `i + 1` to `i + 2`
+const MIGRATIONS: [Migration; 3] = [migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+/// Bring a clinic data document up to `CURRENT_SCHEMA_VERSION` in place.
+/// Returns true when anything was upgraded and the result should be saved back.
+pub fn migrate(doc: &mut Map<String, Value>) -> Result<bool, String> {
+    let has_data = doc.keys().any(|k| k != KEY_SCHEMA_VERSION);
+    let version = match doc.get(KEY_SCHEMA_VERSION).and_then(Value::as_u64) {
+        Some(v) => v as u32,
+        // Data saved before versioning existed
+        None if has_data => 1,
+        None => CURRENT_SCHEMA_VERSION,
+    };
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "ข้อมูลมาจากโปรแกรมเวอร์ชันใหม่กว่า (schema {} > {}) กรุณาอัปเดตโปรแกรม",
+            version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        migration(doc);
+    }
+
+    doc.insert(KEY_SCHEMA_VERSION.to_string(), Value::from(CURRENT_SCHEMA_VERSION));
+    Ok(version != CURRENT_SCHEMA_VERSION)
+}
+
+fn objects_in<'a>(doc: &'a mut Map<String, Value>, key: &str) -> impl Iterator<Item = &'a mut Map<String, Value>> {
+    doc.get_mut(key)
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn fill(obj: &mut Map<String, Value>, field: &str, default: Value) {
+    if !obj.contains_key(field) || (obj[field].is_null() && !default.is_null()) {
+        obj.insert(field.to_string(), default);
+    }
+}
+
+fn migrate_v1_to_v2(doc: &mut Map<String, Value>) {
+    for patient in objects_in(doc, "clinic_patients") {
+        fill(patient, "age", Value::Null);
+        fill(patient, "blood_group", Value::from(""));
+        fill(patient, "underlying_disease", Value::from(""));
+    }
+
+    for record in objects_in(doc, "clinic_records") {
+        let Some(items) = record.get_mut("prescriptions").and_then(Value::as_array_mut) else {
+            continue;
+        };
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            for dose in ["morning", "noon", "evening", "before_bed"] {
+                fill(item, dose, Value::from(false));
+            }
+            fill(item, "timing", Value::from("หลังอาหาร"));
+            fill(item, "warning", Value::from(""));
+        }
+    }
+
+    if let Some(settings) = doc.get_mut("clinic_settings").and_then(Value::as_object_mut) {
+        fill(settings, "staff_name", Value::from(""));
+        fill(settings, "staff_position", Value::from(""));
+        fill(settings, "license_number", Value::from(""));
+    }
+}
+
+fn migrate_v2_to_v3(doc: &mut Map<String, Value>) {
+    for record in objects_in(doc, "clinic_records") {
+        let Some(items) = record.get_mut("prescriptions").and_then(Value::as_array_mut) else {
+            continue;
+        };
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            for dose in ["morning", "noon", "evening", "before_bed"] {
+                if let Some(value) = item.get_mut(dose) {
+                    *value = Value::from(dose_amount(value));
+                }
+            }
+        }
+    }
+}
+
+fn migrate_v3_to_v4(doc: &mut Map<String, Value>) {
+    for patient in objects_in(doc, "clinic_patients") {
+        let Some(text) = patient.remove("drug_allergy") else {
+            continue;
+        };
+        if !patient.contains_key("allergies") {
+            let allergies = allergy::from_text(text.as_str().unwrap_or(""));
+            patient.insert("allergies".to_string(), serde_json::to_value(allergies).unwrap_or_default());
+        }
+    }
+}
+
+/// A dose from any earlier shape: ticked box = 1, numbers as-is, text parsed
+fn dose_amount(value: &Value) -> f64 {
+    match value {
+        Value::Bool(true) => 1.0,
+        Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        Value::String(s) => s.trim().parse().unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::storage::ClinicData;
+
+    fn doc(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn v1_document() -> Map<String, Value> {
+        doc(json!({
+            "clinic_patients": [{
+                "id": "p1", "hn": "HN-00001", "citizen_id": "", "title": "นาย",
+                "first_name": "สมชาย", "last_name": "ใจดี", "birth_date": null,
+                "drug_allergy": "", "phone": "", "address": "",
+                "created_at": "2023-06-01T03:00:00Z"
+            }],
+            "clinic_records": [{
+                "id": "r1", "patient_id": "p1", "date": "2023-06-01T03:00:00Z",
+                "symptoms": "ไข้", "diagnosis": "", "weight": null, "pressure": "",
+                "prescriptions": [{"name": "Paracetamol", "amount": "10 เม็ด", "usage": "", "duration_days": 3}],
+                "injections": [], "doctor_note": "", "price": 150.0
+            }],
+            "clinic_settings": {
+                "clinic_name": "คลินิก", "clinic_address": "", "clinic_phone": "", "clinic_tax_id": "",
+                "font_size": "large", "theme": "light", "sticker_size": "large", "next_receipt_no": 1
+            }
+        }))
+    }
+
+    #[test]
+    fn test_upgrade_from_v1() {
+        let mut d = v1_document();
+        assert!(migrate(&mut d).unwrap());
+        assert_eq!(d[KEY_SCHEMA_VERSION], CURRENT_SCHEMA_VERSION);
+
+        let data: ClinicData = serde_json::from_value(Value::Object(d)).unwrap();
+        assert_eq!(data.patients[0].blood_group, "");
+        let rx = &data.records[0].prescriptions[0];
+        assert_eq!(rx.morning, 0.0);
+        assert_eq!(rx.timing, "หลังอาหาร");
+        assert_eq!(data.settings.staff_name, "");
+    }
+
+    #[test]
+    fn test_upgrade_from_v2_bool_doses() {
+        let mut d = v1_document();
+        d.insert(KEY_SCHEMA_VERSION.to_string(), json!(2));
+        d["clinic_records"][0]["prescriptions"][0] = json!({
+            "name": "Paracetamol", "amount": "10 เม็ด", "usage": "", "duration_days": 3,
+            "morning": true, "noon": false, "evening": true, "before_bed": "0.5",
+            "timing": "ก่อนอาหาร", "warning": ""
+        });
+
+        assert!(migrate(&mut d).unwrap());
+        let data: ClinicData = serde_json::from_value(Value::Object(d)).unwrap();
+        let rx = &data.records[0].prescriptions[0];
+        assert_eq!((rx.morning, rx.noon, rx.evening, rx.before_bed), (1.0, 0.0, 1.0, 0.5));
+        assert_eq!(rx.timing, "ก่อนอาหาร");
+    }
+
+    #[test]
+    fn test_upgrade_from_v3_allergy_text() {
+        let mut d = v1_document();
+        d.insert(KEY_SCHEMA_VERSION.to_string(), json!(3));
+        d["clinic_patients"][0]["drug_allergy"] = json!("Penicillin, Brufen");
+
+        assert!(migrate(&mut d).unwrap());
+        assert!(d["clinic_patients"][0].get("drug_allergy").is_none());
+        let data: ClinicData = serde_json::from_value(Value::Object(d)).unwrap();
+        let names: Vec<String> = data.patients[0].allergies.iter().map(|a| allergy::allergen_name(&a.allergen)).collect();
+        assert_eq!(names, vec!["กลุ่มเพนิซิลลิน (Penicillins)", "Brufen"]);
+    }
+
+    #[test]
+    fn test_current_version_untouched() {
+        let mut d = v1_document();
+        migrate(&mut d).unwrap();
+        let before = d.clone();
+        assert!(!migrate(&mut d).unwrap());
+        assert_eq!(d, before);
+    }
+
+    #[test]
+    fn test_unversioned_current_data_is_kept() {
+        // Old backups say version 1 but may already hold f64 doses
+        let mut d = v1_document();
+        d["clinic_records"][0]["prescriptions"][0]["morning"] = json!(1.5);
+        migrate(&mut d).unwrap();
+        assert_eq!(d["clinic_records"][0]["prescriptions"][0]["morning"], 1.5);
+    }
+
+    #[test]
+    fn test_empty_document_is_current() {
+        let mut d = Map::new();
+        assert!(!migrate(&mut d).unwrap());
+        assert_eq!(d[KEY_SCHEMA_VERSION], CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_newer_version_rejected() {
+        let mut d = doc(json!({ KEY_SCHEMA_VERSION: CURRENT_SCHEMA_VERSION + 1, "clinic_patients": [] }));
+        assert!(migrate(&mut d).is_err());
+    }
+}
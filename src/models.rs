@@ -16,10 +16,15 @@ pub struct Patient {
     pub blood_group: String,
     #[serde(default)]
     pub underlying_disease: String, // โรคประจำตัว
-    pub drug_allergy: String,
+    #[serde(default)]
+    pub allergies: Vec<Allergy>,    // ประวัติแพ้ยา (schema 4 แทน drug_allergy ที่เป็นข้อความ)
     pub phone: String,
     pub address: String,
     pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>, // อยู่ในถังขยะตั้งแต่เวลานี้
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
@@ -80,6 +85,53 @@ pub struct TreatmentRecord {
     pub injections: Vec<InjectionItem>,
     pub doctor_note: String,
     pub price: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allergy_override: Option<AllergyOverride>, // จ่ายยาที่ผู้ป่วยแพ้ พร้อมเหตุผล
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>, // อยู่ในถังขยะตั้งแต่เวลานี้
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
+}
+
+// ========== NEW: Drug Allergies ==========
+
+/// What the patient is allergic to
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Allergen {
+    /// A drug in the inventory; the name is kept so the entry still reads after
+    /// the drug is renamed or removed
+    Drug { drug_id: String, name: String },
+    /// A group of related drugs, one of allergy::GROUPS
+    Group { group: String },
+    /// Typed by hand, e.g. a drug the clinic doesn't stock
+    Other { name: String },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AllergySeverity {
+    #[default]
+    Unknown,
+    Mild,                        // ผื่น คัน
+    Moderate,                    // บวม หายใจลำบากเล็กน้อย
+    Severe,                      // SJS, anaphylaxis
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Allergy {
+    pub allergen: Allergen,
+    #[serde(default)]
+    pub reaction: String,        // อาการที่เกิด เช่น ผื่น ปากบวม
+    #[serde(default)]
+    pub severity: AllergySeverity,
+}
+
+/// Drugs prescribed although the patient is allergic to them, and why
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct AllergyOverride {
+    pub drugs: Vec<String>,      // ชื่อยาในใบสั่งยาที่ตรงกับประวัติแพ้ยา
+    pub reason: String,
 }
 
 // ========== NEW: Drug Inventory System ==========
@@ -98,6 +150,10 @@ pub struct DrugItem {
     pub description: String,    // คำอธิบาย
     pub default_usage: String,  // วิธีใช้เริ่มต้น
     pub warning: String,        // คำเตือนเริ่มต้น
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>, // อยู่ในถังขยะตั้งแต่เวลานี้
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
 }
 
 impl Default for DrugItem {
@@ -115,6 +171,8 @@ impl Default for DrugItem {
             description: String::new(),
             default_usage: String::new(),
             warning: String::new(),
+            deleted_at: None,
+            updated_at: None,
         }
     }
 }
@@ -141,6 +199,60 @@ pub struct ClinicSettings {
     pub theme: String,            // light, dark, high-contrast
     pub sticker_size: String,     // small, medium, large
     pub next_receipt_no: u32,
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32, // ลบถาวรจากถังขยะหลังกี่วัน (0 = เก็บไว้ตลอด)
+    #[serde(default)]
+    pub backup_schedule: BackupSchedule, // สำรองอัตโนมัติ (โปรแกรมบนเครื่องเท่านั้น)
+    #[serde(default)]
+    pub hn_format: HnFormat,      // รูปแบบเลข HN ที่ออกให้อัตโนมัติ
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+/// When the desktop app backs up by itself, and how many backups it keeps.
+/// Read by the backend too (src-tauri/src/backup.rs).
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct BackupSchedule {
+    pub on_close: bool,         // ตอนปิดโปรแกรม
+    pub daily: bool,            // วันละครั้ง
+    pub before_restore: bool,   // ก่อนกู้คืนและก่อนอัปเดตโปรแกรม
+    pub keep_daily: u32,        // เก็บรายวันกี่วันล่าสุด
+    pub keep_weekly: u32,       // เก็บรายสัปดาห์กี่สัปดาห์
+    pub keep_monthly: u32,      // เก็บรายเดือนกี่เดือน (ทั้งหมดเป็น 0 = ไม่ลบเลย)
+    pub mirror_dir: String,     // โฟลเดอร์สำรองภายนอก เช่น USB (ว่าง = ไม่ใช้)
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        Self {
+            on_close: true,
+            daily: true,
+            before_restore: true,
+            keep_daily: 7,
+            keep_weekly: 4,
+            keep_monthly: 12,
+            mirror_dir: String::new(),
+        }
+    }
+}
+
+/// How new HNs are numbered, e.g. HN-00016, or HN-67-00016 with the year.
+/// See hn.rs.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct HnFormat {
+    pub prefix: String,         // ขึ้นต้นด้วย เช่น HN-
+    pub padding: u32,           // จำนวนหลักของเลขลำดับ
+    pub with_year: bool,        // ใส่ปี พ.ศ. 2 หลัก และเริ่มนับ 1 ใหม่ทุกปี
+}
+
+impl Default for HnFormat {
+    fn default() -> Self {
+        Self { prefix: "HN-".to_string(), padding: 5, with_year: false }
+    }
 }
 
 impl Default for ClinicSettings {
@@ -157,6 +269,9 @@ impl Default for ClinicSettings {
             theme: "light".to_string(),
             sticker_size: "large".to_string(),
             next_receipt_no: 1,
+            trash_retention_days: default_trash_retention_days(),
+            backup_schedule: BackupSchedule::default(),
+            hn_format: HnFormat::default(),
         }
     }
 }
@@ -171,6 +286,10 @@ pub struct Expense {
     pub description: String,
     pub amount: f64,
     pub note: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>, // อยู่ในถังขยะตั้งแต่เวลานี้
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
 }
 
 impl Default for Expense {
@@ -182,6 +301,8 @@ impl Default for Expense {
             description: String::new(),
             amount: 0.0,
             note: String::new(),
+            deleted_at: None,
+            updated_at: None,
         }
     }
 }
@@ -201,6 +322,8 @@ pub struct DrugPurchase {
     pub expiry_date: Option<NaiveDate>,
     pub supplier: String,
     pub note: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
 }
 
 impl Default for DrugPurchase {
@@ -217,6 +340,7 @@ impl Default for DrugPurchase {
             expiry_date: None,
             supplier: String::new(),
             note: String::new(),
+            updated_at: None,
         }
     }
 }
@@ -233,6 +357,10 @@ pub struct Appointment {
     pub reason: String,          // เหตุผลนัด
     pub status: String,          // pending, completed, cancelled
     pub note: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>, // อยู่ในถังขยะตั้งแต่เวลานี้
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
 }
 
 impl Default for Appointment {
@@ -246,6 +374,146 @@ impl Default for Appointment {
             reason: String::new(),
             status: "pending".to_string(),
             note: String::new(),
+            deleted_at: None,
+            updated_at: None,
         }
     }
 }
+
+// ========== NEW: Drug Interactions ==========
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionSeverity {
+    Minor,                       // เฝ้าระวัง
+    #[default]
+    Moderate,                    // ปรับขนาดยาหรือเวลาที่กิน
+    Major,                       // หลีกเลี่ยงถ้าทำได้
+    Contraindicated,             // ห้ามใช้ร่วมกัน
+}
+
+/// Two drugs, or drug classes, that should not be given together without care.
+/// See interactions.rs for how a side is matched against a prescription.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct InteractionRule {
+    pub id: String,
+    pub a: String,               // ชื่อยา หรือรหัสกลุ่มยา เช่น warfarin, nsaids
+    pub b: String,
+    #[serde(default)]
+    pub severity: InteractionSeverity,
+    #[serde(default)]
+    pub advice: String,          // ผลที่เกิดและสิ่งที่ควรทำ
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>, // แก้ไขล่าสุดเมื่อ ใช้ตัดสินตอนซิงก์
+}
+
+// ========== NEW: Audit Log ==========
+
+/// One change to the clinic data. Written by the Store, never edited or removed.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct AuditEntry {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,           // staff_name ในการตั้งค่าขณะที่แก้ไข
+    pub action: String,          // create, update, delete
+    pub entity: String,          // patient, record, drug, settings, expense, drug_purchase, appointment, interaction
+    pub entity_id: String,
+    pub label: String,           // ชื่อที่อ่านเข้าใจได้ เช่น HN และชื่อผู้ป่วย
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+// ========== NEW: Sync ==========
+
+/// An item that was removed outright (purged from the Recycle Bin) or moved to
+/// a yearly archive, kept so sync can remove it on the other computer too
+/// instead of bringing it back
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Tombstone {
+    pub entity: String,          // same names as AuditEntry::entity
+    pub id: String,
+    pub deleted_at: DateTime<Utc>,
+    /// The archive year when the item was archived rather than removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived: Option<i32>,
+}
+
+/// Items that carry when they were last changed. The Store stamps them on
+/// every save; sync keeps whichever copy is newer.
+pub trait Versioned {
+    fn updated_at(&self) -> Option<DateTime<Utc>>;
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>);
+}
+
+impl Versioned for Patient {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for TreatmentRecord {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for DrugItem {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for Expense {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for DrugPurchase {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for Appointment {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+impl Versioned for InteractionRule {
+    fn updated_at(&self) -> Option<DateTime<Utc>> { self.updated_at }
+    fn set_updated_at(&mut self, at: Option<DateTime<Utc>>) { self.updated_at = at; }
+}
+
+// ========== NEW: Recycle Bin ==========
+
+/// Items that go to the Recycle Bin instead of being deleted straight away.
+/// Readers in the Store skip anything with `deleted_at` set.
+pub trait SoftDelete {
+    fn deleted_at(&self) -> Option<DateTime<Utc>>;
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>);
+
+    fn is_deleted(&self) -> bool {
+        self.deleted_at().is_some()
+    }
+}
+
+impl SoftDelete for Patient {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> { self.deleted_at }
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>) { self.deleted_at = at; }
+}
+
+impl SoftDelete for TreatmentRecord {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> { self.deleted_at }
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>) { self.deleted_at = at; }
+}
+
+impl SoftDelete for DrugItem {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> { self.deleted_at }
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>) { self.deleted_at = at; }
+}
+
+impl SoftDelete for Expense {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> { self.deleted_at }
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>) { self.deleted_at = at; }
+}
+
+impl SoftDelete for Appointment {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> { self.deleted_at }
+    fn set_deleted_at(&mut self, at: Option<DateTime<Utc>>) { self.deleted_at = at; }
+}
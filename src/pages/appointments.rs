@@ -3,22 +3,22 @@ use web_sys::HtmlInputElement;
 use uuid::Uuid;
 use crate::models::Appointment;
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::components::{ToastContext, ToastAction, ToastType, toast_error};
 use chrono::prelude::*;
 
 #[function_component(Appointments)]
 pub fn appointments() -> Html {
     let toast = use_context::<ToastContext>();
-    let appointments = use_state(|| Store::get_appointments());
+    let appointments = use_state(Store::get_appointments);
     let show_form = use_state(|| false);
     
     // Form state
-    let patient_id = use_state(|| String::new());
-    let patient_name = use_state(|| String::new());
+    let patient_id = use_state(String::new);
+    let patient_name = use_state(String::new);
     let date_str = use_state(|| (Local::now() + chrono::Duration::days(1)).format("%Y-%m-%d").to_string());
     let time = use_state(|| "09:00".to_string());
-    let reason = use_state(|| String::new());
-    let note = use_state(|| String::new());
+    let reason = use_state(String::new);
+    let note = use_state(String::new);
     
     // View date filter
     let view_date = use_state(|| Local::now().date_naive());
@@ -64,21 +64,35 @@ pub fn appointments() -> Html {
                 reason: (*reason).clone(),
                 status: "pending".to_string(),
                 note: (*note).clone(),
+                deleted_at: None,
+                updated_at: None,
             };
             
-            Store::save_appointment(appointment);
-            appointments.set(Store::get_appointments());
-            
-            // Reset form
-            patient_id.set(String::new());
-            patient_name.set(String::new());
-            reason.set(String::new());
-            note.set(String::new());
-            show_form.set(false);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add("✅ บันทึกนัดหมายแล้ว".to_string(), ToastType::Success));
-            }
+            let appointments = appointments.clone();
+            let patient_id = patient_id.clone();
+            let patient_name = patient_name.clone();
+            let reason = reason.clone();
+            let note = note.clone();
+            let show_form = show_form.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::save_appointment(appointment).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                appointments.set(Store::get_appointments());
+
+                // Reset form
+                patient_id.set(String::new());
+                patient_name.set(String::new());
+                reason.set(String::new());
+                note.set(String::new());
+                show_form.set(false);
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add("✅ บันทึกนัดหมายแล้ว".to_string(), ToastType::Success));
+                }
+            });
         })
     };
     
@@ -90,11 +104,18 @@ pub fn appointments() -> Html {
             let all = Store::get_appointments();
             if let Some(mut apt) = all.into_iter().find(|a| a.id == id) {
                 apt.status = "completed".to_string();
-                Store::update_appointment(apt);
-                appointments.set(Store::get_appointments());
-                if let Some(ref t) = toast {
-                    t.dispatch(ToastAction::Add("✅ เสร็จสิ้นนัดหมาย".to_string(), ToastType::Success));
-                }
+                let appointments = appointments.clone();
+                let toast = toast.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = Store::update_appointment(apt).await {
+                        toast_error(&toast, err);
+                        return;
+                    }
+                    appointments.set(Store::get_appointments());
+                    if let Some(ref t) = toast {
+                        t.dispatch(ToastAction::Add("✅ เสร็จสิ้นนัดหมาย".to_string(), ToastType::Success));
+                    }
+                });
             }
         })
     };
@@ -108,11 +129,18 @@ pub fn appointments() -> Html {
                 let all = Store::get_appointments();
                 if let Some(mut apt) = all.into_iter().find(|a| a.id == id) {
                     apt.status = "cancelled".to_string();
-                    Store::update_appointment(apt);
-                    appointments.set(Store::get_appointments());
-                    if let Some(ref t) = toast {
-                        t.dispatch(ToastAction::Add("❌ ยกเลิกนัดหมายแล้ว".to_string(), ToastType::Error));
-                    }
+                    let appointments = appointments.clone();
+                    let toast = toast.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(err) = Store::update_appointment(apt).await {
+                            toast_error(&toast, err);
+                            return;
+                        }
+                        appointments.set(Store::get_appointments());
+                        if let Some(ref t) = toast {
+                            t.dispatch(ToastAction::Add("❌ ยกเลิกนัดหมายแล้ว".to_string(), ToastType::Error));
+                        }
+                    });
                 }
             }
         })
@@ -120,11 +148,19 @@ pub fn appointments() -> Html {
     
     let on_delete = {
         let appointments = appointments.clone();
+        let toast = toast.clone();
         
         Callback::from(move |id: String| {
             if web_sys::window().unwrap().confirm_with_message("ลบนัดหมายนี้?").unwrap_or(false) {
-                Store::delete_appointment(&id);
-                appointments.set(Store::get_appointments());
+                let appointments = appointments.clone();
+                let toast = toast.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = Store::delete_appointment(&id).await {
+                        toast_error(&toast, err);
+                        return;
+                    }
+                    appointments.set(Store::get_appointments());
+                });
             }
         })
     };
@@ -0,0 +1,242 @@
+use yew::prelude::*;
+use web_sys::{HtmlInputElement, Blob, Url, HtmlAnchorElement};
+use wasm_bindgen::JsCast;
+use chrono::{Local, NaiveDate};
+use crate::archive;
+use crate::audit::{self, ENTITIES, ACTIONS, ACTION_CREATE, ACTION_DELETE, ACTION_PURGE};
+use crate::components::{ToastContext, toast_error};
+use crate::models::AuditEntry;
+use crate::store::Store;
+
+// Showing thousands of rows at once makes the page slow; the CSV always has every match
+const MAX_ROWS: usize = 200;
+
+/// Save `csv` as `<name>_<time>.csv` through the browser's download
+pub fn download_csv(csv: &str, name: &str) {
+    let window = web_sys::window().unwrap();
+    let document = window.document().unwrap();
+
+    let blob_parts = js_sys::Array::new();
+    blob_parts.push(&wasm_bindgen::JsValue::from_str(csv));
+
+    let blob_options = web_sys::BlobPropertyBag::new();
+    blob_options.set_type("text/csv;charset=utf-8");
+
+    if let Ok(blob) = Blob::new_with_str_sequence_and_options(&blob_parts, &blob_options) {
+        if let Ok(url) = Url::create_object_url_with_blob(&blob) {
+            let a: HtmlAnchorElement = document.create_element("a").unwrap().unchecked_into();
+            let filename = format!("{}_{}.csv", name, Local::now().format("%Y%m%d_%H%M%S"));
+            a.set_href(&url);
+            a.set_download(&filename);
+            a.click();
+            let _ = Url::revoke_object_url(&url);
+        }
+    }
+}
+
+fn pretty(value: &Option<serde_json::Value>) -> String {
+    value.as_ref().and_then(|v| serde_json::to_string_pretty(v).ok()).unwrap_or_else(|| "-".to_string())
+}
+
+#[function_component(Audit)]
+pub fn audit_page() -> Html {
+    let toast = use_context::<ToastContext>();
+    let log = use_memo((), |_| Store::get_audit_log());
+    // Older entries sit in the yearly archives and are only read when asked for
+    let has_archives = use_state(|| false);
+    let archived = use_state(|| None::<Vec<AuditEntry>>);
+    {
+        let has_archives = has_archives.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                has_archives.set(archive::years().await.is_ok_and(|years| !years.is_empty()));
+            });
+            || ()
+        });
+    }
+    let on_show_archived = {
+        let archived = archived.clone();
+        Callback::from(move |_: MouseEvent| {
+            let archived = archived.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::audit_log().await {
+                    Ok(entries) => archived.set(Some(entries)),
+                    Err(err) => toast_error(&toast, format!("❌ อ่านประวัติที่เก็บถาวรไม่สำเร็จ: {}", err)),
+                }
+            });
+        })
+    };
+    let entity = use_state(String::new);
+    let action = use_state(String::new);
+    let from = use_state(String::new);
+    let to = use_state(String::new);
+    let search = use_state(String::new);
+    let expanded = use_state(|| None::<String>);
+
+    let from_date = NaiveDate::parse_from_str(&from, "%Y-%m-%d").ok();
+    let to_date = NaiveDate::parse_from_str(&to, "%Y-%m-%d").ok();
+    let query = search.trim().to_lowercase();
+
+    // Newest first
+    let matches: Vec<AuditEntry> = archived
+        .iter()
+        .flatten()
+        .chain(log.iter())
+        .rev()
+        .filter(|e| entity.is_empty() || e.entity == *entity)
+        .filter(|e| action.is_empty() || e.action == *action)
+        .filter(|e| {
+            let day = e.timestamp.with_timezone(&Local).date_naive();
+            from_date.is_none_or(|from| day >= from) && to_date.is_none_or(|to| day <= to)
+        })
+        .filter(|e| {
+            query.is_empty()
+                || e.label.to_lowercase().contains(&query)
+                || e.actor.to_lowercase().contains(&query)
+                || e.entity_id.to_lowercase().contains(&query)
+        })
+        .cloned()
+        .collect();
+
+    let on_export = {
+        let matches = matches.clone();
+        Callback::from(move |_: MouseEvent| download_csv(&audit::to_csv(&matches), "clinic_audit"))
+    };
+
+    let text_input = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: InputEvent| state.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let select_input = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: Event| state.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+
+    html! {
+        <>
+            <div class="page-header">
+                <h1 class="page-title">{ "📜 ประวัติการแก้ไขข้อมูล" }</h1>
+                <p class="page-subtitle">{ "บันทึกทุกการเพิ่ม แก้ไข และลบข้อมูล เพื่อใช้ตรวจสอบย้อนหลัง" }</p>
+            </div>
+
+            // Filter
+            <div class="card mb-4">
+                <div class="flex items-center gap-4 flex-wrap">
+                    <select onchange={select_input(&entity)}>
+                        <option value="" selected={entity.is_empty()}>{ "ทุกประเภท" }</option>
+                        { for ENTITIES.iter().map(|e| html! {
+                            <option value={*e} selected={*entity == *e}>{ audit::entity_name(e) }</option>
+                        })}
+                    </select>
+                    <select onchange={select_input(&action)}>
+                        <option value="" selected={action.is_empty()}>{ "ทุกการกระทำ" }</option>
+                        { for ACTIONS.iter().map(|a| html! {
+                            <option value={*a} selected={*action == *a}>{ audit::action_name(a) }</option>
+                        })}
+                    </select>
+                    <span>{ "ตั้งแต่" }</span>
+                    <input type="date" value={(*from).clone()} oninput={text_input(&from)} />
+                    <span>{ "ถึง" }</span>
+                    <input type="date" value={(*to).clone()} oninput={text_input(&to)} />
+                    <input type="text" placeholder="ค้นหาชื่อ, HN, ผู้แก้ไข" value={(*search).clone()} oninput={text_input(&search)} />
+                    <div class="flex gap-2 ml-auto">
+                        { if *has_archives && archived.is_none() { html! {
+                            <button class="btn btn-secondary" onclick={on_show_archived}>{ "📦 รวมประวัติที่เก็บถาวร" }</button>
+                        } } else { html! {} } }
+                        <button class="btn btn-primary" onclick={on_export} disabled={matches.is_empty()}>
+                            { format!("📥 ส่งออก CSV ({})", matches.len()) }
+                        </button>
+                    </div>
+                </div>
+            </div>
+
+            <div class="card">
+                { if matches.is_empty() {
+                    html! {
+                        <div class="empty-state">
+                            <div class="empty-state-icon">{ "📜" }</div>
+                            <h3 class="empty-state-title">{ "ไม่พบประวัติการแก้ไข" }</h3>
+                            <p class="empty-state-text">{ "ลองเปลี่ยนตัวกรอง หรือเริ่มบันทึกข้อมูลก่อน" }</p>
+                        </div>
+                    }
+                } else {
+                    html! {
+                        <>
+                            <table class="data-table">
+                                <thead>
+                                    <tr>
+                                        <th>{ "เวลา" }</th>
+                                        <th>{ "ผู้แก้ไข" }</th>
+                                        <th>{ "การกระทำ" }</th>
+                                        <th>{ "ประเภท" }</th>
+                                        <th>{ "รายการ" }</th>
+                                        <th>{ "" }</th>
+                                    </tr>
+                                </thead>
+                                <tbody>
+                                    { for matches.iter().take(MAX_ROWS).map(|entry| {
+                                        let is_open = expanded.as_deref() == Some(entry.id.as_str());
+                                        let on_toggle = {
+                                            let expanded = expanded.clone();
+                                            let id = entry.id.clone();
+                                            Callback::from(move |_: MouseEvent| {
+                                                expanded.set(if is_open { None } else { Some(id.clone()) });
+                                            })
+                                        };
+                                        let badge = match entry.action.as_str() {
+                                            ACTION_CREATE => "badge badge-success",
+                                            ACTION_DELETE | ACTION_PURGE => "badge badge-error",
+                                            _ => "badge badge-warning",
+                                        };
+                                        html! {
+                                            <>
+                                                <tr>
+                                                    <td>{ entry.timestamp.with_timezone(&Local).format("%d/%m/%Y %H:%M:%S").to_string() }</td>
+                                                    <td>{ if entry.actor.is_empty() { "-" } else { &entry.actor } }</td>
+                                                    <td><span class={badge}>{ audit::action_name(&entry.action) }</span></td>
+                                                    <td>{ audit::entity_name(&entry.entity) }</td>
+                                                    <td>{ &entry.label }</td>
+                                                    <td>
+                                                        <button class="btn btn-ghost btn-sm" onclick={on_toggle}>
+                                                            { if is_open { "ซ่อน" } else { "ดูรายละเอียด" } }
+                                                        </button>
+                                                    </td>
+                                                </tr>
+                                                { if is_open {
+                                                    html! {
+                                                        <tr>
+                                                            <td colspan="6">
+                                                                <div class="grid grid-cols-2 gap-4">
+                                                                    <div>
+                                                                        <div class="font-bold">{ "ก่อนแก้ไข" }</div>
+                                                                        <pre style="white-space: pre-wrap; font-size: 0.85rem;">{ pretty(&entry.before) }</pre>
+                                                                    </div>
+                                                                    <div>
+                                                                        <div class="font-bold">{ "หลังแก้ไข" }</div>
+                                                                        <pre style="white-space: pre-wrap; font-size: 0.85rem;">{ pretty(&entry.after) }</pre>
+                                                                    </div>
+                                                                </div>
+                                                            </td>
+                                                        </tr>
+                                                    }
+                                                } else { html! {} } }
+                                            </>
+                                        }
+                                    })}
+                                </tbody>
+                            </table>
+                            { if matches.len() > MAX_ROWS {
+                                html! {
+                                    <p class="text-muted" style="margin-top: 1rem; text-align: center;">
+                                        { format!("แสดง {} รายการล่าสุดจากทั้งหมด {} รายการ ส่งออก CSV เพื่อดูทั้งหมด", MAX_ROWS, matches.len()) }
+                                    </p>
+                                }
+                            } else { html! {} } }
+                        </>
+                    }
+                }}
+            </div>
+        </>
+    }
+}
@@ -1,6 +1,8 @@
 use yew::prelude::*;
 use crate::models::TreatmentRecord;
 use crate::store::Store;
+use crate::allergy;
+use crate::thai_id;
 use chrono::prelude::*;
 use gloo::timers::callback::Timeout;
 use yew_router::prelude::use_navigator;
@@ -14,7 +16,7 @@ pub struct Props {
 #[function_component(Document)]
 pub fn document(props: &Props) -> Html {
     let record = use_state(|| -> Option<TreatmentRecord> {
-        Store::get_records().into_iter().find(|r| r.id == props.id)
+        Store::get_record(&props.id)
     });
     
     let settings = Store::get_settings();
@@ -33,7 +35,7 @@ pub fn document(props: &Props) -> Html {
         return html! { <div class="print-document"><p>{ "ไม่พบข้อมูล" }</p></div> };
     }
     let r = record.as_ref().unwrap();
-    let patient = Store::get_patients().into_iter().find(|p| p.id == r.patient_id);
+    let patient = Store::get_patient(&r.patient_id);
     if patient.is_none() {
         return html! { <div class="print-document"><p>{ "ไม่พบข้อมูลผู้ป่วย" }</p></div> };
     }
@@ -61,7 +63,7 @@ pub fn document(props: &Props) -> Html {
                     <h1 style="margin: 0; font-size: 1.3rem;">{ &settings.clinic_name }</h1>
                     <p style="margin: 0.25rem 0 0; font-size: 0.85rem;">{ &settings.clinic_address }</p>
                     <p style="margin: 0; font-size: 0.8rem;">
-                        { format!("โทร: {} • Tax ID: {}", settings.clinic_phone, settings.clinic_tax_id) }
+                        { format!("โทร: {} • Tax ID: {}", settings.clinic_phone, thai_id::format(&settings.clinic_tax_id)) }
                     </p>
                 </div>
                 
@@ -190,8 +192,8 @@ pub fn document(props: &Props) -> Html {
                     <div>
                         <p style="margin: 0;"><strong>{ "ผู้ป่วย/Patient/လူနာ:" }</strong> { format!("{}{} {}", p.title, p.first_name, p.last_name) }</p>
                         <p style="margin: 0.15rem 0 0;"><strong>{ "HN:" }</strong> { &p.hn }</p>
-                        { if !p.drug_allergy.is_empty() && p.drug_allergy != "ไม่มี" {
-                            html! { <p style="margin: 0.15rem 0 0; color: #dc2626;"><strong>{ "⚠️ แพ้ยา/Allergy:" }</strong> { &p.drug_allergy }</p> }
+                        { if !p.allergies.is_empty() {
+                            html! { <p style="margin: 0.15rem 0 0; color: #dc2626;"><strong>{ "⚠️ แพ้ยา/Allergy:" }</strong> { allergy::summary(&p.allergies) }</p> }
                         } else { html! {} }}
                     </div>
                     <div style="text-align: right;">
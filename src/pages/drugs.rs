@@ -1,27 +1,27 @@
 use yew::prelude::*;
 use crate::models::DrugItem;
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::components::{ToastContext, ToastAction, ToastType, toast_error, InteractionRules};
 use web_sys::HtmlInputElement;
 use uuid::Uuid;
 
 #[function_component(Drugs)]
 pub fn drugs() -> Html {
     let toast = use_context::<ToastContext>();
-    let drugs = use_state(|| Store::get_drugs());
+    let drugs = use_state(Store::get_drugs);
     let show_form = use_state(|| false);
     let editing = use_state(|| None::<DrugItem>);
     
     // Form state
-    let name = use_state(|| String::new());
+    let name = use_state(String::new);
     let unit = use_state(|| "เม็ด".to_string());
     let stock = use_state(|| "0".to_string());
     let min_stock = use_state(|| "10".to_string());
     let cost_price = use_state(|| "0".to_string());
     let sell_price = use_state(|| "0".to_string());
     let category = use_state(|| "ยาทั่วไป".to_string());
-    let default_usage = use_state(|| String::new());
-    let warning = use_state(|| String::new());
+    let default_usage = use_state(String::new);
+    let warning = use_state(String::new);
     
     let low_stock = Store::get_low_stock_drugs();
     let expiring = Store::get_expiring_drugs();
@@ -93,14 +93,21 @@ pub fn drugs() -> Html {
         let drugs = drugs.clone();
         let toast = toast.clone();
         Callback::from(move |drug_id: String| {
-            Store::delete_drug(&drug_id);
-            drugs.set(Store::get_drugs());
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "🗑️ ลบยาเรียบร้อยแล้ว".to_string(),
-                    ToastType::Success
-                ));
-            }
+            let drugs = drugs.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::delete_drug(&drug_id).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                drugs.set(Store::get_drugs());
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(
+                        "🗑️ ย้ายยาไปถังขยะแล้ว".to_string(),
+                        ToastType::Success
+                    ));
+                }
+            });
         })
     };
     
@@ -136,23 +143,34 @@ pub fn drugs() -> Html {
                 description: String::new(),
                 default_usage: (*default_usage).clone(),
                 warning: (*warning).clone(),
+                deleted_at: None,
+                updated_at: None,
             };
             
-            let msg = if editing.is_some() {
-                Store::update_drug(drug);
-                "✅ แก้ไขข้อมูลยาเรียบร้อยแล้ว!"
-            } else {
-                Store::save_drug(drug);
-                "✅ เพิ่มยาใหม่เรียบร้อยแล้ว!"
-            };
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(msg.to_string(), ToastType::Success));
-            }
-            
-            drugs.set(Store::get_drugs());
-            clear_form.emit(());
-            show_form.set(false);
+            let is_edit = editing.is_some();
+            let drugs = drugs.clone();
+            let show_form = show_form.clone();
+            let clear_form = clear_form.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let (result, msg) = if is_edit {
+                    (Store::update_drug(drug).await, "✅ แก้ไขข้อมูลยาเรียบร้อยแล้ว!")
+                } else {
+                    (Store::save_drug(drug).await, "✅ เพิ่มยาใหม่เรียบร้อยแล้ว!")
+                };
+                if let Err(err) = result {
+                    toast_error(&toast, err);
+                    return;
+                }
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(msg.to_string(), ToastType::Success));
+                }
+
+                drugs.set(Store::get_drugs());
+                clear_form.emit(());
+                show_form.set(false);
+            });
         })
     };
 
@@ -431,6 +449,8 @@ pub fn drugs() -> Html {
                     }
                 }}
             </div>
+
+            <InteractionRules />
         </>
     }
 }
@@ -1,8 +1,9 @@
 use yew::prelude::*;
 use web_sys::HtmlInputElement;
-use crate::models::Patient;
+use crate::models::{Allergy, Patient};
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::thai_id;
+use crate::components::{AllergyEditor, ToastContext, ToastAction, ToastType, toast_error};
 
 // Helper to filter non-digits
 fn digits_only(s: &str) -> String {
@@ -25,9 +26,7 @@ pub fn edit_patient(props: &Props) -> Html {
     let toast = use_context::<ToastContext>();
     
     // Get existing patient
-    let existing_patient = Store::get_patients()
-        .into_iter()
-        .find(|p| p.id == props.patient_id);
+    let existing_patient = Store::get_patient(&props.patient_id);
     
     if existing_patient.is_none() {
         return html! {
@@ -57,13 +56,16 @@ pub fn edit_patient(props: &Props) -> Html {
     });
     let blood_group = use_state(|| patient.blood_group.clone());
     let underlying_disease = use_state(|| patient.underlying_disease.clone());
-    let drug_allergy = use_state(|| patient.drug_allergy.clone());
+    let allergies = use_state(|| patient.allergies.clone());
     let phone = use_state(|| patient.phone.clone());
     let address = use_state(|| patient.address.clone());
     let created_at = use_state(|| patient.created_at);
     
     // Validation
-    let form_valid = !(*hn).is_empty() && !(*first_name).is_empty() && !(*last_name).is_empty();
+    let filled = !(*hn).is_empty() && !(*first_name).is_empty() && !(*last_name).is_empty();
+    // Optional, but a number that fails the check digit is a typo
+    let citizen_problem = (!(*citizen_id).is_empty()).then(|| thai_id::problem(&citizen_id)).flatten();
+    let form_valid = filled && citizen_problem.is_none();
 
     let onsubmit = {
         let patient_id = patient_id.clone();
@@ -76,7 +78,7 @@ pub fn edit_patient(props: &Props) -> Html {
         let age = age.clone();
         let blood_group = blood_group.clone();
         let underlying_disease = underlying_disease.clone();
-        let drug_allergy = drug_allergy.clone();
+        let allergies = allergies.clone();
         let phone = phone.clone();
         let address = address.clone();
         let created_at = created_at.clone();
@@ -103,22 +105,31 @@ pub fn edit_patient(props: &Props) -> Html {
                 age: (*age).parse().ok(),
                 blood_group: (*blood_group).clone(),
                 underlying_disease: (*underlying_disease).clone(),
-                drug_allergy: (*drug_allergy).clone(),
+                allergies: (*allergies).clone(),
                 phone: (*phone).clone(),
                 address: (*address).clone(),
                 created_at: *created_at,
+                deleted_at: None,
+                updated_at: None,
             };
 
-            Store::update_patient(updated_patient);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "✅ บันทึกการแก้ไขเรียบร้อยแล้ว!".to_string(),
-                    ToastType::Success
-                ));
-            }
-            
-            navigator.back();
+            let navigator = navigator.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::update_patient(updated_patient).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(
+                        "✅ บันทึกการแก้ไขเรียบร้อยแล้ว!".to_string(),
+                        ToastType::Success
+                    ));
+                }
+
+                navigator.back();
+            });
         })
     };
 
@@ -188,7 +199,14 @@ pub fn edit_patient(props: &Props) -> Html {
 
                         // Citizen ID
                         <div class="form-group">
-                            <label class="form-label">{ "เลขบัตรประชาชน" }</label>
+                            <label class="form-label">
+                                { "เลขบัตรประชาชน" }
+                                { match &citizen_problem {
+                                    Some(problem) => html! { <span class="badge badge-warning" style="margin-left: 5px;">{ problem }</span> },
+                                    None if !(*citizen_id).is_empty() => html! { <span class="badge badge-success" style="margin-left: 5px;">{ thai_id::format(&citizen_id) }</span> },
+                                    None => html! {},
+                                }}
+                            </label>
                             <input type="text" 
                                 maxlength="13"
                                 value={(*citizen_id).clone()}
@@ -268,19 +286,15 @@ pub fn edit_patient(props: &Props) -> Html {
                         </div>
                         
                         // Drug Allergy
-                        <div class="form-group">
+                        <div class="form-group" style="grid-column: 1 / -1;">
                             <label class="form-label">
                                 { "แพ้ยา" }
                                 <span class="badge badge-error" style="margin-left: 5px;">{ "สำคัญ" }</span>
                             </label>
-                            <input type="text" value={(*drug_allergy).clone()}
-                                placeholder="ระบุชื่อยาที่แพ้ หรือ 'ไม่มี'"
-                                oninput={
-                                    let drug_allergy = drug_allergy.clone();
-                                    Callback::from(move |e: InputEvent| {
-                                        let input: HtmlInputElement = e.target_unchecked_into();
-                                        drug_allergy.set(input.value());
-                                    })
+                            <AllergyEditor allergies={(*allergies).clone()}
+                                on_change={
+                                    let allergies = allergies.clone();
+                                    Callback::from(move |list: Vec<Allergy>| allergies.set(list))
                                 } />
                         </div>
 
@@ -318,8 +332,10 @@ pub fn edit_patient(props: &Props) -> Html {
                             { "← ยกเลิก" }
                         </button>
                         <div>
-                            { if !form_valid {
+                            { if !filled {
                                 html! { <span class="text-warning" style="margin-right: 1rem;">{ "⚠️ กรุณากรอกข้อมูลที่มี * ให้ครบ" }</span> }
+                            } else if citizen_problem.is_some() {
+                                html! { <span class="text-warning" style="margin-right: 1rem;">{ "⚠️ เลขบัตรประชาชนไม่ถูกต้อง" }</span> }
                             } else { html! {} }}
                             <button type="submit" class="btn btn-primary btn-lg" disabled={!form_valid}>
                                 { "💾 บันทึกการแก้ไข" }
@@ -4,20 +4,20 @@ use chrono::Utc;
 use uuid::Uuid;
 use crate::models::Expense;
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::components::{ToastContext, ToastAction, ToastType, toast_error};
 use chrono::prelude::*;
 
 #[function_component(Expenses)]
 pub fn expenses() -> Html {
     let toast = use_context::<ToastContext>();
-    let expenses = use_state(|| Store::get_expenses());
+    let expenses = use_state(Store::get_expenses);
     let show_form = use_state(|| false);
     
     // Form state
     let category = use_state(|| "อื่นๆ".to_string());
-    let description = use_state(|| String::new());
-    let amount = use_state(|| String::new());
-    let note = use_state(|| String::new());
+    let description = use_state(String::new);
+    let amount = use_state(String::new);
+    let note = use_state(String::new);
     let date_str = use_state(|| Local::now().format("%Y-%m-%d").to_string());
     
     // Filter state
@@ -65,21 +65,35 @@ pub fn expenses() -> Html {
                 description: (*description).clone(),
                 amount: amount_val,
                 note: (*note).clone(),
+                deleted_at: None,
+                updated_at: None,
             };
             
-            Store::save_expense(expense);
-            expenses.set(Store::get_expenses());
-            
-            // Reset form
-            category.set("อื่นๆ".to_string());
-            description.set(String::new());
-            amount.set(String::new());
-            note.set(String::new());
-            show_form.set(false);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add("✅ บันทึกค่าใช้จ่ายแล้ว".to_string(), ToastType::Success));
-            }
+            let expenses = expenses.clone();
+            let category = category.clone();
+            let description = description.clone();
+            let amount = amount.clone();
+            let note = note.clone();
+            let show_form = show_form.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::save_expense(expense).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                expenses.set(Store::get_expenses());
+
+                // Reset form
+                category.set("อื่นๆ".to_string());
+                description.set(String::new());
+                amount.set(String::new());
+                note.set(String::new());
+                show_form.set(false);
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add("✅ บันทึกค่าใช้จ่ายแล้ว".to_string(), ToastType::Success));
+                }
+            });
         })
     };
     
@@ -89,16 +103,23 @@ pub fn expenses() -> Html {
         
         Callback::from(move |id: String| {
             if web_sys::window().unwrap().confirm_with_message("ยืนยันการลบรายการนี้?").unwrap_or(false) {
-                Store::delete_expense(&id);
-                expenses.set(Store::get_expenses());
-                if let Some(ref t) = toast {
-                    t.dispatch(ToastAction::Add("🗑️ ลบรายการแล้ว".to_string(), ToastType::Success));
-                }
+                let expenses = expenses.clone();
+                let toast = toast.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = Store::delete_expense(&id).await {
+                        toast_error(&toast, err);
+                        return;
+                    }
+                    expenses.set(Store::get_expenses());
+                    if let Some(ref t) = toast {
+                        t.dispatch(ToastAction::Add("🗑️ ลบรายการแล้ว".to_string(), ToastType::Success));
+                    }
+                });
             }
         })
     };
     
-    let expense_categories = vec!["ค่าเช่า", "ค่าน้ำ", "ค่าไฟ", "ค่ายา", "ค่าอุปกรณ์", "ค่าจ้าง", "อื่นๆ"];
+    let expense_categories = ["ค่าเช่า", "ค่าน้ำ", "ค่าไฟ", "ค่ายา", "ค่าอุปกรณ์", "ค่าจ้าง", "อื่นๆ"];
 
     html! {
         <>
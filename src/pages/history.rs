@@ -1,10 +1,49 @@
 use yew::prelude::*;
-use crate::models::Patient;
+use crate::archive;
+use crate::models::{Patient, TreatmentRecord};
 use crate::store::Store;
+use crate::components::{ToastContext, toast_error};
 use chrono::prelude::*;
 use yew_router::prelude::{Link, use_navigator};
 use crate::Route;
 
+/// A record from an archived year: shown as it was, with nothing to print or change
+fn archived_item(r: &TreatmentRecord) -> Html {
+    html! {
+        <div class="history-item" style="opacity: 0.85;">
+            <div class="history-item-header">
+                <div class="history-item-date">
+                    { "📅 " }{ r.date.with_timezone(&Local).format("%d/%m/%Y เวลา %H:%M น.").to_string() }
+                    <span class="badge badge-accent" style="margin-left: 0.5rem;">{ "📦 เก็บถาวร" }</span>
+                </div>
+                <div class="history-item-price">{ format!("฿{:.2}", r.price) }</div>
+            </div>
+            <div class="history-item-details">
+                <div>
+                    <div class="history-item-label">{ "การวินิจฉัย" }</div>
+                    <div style="font-weight: 600;">{ &r.diagnosis }</div>
+                </div>
+                <div>
+                    <div class="history-item-label">{ "อาการ" }</div>
+                    <div>{ &r.symptoms }</div>
+                </div>
+            </div>
+            { if r.prescriptions.is_empty() { html! {} } else {
+                html! {
+                    <div class="history-item-rx">
+                        <div class="history-item-label">{ "💊 รายการยา" }</div>
+                        <ul style="padding-left: 1.5rem; margin: 0.5rem 0 0;">
+                            { for r.prescriptions.iter().map(|rx| html! {
+                                <li><strong>{ &rx.name }</strong>{ format!(" - {}", rx.amount) }</li>
+                            })}
+                        </ul>
+                    </div>
+                }
+            } }
+        </div>
+    }
+}
+
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub id: String,
@@ -13,12 +52,41 @@ pub struct Props {
 #[function_component(History)]
 pub fn history(props: &Props) -> Html {
     let navigator = use_navigator().unwrap();
+    let toast = use_context::<ToastContext>();
     
     let patient = use_state(|| -> Option<Patient> {
-        Store::get_patients().into_iter().find(|p| p.id == props.id)
+        Store::get_patient(&props.id)
     });
     
     let records = use_state(|| Store::get_records_by_patient(&props.id));
+    // Archived years exist at all; their records are only read when asked for
+    let has_archives = use_state(|| false);
+    let archived = use_state(|| None::<Vec<TreatmentRecord>>);
+    {
+        let has_archives = has_archives.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                has_archives.set(archive::years().await.is_ok_and(|years| !years.is_empty()));
+            });
+            || ()
+        });
+    }
+    let on_show_archived = {
+        let archived = archived.clone();
+        let toast = toast.clone();
+        let id = props.id.clone();
+        Callback::from(move |_: MouseEvent| {
+            let archived = archived.clone();
+            let toast = toast.clone();
+            let id = id.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::records_of_patient(&id).await {
+                    Ok(records) => archived.set(Some(records)),
+                    Err(err) => toast_error(&toast, format!("❌ อ่านข้อมูลเก่าที่เก็บถาวรไม่สำเร็จ: {}", err)),
+                }
+            });
+        })
+    };
 
     if patient.is_none() {
         return html! {
@@ -36,7 +104,7 @@ pub fn history(props: &Props) -> Html {
     // Sort records by date descending
     let sorted_records = {
         let mut r = (*records).clone();
-        r.sort_by(|a, b| b.date.cmp(&a.date));
+        r.sort_by_key(|b| std::cmp::Reverse(b.date));
         r
     };
 
@@ -54,10 +122,18 @@ pub fn history(props: &Props) -> Html {
                     <button class="btn btn-danger" onclick={
                         let id = props.id.clone();
                         let navigator = navigator.clone();
+                        let toast = toast.clone();
                         move |_| {
-                            if web_sys::window().unwrap().confirm_with_message("⚠️ ยืนยันการลบข้อมูลผู้ป่วยและประวัติทั้งหมด? (ไม่สามารถกู้คืนได้)").unwrap() {
-                                Store::delete_patient(&id);
-                                navigator.push(&Route::Search);
+                            if web_sys::window().unwrap().confirm_with_message("⚠️ ยืนยันการลบข้อมูลผู้ป่วยและประวัติทั้งหมด? (ย้ายไปถังขยะ กู้คืนได้ที่หน้าถังขยะ)").unwrap() {
+                                let id = id.clone();
+                                let navigator = navigator.clone();
+                                let toast = toast.clone();
+                                wasm_bindgen_futures::spawn_local(async move {
+                                    match Store::delete_patient(&id).await {
+                                        Ok(()) => navigator.push(&Route::Search),
+                                        Err(err) => toast_error(&toast, err),
+                                    }
+                                });
                             }
                         }
                     }>
@@ -147,6 +223,14 @@ pub fn history(props: &Props) -> Html {
                                             </div>
                                         }
                                     } else { html! {} }}
+
+                                    { if let Some(o) = &r.allergy_override {
+                                        html! {
+                                            <div class="patient-header-allergy" style="margin-top: 0.5rem;">
+                                                { format!("⛔ จ่ายยาที่แพ้ ({}) เหตุผล: {}", o.drugs.join(", "), o.reason) }
+                                            </div>
+                                        }
+                                    } else { html! {} }}
                                     
                                     // Action buttons
                                     <div class="history-item-actions">
@@ -172,6 +256,28 @@ pub fn history(props: &Props) -> Html {
                     </div>
                 }
             }}
+
+            { match archived.as_ref() {
+                _ if !*has_archives => html! {},
+                None => html! {
+                    <div class="flex justify-center" style="margin-top: 1.5rem;">
+                        <button class="btn btn-secondary btn-lg" onclick={on_show_archived}>
+                            { "📦 ดูประวัติปีเก่าที่เก็บถาวร" }
+                        </button>
+                    </div>
+                },
+                Some(list) if list.is_empty() => html! {
+                    <p class="text-muted" style="margin-top: 1.5rem; text-align: center;">{ "ไม่มีประวัติการรักษาในข้อมูลที่เก็บถาวร" }</p>
+                },
+                Some(list) => html! {
+                    <>
+                        <h3 style="margin: 1.5rem 0 1rem;">{ format!("📦 ประวัติที่เก็บถาวร ({} ครั้ง) ดูได้อย่างเดียว", list.len()) }</h3>
+                        <div class="history-timeline">
+                            { for list.iter().map(archived_item) }
+                        </div>
+                    </>
+                },
+            } }
         </>
     }
 }
@@ -1,5 +1,6 @@
 use yew::prelude::*;
 use crate::store::Store;
+use crate::tauri_bridge::{self, BackupStatus};
 use yew_router::prelude::Link;
 use crate::Route;
 use chrono::prelude::*;
@@ -20,25 +21,17 @@ fn format_thai_date() -> String {
 
 #[function_component(Home)]
 pub fn home() -> Html {
-    let patients = Store::get_patients();
-    let records = Store::get_records();
     let low_stock_drugs = Store::get_low_stock_drugs();
     let expiring_drugs = Store::get_expiring_drugs();
     let today_appointments = Store::get_today_appointments();
     
-    let total_patients = patients.len();
-    let total_records = records.len();
+    let total_patients = Store::patient_count();
+    let total_records = Store::record_count();
     
     // Calculate THIS MONTH's revenue (reset each month)
     let now = Local::now();
     let current_month = now.month();
-    let current_year = now.year();
-    let monthly_revenue: f64 = records.iter()
-        .filter(|r| {
-            r.date.month() == current_month && r.date.year() == current_year
-        })
-        .map(|r| r.price)
-        .sum();
+    let monthly_revenue = Store::get_monthly_revenue(now.year(), current_month);
     
     let today_revenue = Store::get_today_revenue();
     let today_revenue = if today_revenue == 0.0 { 0.0_f64 } else { today_revenue.max(0.0) };
@@ -52,6 +45,23 @@ pub fn home() -> Html {
     
     let current_date = format_thai_date();
 
+    // Desktop app only: the browser keeps no backups folder
+    let backup_status = use_state(|| None::<BackupStatus>);
+    {
+        let backup_status = backup_status.clone();
+        use_effect_with((), move |_| {
+            if tauri_bridge::is_tauri() {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match tauri_bridge::backup_status().await {
+                        Ok(status) => backup_status.set(Some(status)),
+                        Err(err) => gloo::console::error!(format!("Failed to read the backup status: {}", err)),
+                    }
+                });
+            }
+            || ()
+        });
+    }
+
     html! {
         <>
             // Header with greeting
@@ -92,6 +102,33 @@ pub fn home() -> Html {
                 }
             } else { html! {} }}
             
+            { match &*backup_status {
+                Some(status) => {
+                    let time = status.last_backup;
+                    let stale = time.is_none_or(|t| now.naive_local() - t > chrono::Duration::days(2));
+                    let text = match time {
+                        Some(t) => format!("สำรองข้อมูลล่าสุด {}", t.format("%d/%m/%Y %H:%M")),
+                        None => "ยังไม่เคยสำรองข้อมูล".to_string(),
+                    };
+                    html! {
+                        <div class="alerts-row">
+                            <div class={classes!("alert-compact", stale.then_some("warning"))}>
+                                <span class="alert-icon">{ "💾" }</span>
+                                <span>{ text }</span>
+                                <Link<Route> to={Route::Settings} classes="alert-link">{ "ตั้งค่า →" }</Link<Route>>
+                            </div>
+                            { for status.mirror_warning.iter().map(|warning| html! {
+                                <div class="alert-compact warning">
+                                    <span class="alert-icon">{ "🔌" }</span>
+                                    <span>{ warning }</span>
+                                </div>
+                            }) }
+                        </div>
+                    }
+                }
+                None => html! {},
+            }}
+            
             // Today's Stats - Clean 4-column grid
             <div class="stats-grid-4">
                 <div class="stat-card-minimal">
@@ -176,7 +213,7 @@ pub fn home() -> Html {
                         html! {
                             <div class="appointments-list">
                                 { for today_appointments.iter().take(5).map(|apt| {
-                                    let patient = patients.iter().find(|p| p.id == apt.patient_id);
+                                    let patient = Store::get_patient(&apt.patient_id);
                                     let patient_name = patient.map(|p| format!("{} {}", p.first_name, p.last_name))
                                         .unwrap_or_else(|| "-".to_string());
                                     html! {
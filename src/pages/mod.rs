@@ -12,6 +12,8 @@ pub mod settings;
 pub mod edit_patient;
 pub mod expenses;
 pub mod appointments;
+pub mod audit;
+pub mod trash;
 
 pub use home::Home;
 pub use register::Register;
@@ -27,3 +29,5 @@ pub use settings::Settings;
 pub use edit_patient::EditPatient;
 pub use expenses::Expenses;
 pub use appointments::Appointments;
+pub use audit::Audit;
+pub use trash::RecycleBin;
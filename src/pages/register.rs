@@ -2,9 +2,12 @@ use yew::prelude::*;
 use web_sys::HtmlInputElement;
 use chrono::{Utc, Datelike};
 use uuid::Uuid;
-use crate::models::Patient;
+use crate::models::{Allergy, Patient};
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::thai_id;
+use crate::Route;
+use yew_router::prelude::Link;
+use crate::components::{AllergyEditor, ToastContext, ToastAction, ToastType, toast_error};
 
 // Helper to filter non-digits
 fn digits_only(s: &str) -> String {
@@ -22,86 +25,98 @@ pub fn register() -> Html {
     let toast = use_context::<ToastContext>();
     
     // Form state
-    let hn = use_state(|| String::new()); // No longer default, manual entry
-    let citizen_id = use_state(|| String::new());
+    // HN is issued on save; typed by hand only for patients with an old paper card
+    let manual_hn = use_state(|| false);
+    let hn = use_state(String::new);
+    let citizen_id = use_state(String::new);
     let title = use_state(|| "นาย".to_string());
-    let first_name = use_state(|| String::new());
-    let last_name = use_state(|| String::new());
-    let birth_date = use_state(|| String::new());
-    let age = use_state(|| String::new()); // อายุ
+    let first_name = use_state(String::new);
+    let last_name = use_state(String::new);
+    let birth_date = use_state(String::new);
+    let age = use_state(String::new); // อายุ
     let blood_group = use_state(|| "ไม่ทราบ".to_string());
-    let underlying_disease = use_state(|| String::new());
-    let drug_allergy = use_state(|| String::new());
-    let phone = use_state(|| String::new());
-    let address = use_state(|| String::new());
+    let underlying_disease = use_state(String::new);
+    let allergies = use_state(Vec::new);
+    let phone = use_state(String::new);
+    let address = use_state(String::new);
     
     // Validation states
-    let form_valid = !(*hn).is_empty() && !(*first_name).is_empty() && !(*last_name).is_empty();
+    let filled = (!*manual_hn || !(*hn).trim().is_empty()) && !(*first_name).is_empty() && !(*last_name).is_empty();
+    // Optional, but a number that fails the check digit is a typo
+    let citizen_problem = (!(*citizen_id).is_empty()).then(|| thai_id::problem(&citizen_id)).flatten();
+    let form_valid = filled && citizen_problem.is_none();
+
+    // The patient as entered so far, to look for someone already registered
+    let draft = Patient {
+        id: String::new(),
+        hn: (*hn).trim().to_string(),
+        citizen_id: (*citizen_id).clone(),
+        title: (*title).clone(),
+        first_name: (*first_name).clone(),
+        last_name: (*last_name).clone(),
+        birth_date: chrono::NaiveDate::parse_from_str(&birth_date, "%Y-%m-%d").ok(),
+        age: (*age).parse().ok(),
+        blood_group: (*blood_group).clone(),
+        underlying_disease: (*underlying_disease).clone(),
+        allergies: (*allergies).clone(),
+        phone: (*phone).clone(),
+        address: (*address).clone(),
+        created_at: Utc::now(),
+        deleted_at: None,
+        updated_at: None,
+    };
+    let duplicates = Store::possible_duplicates(&draft);
 
     let onsubmit = {
-        let hn = hn.clone();
-        let citizen_id = citizen_id.clone();
-        let title = title.clone();
-        let first_name = first_name.clone();
-        let last_name = last_name.clone();
-        let birth_date = birth_date.clone();
-        let age = age.clone();
-        let blood_group = blood_group.clone();
-        let underlying_disease = underlying_disease.clone();
-        let drug_allergy = drug_allergy.clone();
-        let phone = phone.clone();
-        let address = address.clone();
+        let manual_hn = manual_hn.clone();
+        let draft = draft.clone();
+        let count = duplicates.len();
         let navigator = navigator.clone();
         let toast = toast.clone();
 
         Callback::from(move |e: SubmitEvent| {
             e.prevent_default();
-            
-            // Check if HN already exists (simple check)
-            let existing_patients = Store::get_patients();
-            if existing_patients.iter().any(|p| p.hn == *hn) {
-                 if let Some(ref t) = toast {
+
+            if count > 0 {
+                let message = format!(
+                    "พบผู้ป่วยที่อาจเป็นคนเดียวกัน {} คน\nถ้าเคยลงทะเบียนแล้ว ให้ใช้ประวัติเดิมแทนการลงทะเบียนใหม่\n\nยืนยันว่าเป็นผู้ป่วยใหม่?",
+                    count
+                );
+                if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                    return;
+                }
+            }
+
+            let new_patient = Patient { id: Uuid::new_v4().to_string(), created_at: Utc::now(), ..draft.clone() };
+
+            let manual_hn = *manual_hn;
+            let hn = draft.hn.clone();
+            let navigator = navigator.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                // Store checks the HN typed by hand isn't taken
+                let saved = if manual_hn {
+                    Store::save_patient(new_patient).await.map(|_| hn)
+                } else {
+                    Store::register_patient(new_patient).await
+                };
+                let assigned = match saved {
+                    Ok(assigned) => assigned,
+                    Err(err) => {
+                        toast_error(&toast, err);
+                        return;
+                    }
+                };
+
+                if let Some(ref t) = toast {
                     t.dispatch(ToastAction::Add(
-                        "❌ เลข HN นี้มีในระบบแล้ว".to_string(),
-                        ToastType::Error
+                        format!("✅ บันทึกข้อมูลผู้ป่วยเรียบร้อยแล้ว! เลข HN {}", assigned),
+                        ToastType::Success
                     ));
                 }
-                return;
-            }
-            
-            let bd = if (*birth_date).is_empty() {
-                None
-            } else {
-                chrono::NaiveDate::parse_from_str(&birth_date, "%Y-%m-%d").ok()
-            };
-            
-            let new_patient = Patient {
-                id: Uuid::new_v4().to_string(),
-                hn: (*hn).clone(),
-                citizen_id: (*citizen_id).clone(),
-                title: (*title).clone(),
-                first_name: (*first_name).clone(),
-                last_name: (*last_name).clone(),
-                birth_date: bd,
-                age: (*age).parse().ok(),
-                blood_group: (*blood_group).clone(),
-                underlying_disease: (*underlying_disease).clone(),
-                drug_allergy: (*drug_allergy).clone(),
-                phone: (*phone).clone(),
-                address: (*address).clone(),
-                created_at: Utc::now(),
-            };
 
-            Store::save_patient(new_patient);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "✅ บันทึกข้อมูลผู้ป่วยเรียบร้อยแล้ว!".to_string(),
-                    ToastType::Success
-                ));
-            }
-            
-            navigator.push(&crate::Route::Search);
+                navigator.push(&Route::Search);
+            });
         })
     };
 
@@ -117,17 +132,40 @@ pub fn register() -> Html {
                     <div class="grid grid-cols-2 gap-4">
                         // HN
                         <div class="form-group">
-                            <label class="form-label">{ "เลข HN * (กรอกตามบัตร)" }</label>
-                            <input type="text" value={(*hn).clone()} required=true
-                                placeholder="เช่น 66001"
-                                style="font-weight: bold; font-family: monospace;" 
-                                oninput={
-                                    let hn = hn.clone();
-                                    Callback::from(move |e: InputEvent| {
-                                        let input: HtmlInputElement = e.target_unchecked_into();
-                                        hn.set(input.value());
-                                    })
-                                } />
+                            <label class="form-label flex justify-between items-center">
+                                <span>{ if *manual_hn { "เลข HN * (กรอกตามบัตร)" } else { "เลข HN (ออกให้อัตโนมัติ)" } }</span>
+                                <label class="flex items-center gap-2" style="cursor: pointer; font-weight: normal;">
+                                    <input type="checkbox" checked={*manual_hn}
+                                        onchange={
+                                            let manual_hn = manual_hn.clone();
+                                            Callback::from(move |e: Event| {
+                                                manual_hn.set(e.target_unchecked_into::<HtmlInputElement>().checked());
+                                            })
+                                        } />
+                                    <span>{ "มีบัตรเดิม" }</span>
+                                </label>
+                            </label>
+                            { if *manual_hn { html! { <>
+                                <input type="text" value={(*hn).clone()} required=true
+                                    placeholder="เช่น 66001"
+                                    style="font-weight: bold; font-family: monospace;" 
+                                    oninput={
+                                        let hn = hn.clone();
+                                        Callback::from(move |e: InputEvent| {
+                                            let input: HtmlInputElement = e.target_unchecked_into();
+                                            hn.set(input.value());
+                                        })
+                                    } />
+                                { match Store::get_patient_by_hn((*hn).trim()) {
+                                    Some(p) => html! {
+                                        <p class="text-error">{ format!("❌ เลข HN นี้เป็นของ {}{} {} แล้ว", p.title, p.first_name, p.last_name) }</p>
+                                    },
+                                    None => html! {},
+                                } }
+                            </> } } else { html! {
+                                <input type="text" value={Store::next_hn()} readonly=true
+                                    style="font-weight: bold; font-family: monospace;" />
+                            } } }
                         </div>
                         
                         // Title
@@ -178,7 +216,14 @@ pub fn register() -> Html {
 
                         // Citizen ID (Optional)
                         <div class="form-group">
-                            <label class="form-label">{ "เลขบัตรประชาชน (ไม่บังคับ)" }</label>
+                            <label class="form-label">
+                                { "เลขบัตรประชาชน (ไม่บังคับ)" }
+                                { match &citizen_problem {
+                                    Some(problem) => html! { <span class="badge badge-warning" style="margin-left: 5px;">{ problem }</span> },
+                                    None if !(*citizen_id).is_empty() => html! { <span class="badge badge-success" style="margin-left: 5px;">{ thai_id::format(&citizen_id) }</span> },
+                                    None => html! {},
+                                }}
+                            </label>
                             <input type="text" 
                                 maxlength="13"
                                 value={(*citizen_id).clone()}
@@ -279,19 +324,15 @@ pub fn register() -> Html {
                         </div>
                         
                         // Drug Allergy
-                        <div class="form-group">
+                        <div class="form-group" style="grid-column: 1 / -1;">
                             <label class="form-label">
                                 { "แพ้ยา" }
                                 <span class="badge badge-error" style="margin-left: 5px;">{ "สำคัญ" }</span>
                             </label>
-                            <input type="text" value={(*drug_allergy).clone()}
-                                placeholder="ระบุชื่อยาที่แพ้ หรือ 'ไม่มี'"
-                                oninput={
-                                    let drug_allergy = drug_allergy.clone();
-                                    Callback::from(move |e: InputEvent| {
-                                        let input: HtmlInputElement = e.target_unchecked_into();
-                                        drug_allergy.set(input.value());
-                                    })
+                            <AllergyEditor allergies={(*allergies).clone()}
+                                on_change={
+                                    let allergies = allergies.clone();
+                                    Callback::from(move |list: Vec<Allergy>| allergies.set(list))
                                 } />
                         </div>
 
@@ -323,11 +364,44 @@ pub fn register() -> Html {
                                 } />
                         </div>
                     </div>
+
+                    { if duplicates.is_empty() { html! {} } else { html! {
+                        <div class="mt-6">
+                            <p class="text-warning">{ "⚠️ อาจเคยลงทะเบียนแล้ว ตรวจสอบก่อนบันทึก" }</p>
+                            <table class="data-table">
+                                <tbody>
+                                    { for duplicates.iter().map(|m| html! {
+                                        <tr key={m.patient.id.clone()}>
+                                            <td class="font-mono font-semibold">{ &m.patient.hn }</td>
+                                            <td>{ format!("{}{} {}", m.patient.title, m.patient.first_name, m.patient.last_name) }</td>
+                                            <td>
+                                                { for m.reasons.iter().map(|r| html! {
+                                                    <span class="badge badge-warning" style="margin-right: 5px;">{ r.describe() }</span>
+                                                }) }
+                                            </td>
+                                            <td>
+                                                <div class="flex gap-2">
+                                                    <Link<Route> to={Route::Treatment { id: m.patient.id.clone() }} classes="btn btn-primary btn-sm">
+                                                        { "💊 รักษาคนนี้" }
+                                                    </Link<Route>>
+                                                    <Link<Route> to={Route::History { id: m.patient.id.clone() }} classes="btn btn-secondary btn-sm">
+                                                        { "📋 ประวัติ" }
+                                                    </Link<Route>>
+                                                </div>
+                                            </td>
+                                        </tr>
+                                    }) }
+                                </tbody>
+                            </table>
+                        </div>
+                    } } }
                     
                     <div class="flex justify-between items-center mt-6">
                         <div>
-                            { if !form_valid {
+                            { if !filled {
                                 html! { <p class="text-warning">{ "⚠️ กรุณากรอกข้อมูลที่มี * ให้ครบ" }</p> }
+                            } else if citizen_problem.is_some() {
+                                html! { <p class="text-warning">{ "⚠️ เลขบัตรประชาชนไม่ถูกต้อง" }</p> }
                             } else { 
                                 html! { <p class="text-success">{ "✅ พร้อมบันทึก" }</p> }
                             }}
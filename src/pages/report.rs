@@ -1,19 +1,47 @@
+use std::rc::Rc;
 use yew::prelude::*;
+use crate::archive::{self, Archive};
 use crate::store::Store;
 use chrono::prelude::*;
+use chrono::{Days, Months};
 
 #[function_component(Report)]
 pub fn report() -> Html {
-    let records = Store::get_records();
-    let patients = Store::get_patients();
     
     // Current month filter
     let selected_month = use_state(|| Local::now().format("%Y-%m").to_string());
+    let year_i32: i32 = selected_month.split('-').next().unwrap_or("2026").parse().unwrap_or(2026);
+    let month_u32: u32 = selected_month.split('-').nth(1).unwrap_or("1").parse().unwrap_or(1);
+    
+    // Archived years, read once the page is open so startup doesn't pay for them
+    let archives = use_state(|| Rc::new(Vec::<Archive>::new()));
+    {
+        let archives = archives.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                match archive::load_all().await {
+                    Ok(list) => archives.set(Rc::new(list)),
+                    Err(err) => gloo::console::error!(format!("Failed to read archives: {}", err)),
+                }
+            });
+            || ()
+        });
+    }
+    let in_month = |at: &DateTime<Utc>| {
+        let local = at.with_timezone(&Local);
+        local.year() == year_i32 && local.month() == month_u32
+    };
+    let archived = archives.iter().find(|a| a.year == year_i32);
     
-    // Filter records by selected month
-    let month_records: Vec<_> = records.iter().filter(|r| {
-        r.date.with_timezone(&Local).format("%Y-%m").to_string() == *selected_month
-    }).collect();
+    // Records of the selected month, archived ones included
+    let mut month_records = NaiveDate::from_ymd_opt(year_i32, month_u32, 1)
+        .map(|start| {
+            let end = start + Months::new(1) - Days::new(1);
+            Store::get_records_by_date_range(start, end)
+        })
+        .unwrap_or_default();
+    month_records.extend(archived.into_iter().flat_map(|a| &a.records).filter(|r| in_month(&r.date)).cloned());
+    month_records.sort_by_key(|r| r.date);
     
     // Calculate stats
     let total_revenue: f64 = month_records.iter().map(|r| r.price).sum();
@@ -32,7 +60,7 @@ pub fn report() -> Html {
         }
     }
     let mut diagnosis_sorted: Vec<_> = diagnosis_count.into_iter().collect();
-    diagnosis_sorted.sort_by(|a, b| b.1.cmp(&a.1));
+    diagnosis_sorted.sort_by_key(|b| std::cmp::Reverse(b.1));
     
     // Daily breakdown
     let mut daily_revenue: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
@@ -44,13 +72,17 @@ pub fn report() -> Html {
     }
     
     // Get available months
-    let mut months: Vec<String> = records.iter()
-        .map(|r| r.date.with_timezone(&Local).format("%Y-%m").to_string())
-        .collect::<std::collections::HashSet<_>>()
+    let mut months: Vec<String> = Store::get_record_months()
         .into_iter()
+        .map(|(y, m)| format!("{:04}-{:02}", y, m))
         .collect();
-    months.sort();
-    months.reverse();
+    for record in archives.iter().flat_map(|a| &a.records) {
+        let month = record.date.with_timezone(&Local).format("%Y-%m").to_string();
+        if !months.contains(&month) {
+            months.push(month);
+        }
+    }
+    months.sort_by(|a, b| b.cmp(a));
     
     // Add current month if not exists
     let current_month = Local::now().format("%Y-%m").to_string();
@@ -59,9 +91,8 @@ pub fn report() -> Html {
     }
     
     // Monthly expenses
-    let year_i32: i32 = selected_month.split('-').next().unwrap_or("2026").parse().unwrap_or(2026);
-    let month_u32: u32 = selected_month.split('-').nth(1).unwrap_or("1").parse().unwrap_or(1);
-    let monthly_expenses = Store::get_monthly_expenses(year_i32, month_u32);
+    let mut monthly_expenses = Store::get_monthly_expenses(year_i32, month_u32);
+    monthly_expenses.extend(archived.into_iter().flat_map(|a| &a.expenses).filter(|e| in_month(&e.date)).cloned());
     let total_expense: f64 = monthly_expenses.iter().map(|e| e.amount).sum();
     let net_profit = total_revenue - total_expense;
 
@@ -70,7 +101,10 @@ pub fn report() -> Html {
             <div class="page-header flex justify-between items-center flex-wrap gap-4">
                 <div>
                     <h1 class="page-title">{ "📊 รายงานประจำเดือน" }</h1>
-                    <p class="page-subtitle">{ "สรุปรายได้และสถิติการรักษา" }</p>
+                    <p class="page-subtitle">
+                        { "สรุปรายได้และสถิติการรักษา" }
+                        { if archived.is_some() { " (รวมข้อมูลที่เก็บถาวร)" } else { "" } }
+                    </p>
                 </div>
                 
                 // Month selector
@@ -174,8 +208,7 @@ pub fn report() -> Html {
                                 </thead>
                                 <tbody>
                                     { for month_records.iter().rev().take(10).map(|r| {
-                                        let patient_name = patients.iter()
-                                            .find(|p| p.id == r.patient_id)
+                                        let patient_name = Store::get_patient(&r.patient_id)
                                             .map(|p| format!("{}{}", p.first_name, p.last_name))
                                             .unwrap_or_else(|| "-".to_string());
                                         let date = r.date.with_timezone(&Local).format("%d/%m %H:%M").to_string();
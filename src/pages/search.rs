@@ -1,14 +1,16 @@
 use yew::prelude::*;
 use crate::models::Patient;
 use crate::store::Store;
+use crate::allergy;
+use crate::thai_id;
 use yew_router::prelude::Link;
 use crate::Route;
 use web_sys::HtmlInputElement;
 
 #[function_component(Search)]
 pub fn search() -> Html {
-    let patients = use_state(|| Store::get_patients());
-    let search_term = use_state(|| String::new());
+    let patients = use_state(Store::get_patients);
+    let search_term = use_state(String::new);
     
     let onsearch = {
         let search_term = search_term.clone();
@@ -23,7 +25,8 @@ pub fn search() -> Html {
         p.hn.to_lowercase().contains(&term) ||
         p.first_name.to_lowercase().contains(&term) ||
         p.last_name.to_lowercase().contains(&term) ||
-        p.citizen_id.contains(&term)
+        // A citizen ID typed the way it's printed, with dashes
+        p.citizen_id.contains(&term.replace(['-', ' '], ""))
     }).cloned().collect();
 
     html! {
@@ -78,10 +81,10 @@ pub fn search() -> Html {
                             </thead>
                             <tbody>
                                 { for filtered_patients.iter().map(|p| {
-                                    let allergy = if p.drug_allergy.is_empty() || p.drug_allergy.to_lowercase() == "none" || p.drug_allergy == "ไม่มี" {
+                                    let allergy = if p.allergies.is_empty() {
                                         html! { <span class="text-muted">{ "ไม่มี" }</span> }
                                     } else {
-                                        html! { <span class="badge badge-error">{ &p.drug_allergy }</span> }
+                                        html! { <span class="badge badge-error">{ allergy::summary(&p.allergies) }</span> }
                                     };
                                     
                                     html! {
@@ -92,7 +95,7 @@ pub fn search() -> Html {
                                             <td>
                                                 <div class="font-semibold">{ format!("{}{} {}", p.title, p.first_name, p.last_name) }</div>
                                             </td>
-                                            <td class="font-mono">{ &p.citizen_id }</td>
+                                            <td class="font-mono">{ thai_id::format(&p.citizen_id) }</td>
                                             <td>{ allergy }</td>
                                             <td>
                                                 <div class="flex gap-2">
@@ -1,10 +1,18 @@
 use yew::prelude::*;
-use crate::models::ClinicSettings;
+use crate::models::{ClinicSettings, BackupSchedule, HnFormat};
+use crate::archive;
+use crate::thai_id;
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
-use web_sys::{HtmlInputElement, Blob, Url, HtmlAnchorElement};
+use crate::tauri_bridge;
+use crate::backup;
+use crate::storage::{LocalStorageBackend, LOCAL_STORAGE_QUOTA};
+use crate::components::{
+    ToastContext, ToastAction, ToastType, toast_error, RestorePreview, Restoring, open_backup, BackupPanel, seal_backup,
+    download_backup, EncryptionCard, SyncCard, IntegrityPanel, HnPanel, MergePanel, ArchivePanel, HnFormatCard, BackupScheduleCard,
+    MAX_HN_PADDING,
+};
+use web_sys::HtmlInputElement;
 use wasm_bindgen::JsCast;
-use serde::{Serialize, Deserialize};
 
 // Helper to filter non-digits
 fn digits_only(s: &str) -> String {
@@ -12,7 +20,7 @@ fn digits_only(s: &str) -> String {
 }
 
 // Helper to filter digits with max length
-fn digits_max(s: &str, max: usize) -> String {
+pub fn digits_max(s: &str, max: usize) -> String {
     digits_only(s).chars().take(max).collect()
 }
 
@@ -33,79 +41,6 @@ fn apply_font_size(size: &str) {
     }
 }
 
-// Backup data structure
-#[derive(Serialize, Deserialize)]
-struct BackupData {
-    version: u32,
-    backup_date: String,
-    patients: Vec<crate::models::Patient>,
-    records: Vec<crate::models::TreatmentRecord>,
-    drugs: Vec<crate::models::DrugItem>,
-    settings: ClinicSettings,
-}
-
-fn create_backup() -> String {
-    let backup = BackupData {
-        version: 1,
-        backup_date: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-        patients: Store::get_patients(),
-        records: Store::get_records(),
-        drugs: Store::get_drugs(),
-        settings: Store::get_settings(),
-    };
-    serde_json::to_string_pretty(&backup).unwrap_or_default()
-}
-
-fn download_backup(data: &str) {
-    let window = web_sys::window().unwrap();
-    let document = window.document().unwrap();
-    
-    // Create blob
-    let blob_parts = js_sys::Array::new();
-    blob_parts.push(&wasm_bindgen::JsValue::from_str(data));
-    
-    let blob_options = web_sys::BlobPropertyBag::new();
-    blob_options.set_type("application/json");
-    
-    if let Ok(blob) = Blob::new_with_str_sequence_and_options(&blob_parts, &blob_options) {
-        if let Ok(url) = Url::create_object_url_with_blob(&blob) {
-            // Create download link
-            let a: HtmlAnchorElement = document.create_element("a").unwrap().unchecked_into();
-            let filename = format!("clinic_backup_{}.json", chrono::Local::now().format("%Y%m%d_%H%M%S"));
-            a.set_href(&url);
-            a.set_download(&filename);
-            a.click();
-            let _ = Url::revoke_object_url(&url);
-        }
-    }
-}
-
-fn restore_from_json(json: &str) -> Result<(usize, usize, usize), String> {
-    let backup: BackupData = serde_json::from_str(json)
-        .map_err(|e| format!("ไฟล์ไม่ถูกต้อง: {}", e))?;
-    
-    // Restore all data
-    let patient_count = backup.patients.len();
-    let record_count = backup.records.len();
-    let drug_count = backup.drugs.len();
-    
-    for patient in backup.patients {
-        Store::save_patient(patient);
-    }
-    
-    for record in backup.records {
-        Store::save_record(record);
-    }
-    
-    for drug in backup.drugs {
-        Store::save_drug(drug);
-    }
-    
-    Store::save_settings(backup.settings);
-    
-    Ok((patient_count, record_count, drug_count))
-}
-
 async fn invoke_check_update() -> Result<String, String> {
     use wasm_bindgen::JsValue;
     use wasm_bindgen::JsCast;
@@ -151,7 +86,7 @@ async fn invoke_check_update() -> Result<String, String> {
 #[function_component(Settings)]
 pub fn settings() -> Html {
     let toast = use_context::<ToastContext>();
-    let settings = use_state(|| Store::get_settings());
+    let settings = use_state(Store::get_settings);
     
     let clinic_name = use_state(|| settings.clinic_name.clone());
     let clinic_address = use_state(|| settings.clinic_address.clone());
@@ -162,14 +97,20 @@ pub fn settings() -> Html {
     let license_number = use_state(|| settings.license_number.clone());
     let font_size = use_state(|| settings.font_size.clone());
     let sticker_size = use_state(|| settings.sticker_size.clone());
+    let trash_retention_days = use_state(|| settings.trash_retention_days.to_string());
+    let backup_schedule = use_state(|| settings.backup_schedule.clone());
+    let hn_format = use_state(|| settings.hn_format.clone());
     
     // Stats for display
     let patient_count = Store::get_patients().len();
     let record_count = Store::get_records().len();
     let drug_count = Store::get_drugs().len();
+    // Only the browser build is limited by the LocalStorage quota
+    let storage_percent = (!tauri_bridge::is_tauri())
+        .then(|| LocalStorageBackend::usage() * 100 / LOCAL_STORAGE_QUOTA);
     
     // Validation
-    let tax_id_valid = (*clinic_tax_id).is_empty() || (*clinic_tax_id).len() == 13;
+    let tax_id_problem = (!(*clinic_tax_id).is_empty()).then(|| thai_id::problem(&clinic_tax_id)).flatten();
     let phone_valid = (*clinic_phone).is_empty() || (*clinic_phone).len() >= 9;
     
     // Apply font size immediately when changed
@@ -192,6 +133,9 @@ pub fn settings() -> Html {
         let license_number = license_number.clone();
         let font_size = font_size.clone();
         let sticker_size = sticker_size.clone();
+        let trash_retention_days = trash_retention_days.clone();
+        let backup_schedule = backup_schedule.clone();
+        let hn_format = hn_format.clone();
         let settings = settings.clone();
         let toast = toast.clone();
         
@@ -210,17 +154,34 @@ pub fn settings() -> Html {
                 theme: "light".to_string(),
                 sticker_size: (*sticker_size).clone(),
                 next_receipt_no: settings.next_receipt_no,
+                trash_retention_days: (*trash_retention_days).parse().unwrap_or(settings.trash_retention_days),
+                backup_schedule: BackupSchedule {
+                    mirror_dir: backup_schedule.mirror_dir.trim().to_string(),
+                    ..(*backup_schedule).clone()
+                },
+                hn_format: HnFormat {
+                    prefix: hn_format.prefix.trim().to_string(),
+                    padding: hn_format.padding.clamp(1, MAX_HN_PADDING),
+                    ..(*hn_format).clone()
+                },
             };
             
-            Store::save_settings(new_settings.clone());
-            settings.set(new_settings);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "✅ บันทึกการตั้งค่าเรียบร้อยแล้ว!".to_string(),
-                    ToastType::Success
-                ));
-            }
+            let settings = settings.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::save_settings(new_settings.clone()).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                settings.set(new_settings);
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(
+                        "✅ บันทึกการตั้งค่าเรียบร้อยแล้ว!".to_string(),
+                        ToastType::Success
+                    ));
+                }
+            });
         })
     };
     
@@ -228,53 +189,55 @@ pub fn settings() -> Html {
     let on_backup = {
         let toast = toast.clone();
         Callback::from(move |_: MouseEvent| {
-            let backup_data = create_backup();
-            download_backup(&backup_data);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "📥 ดาวน์โหลดไฟล์สำรองข้อมูลเรียบร้อย!".to_string(),
-                    ToastType::Success
-                ));
-            }
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let created = archive::load_all()
+                    .await
+                    .and_then(|archives| backup::create(&Store::snapshot(), &archives))
+                    .map(seal_backup);
+                let backup_data = match created {
+                    Ok(Some(Ok(data))) => data,
+                    Ok(Some(Err(err))) | Err(err) => {
+                        toast_error(&toast, err);
+                        return;
+                    }
+                    Ok(None) => return,
+                };
+                let filename = format!("clinic_backup_{}.json", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+                download_backup(&backup_data, &filename);
+                
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(
+                        "📥 ดาวน์โหลดไฟล์สำรองข้อมูลเรียบร้อย!".to_string(),
+                        ToastType::Success
+                    ));
+                }
+            });
         })
     };
     
-    // Restore handler
+    // Restore handler: read the file, then show what it would change
+    let pending = use_state(|| None::<Restoring>);
     let on_restore = {
         let toast = toast.clone();
+        let pending = pending.clone();
         Callback::from(move |e: Event| {
             let input: HtmlInputElement = e.target_unchecked_into();
             let toast = toast.clone();
+            let pending = pending.clone();
             
             if let Some(files) = input.files() {
                 if let Some(file) = files.get(0) {
                     let reader = web_sys::FileReader::new().unwrap();
                     let reader_clone = reader.clone();
+                    let file_name = file.name();
                     
                     let onload = wasm_bindgen::closure::Closure::wrap(Box::new(move |_: web_sys::Event| {
                         if let Ok(result) = reader_clone.result() {
                             if let Some(text) = result.as_string() {
-                                match restore_from_json(&text) {
-                                    Ok((patients, records, drugs)) => {
-                                        if let Some(ref t) = toast {
-                                            t.dispatch(ToastAction::Add(
-                                                format!("✅ กู้คืนข้อมูลเรียบร้อย! ผู้ป่วย {} คน, การรักษา {} รายการ, ยา {} รายการ", 
-                                                    patients, records, drugs),
-                                                ToastType::Success
-                                            ));
-                                        }
-                                        // Reload page to show restored data
-                                        let _ = web_sys::window().unwrap().location().reload();
-                                    }
-                                    Err(err) => {
-                                        if let Some(ref t) = toast {
-                                            t.dispatch(ToastAction::Add(
-                                                format!("❌ กู้คืนล้มเหลว: {}", err),
-                                                ToastType::Error
-                                            ));
-                                        }
-                                    }
+                                match open_backup(&text).and_then(|json| Restoring::read(file_name.clone(), &json)) {
+                                    Ok(backup) => pending.set(Some(backup)),
+                                    Err(err) => toast_error(&toast, format!("❌ กู้คืนล้มเหลว: {}", err)),
                                 }
                             }
                         }
@@ -363,6 +326,33 @@ pub fn settings() -> Html {
                         <div class="stat-card-label">{ "รายการยา" }</div>
                     </div>
                 </div>
+
+                { if let Some(percent) = storage_percent {
+                    let (color, note) = match percent {
+                        0..=79 => ("var(--color-success)", None),
+                        80..=94 => ("var(--color-warning)", Some(("alert-warning", "พื้นที่จัดเก็บใกล้เต็ม กรุณาสำรองข้อมูลและลบข้อมูลเก่าที่ไม่ใช้"))),
+                        _ => ("var(--color-error)", Some(("alert-error", "พื้นที่จัดเก็บเกือบเต็ม! ข้อมูลใหม่อาจบันทึกไม่ได้ กรุณาสำรองข้อมูลทันที"))),
+                    };
+                    html! {
+                        <div style="margin-bottom: 1rem;">
+                            <div class="flex justify-between">
+                                <span>{ "💽 พื้นที่จัดเก็บในเบราว์เซอร์" }</span>
+                                <strong>{ format!("ใช้ไป {}%", percent) }</strong>
+                            </div>
+                            <div style="height: 12px; border-radius: 6px; background: var(--color-border-light); overflow: hidden; margin-top: 0.25rem;">
+                                <div style={format!("height: 100%; width: {}%; background: {};", percent.min(100), color)}></div>
+                            </div>
+                            { if let Some((class, text)) = note {
+                                html! {
+                                    <div class={classes!("alert", class)} style="margin-top: 0.5rem;">
+                                        <span class="alert-icon">{ "⚠️" }</span>
+                                        <span>{ text }</span>
+                                    </div>
+                                }
+                            } else { html! {} } }
+                        </div>
+                    }
+                } else { html! {} } }
                 
                 <div class="grid grid-cols-2 gap-4">
                     <div>
@@ -394,6 +384,33 @@ pub fn settings() -> Html {
                 </div>
             </div>
             
+            { if let Some(backup) = (*pending).clone() {
+                let on_close = {
+                    let pending = pending.clone();
+                    Callback::from(move |_| pending.set(None))
+                };
+                html! { <RestorePreview {backup} {on_close} /> }
+            } else { html! {} } }
+
+            { if tauri_bridge::is_tauri() {
+                let on_restore = {
+                    let pending = pending.clone();
+                    Callback::from(move |backup| pending.set(Some(backup)))
+                };
+                html! {
+                    <>
+                        <BackupPanel {on_restore} />
+                        <EncryptionCard />
+                        <SyncCard />
+                    </>
+                }
+            } else { html! {} } }
+
+            <IntegrityPanel />
+            <HnPanel />
+            <MergePanel />
+            <ArchivePanel />
+
             <form onsubmit={on_save}>
                 // Clinic Information
                 <div class="card mb-6">
@@ -454,13 +471,11 @@ pub fn settings() -> Html {
                         <div class="form-group">
                             <label class="form-label">
                                 { "เลขประจำตัวผู้เสียภาษี " }
-                                { if !(*clinic_tax_id).is_empty() {
-                                    html! {
-                                        <span class={if tax_id_valid { "badge badge-success" } else { "badge badge-warning" }}>
-                                            { format!("{}/13 หลัก", (*clinic_tax_id).len()) }
-                                        </span>
-                                    }
-                                } else { html! {} }}
+                                { match (&tax_id_problem, (*clinic_tax_id).is_empty()) {
+                                    (_, true) => html! {},
+                                    (None, false) => html! { <span class="badge badge-success">{ thai_id::format(&clinic_tax_id) }</span> },
+                                    (Some(problem), false) => html! { <span class="badge badge-warning">{ problem }</span> },
+                                }}
                             </label>
                             <input 
                                 type="text" 
@@ -589,6 +604,48 @@ pub fn settings() -> Html {
                     </div>
                 </div>
                 
+                // HN format
+                <HnFormatCard format={(*hn_format).clone()} on_change={{
+                    let hn_format = hn_format.clone();
+                    Callback::from(move |format| hn_format.set(format))
+                }} />
+                
+                // Recycle Bin
+                <div class="card mb-6">
+                    <div class="card-header">
+                        <h3 class="card-title">{ "🗑️ ถังขยะ" }</h3>
+                        <p class="card-subtitle">{ "ข้อมูลที่ลบจะอยู่ในถังขยะก่อน กู้คืนได้จนกว่าจะครบกำหนด" }</p>
+                    </div>
+                    <div class="form-group">
+                        <label class="form-label">{ "ลบถาวรอัตโนมัติหลังจาก (วัน)" }</label>
+                        <input
+                            type="text"
+                            inputmode="numeric"
+                            maxlength="4"
+                            value={(*trash_retention_days).clone()}
+                            oninput={{
+                                let trash_retention_days = trash_retention_days.clone();
+                                Callback::from(move |e: InputEvent| {
+                                    let input: HtmlInputElement = e.target_unchecked_into();
+                                    let filtered = digits_max(&input.value(), 4);
+                                    trash_retention_days.set(filtered.clone());
+                                    input.set_value(&filtered);
+                                })
+                            }} />
+                        <p class="text-muted" style="margin-top: 0.5rem;">{ "ใส่ 0 หากต้องการเก็บไว้ในถังขยะตลอดไป" }</p>
+                    </div>
+                </div>
+                
+                // Automatic Backups (desktop app only)
+                { if tauri_bridge::is_tauri() {
+                    html! {
+                        <BackupScheduleCard schedule={(*backup_schedule).clone()} on_change={{
+                            let backup_schedule = backup_schedule.clone();
+                            Callback::from(move |schedule| backup_schedule.set(schedule))
+                        }} />
+                    }
+                } else { html! {} } }
+                
                 // System Update Section
                 <div class="card mb-6">
                     <div class="card-header">
@@ -131,14 +131,14 @@ pub fn sticker(props: &Props) -> Html {
         || ()
     });
 
-    let record = Store::get_records().into_iter().find(|r| r.id == props.record_id);
+    let record = Store::get_record(&props.record_id);
     
     if record.is_none() {
         return html! { <div class="print-document"><p>{ "ไม่พบข้อมูล" }</p></div> };
     }
     
     let r = record.unwrap();
-    let patient = Store::get_patients().into_iter().find(|p| p.id == r.patient_id);
+    let patient = Store::get_patient(&r.patient_id);
     
     if patient.is_none() {
         return html! { <div class="print-document"><p>{ "ไม่พบข้อมูลผู้ป่วย" }</p></div> };
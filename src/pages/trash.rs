@@ -0,0 +1,123 @@
+use yew::prelude::*;
+use chrono::{Duration, Local};
+use crate::audit;
+use crate::store::{Store, TrashItem};
+use crate::components::{ToastContext, ToastAction, ToastType, toast_error};
+
+#[function_component(RecycleBin)]
+pub fn recycle_bin() -> Html {
+    let toast = use_context::<ToastContext>();
+    let items = use_state(Store::get_trash);
+    let retention_days = Store::get_settings().trash_retention_days;
+
+    let on_restore = {
+        let toast = toast.clone();
+        let items = items.clone();
+        Callback::from(move |item: TrashItem| {
+            let toast = toast.clone();
+            let items = items.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::restore(item.entity, &item.id).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                items.set(Store::get_trash());
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(format!("♻️ กู้คืน {} แล้ว", item.label), ToastType::Success));
+                }
+            });
+        })
+    };
+
+    let on_purge = {
+        let toast = toast.clone();
+        let items = items.clone();
+        Callback::from(move |item: TrashItem| {
+            let message = format!("ลบ \"{}\" ถาวร? ข้อมูลนี้จะกู้คืนไม่ได้อีก", item.label);
+            if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                return;
+            }
+            let toast = toast.clone();
+            let items = items.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::purge(item.entity, &item.id).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+                items.set(Store::get_trash());
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add("🗑️ ลบถาวรเรียบร้อยแล้ว".to_string(), ToastType::Success));
+                }
+            });
+        })
+    };
+
+    html! {
+        <>
+            <div class="page-header">
+                <h1 class="page-title">{ "🗑️ ถังขยะ" }</h1>
+                <p class="page-subtitle">
+                    { if retention_days == 0 {
+                        "ข้อมูลที่ลบจะอยู่ที่นี่จนกว่าจะลบถาวร".to_string()
+                    } else {
+                        format!("ข้อมูลที่ลบจะอยู่ที่นี่ {} วัน แล้วจึงถูกลบถาวรโดยอัตโนมัติ (ตั้งค่าได้ที่หน้าตั้งค่า)", retention_days)
+                    } }
+                </p>
+            </div>
+
+            <div class="card">
+                { if items.is_empty() {
+                    html! {
+                        <div class="empty-state">
+                            <div class="empty-state-icon">{ "🗑️" }</div>
+                            <h3 class="empty-state-title">{ "ถังขยะว่าง" }</h3>
+                            <p class="empty-state-text">{ "ไม่มีข้อมูลที่ถูกลบ" }</p>
+                        </div>
+                    }
+                } else {
+                    html! {
+                        <table class="data-table">
+                            <thead>
+                                <tr>
+                                    <th>{ "ประเภท" }</th>
+                                    <th>{ "รายการ" }</th>
+                                    <th>{ "ลบเมื่อ" }</th>
+                                    <th>{ "ลบถาวรวันที่" }</th>
+                                    <th>{ "" }</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                { for items.iter().map(|item| {
+                                    let restore = { let item = item.clone(); let cb = on_restore.clone(); move |_| cb.emit(item.clone()) };
+                                    let purge = { let item = item.clone(); let cb = on_purge.clone(); move |_| cb.emit(item.clone()) };
+                                    let purge_on = (retention_days > 0).then(|| {
+                                        (item.deleted_at + Duration::days(retention_days as i64)).with_timezone(&Local).format("%d/%m/%Y").to_string()
+                                    });
+                                    html! {
+                                        <tr>
+                                            <td><span class="badge">{ audit::entity_name(item.entity) }</span></td>
+                                            <td>
+                                                { &item.label }
+                                                { if item.related > 0 {
+                                                    html! { <span class="text-muted">{ format!(" (พร้อมการรักษา {} รายการ)", item.related) }</span> }
+                                                } else { html! {} } }
+                                            </td>
+                                            <td>{ item.deleted_at.with_timezone(&Local).format("%d/%m/%Y %H:%M").to_string() }</td>
+                                            <td>{ purge_on.unwrap_or_else(|| "-".to_string()) }</td>
+                                            <td>
+                                                <div class="flex gap-2">
+                                                    <button class="btn btn-success btn-sm" onclick={restore}>{ "♻️ กู้คืน" }</button>
+                                                    <button class="btn btn-danger btn-sm" onclick={purge}>{ "ลบถาวร" }</button>
+                                                </div>
+                                            </td>
+                                        </tr>
+                                    }
+                                })}
+                            </tbody>
+                        </table>
+                    }
+                }}
+            </div>
+        </>
+    }
+}
@@ -1,15 +1,25 @@
 use yew::prelude::*;
-use crate::models::{Patient, TreatmentRecord, PrescriptionItem, InjectionItem};
+use crate::allergy;
+use crate::interactions::{self, Interaction};
+use crate::models::{AllergyOverride, InteractionSeverity, Patient, TreatmentRecord, PrescriptionItem, InjectionItem};
 use crate::store::Store;
-use crate::components::{ToastContext, ToastAction, ToastType};
+use crate::components::{ToastContext, ToastAction, ToastType, toast_error, severity_badge};
 use web_sys::HtmlInputElement;
-use chrono::Utc;
+use chrono::{Local, Utc};
 use uuid::Uuid;
 use yew_router::prelude::*;
 use crate::Route;
 
 const SERVICE_FEE: f64 = 50.0; // ค่าบริการทางการพยาบาล fix
 
+/// What an interaction is with, for warnings: the same prescription or an earlier visit
+fn interacts_with(interaction: &Interaction) -> String {
+    match interaction.other.given {
+        Some(given) => format!("{} (จ่ายเมื่อ {})", interaction.other.name, given.with_timezone(&Local).format("%d/%m/%Y")),
+        None => interaction.other.name.clone(),
+    }
+}
+
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub id: String,
@@ -29,7 +39,7 @@ pub fn treatment(props: &Props) -> Html {
     let navigator = use_navigator().unwrap();
     let toast = use_context::<ToastContext>();
     let patient = use_state(|| -> Option<Patient> {
-        Store::get_patients().into_iter().find(|p| p.id == props.id)
+        Store::get_patient(&props.id)
     });
     
     // Drug list from inventory for autocomplete
@@ -50,26 +60,25 @@ pub fn treatment(props: &Props) -> Html {
     let patient_data = patient.as_ref().unwrap();
 
     // Form States
-    let symptoms = use_state(|| String::new());
-    let diagnosis = use_state(|| String::new());
-    let weight = use_state(|| String::new());
-    let pressure = use_state(|| String::new());
-    let doctor_note = use_state(|| String::new());
+    let symptoms = use_state(String::new);
+    let diagnosis = use_state(String::new);
+    let weight = use_state(String::new);
+    let pressure = use_state(String::new);
+    let doctor_note = use_state(String::new);
     let manual_price_override = use_state(|| false); // Flag for manual override
     let is_drug_only_purchase = use_state(|| false); // ซื้อยาอย่างเดียว ไม่คิดค่าบริการ
     
     // Dynamic lists
-    let prescriptions = use_state(|| Vec::<PrescriptionItem>::new());
-    let _injections = use_state(|| Vec::<InjectionItem>::new());
+    let prescriptions = use_state(Vec::<PrescriptionItem>::new);
+    let _injections = use_state(Vec::<InjectionItem>::new);
 
     // Calculate total price automatically
     let calculated_drug_cost = {
-        let drug_list = drug_list.clone();
         let prescriptions = (*prescriptions).clone();
         
         prescriptions.iter().map(|rx| {
             // Find drug in inventory by name
-            let drug = drug_list.iter().find(|d| d.name == rx.name);
+            let drug = Store::get_drug_by_name(&rx.name);
             let unit_price = drug.map(|d| d.sell_price).unwrap_or(0.0);
             let quantity = parse_quantity(&rx.amount);
             unit_price * quantity
@@ -126,7 +135,14 @@ pub fn treatment(props: &Props) -> Html {
         })
     };
 
-    let onsubmit = {
+    // Prescribed drugs the patient is allergic to; saving them needs a reason
+    let conflicts = allergy::conflicts(&patient_data.allergies, &prescriptions, &drug_list);
+    let confirming_override = use_state(|| false);
+    let override_reason = use_state(String::new);
+    // Drugs that interact with each other or with what the patient still takes; only a warning
+    let drug_interactions = Store::check_interactions(&props.id, &prescriptions);
+
+    let save = {
         let patient_id = props.id.clone();
         let symptoms = symptoms.clone();
         let diagnosis = diagnosis.clone();
@@ -138,9 +154,7 @@ pub fn treatment(props: &Props) -> Html {
         let navigator = navigator.clone();
         let toast = toast.clone();
 
-        Callback::from(move |e: SubmitEvent| {
-            e.prevent_default();
-            
+        Callback::from(move |allergy_override: Option<AllergyOverride>| {
             let record = TreatmentRecord {
                 id: Uuid::new_v4().to_string(),
                 patient_id: patient_id.clone(),
@@ -153,21 +167,72 @@ pub fn treatment(props: &Props) -> Html {
                 injections: vec![],
                 doctor_note: (*doctor_note).clone(),
                 price: *final_price,
+                allergy_override,
+                deleted_at: None,
+                updated_at: None,
             };
             
-            Store::save_record(record);
-            
-            if let Some(ref t) = toast {
-                t.dispatch(ToastAction::Add(
-                    "✅ บันทึกการรักษาเรียบร้อยแล้ว!".to_string(),
-                    ToastType::Success
-                ));
+            let patient_id = patient_id.clone();
+            let navigator = navigator.clone();
+            let toast = toast.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(err) = Store::save_record(record).await {
+                    toast_error(&toast, err);
+                    return;
+                }
+
+                if let Some(ref t) = toast {
+                    t.dispatch(ToastAction::Add(
+                        "✅ บันทึกการรักษาเรียบร้อยแล้ว!".to_string(),
+                        ToastType::Success
+                    ));
+                }
+
+                navigator.push(&Route::History { id: patient_id });
+            });
+        })
+    };
+
+    let onsubmit = {
+        let save = save.clone();
+        let has_conflicts = !conflicts.is_empty();
+        let confirming_override = confirming_override.clone();
+        let serious: Vec<String> = drug_interactions
+            .iter()
+            .filter(|i| i.rule.severity >= InteractionSeverity::Major)
+            .map(|i| format!("• {} + {} ({})", i.drug, interacts_with(i), interactions::severity_name(i.rule.severity)))
+            .collect();
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+            if has_conflicts {
+                confirming_override.set(true);
+                return;
             }
-            
-            navigator.push(&Route::History { id: patient_id.clone() });
+            if !serious.is_empty() {
+                let message = format!("ใบสั่งยามียาที่ตีกันรุนแรง:\n{}\n\nยืนยันบันทึกการรักษา?", serious.join("\n"));
+                if !web_sys::window().unwrap().confirm_with_message(&message).unwrap_or(false) {
+                    return;
+                }
+            }
+            save.emit(None);
+        })
+    };
+
+    let on_override = {
+        let save = save.clone();
+        let override_reason = override_reason.clone();
+        let mut drugs: Vec<String> = conflicts.iter().map(|c| c.drug.clone()).collect();
+        drugs.dedup();
+        Callback::from(move |_: MouseEvent| {
+            save.emit(Some(AllergyOverride { drugs: drugs.clone(), reason: override_reason.trim().to_string() }))
         })
     };
 
+    let on_cancel_override = {
+        let confirming_override = confirming_override.clone();
+        Callback::from(move |_: MouseEvent| confirming_override.set(false))
+    };
+
     html! {
         <>
             <div class="page-header">
@@ -191,10 +256,10 @@ pub fn treatment(props: &Props) -> Html {
                         </div>
                     }
                 } else { html! {} }}
-                { if !patient_data.drug_allergy.is_empty() && patient_data.drug_allergy.to_lowercase() != "none" && patient_data.drug_allergy != "ไม่มี" {
+                { if !patient_data.allergies.is_empty() {
                     html! {
                         <div class="patient-header-allergy">
-                            { "⚠️ แพ้ยา: " }<strong>{ &patient_data.drug_allergy }</strong>
+                            { "⚠️ แพ้ยา: " }<strong>{ allergy::summary(&patient_data.allergies) }</strong>
                         </div>
                     }
                 } else {
@@ -561,6 +626,23 @@ pub fn treatment(props: &Props) -> Html {
                             </div>
                         }
                     }}
+                    { for conflicts.iter().map(|c| html! {
+                        <div class="patient-header-allergy" style="margin-top: 0.5rem;">
+                            { format!("⛔ {} — ผู้ป่วยแพ้ {}", c.drug, allergy::describe(&c.allergy)) }
+                        </div>
+                    }) }
+                    { for drug_interactions.iter().map(|i| html! {
+                        <div class="alert alert-warning" style="margin-top: 0.5rem;">
+                            <span class="alert-icon">{ "🔀" }</span>
+                            <div>
+                                <div>
+                                    { severity_badge(i.rule.severity) }
+                                    <strong>{ format!(" {} + {}", i.drug, interacts_with(i)) }</strong>
+                                </div>
+                                { if i.rule.advice.is_empty() { html! {} } else { html! { <div>{ &i.rule.advice }</div> } } }
+                            </div>
+                        </div>
+                    }) }
                 </div>
                 
                 // Price Calculation Summary Card
@@ -673,6 +755,35 @@ pub fn treatment(props: &Props) -> Html {
                     </button>
                 </div>
             </form>
+
+            { if *confirming_override { html! {
+                <div class="modal-overlay">
+                    <div class="modal-content">
+                        <div class="modal-header">
+                            <div class="modal-icon error">{ "⛔" }</div>
+                            <h3 class="modal-title">{ "ผู้ป่วยแพ้ยาในใบสั่งยา" }</h3>
+                        </div>
+                        <div class="modal-body">
+                            <ul>
+                                { for conflicts.iter().map(|c| html! {
+                                    <li><strong>{ &c.drug }</strong>{ format!(" — แพ้ {}", allergy::describe(&c.allergy)) }</li>
+                                }) }
+                            </ul>
+                            <div class="form-group">
+                                <label class="form-label">{ "เหตุผลที่ยังจ่ายยานี้ *" }</label>
+                                <textarea value={(*override_reason).clone()} placeholder="เช่น แพทย์ตรวจแล้วยืนยันให้ใช้ได้"
+                                    oninput={let r = override_reason.clone(); Callback::from(move |e: InputEvent| r.set(e.target_unchecked_into::<HtmlInputElement>().value()))} />
+                            </div>
+                        </div>
+                        <div class="modal-actions">
+                            <button type="button" class="btn btn-ghost" onclick={on_cancel_override}>{ "← กลับไปแก้ใบสั่งยา" }</button>
+                            <button type="button" class="btn btn-danger" onclick={on_override} disabled={override_reason.trim().is_empty()}>
+                                { "ยืนยันจ่ายยาและบันทึก" }
+                            </button>
+                        </div>
+                    </div>
+                </div>
+            } } else { html! {} } }
         </>
     }
 }
@@ -0,0 +1,522 @@
+// Storage backends for the Store
+// The Store keeps all data in memory and hands the touched keys to a backend:
+// LocalStorage in the browser, clinic_data.json under Tauri, or plain memory in tests.
+// The audit log is kept apart from the data, under its own LocalStorage key or in
+// audit_log.jsonl, and a save only adds the new entries to it, so a long history
+// doesn't make every save slower or push the data out of the browser's quota.
+
+use std::cell::RefCell;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::rc::Rc;
+use gloo::storage::{LocalStorage, Storage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::migrations::{self, CURRENT_SCHEMA_VERSION, KEY_SCHEMA_VERSION};
+use crate::models::{Patient, TreatmentRecord, DrugItem, ClinicSettings, Expense, DrugPurchase, Appointment, InteractionRule, AuditEntry, Tombstone};
+use crate::tauri_bridge;
+
+pub const KEY_PATIENTS: &str = "clinic_patients";
+pub const KEY_RECORDS: &str = "clinic_records";
+pub const KEY_LAST_HN: &str = "clinic_last_hn";
+pub const KEY_DRUGS: &str = "clinic_drugs";
+pub const KEY_SETTINGS: &str = "clinic_settings";
+pub const KEY_EXPENSES: &str = "clinic_expenses";
+pub const KEY_DRUG_PURCHASES: &str = "clinic_drug_purchases";
+pub const KEY_APPOINTMENTS: &str = "clinic_appointments";
+pub const KEY_INTERACTIONS: &str = "clinic_interactions";
+pub const KEY_UNREADABLE: &str = "clinic_unreadable";
+pub const KEY_AUDIT_LOG: &str = "clinic_audit_log";
+pub const KEY_TOMBSTONES: &str = "clinic_tombstones";
+/// Every key that holds clinic data, in the order they are written. The audit
+/// log under `KEY_AUDIT_LOG` is stored on its own.
+pub const DATA_KEYS: [&str; 11] = [
+    KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS, KEY_SETTINGS,
+    KEY_EXPENSES, KEY_DRUG_PURCHASES, KEY_APPOINTMENTS, KEY_INTERACTIONS, KEY_LAST_HN, KEY_UNREADABLE, KEY_TOMBSTONES,
+];
+/// Browsers give each site about 5 MB of LocalStorage (counted in UTF-16 code units)
+pub const LOCAL_STORAGE_QUOTA: usize = 5 * 1024 * 1024;
+// Set once LocalStorage data has been copied into clinic_data.json
+const KEY_MIGRATED_TO_FILE: &str = "clinic_migrated_to_file";
+
+/// All clinic data as one document. Field names match the LocalStorage keys,
+/// which is also the layout of `clinic_data.json` in the desktop app.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ClinicData {
+    #[serde(rename = "clinic_patients", default)]
+    pub patients: Vec<Patient>,
+    #[serde(rename = "clinic_records", default)]
+    pub records: Vec<TreatmentRecord>,
+    #[serde(rename = "clinic_drugs", default)]
+    pub drugs: Vec<DrugItem>,
+    #[serde(rename = "clinic_settings", default)]
+    pub settings: ClinicSettings,
+    #[serde(rename = "clinic_expenses", default)]
+    pub expenses: Vec<Expense>,
+    #[serde(rename = "clinic_drug_purchases", default)]
+    pub drug_purchases: Vec<DrugPurchase>,
+    #[serde(rename = "clinic_appointments", default)]
+    pub appointments: Vec<Appointment>,
+    /// Drug interaction rules checked when prescribing, see interactions.rs
+    #[serde(rename = "clinic_interactions", default, skip_serializing_if = "Vec::is_empty")]
+    pub interactions: Vec<InteractionRule>,
+    #[serde(rename = "clinic_last_hn", default)]
+    pub last_hn: u32,
+    #[serde(rename = "clinic_audit_log", default)]
+    pub audit_log: Vec<AuditEntry>,
+    /// Items removed for good, so sync removes them on the other computer too
+    #[serde(rename = "clinic_tombstones", default, skip_serializing_if = "Vec::is_empty")]
+    pub tombstones: Vec<Tombstone>,
+    #[serde(rename = "clinic_schema_version", default)]
+    pub schema_version: u32,
+    /// Entries that failed to parse on load, kept as-is so nothing is thrown away
+    #[serde(rename = "clinic_unreadable", default, skip_serializing_if = "Vec::is_empty")]
+    pub unreadable: Vec<Value>,
+}
+
+/// A parsed document and what happened while parsing it
+pub struct Parsed {
+    pub data: ClinicData,
+    /// The document was upgraded and should be saved back
+    pub migrated: bool,
+    /// How many entries could not be read this time and were moved to `unreadable`
+    pub skipped: usize,
+}
+
+impl ClinicData {
+    /// Migrate a raw document to the current schema and parse it. An entry that
+    /// fails to parse is set aside in `unreadable` instead of failing the whole load.
+    pub fn from_document(mut doc: Map<String, Value>) -> Result<Parsed, String> {
+        let migrated = migrations::migrate(&mut doc)?;
+        let mut unreadable: Vec<Value> = doc
+            .remove(KEY_UNREADABLE)
+            .and_then(|v| serde_json::from_value(v).ok())
+            .unwrap_or_default();
+        let before = unreadable.len();
+
+        let mut list = |key: &str| -> Vec<Value> {
+            match doc.remove(key) {
+                Some(Value::Array(items)) => items,
+                Some(Value::Null) | None => Vec::new(),
+                Some(other) => {
+                    unreadable.push(serde_json::json!({ "key": key, "value": other }));
+                    Vec::new()
+                }
+            }
+        };
+        let raw = [
+            list(KEY_PATIENTS), list(KEY_RECORDS), list(KEY_DRUGS),
+            list(KEY_EXPENSES), list(KEY_DRUG_PURCHASES), list(KEY_APPOINTMENTS), list(KEY_INTERACTIONS),
+            list(KEY_AUDIT_LOG), list(KEY_TOMBSTONES),
+        ];
+        let settings = doc.remove(KEY_SETTINGS);
+        let last_hn = doc.remove(KEY_LAST_HN);
+
+        fn parse<T: DeserializeOwned>(key: &str, items: Vec<Value>, unreadable: &mut Vec<Value>) -> Vec<T> {
+            items
+                .into_iter()
+                .filter_map(|item| match serde_json::from_value(item.clone()) {
+                    Ok(parsed) => Some(parsed),
+                    Err(e) => {
+                        unreadable.push(serde_json::json!({ "key": key, "error": e.to_string(), "value": item }));
+                        None
+                    }
+                })
+                .collect()
+        }
+        fn single<T: DeserializeOwned + Default>(key: &str, value: Option<Value>, unreadable: &mut Vec<Value>) -> T {
+            match value {
+                Some(Value::Null) | None => T::default(),
+                Some(value) => parse(key, vec![value], unreadable).pop().unwrap_or_default(),
+            }
+        }
+
+        let [patients, records, drugs, expenses, drug_purchases, appointments, interactions, audit_log, tombstones] = raw;
+        let u = &mut unreadable;
+        let mut data = Self {
+            patients: parse(KEY_PATIENTS, patients, u),
+            records: parse(KEY_RECORDS, records, u),
+            drugs: parse(KEY_DRUGS, drugs, u),
+            settings: single(KEY_SETTINGS, settings, u),
+            expenses: parse(KEY_EXPENSES, expenses, u),
+            drug_purchases: parse(KEY_DRUG_PURCHASES, drug_purchases, u),
+            appointments: parse(KEY_APPOINTMENTS, appointments, u),
+            interactions: parse(KEY_INTERACTIONS, interactions, u),
+            last_hn: single(KEY_LAST_HN, last_hn, u),
+            audit_log: parse(KEY_AUDIT_LOG, audit_log, u),
+            tombstones: parse(KEY_TOMBSTONES, tombstones, u),
+            schema_version: CURRENT_SCHEMA_VERSION,
+            unreadable: Vec::new(),
+        };
+        let skipped = unreadable.len() - before;
+        data.unreadable = unreadable;
+        Ok(Parsed { data, migrated, skipped })
+    }
+
+    /// The document clinic_data.json holds: every data key, without the audit log
+    pub fn to_file_json(&self) -> Result<String, String> {
+        let mut json = String::from("{");
+        for key in DATA_KEYS {
+            let value = encode(self, key).map_err(|e| format!("Failed to encode {}: {}", key, e))?;
+            json.push_str(&format!("\"{}\":{},", key, value.unwrap_or_default()));
+        }
+        json.push_str(&format!("\"{}\":{}}}", KEY_SCHEMA_VERSION, self.schema_version));
+        Ok(json)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patients.is_empty()
+            && self.records.is_empty()
+            && self.drugs.is_empty()
+            && self.expenses.is_empty()
+            && self.drug_purchases.is_empty()
+            && self.appointments.is_empty()
+    }
+}
+
+/// Message for entries that were set aside while loading, if there were any
+pub fn unreadable_notice(skipped: usize) -> Option<String> {
+    (skipped > 0).then(|| format!(
+        "พบข้อมูลที่อ่านไม่ได้ {} รายการ ระบบแยกเก็บไว้แล้ว ข้อมูลส่วนที่เหลือใช้งานได้ตามปกติ",
+        skipped
+    ))
+}
+
+type ErrorListener = Box<dyn Fn(String)>;
+
+thread_local! {
+    static ERROR_LISTENER: RefCell<Option<ErrorListener>> = RefCell::new(None);
+}
+
+/// Send errors that don't fail a save to the UI, e.g. audit entries the Tauri
+/// file couldn't add after the data itself was written
+pub fn set_error_listener(listener: impl Fn(String) + 'static) {
+    ERROR_LISTENER.with(|l| *l.borrow_mut() = Some(Box::new(listener)));
+}
+
+/// Log a storage error and pass it on to the UI if it is listening
+pub fn report_error(err: String) {
+    gloo::console::error!(err.clone());
+    ERROR_LISTENER.with(|l| {
+        if let Some(listener) = l.borrow().as_ref() {
+            listener(err);
+        }
+    });
+}
+
+/// A save in progress, finished once the data is really stored
+pub type Saving = Pin<Box<dyn Future<Output = Result<(), String>>>>;
+
+/// Where the Store writes its data
+pub trait StorageBackend {
+    /// Persist the collections named by `keys` and add `logged` to the end of the
+    /// audit log, all of it or none. The whole log is only written when `keys`
+    /// names it, e.g. after a restore. Backends that can only store the whole
+    /// document ignore the other keys and write everything. The data is encoded
+    /// before this returns, so it may change while the save is still running.
+    fn save(&self, data: &ClinicData, keys: &[&str], logged: &[AuditEntry]) -> Saving;
+}
+
+fn encode(data: &ClinicData, key: &str) -> Result<Option<String>, serde_json::Error> {
+    let json = match key {
+        KEY_PATIENTS => serde_json::to_string(&data.patients)?,
+        KEY_RECORDS => serde_json::to_string(&data.records)?,
+        KEY_DRUGS => serde_json::to_string(&data.drugs)?,
+        KEY_SETTINGS => serde_json::to_string(&data.settings)?,
+        KEY_EXPENSES => serde_json::to_string(&data.expenses)?,
+        KEY_DRUG_PURCHASES => serde_json::to_string(&data.drug_purchases)?,
+        KEY_APPOINTMENTS => serde_json::to_string(&data.appointments)?,
+        KEY_INTERACTIONS => serde_json::to_string(&data.interactions)?,
+        KEY_LAST_HN => serde_json::to_string(&data.last_hn)?,
+        KEY_UNREADABLE => serde_json::to_string(&data.unreadable)?,
+        KEY_AUDIT_LOG => serde_json::to_string(&data.audit_log)?,
+        KEY_TOMBSTONES => serde_json::to_string(&data.tombstones)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(json))
+}
+
+/// A stored JSON list with `entries` added to the end, without reading the list
+fn append_json(list: Option<&str>, entries: &[AuditEntry]) -> Result<String, serde_json::Error> {
+    let added = serde_json::to_string(entries)?;
+    Ok(match list.map(str::trim).and_then(|list| list.strip_suffix(']')) {
+        Some(head) if head.trim_end() != "[" => format!("{},{}", head, &added[1..]),
+        _ => added,
+    })
+}
+
+// ========== LocalStorage (browser) ==========
+
+pub struct LocalStorageBackend;
+
+impl LocalStorageBackend {
+    /// Load every key. The second value is how many entries could not be read.
+    pub fn load() -> Result<(ClinicData, usize), String> {
+        let mut doc = Map::new();
+        for key in DATA_KEYS.iter().chain([&KEY_AUDIT_LOG, &KEY_SCHEMA_VERSION]) {
+            if let Ok(Some(raw)) = LocalStorage::raw().get_item(key) {
+                // Text that isn't JSON at all is kept as a string so it still lands in `unreadable`
+                let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+                doc.insert(key.to_string(), value);
+            }
+        }
+        let stamped = doc.contains_key(KEY_SCHEMA_VERSION);
+
+        let Parsed { data, migrated, skipped } = ClinicData::from_document(doc)?;
+        if migrated || skipped > 0 {
+            Self::write(&data, &[&DATA_KEYS[..], &[KEY_AUDIT_LOG]].concat(), &[])?;
+        }
+        if migrated || !stamped {
+            let _ = LocalStorage::set(KEY_SCHEMA_VERSION, CURRENT_SCHEMA_VERSION);
+        }
+        Ok((data, skipped))
+    }
+
+    /// Remove the clinic data from LocalStorage, e.g. once the desktop app keeps
+    /// it encrypted and a plain copy in the webview would defeat that
+    pub fn clear() {
+        let storage = LocalStorage::raw();
+        for key in DATA_KEYS.iter().chain([&KEY_AUDIT_LOG, &KEY_SCHEMA_VERSION]) {
+            let _ = storage.remove_item(key);
+        }
+        // Left behind by the oldest builds, see tauri_bridge::load_data
+        let _ = storage.remove_item("clinic_all_data");
+        let _ = LocalStorage::set(KEY_MIGRATED_TO_FILE, true);
+    }
+
+    /// Roughly how much of the LocalStorage quota this site uses, in the same units as `LOCAL_STORAGE_QUOTA`
+    pub fn usage() -> usize {
+        let storage = LocalStorage::raw();
+        let len = storage.length().unwrap_or(0);
+        (0..len)
+            .filter_map(|i| storage.key(i).ok().flatten())
+            .map(|key| {
+                let value = storage.get_item(&key).ok().flatten().unwrap_or_default();
+                key.encode_utf16().count() + value.encode_utf16().count()
+            })
+            .sum()
+    }
+
+    /// All keys are written or none: if one write fails (usually the quota),
+    /// the keys already written are put back to what they were.
+    fn write(data: &ClinicData, keys: &[&str], logged: &[AuditEntry]) -> Result<(), String> {
+        let mut encoded = Vec::new();
+        for key in keys {
+            if let Some(json) = encode(data, key).map_err(|e| format!("Failed to encode {}: {}", key, e))? {
+                encoded.push((*key, json));
+            }
+        }
+
+        let storage = LocalStorage::raw();
+        let mut previous: Vec<Option<String>> = encoded
+            .iter()
+            .map(|(key, _)| storage.get_item(key).ok().flatten())
+            .collect();
+        if !logged.is_empty() && !keys.contains(&KEY_AUDIT_LOG) {
+            let log = storage.get_item(KEY_AUDIT_LOG).ok().flatten();
+            let json = append_json(log.as_deref(), logged).map_err(|e| format!("Failed to encode {}: {}", KEY_AUDIT_LOG, e))?;
+            encoded.push((KEY_AUDIT_LOG, json));
+            previous.push(log);
+        }
+
+        for (i, (key, json)) in encoded.iter().enumerate() {
+            if storage.set_item(key, json).is_err() {
+                for ((key, _), old) in encoded[..i].iter().zip(&previous) {
+                    let _ = match old {
+                        Some(old) => storage.set_item(key, old),
+                        None => storage.remove_item(key),
+                    };
+                }
+                return Err("พื้นที่จัดเก็บข้อมูลของเบราว์เซอร์เต็ม ข้อมูลยังไม่ถูกบันทึก กรุณาสำรองข้อมูลแล้วลบข้อมูลเก่าที่ไม่ใช้".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for LocalStorageBackend {
+    fn save(&self, data: &ClinicData, keys: &[&str], logged: &[AuditEntry]) -> Saving {
+        Box::pin(future::ready(Self::write(data, keys, logged)))
+    }
+}
+
+// ========== clinic_data.json (Tauri) ==========
+
+pub struct TauriFileBackend;
+
+impl TauriFileBackend {
+    /// Load clinic_data.json. On the first desktop launch the file is still empty,
+    /// so whatever the webview kept in LocalStorage is moved into it.
+    /// Also returns anything the user should be told about the load.
+    pub async fn load() -> Result<(ClinicData, Option<String>), String> {
+        let loaded = tauri_bridge::load_data().await?;
+        let mut doc: Map<String, Value> = serde_json::from_str(&loaded.data)
+            .map_err(|e| format!("Invalid clinic_data.json: {}", e))?;
+        // Files from before the log had its own file carry it inline, as does a restored backup
+        let inline_log = doc.contains_key(KEY_AUDIT_LOG);
+        if !inline_log {
+            let log = tauri_bridge::load_audit_log().await?;
+            let log = serde_json::from_str(&log).map_err(|e| format!("Invalid audit log: {}", e))?;
+            doc.insert(KEY_AUDIT_LOG.to_string(), log);
+        }
+        let Parsed { data: file_data, migrated: upgraded, skipped } = ClinicData::from_document(doc)?;
+        if upgraded || skipped > 0 || inline_log {
+            Self.save(&file_data, &[KEY_AUDIT_LOG], &[]).await?;
+        }
+        let notice = loaded.warning.or_else(|| unreadable_notice(skipped));
+
+        let migrated: bool = LocalStorage::get(KEY_MIGRATED_TO_FILE).unwrap_or(false);
+        if !file_data.is_empty() || migrated {
+            return Ok((file_data, notice));
+        }
+
+        let (local_data, skipped) = LocalStorageBackend::load()?;
+        if local_data.is_empty() {
+            return Ok((file_data, notice));
+        }
+        tauri_bridge::save_data(&local_data.to_file_json()?).await?;
+        let log = serde_json::to_string(&local_data.audit_log).map_err(|e| e.to_string())?;
+        tauri_bridge::save_audit_log(&log).await?;
+        let _ = LocalStorage::set(KEY_MIGRATED_TO_FILE, true);
+        Ok((local_data, unreadable_notice(skipped)))
+    }
+}
+
+impl StorageBackend for TauriFileBackend {
+    fn save(&self, data: &ClinicData, keys: &[&str], logged: &[AuditEntry]) -> Saving {
+        // The file always holds the whole document. A whole log is written first,
+        // so moving it out of an old data file can't lose it; new entries go after
+        // the change they describe.
+        let encoded = data.to_file_json().and_then(|json| {
+            let log = if keys.contains(&KEY_AUDIT_LOG) {
+                Some(serde_json::to_string(&data.audit_log).map_err(|e| e.to_string())?)
+            } else {
+                None
+            };
+            let added = if log.is_none() && !logged.is_empty() {
+                Some(serde_json::to_string(logged).map_err(|e| e.to_string())?)
+            } else {
+                None
+            };
+            Ok((json, log, added))
+        });
+        Box::pin(async move {
+            let (json, log, added) = encoded?;
+            let log_error = |err: String| format!("บันทึกประวัติการแก้ไขไม่สำเร็จ: {}", err);
+            if let Some(log) = log {
+                tauri_bridge::save_audit_log(&log).await.map_err(log_error)?;
+            }
+            tauri_bridge::save_data(&json).await.map_err(|err| format!("บันทึกไฟล์ข้อมูลไม่สำเร็จ: {}", err))?;
+            if let Some(added) = added {
+                // The change itself is in the file by now, so the save stands
+                if let Err(err) = tauri_bridge::append_audit_log(&added).await {
+                    report_error(log_error(err));
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+// ========== Read-only (after a failed load) ==========
+
+/// Refuses every save with the reason the data couldn't be loaded, so nothing
+/// typed in meanwhile looks saved when it isn't, and the data that couldn't be
+/// read is never overwritten
+pub struct ReadOnlyBackend {
+    pub reason: String,
+}
+
+impl StorageBackend for ReadOnlyBackend {
+    fn save(&self, _data: &ClinicData, _keys: &[&str], _logged: &[AuditEntry]) -> Saving {
+        Box::pin(future::ready(Err(format!("บันทึกไม่ได้ เพราะเปิดข้อมูลเดิมไม่สำเร็จ: {}", self.reason))))
+    }
+}
+
+// ========== In-memory (tests) ==========
+
+/// Keeps the last saved document in memory. Clones share the same copy, so a
+/// test can hold on to one and inspect what the Store wrote.
+#[derive(Clone, Default)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct MemoryBackend {
+    saved: Rc<RefCell<ClinicData>>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemoryBackend {
+    pub fn saved(&self) -> ClinicData {
+        self.saved.borrow().clone()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn save(&self, data: &ClinicData, _keys: &[&str], _logged: &[AuditEntry]) -> Saving {
+        *self.saved.borrow_mut() = data.clone();
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_corrupt_entry_is_set_aside() {
+        let doc = json!({
+            KEY_SCHEMA_VERSION: CURRENT_SCHEMA_VERSION,
+            KEY_PATIENTS: [
+                { "id": "p1", "hn": "HN-00001", "citizen_id": "", "title": "นาย", "first_name": "สมชาย",
+                  "last_name": "ใจดี", "birth_date": null, "drug_allergy": "", "phone": "", "address": "",
+                  "created_at": "2024-01-01T00:00:00Z" },
+                { "id": "p2", "first_name": 42 }
+            ],
+            KEY_DRUGS: "not a list",
+            KEY_LAST_HN: 7
+        });
+
+        let parsed = ClinicData::from_document(doc.as_object().unwrap().clone()).unwrap();
+        assert_eq!(parsed.data.patients.len(), 1);
+        assert_eq!(parsed.data.last_hn, 7);
+        assert_eq!(parsed.skipped, 2);
+        assert!(parsed.data.unreadable.iter().any(|u| u["key"] == KEY_PATIENTS && u["value"]["id"] == "p2"));
+
+        // Entries set aside earlier are kept, but not reported again
+        let saved = serde_json::to_value(&parsed.data).unwrap();
+        let again = ClinicData::from_document(saved.as_object().unwrap().clone()).unwrap();
+        assert_eq!(again.skipped, 0);
+        assert_eq!(again.data.unreadable.len(), 2);
+    }
+
+    #[test]
+    fn test_log_is_appended_and_kept_out_of_the_file() {
+        let entry = |id: &str| AuditEntry {
+            id: id.to_string(),
+            timestamp: chrono::Utc::now(),
+            actor: String::new(),
+            action: "create".to_string(),
+            entity: "drug".to_string(),
+            entity_id: "d1".to_string(),
+            label: String::new(),
+            before: None,
+            after: None,
+        };
+        let ids = |json: &str| -> Vec<String> {
+            serde_json::from_str::<Vec<AuditEntry>>(json).unwrap().into_iter().map(|e| e.id).collect()
+        };
+        let first = append_json(None, &[entry("a")]).unwrap();
+        assert_eq!(ids(&first), ["a"]);
+        assert_eq!(ids(&append_json(Some(&first), &[entry("b"), entry("c")]).unwrap()), ["a", "b", "c"]);
+        assert_eq!(ids(&append_json(Some("[]"), &[entry("b")]).unwrap()), ["b"]);
+
+        let data = ClinicData { last_hn: 3, audit_log: vec![entry("a")], ..ClinicData::default() };
+        let file: Map<String, Value> = serde_json::from_str(&data.to_file_json().unwrap()).unwrap();
+        assert!(!file.contains_key(KEY_AUDIT_LOG));
+        assert_eq!(file[KEY_LAST_HN], 3);
+        let parsed = ClinicData::from_document(file).unwrap().data;
+        assert_eq!(parsed.last_hn, 3);
+        assert!(parsed.audit_log.is_empty());
+    }
+}
@@ -1,166 +1,769 @@
-use gloo::storage::{LocalStorage, Storage};
-use crate::models::{Patient, TreatmentRecord, DrugItem, ClinicSettings, Expense, DrugPurchase, Appointment};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+use crate::allergy;
+use crate::archive::{self, Archive};
+use crate::audit::{self, Audited};
+use crate::cache::Indexes;
+use crate::duplicates;
+use crate::hn;
+use crate::integrity;
+use crate::interactions;
+use crate::models::{Patient, TreatmentRecord, PrescriptionItem, DrugItem, ClinicSettings, Expense, DrugPurchase, Appointment, InteractionRule, AuditEntry, SoftDelete, Tombstone, HnFormat};
+use crate::storage::{
+    ClinicData, StorageBackend, LocalStorageBackend, TauriFileBackend, ReadOnlyBackend, unreadable_notice,
+    KEY_PATIENTS, KEY_RECORDS, KEY_LAST_HN, KEY_DRUGS, KEY_SETTINGS, KEY_EXPENSES, KEY_DRUG_PURCHASES, KEY_APPOINTMENTS,
+    KEY_INTERACTIONS, KEY_AUDIT_LOG, KEY_TOMBSTONES,
+};
+use crate::sync;
+use crate::tauri_bridge;
 
-const KEY_PATIENTS: &str = "clinic_patients";
-const KEY_RECORDS: &str = "clinic_records";
-#[allow(dead_code)]
-const KEY_LAST_HN: &str = "clinic_last_hn";
-const KEY_DRUGS: &str = "clinic_drugs";
-const KEY_SETTINGS: &str = "clinic_settings";
-const KEY_EXPENSES: &str = "clinic_expenses";
-const KEY_DRUG_PURCHASES: &str = "clinic_drug_purchases";
-const KEY_APPOINTMENTS: &str = "clinic_appointments";
+thread_local! {
+    static DATA: RefCell<ClinicData> = RefCell::new(ClinicData::default());
+    static INDEXES: RefCell<Indexes> = RefCell::new(Indexes::default());
+    static BACKEND: RefCell<Box<dyn StorageBackend>> = RefCell::new(Box::new(LocalStorageBackend));
+    // Problem found while loading, shown to the user once the UI is up
+    static LOAD_NOTICE: RefCell<Option<String>> = const { RefCell::new(None) };
+    static WRITER: RefCell<Writer> = RefCell::new(Writer::default());
+}
+
+/// Whose turn it is to write. A transaction keeps its turn until the backend
+/// has saved it, so the next one starts from saved data and the saves reach
+/// the backend in the order they were made.
+#[derive(Default)]
+struct Writer {
+    busy: bool,
+    waiting: Vec<Waker>,
+}
+
+/// Waits until no other transaction is writing
+struct Turn;
+
+impl Future for Turn {
+    type Output = TurnGuard;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TurnGuard> {
+        WRITER.with(|w| {
+            let mut writer = w.borrow_mut();
+            if writer.busy {
+                writer.waiting.push(cx.waker().clone());
+                Poll::Pending
+            } else {
+                writer.busy = true;
+                Poll::Ready(TurnGuard)
+            }
+        })
+    }
+}
 
+/// Gives up the turn when dropped, even if the transaction was
+struct TurnGuard;
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        let waiting = WRITER.with(|w| {
+            let mut writer = w.borrow_mut();
+            writer.busy = false;
+            std::mem::take(&mut writer.waiting)
+        });
+        // Woken in the order they arrived; the first to run takes the turn and the rest wait again
+        waiting.into_iter().for_each(Waker::wake);
+    }
+}
 
 pub struct Store;
 
+/// Something in the Recycle Bin
+#[derive(Clone, PartialEq)]
+pub struct TrashItem {
+    /// Same names as `AuditEntry::entity`
+    pub entity: &'static str,
+    pub id: String,
+    pub label: String,
+    pub deleted_at: DateTime<Utc>,
+    /// Records that were deleted together with this patient and come back with them
+    pub related: usize,
+}
+
+/// `patient` needs an HN no other patient has, Recycle Bin included, since
+/// restoring one from there would bring back the same HN twice
+fn check_hn(patients: &[Patient], patient: &Patient) -> Result<(), String> {
+    let hn = patient.hn.trim();
+    if hn.is_empty() {
+        return Err("กรุณาระบุเลข HN".to_string());
+    }
+    match patients.iter().find(|p| p.id != patient.id && p.hn.trim() == hn) {
+        Some(p) if p.is_deleted() => Err(format!("เลข HN {} เป็นของผู้ป่วยในถังขยะ ({}{} {})", hn, p.title, p.first_name, p.last_name)),
+        Some(p) => Err(format!("เลข HN {} มีในระบบแล้ว ({}{} {})", hn, p.title, p.first_name, p.last_name)),
+        None => Ok(()),
+    }
+}
+
+/// Refuse a record prescribing a drug its patient is allergic to, unless its
+/// override covers that drug and gives a reason
+fn check_allergies(data: &ClinicData, record: &TreatmentRecord) -> Result<(), String> {
+    let Some(patient) = data.patients.iter().find(|p| p.id == record.patient_id) else {
+        return Ok(());
+    };
+    let conflicts = allergy::conflicts(&patient.allergies, &record.prescriptions, &data.drugs);
+    let overridden = |drug: &str| {
+        record.allergy_override.as_ref().is_some_and(|o| !o.reason.trim().is_empty() && o.drugs.iter().any(|d| d == drug))
+    };
+    match conflicts.iter().find(|c| !overridden(&c.drug)) {
+        Some(c) => Err(format!(
+            "ผู้ป่วยแพ้ {} แต่ใบสั่งยามี {} กรุณาระบุเหตุผลก่อนจ่ายยา",
+            allergy::allergen_name(&c.allergy.allergen), c.drug
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Copies of the items that are not in the Recycle Bin
+fn live<T: SoftDelete + Clone>(items: &[T]) -> Vec<T> {
+    items.iter().filter(|item| !item.is_deleted()).cloned().collect()
+}
+
+/// Move matching items that are not deleted yet to the Recycle Bin
+fn mark_deleted<T: SoftDelete>(items: &mut [T], at: DateTime<Utc>, matches: impl Fn(&T) -> bool) {
+    for item in items.iter_mut().filter(|item| !item.is_deleted() && matches(item)) {
+        item.set_deleted_at(Some(at));
+    }
+}
+
+fn trash_item<T: SoftDelete + Audited>(item: &T) -> Option<TrashItem> {
+    let deleted_at = item.deleted_at()?;
+    Some(TrashItem { entity: T::ENTITY, id: item.id().to_string(), label: item.label(), deleted_at, related: 0 })
+}
+
+/// Take an item out of the Recycle Bin. Returns when it had been deleted.
+fn restore_item<T: SoftDelete + Audited>(items: &mut [T], id: &str) -> Option<DateTime<Utc>> {
+    let item = items.iter_mut().find(|item| item.id() == id && item.is_deleted())?;
+    let at = item.deleted_at();
+    item.set_deleted_at(None);
+    at
+}
+
+/// The patient to move things to; one in the Recycle Bin doesn't count
+fn live_patient<'a>(data: &'a ClinicData, patient_id: &str) -> Result<&'a Patient, String> {
+    data.patients
+        .iter()
+        .find(|p| p.id == patient_id && !p.is_deleted())
+        .ok_or_else(|| "ไม่พบผู้ป่วยปลายทาง หรือผู้ป่วยอยู่ในถังขยะ".to_string())
+}
+
+fn purge_item<T: SoftDelete + Audited>(items: &mut Vec<T>, id: &str) {
+    items.retain(|item| !(item.id() == id && item.is_deleted()));
+}
+
+fn has_expired<T: SoftDelete>(items: &[T], cutoff: DateTime<Utc>) -> bool {
+    items.iter().any(|item| item.deleted_at().is_some_and(|at| at < cutoff))
+}
+
+fn purge_expired<T: SoftDelete>(items: &mut Vec<T>, cutoff: DateTime<Utc>) {
+    items.retain(|item| item.deleted_at().is_none_or(|at| at >= cutoff));
+}
+
+/// Copy the collection stored under `key` from one document to the other
+fn copy_collection(from: &ClinicData, to: &mut ClinicData, key: &str) {
+    match key {
+        KEY_PATIENTS => to.patients.clone_from(&from.patients),
+        KEY_RECORDS => to.records.clone_from(&from.records),
+        KEY_DRUGS => to.drugs.clone_from(&from.drugs),
+        KEY_SETTINGS => to.settings.clone_from(&from.settings),
+        KEY_EXPENSES => to.expenses.clone_from(&from.expenses),
+        KEY_DRUG_PURCHASES => to.drug_purchases.clone_from(&from.drug_purchases),
+        KEY_APPOINTMENTS => to.appointments.clone_from(&from.appointments),
+        KEY_INTERACTIONS => to.interactions.clone_from(&from.interactions),
+        KEY_LAST_HN => to.last_hn = from.last_hn,
+        KEY_AUDIT_LOG => to.audit_log.clone_from(&from.audit_log),
+        KEY_TOMBSTONES => to.tombstones.clone_from(&from.tombstones),
+        _ => {}
+    }
+}
+
+/// Swap the collection stored under `key` between two documents
+fn swap_collection(a: &mut ClinicData, b: &mut ClinicData, key: &str) {
+    use std::mem::swap;
+    match key {
+        KEY_PATIENTS => swap(&mut a.patients, &mut b.patients),
+        KEY_RECORDS => swap(&mut a.records, &mut b.records),
+        KEY_DRUGS => swap(&mut a.drugs, &mut b.drugs),
+        KEY_SETTINGS => swap(&mut a.settings, &mut b.settings),
+        KEY_EXPENSES => swap(&mut a.expenses, &mut b.expenses),
+        KEY_DRUG_PURCHASES => swap(&mut a.drug_purchases, &mut b.drug_purchases),
+        KEY_APPOINTMENTS => swap(&mut a.appointments, &mut b.appointments),
+        KEY_INTERACTIONS => swap(&mut a.interactions, &mut b.interactions),
+        KEY_LAST_HN => swap(&mut a.last_hn, &mut b.last_hn),
+        KEY_AUDIT_LOG => swap(&mut a.audit_log, &mut b.audit_log),
+        KEY_TOMBSTONES => swap(&mut a.tombstones, &mut b.tombstones),
+        _ => {}
+    }
+}
+
+/// Changes made to the data while the closure given to `Store::transaction`
+/// runs. Each collection is copied aside the first time it is touched, so if the
+/// closure fails those copies are put back and the data is left exactly as it
+/// was; otherwise every touched collection is saved together or not at all.
+pub struct Transaction<'a> {
+    data: &'a mut ClinicData,
+    /// The touched collections as they were before the transaction; the rest are left empty
+    before: ClinicData,
+    keys: Vec<&'static str>,
+    /// Ids moved to an archive; they are logged once per year, not once each
+    archived: HashSet<String>,
+    /// Entries to add to the audit log besides the ones worked out from the changes
+    logged: Vec<AuditEntry>,
+    /// Changes from the other computer: already stamped and logged over there
+    from_peer: bool,
+}
+
+impl Transaction<'_> {
+    fn touch(&mut self, key: &'static str) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+            copy_collection(self.data, &mut self.before, key);
+        }
+    }
+
+    /// Put every touched collection back as it was
+    fn roll_back(&mut self) {
+        for key in &self.keys {
+            copy_collection(&self.before, self.data, key);
+        }
+    }
+
+    /// The data, including changes made so far in this transaction
+    pub fn data(&self) -> &ClinicData {
+        self.data
+    }
+
+    pub fn patients(&mut self) -> &mut Vec<Patient> {
+        self.touch(KEY_PATIENTS);
+        &mut self.data.patients
+    }
+
+    pub fn records(&mut self) -> &mut Vec<TreatmentRecord> {
+        self.touch(KEY_RECORDS);
+        &mut self.data.records
+    }
+
+    pub fn drugs(&mut self) -> &mut Vec<DrugItem> {
+        self.touch(KEY_DRUGS);
+        &mut self.data.drugs
+    }
+
+    /// A live drug by id, found through the index unless this transaction has
+    /// already moved it
+    pub fn drug(&mut self, id: &str) -> Option<&mut DrugItem> {
+        let indexed = INDEXES.with(|i| i.borrow().drug(id));
+        let drugs = self.drugs();
+        let pos = indexed
+            .filter(|&pos| drugs.get(pos).is_some_and(|x| x.id == id && !x.is_deleted()))
+            .or_else(|| drugs.iter().position(|x| x.id == id && !x.is_deleted()))?;
+        Some(&mut drugs[pos])
+    }
+
+    pub fn settings(&mut self) -> &mut ClinicSettings {
+        self.touch(KEY_SETTINGS);
+        &mut self.data.settings
+    }
+
+    pub fn expenses(&mut self) -> &mut Vec<Expense> {
+        self.touch(KEY_EXPENSES);
+        &mut self.data.expenses
+    }
+
+    pub fn drug_purchases(&mut self) -> &mut Vec<DrugPurchase> {
+        self.touch(KEY_DRUG_PURCHASES);
+        &mut self.data.drug_purchases
+    }
+
+    pub fn appointments(&mut self) -> &mut Vec<Appointment> {
+        self.touch(KEY_APPOINTMENTS);
+        &mut self.data.appointments
+    }
+
+    pub fn interactions(&mut self) -> &mut Vec<InteractionRule> {
+        self.touch(KEY_INTERACTIONS);
+        &mut self.data.interactions
+    }
+
+    pub fn last_hn(&mut self) -> &mut u32 {
+        self.touch(KEY_LAST_HN);
+        &mut self.data.last_hn
+    }
+
+    /// Add an entry that changes alone don't produce, e.g. for a whole archived year
+    pub fn log(&mut self, entry: AuditEntry) {
+        self.logged.push(entry);
+    }
+
+    /// Only for bringing in entries from elsewhere, e.g. a restored backup, and
+    /// rewrites the whole log. Changes made in the transaction are logged without this.
+    pub fn audit_log(&mut self) -> &mut Vec<AuditEntry> {
+        self.touch(KEY_AUDIT_LOG);
+        &mut self.data.audit_log
+    }
+
+    pub fn tombstones(&mut self) -> &mut Vec<Tombstone> {
+        self.touch(KEY_TOMBSTONES);
+        &mut self.data.tombstones
+    }
+}
+
 impl Store {
+    // ========== Startup ==========
+
+    /// Load all data once before the app renders. Inside Tauri the data lives in
+    /// `clinic_data.json`; in a plain browser LocalStorage is used instead.
+    pub async fn init() {
+        Self::load().await;
+        if let Err(err) = Self::purge_expired().await {
+            gloo::console::error!(format!("Failed to empty the Recycle Bin: {}", err));
+        }
+        if let Err(err) = archive::rotate_log().await {
+            gloo::console::error!(format!("Failed to archive old audit entries: {}", err));
+        }
+    }
+
+    async fn load() {
+        if tauri_bridge::is_tauri() {
+            match TauriFileBackend::load().await {
+                Ok((data, warning)) => {
+                    LOAD_NOTICE.with(|n| *n.borrow_mut() = warning);
+                    return Self::init_with(Box::new(TauriFileBackend), data);
+                }
+                Err(err) => {
+                    // Don't risk overwriting a file we could not read, nor keep
+                    // entries anywhere the next start won't look
+                    gloo::console::error!(format!("Failed to load data file: {}", err));
+                    return Self::init_read_only(err);
+                }
+            }
+        }
+
+        match LocalStorageBackend::load() {
+            Ok((data, skipped)) => {
+                LOAD_NOTICE.with(|n| *n.borrow_mut() = unreadable_notice(skipped));
+                Self::init_with(Box::new(LocalStorageBackend), data);
+            }
+            Err(err) => {
+                // Data from a newer version: show nothing rather than overwrite it
+                gloo::console::error!(format!("Failed to load data: {}", err));
+                Self::init_read_only(err);
+            }
+        }
+    }
+
+    /// Start with no data and refuse every save, after the data couldn't be loaded
+    fn init_read_only(reason: String) {
+        LOAD_NOTICE.with(|n| *n.borrow_mut() = Some(reason.clone()));
+        Self::init_with(Box::new(ReadOnlyBackend { reason }), ClinicData::default());
+    }
+
+    /// The loading problem to tell the user about, if any. Returns it only once.
+    pub fn take_load_notice() -> Option<String> {
+        LOAD_NOTICE.with(|n| n.borrow_mut().take())
+    }
+
+    /// Replace the in-memory data and the backend it is saved to
+    pub fn init_with(backend: Box<dyn StorageBackend>, data: ClinicData) {
+        INDEXES.with(|i| *i.borrow_mut() = Indexes::build(&data));
+        DATA.with(|d| *d.borrow_mut() = data);
+        BACKEND.with(|b| *b.borrow_mut() = backend);
+    }
+
+    fn read<R>(f: impl FnOnce(&ClinicData) -> R) -> R {
+        DATA.with(|d| f(&d.borrow()))
+    }
+
+    /// Read through the indexes instead of scanning
+    fn lookup<R>(f: impl FnOnce(&ClinicData, &Indexes) -> R) -> R {
+        DATA.with(|d| INDEXES.with(|i| f(&d.borrow(), &i.borrow())))
+    }
+
+    fn records_between(start: NaiveDate, end: NaiveDate) -> Vec<TreatmentRecord> {
+        Self::lookup(|d, i| i.records_between(start, end).map(|pos| d.records[pos].clone()).collect())
+    }
+
+    /// Run `f` against the data. If it returns `Ok` the touched collections are
+    /// saved in one go and the changes show once the backend has stored them;
+    /// if `f` fails or the backend can't save, the data is left exactly as it
+    /// was and the error is returned. Transactions run one at a time, each
+    /// starting after the one before it is saved.
+    pub async fn transaction<R>(f: impl FnOnce(&mut Transaction) -> Result<R, String>) -> Result<R, String> {
+        let _turn = Turn.await;
+        let (result, saving, mut after, keys, logged) = DATA.with(|d| {
+            let mut data = d.borrow_mut();
+            let actor = data.settings.staff_name.clone();
+            let logged_before = data.audit_log.len();
+            let mut tx = Transaction {
+                data: &mut data,
+                before: ClinicData::default(),
+                keys: Vec::new(),
+                archived: HashSet::new(),
+                logged: Vec::new(),
+                from_peer: false,
+            };
+            let result = match f(&mut tx) {
+                Ok(result) => result,
+                Err(err) => {
+                    tx.roll_back();
+                    tx.data.audit_log.truncate(logged_before);
+                    return Err(err);
+                }
+            };
+            if tx.keys.is_empty() {
+                return Ok((result, None, ClinicData::default(), Vec::new(), Vec::new()));
+            }
+
+            // Every change is stamped for sync and logged in the same save, so none
+            // can land without its audit entry
+            if !tx.from_peer {
+                sync::stamp(&tx.before, tx.data, &tx.keys, Utc::now());
+                let mut entries = audit::changes(&tx.before, tx.data, &tx.keys, &actor);
+                entries.retain(|e| e.after.is_some() || !tx.archived.contains(&e.entity_id));
+                let removed = sync::tombstones(&entries);
+                if !removed.is_empty() {
+                    tx.tombstones().extend(removed);
+                }
+                tx.logged.extend(entries);
+                tx.data.audit_log.extend(tx.logged.iter().cloned());
+            }
+            let saving = BACKEND.with(|b| b.borrow().save(tx.data, &tx.keys, &tx.logged));
+
+            // Keep the changes aside until the save is done, so the data only ever
+            // shows what is stored
+            let Transaction { data, before: mut after, keys, logged, .. } = tx;
+            for key in &keys {
+                swap_collection(data, &mut after, key);
+            }
+            data.audit_log.truncate(logged_before);
+            Ok((result, Some(saving), after, keys, logged))
+        })?;
+        let Some(saving) = saving else {
+            return Ok(result);
+        };
+        saving.await?;
+
+        DATA.with(|d| {
+            let mut data = d.borrow_mut();
+            INDEXES.with(|i| i.borrow_mut().update(&data, &after, &keys));
+            for key in &keys {
+                swap_collection(&mut data, &mut after, key);
+            }
+            if !keys.contains(&KEY_AUDIT_LOG) {
+                data.audit_log.extend(logged);
+            }
+        });
+        Ok(result)
+    }
+
+    /// A transaction whose only way to fail is the save itself
+    async fn write<R>(f: impl FnOnce(&mut Transaction) -> R) -> Result<R, String> {
+        Self::transaction(|tx| Ok(f(tx))).await
+    }
+
+    // ========== Backup ==========
+    /// A copy of everything, Recycle Bin and audit log included
+    pub fn snapshot() -> ClinicData {
+        Self::read(ClinicData::clone)
+    }
+
+    /// Swap every collection for the ones in `data`, e.g. from `backup::merge`.
+    /// Only what actually differs is logged.
+    pub async fn replace_data(data: ClinicData) -> Result<(), String> {
+        Self::write(|tx| {
+            *tx.patients() = data.patients;
+            *tx.records() = data.records;
+            *tx.drugs() = data.drugs;
+            *tx.settings() = data.settings;
+            *tx.expenses() = data.expenses;
+            *tx.drug_purchases() = data.drug_purchases;
+            *tx.appointments() = data.appointments;
+            *tx.interactions() = data.interactions;
+            *tx.last_hn() = data.last_hn;
+            *tx.audit_log() = data.audit_log;
+        }).await
+    }
+
+    /// What archiving everything before `before_year` would move, by year
+    pub fn get_archivable(before_year: i32) -> Vec<Archive> {
+        Self::read(|d| archive::split(d, before_year))
+    }
+
+    /// Take what `archives` hold out of the active data, once they are saved.
+    /// Each year is logged as one entry instead of a delete per item, which
+    /// would copy everything archived into the audit log. The tombstones left
+    /// behind carry the year, so sync archives the items on the other computer too.
+    pub async fn remove_archived(archives: &[Archive]) -> Result<(), String> {
+        Self::write(|tx| {
+            let actor = tx.data.settings.staff_name.clone();
+            for archive in archives.iter().filter(|a| !a.is_empty()) {
+                let ids = archive.ids();
+                tx.records().retain(|r| !ids.contains(&r.id));
+                tx.expenses().retain(|e| !ids.contains(&e.id));
+                tx.drug_purchases().retain(|p| !ids.contains(&p.id));
+                tx.log(audit::archived(archive.year, archive.summary(), &actor));
+                tx.tombstones().extend(sync::archived(archive, Utc::now()));
+                tx.archived.extend(ids);
+            }
+        }).await
+    }
+
+    /// What the other computer archived, to file into our own archives before
+    /// `apply_sync` takes it out of the active data
+    pub fn get_archived_by_peer(incoming: &ClinicData) -> Vec<Archive> {
+        Self::read(|d| sync::to_archive(d, incoming))
+    }
+
+    /// Apply changes that came from the other computer, see sync.rs. Returns
+    /// how many items were added, replaced or removed, and any HNs that now clash.
+    pub async fn apply_sync(incoming: ClinicData) -> Result<sync::Applied, String> {
+        Self::transaction(|tx| {
+            let (merged, changed) = sync::apply(tx.data, incoming);
+            if merged == *tx.data {
+                return Ok(sync::Applied::default());
+            }
+            let hn_clashes = sync::hn_clashes(tx.data, &merged);
+            tx.from_peer = true;
+            *tx.patients() = merged.patients;
+            *tx.records() = merged.records;
+            *tx.drugs() = merged.drugs;
+            *tx.expenses() = merged.expenses;
+            *tx.drug_purchases() = merged.drug_purchases;
+            *tx.appointments() = merged.appointments;
+            *tx.interactions() = merged.interactions;
+            *tx.last_hn() = merged.last_hn;
+            *tx.audit_log() = merged.audit_log;
+            *tx.tombstones() = merged.tombstones;
+            Ok(sync::Applied { changed, hn_clashes })
+        }).await
+    }
+
+    // ========== Audit Log ==========
+    /// Every logged change, oldest first
+    pub fn get_audit_log() -> Vec<AuditEntry> {
+        Self::read(|d| d.audit_log.clone())
+    }
+
+    /// The oldest entries beyond the newest `keep`, see `archive::rotate_log`
+    pub fn get_log_overflow(keep: usize) -> Vec<AuditEntry> {
+        Self::read(|d| d.audit_log[..d.audit_log.len().saturating_sub(keep)].to_vec())
+    }
+
+    /// Take entries out of the log once their archives are saved
+    pub async fn remove_logged(ids: &HashSet<String>) -> Result<(), String> {
+        Self::write(|tx| tx.audit_log().retain(|e| !ids.contains(&e.id))).await
+    }
+
     // ========== Patients ==========
     pub fn get_patients() -> Vec<Patient> {
-        LocalStorage::get(KEY_PATIENTS).unwrap_or_else(|_| Vec::new())
+        Self::read(|d| live(&d.patients))
     }
 
-    pub fn save_patient(patient: Patient) {
-        let mut patients = Self::get_patients();
-        patients.push(patient);
-        let _ = LocalStorage::set(KEY_PATIENTS, patients);
+    pub fn get_patient(id: &str) -> Option<Patient> {
+        Self::lookup(|d, i| i.patient(id).map(|pos| d.patients[pos].clone()))
     }
-    
-    pub fn delete_patient(patient_id: &str) {
-        // 1. Delete Patient
-        let patients: Vec<Patient> = Self::get_patients()
-            .into_iter()
-            .filter(|p| p.id != patient_id)
-            .collect();
-        let _ = LocalStorage::set(KEY_PATIENTS, patients);
 
-        // 2. Delete Related Records
-        let records: Vec<TreatmentRecord> = Self::get_records()
-            .into_iter()
-            .filter(|r| r.patient_id != patient_id)
-            .collect();
-        let _ = LocalStorage::set(KEY_RECORDS, records);
+    pub fn get_patient_by_hn(hn: &str) -> Option<Patient> {
+        Self::lookup(|d, i| i.patient_by_hn(hn).map(|pos| d.patients[pos].clone()))
+    }
+
+    pub fn patient_count() -> usize {
+        Self::read(|d| d.patients.iter().filter(|p| !p.is_deleted()).count())
+    }
+
+    pub async fn save_patient(patient: Patient) -> Result<(), String> {
+        Self::transaction(|tx| {
+            check_hn(&tx.data.patients, &patient)?;
+            tx.patients().push(patient);
+            Ok(())
+        }).await
+    }
+
+    /// Save a new patient under the next HN in the format set in Settings.
+    /// Returns the HN they were given.
+    pub async fn register_patient(mut patient: Patient) -> Result<String, String> {
+        Self::transaction(|tx| {
+            let year = Local::now().year();
+            let format = tx.data.settings.hn_format.clone();
+            let number = hn::next_number(&format, year, &tx.data.patients, tx.data.last_hn);
+            patient.hn = hn::format(&format, year, number);
+            check_hn(&tx.data.patients, &patient)?;
+            *tx.last_hn() = number;
+            tx.patients().push(patient.clone());
+            Ok(patient.hn)
+        }).await
     }
     
-    pub fn update_patient(updated: Patient) {
-        let mut patients = Self::get_patients();
-        if let Some(pos) = patients.iter().position(|p| p.id == updated.id) {
-            patients[pos] = updated;
-            let _ = LocalStorage::set(KEY_PATIENTS, patients);
-        }
+    /// Move the patient and their records to the Recycle Bin
+    pub async fn delete_patient(patient_id: &str) -> Result<(), String> {
+        let now = Utc::now();
+        Self::write(|tx| {
+            // Same timestamp on both, so restoring the patient brings back exactly these records
+            mark_deleted(tx.patients(), now, |p| p.id == patient_id);
+            mark_deleted(tx.records(), now, |r| r.patient_id == patient_id);
+        }).await
+    }
+    
+    pub async fn update_patient(updated: Patient) -> Result<(), String> {
+        Self::transaction(|tx| {
+            check_hn(&tx.data.patients, &updated)?;
+            if let Some(p) = tx.patients().iter_mut().find(|p| p.id == updated.id && !p.is_deleted()) {
+                *p = updated;
+            }
+            Ok(())
+        }).await
+    }
+
+    /// The HN the next registered patient will get
+    pub fn next_hn() -> String {
+        Self::next_hn_in(&Self::get_settings().hn_format)
+    }
+
+    /// The HN the next patient would get in `format`, e.g. to preview a new format
+    pub fn next_hn_in(format: &HnFormat) -> String {
+        let year = Local::now().year();
+        Self::read(|d| hn::format(format, year, hn::next_number(format, year, &d.patients, d.last_hn)))
+    }
+
+    /// HNs that are shared or not in the format, Recycle Bin included
+    pub fn check_hns() -> hn::Check {
+        Self::read(|d| hn::check(&d.settings.hn_format, &d.patients))
+    }
+
+    /// Give every patient a new HN in the format, in the order they registered.
+    /// Returns how many HNs changed.
+    pub async fn renumber_hns() -> Result<usize, String> {
+        Self::write(|tx| {
+            let format = tx.data.settings.hn_format.clone();
+            let renumbered = hn::renumber(&format, &tx.data.patients);
+            let mut changed = 0;
+            for p in tx.patients().iter_mut() {
+                if let Some(new) = renumbered.get(&p.id).filter(|new| **new != p.hn) {
+                    p.hn = new.clone();
+                    changed += 1;
+                }
+            }
+            let year = Local::now().year();
+            *tx.last_hn() = hn::next_number(&format, year, &tx.data.patients, 0) - 1;
+            changed
+        }).await
     }
     
 
 
     // ========== Treatment Records ==========
     pub fn get_records() -> Vec<TreatmentRecord> {
-        LocalStorage::get(KEY_RECORDS).unwrap_or_else(|_| Vec::new())
+        Self::read(|d| live(&d.records))
     }
     
     pub fn get_records_by_patient(patient_id: &str) -> Vec<TreatmentRecord> {
-        Self::get_records()
-            .into_iter()
-            .filter(|r| r.patient_id == patient_id)
-            .collect()
+        Self::lookup(|d, i| {
+            i.records_of_patient(patient_id)
+                .iter()
+                .map(|&pos| d.records[pos].clone())
+                .collect()
+        })
     }
 
-    pub fn save_record(record: TreatmentRecord) {
-        // Reduce drug stock for each prescription
-        for rx in &record.prescriptions {
-            Self::reduce_drug_stock_by_name(&rx.name, &rx.amount);
-        }
-        
-        let mut records = Self::get_records();
-        records.push(record);
-        let _ = LocalStorage::set(KEY_RECORDS, records);
+    pub fn get_record(id: &str) -> Option<TreatmentRecord> {
+        Self::lookup(|d, i| i.record(id).map(|pos| d.records[pos].clone()))
     }
-    
-    /// Reduce drug stock by name and amount string (e.g., "10 เม็ด")
-    pub fn reduce_drug_stock_by_name(drug_name: &str, amount_str: &str) {
-        // Parse amount from string like "10 เม็ด" or "5 ซอง"
-        let amount: u32 = amount_str
-            .chars()
-            .take_while(|c| c.is_ascii_digit())
-            .collect::<String>()
-            .parse()
-            .unwrap_or(0);
-        
-        if amount == 0 { return; }
-        
-        let mut drugs = Self::get_drugs();
-        if let Some(pos) = drugs.iter().position(|d| d.name == drug_name) {
-            // Use saturating_sub to prevent underflow
-            drugs[pos].stock = drugs[pos].stock.saturating_sub(amount);
-            let _ = LocalStorage::set(KEY_DRUGS, drugs);
-        }
-    }
-    
 
+    pub fn record_count() -> usize {
+        Self::read(|d| d.records.iter().filter(|r| !r.is_deleted()).count())
+    }
 
-    #[allow(dead_code)]
-    pub fn next_hn() -> String {
-        let last_hn: u32 = LocalStorage::get(KEY_LAST_HN).unwrap_or(0);
-        let next = last_hn + 1;
-        let _ = LocalStorage::set(KEY_LAST_HN, next);
-        format!("HN-{:05}", next)
+    /// Every (year, month) that has records, newest first
+    pub fn get_record_months() -> Vec<(i32, u32)> {
+        let mut months: Vec<(i32, u32)> = INDEXES.with(|i| {
+            i.borrow().record_days().map(|day| (day.year(), day.month())).collect()
+        });
+        months.dedup();
+        months.reverse();
+        months
     }
 
+    /// Save a new treatment. A prescription the patient is allergic to is only
+    /// saved with an override that names the drug and gives a reason.
+    pub async fn save_record(record: TreatmentRecord) -> Result<(), String> {
+        Self::transaction(|tx| {
+            check_allergies(tx.data, &record)?;
+            // Reduce drug stock for each prescription
+            for rx in &record.prescriptions {
+                reduce_drug_stock(tx.drugs(), &rx.name, &rx.amount);
+            }
+            tx.records().push(record);
+            Ok(())
+        }).await
+    }
+    
     // ========== Drug Inventory ==========
     pub fn get_drugs() -> Vec<DrugItem> {
-        LocalStorage::get(KEY_DRUGS).unwrap_or_else(|_| Vec::new())
+        Self::read(|d| live(&d.drugs))
     }
 
-    pub fn save_drug(drug: DrugItem) {
-        let mut drugs = Self::get_drugs();
-        drugs.push(drug);
-        let _ = LocalStorage::set(KEY_DRUGS, drugs);
+    pub fn get_drug_by_name(name: &str) -> Option<DrugItem> {
+        Self::lookup(|d, i| i.drug_by_name(name).map(|pos| d.drugs[pos].clone()))
+    }
+
+    pub async fn save_drug(drug: DrugItem) -> Result<(), String> {
+        Self::write(|tx| tx.drugs().push(drug)).await
     }
     
-    pub fn update_drug(updated: DrugItem) {
-        let mut drugs = Self::get_drugs();
-        if let Some(pos) = drugs.iter().position(|d| d.id == updated.id) {
-            drugs[pos] = updated;
-            let _ = LocalStorage::set(KEY_DRUGS, drugs);
-        }
+    pub async fn update_drug(updated: DrugItem) -> Result<(), String> {
+        Self::write(|tx| {
+            if let Some(x) = tx.drug(&updated.id) {
+                *x = updated;
+            }
+        }).await
     }
     
-    pub fn delete_drug(drug_id: &str) {
-        let drugs: Vec<DrugItem> = Self::get_drugs()
-            .into_iter()
-            .filter(|d| d.id != drug_id)
-            .collect();
-        let _ = LocalStorage::set(KEY_DRUGS, drugs);
+    pub async fn delete_drug(drug_id: &str) -> Result<(), String> {
+        let now = Utc::now();
+        Self::write(|tx| mark_deleted(tx.drugs(), now, |x| x.id == drug_id)).await
     }
     
     pub fn get_low_stock_drugs() -> Vec<DrugItem> {
-        Self::get_drugs()
-            .into_iter()
-            .filter(|d| d.stock <= d.min_stock)
-            .collect()
+        Self::read(|d| {
+            d.drugs
+                .iter()
+                .filter(|x| !x.is_deleted() && x.stock <= x.min_stock)
+                .cloned()
+                .collect()
+        })
     }
     
     pub fn get_expiring_drugs() -> Vec<DrugItem> {
-        use chrono::{Local, Duration};
         let warning_date = Local::now().naive_local().date() + Duration::days(30);
-        Self::get_drugs()
-            .into_iter()
-            .filter(|d| {
-                if let Some(exp) = d.expiry_date {
-                    exp <= warning_date
-                } else {
-                    false
-                }
-            })
-            .collect()
+        Self::read(|d| {
+            d.drugs
+                .iter()
+                .filter(|x| !x.is_deleted())
+                .filter(|x| {
+                    if let Some(exp) = x.expiry_date {
+                        exp <= warning_date
+                    } else {
+                        false
+                    }
+                })
+                .cloned()
+                .collect()
+        })
     }
 
     // ========== Settings ==========
     pub fn get_settings() -> ClinicSettings {
-        LocalStorage::get(KEY_SETTINGS).unwrap_or_else(|_| ClinicSettings::default())
+        Self::read(|d| d.settings.clone())
     }
     
-    pub fn save_settings(settings: ClinicSettings) {
-        let _ = LocalStorage::set(KEY_SETTINGS, settings);
+    pub async fn save_settings(settings: ClinicSettings) -> Result<(), String> {
+        Self::write(|tx| *tx.settings() = settings).await
     }
 
     // ========== Receipt Number ==========
@@ -168,165 +771,473 @@ impl Store {
     
     // ========== Statistics ==========
     pub fn get_today_revenue() -> f64 {
-        use chrono::Local;
         let today = Local::now().date_naive();
-        Self::get_records()
-            .into_iter()
-            .filter(|r| r.date.with_timezone(&Local).date_naive() == today)
-            .map(|r| r.price)
-            .sum()
+        Self::records_between(today, today).iter().map(|r| r.price).sum()
     }
     
 
     
     pub fn get_today_patient_count() -> usize {
-        use chrono::Local;
         let today = Local::now().date_naive();
-        Self::get_records()
-            .into_iter()
-            .filter(|r| r.date.with_timezone(&Local).date_naive() == today)
-            .count()
+        Self::lookup(|_, i| i.records_between(today, today).count())
     }
     
     // ========== Expenses ==========
     pub fn get_expenses() -> Vec<Expense> {
-        LocalStorage::get(KEY_EXPENSES).unwrap_or_else(|_| Vec::new())
+        Self::read(|d| live(&d.expenses))
     }
     
-    pub fn save_expense(expense: Expense) {
-        let mut expenses = Self::get_expenses();
-        expenses.push(expense);
-        let _ = LocalStorage::set(KEY_EXPENSES, expenses);
+    pub async fn save_expense(expense: Expense) -> Result<(), String> {
+        Self::write(|tx| tx.expenses().push(expense)).await
     }
     
-    pub fn delete_expense(expense_id: &str) {
-        let expenses: Vec<Expense> = Self::get_expenses()
-            .into_iter()
-            .filter(|e| e.id != expense_id)
-            .collect();
-        let _ = LocalStorage::set(KEY_EXPENSES, expenses);
+    pub async fn delete_expense(expense_id: &str) -> Result<(), String> {
+        let now = Utc::now();
+        Self::write(|tx| mark_deleted(tx.expenses(), now, |e| e.id == expense_id)).await
     }
     
     pub fn get_monthly_expenses(year: i32, month: u32) -> Vec<Expense> {
-        use chrono::Datelike;
-        Self::get_expenses()
-            .into_iter()
-            .filter(|e| {
-                let d = e.date.with_timezone(&chrono::Local);
-                d.year() == year && d.month() == month
-            })
-            .collect()
-    }
-    
-    // ========== Drug Purchases ==========
-    pub fn get_drug_purchases() -> Vec<DrugPurchase> {
-        LocalStorage::get(KEY_DRUG_PURCHASES).unwrap_or_else(|_| Vec::new())
-    }
-    
-    pub fn save_drug_purchase(purchase: DrugPurchase) {
-        // Also increase drug stock
-        let mut drugs = Self::get_drugs();
-        if let Some(pos) = drugs.iter().position(|d| d.id == purchase.drug_id) {
-            drugs[pos].stock += purchase.quantity;
-            // Update expiry date if provided
-            if purchase.expiry_date.is_some() {
-                drugs[pos].expiry_date = purchase.expiry_date;
-            }
-            let _ = LocalStorage::set(KEY_DRUGS, drugs);
-        }
-        
-        let mut purchases = Self::get_drug_purchases();
-        purchases.push(purchase);
-        let _ = LocalStorage::set(KEY_DRUG_PURCHASES, purchases);
-    }
-    
-    pub fn get_purchases_by_drug(drug_id: &str) -> Vec<DrugPurchase> {
-        Self::get_drug_purchases()
-            .into_iter()
-            .filter(|p| p.drug_id == drug_id)
-            .collect()
+        Self::read(|d| {
+            d.expenses
+                .iter()
+                .filter(|e| !e.is_deleted())
+                .filter(|e| {
+                    let d = e.date.with_timezone(&chrono::Local);
+                    d.year() == year && d.month() == month
+                })
+                .cloned()
+                .collect()
+        })
     }
     
     // ========== Appointments ==========
     pub fn get_appointments() -> Vec<Appointment> {
-        LocalStorage::get(KEY_APPOINTMENTS).unwrap_or_else(|_| Vec::new())
+        Self::read(|d| live(&d.appointments))
     }
     
-    pub fn save_appointment(appointment: Appointment) {
-        let mut appointments = Self::get_appointments();
-        appointments.push(appointment);
-        let _ = LocalStorage::set(KEY_APPOINTMENTS, appointments);
+    pub async fn save_appointment(appointment: Appointment) -> Result<(), String> {
+        Self::write(|tx| tx.appointments().push(appointment)).await
     }
     
-    pub fn update_appointment(updated: Appointment) {
-        let mut appointments = Self::get_appointments();
-        if let Some(pos) = appointments.iter().position(|a| a.id == updated.id) {
-            appointments[pos] = updated;
-            let _ = LocalStorage::set(KEY_APPOINTMENTS, appointments);
-        }
+    pub async fn update_appointment(updated: Appointment) -> Result<(), String> {
+        Self::write(|tx| {
+            if let Some(a) = tx.appointments().iter_mut().find(|a| a.id == updated.id && !a.is_deleted()) {
+                *a = updated;
+            }
+        }).await
     }
     
-    pub fn delete_appointment(appointment_id: &str) {
-        let appointments: Vec<Appointment> = Self::get_appointments()
-            .into_iter()
-            .filter(|a| a.id != appointment_id)
-            .collect();
-        let _ = LocalStorage::set(KEY_APPOINTMENTS, appointments);
+    pub async fn delete_appointment(appointment_id: &str) -> Result<(), String> {
+        let now = Utc::now();
+        Self::write(|tx| mark_deleted(tx.appointments(), now, |a| a.id == appointment_id)).await
     }
     
     pub fn get_today_appointments() -> Vec<Appointment> {
         let today = chrono::Local::now().date_naive();
-        Self::get_appointments()
-            .into_iter()
-            .filter(|a| a.date == today && a.status == "pending")
-            .collect()
+        Self::read(|d| {
+            d.appointments
+                .iter()
+                .filter(|a| !a.is_deleted() && a.date == today && a.status == "pending")
+                .cloned()
+                .collect()
+        })
     }
     
     pub fn get_appointments_by_date(date: chrono::NaiveDate) -> Vec<Appointment> {
-        Self::get_appointments()
-            .into_iter()
-            .filter(|a| a.date == date)
-            .collect()
+        Self::read(|d| {
+            d.appointments
+                .iter()
+                .filter(|a| !a.is_deleted() && a.date == date)
+                .cloned()
+                .collect()
+        })
     }
     
+    // ========== Drug Interactions ==========
+    pub fn get_interaction_rules() -> Vec<InteractionRule> {
+        Self::read(|d| d.interactions.clone())
+    }
+
+    /// Add a rule, or replace the one with the same id
+    pub async fn save_interaction_rule(rule: InteractionRule) -> Result<(), String> {
+        if let Some(problem) = interactions::problem(&rule) {
+            return Err(problem);
+        }
+        Self::transaction(|tx| {
+            if tx.data.interactions.iter().any(|r| r.id != rule.id && interactions::same_pair(r, &rule)) {
+                return Err(format!("มีกฎของ {} กับ {} อยู่แล้ว", rule.a.trim(), rule.b.trim()));
+            }
+            match tx.interactions().iter_mut().find(|r| r.id == rule.id) {
+                Some(existing) => *existing = rule,
+                None => tx.interactions().push(rule),
+            }
+            Ok(())
+        }).await
+    }
+
+    pub async fn delete_interaction_rule(id: &str) -> Result<(), String> {
+        Self::write(|tx| tx.interactions().retain(|r| r.id != id)).await
+    }
+
+    /// Add imported rules. A rule for a pair that already has one replaces its
+    /// severity and advice. Returns how many were added and how many replaced.
+    pub async fn import_interaction_rules(rules: Vec<InteractionRule>) -> Result<(usize, usize), String> {
+        Self::write(|tx| {
+            let (mut added, mut replaced) = (0, 0);
+            for rule in rules {
+                match tx.interactions().iter_mut().find(|r| interactions::same_pair(r, &rule)) {
+                    Some(existing) if existing.severity == rule.severity && existing.advice == rule.advice => {}
+                    Some(existing) => {
+                        existing.severity = rule.severity;
+                        existing.advice = rule.advice;
+                        replaced += 1;
+                    }
+                    None => {
+                        tx.interactions().push(rule);
+                        added += 1;
+                    }
+                }
+            }
+            (added, replaced)
+        }).await
+    }
+
+    /// Interactions between the drugs on `prescriptions`, and between them and
+    /// what the patient is still taking from earlier records
+    pub fn check_interactions(patient_id: &str, prescriptions: &[PrescriptionItem]) -> Vec<interactions::Interaction> {
+        Self::lookup(|d, i| {
+            if d.interactions.is_empty() {
+                return Vec::new();
+            }
+            let records: Vec<TreatmentRecord> =
+                i.records_of_patient(patient_id).iter().map(|&pos| d.records[pos].clone()).collect();
+            let active = interactions::active(&records, Utc::now());
+            interactions::check(&d.interactions, prescriptions, &active, &d.drugs)
+        })
+    }
+
+    // ========== Recycle Bin ==========
+    /// Everything in the Recycle Bin, most recently deleted first. Records that
+    /// went with their patient are counted on the patient instead of listed.
+    pub fn get_trash() -> Vec<TrashItem> {
+        Self::read(|d| {
+            let went_with = |p: &Patient, r: &TreatmentRecord| r.patient_id == p.id && r.deleted_at == p.deleted_at;
+            let mut trash = Vec::new();
+            for p in d.patients.iter().filter(|p| p.is_deleted()) {
+                let related = d.records.iter().filter(|r| went_with(p, r)).count();
+                trash.extend(trash_item(p).map(|item| TrashItem { related, ..item }));
+            }
+            for r in d.records.iter().filter(|r| r.is_deleted()) {
+                if !d.patients.iter().any(|p| p.is_deleted() && went_with(p, r)) {
+                    trash.extend(trash_item(r));
+                }
+            }
+            trash.extend(d.drugs.iter().filter_map(trash_item));
+            trash.extend(d.expenses.iter().filter_map(trash_item));
+            trash.extend(d.appointments.iter().filter_map(trash_item));
+            trash.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+            trash
+        })
+    }
+
+    /// Take an item out of the Recycle Bin. A patient brings back the records
+    /// deleted with them; a record brings back its patient if needed.
+    pub async fn restore(entity: &str, id: &str) -> Result<(), String> {
+        Self::write(|tx| match entity {
+            Patient::ENTITY => {
+                if let Some(at) = restore_item(tx.patients(), id) {
+                    for r in tx.records().iter_mut().filter(|r| r.patient_id == id && r.deleted_at == Some(at)) {
+                        r.deleted_at = None;
+                    }
+                }
+            }
+            TreatmentRecord::ENTITY => {
+                let patient_id = tx.data().records.iter().find(|r| r.id == id).map(|r| r.patient_id.clone());
+                restore_item(tx.records(), id);
+                if let Some(patient_id) = patient_id {
+                    restore_item(tx.patients(), &patient_id);
+                }
+            }
+            DrugItem::ENTITY => { restore_item(tx.drugs(), id); }
+            Expense::ENTITY => { restore_item(tx.expenses(), id); }
+            Appointment::ENTITY => { restore_item(tx.appointments(), id); }
+            _ => {}
+        }).await
+    }
+
+    /// Delete an item in the Recycle Bin for good. A patient takes all their
+    /// deleted records with them.
+    pub async fn purge(entity: &str, id: &str) -> Result<(), String> {
+        Self::write(|tx| match entity {
+            Patient::ENTITY => {
+                purge_item(tx.patients(), id);
+                if !tx.data().patients.iter().any(|p| p.id == id) {
+                    tx.records().retain(|r| !(r.patient_id == id && r.is_deleted()));
+                }
+            }
+            TreatmentRecord::ENTITY => purge_item(tx.records(), id),
+            DrugItem::ENTITY => purge_item(tx.drugs(), id),
+            Expense::ENTITY => purge_item(tx.expenses(), id),
+            Appointment::ENTITY => purge_item(tx.appointments(), id),
+            _ => {}
+        }).await
+    }
+
+    /// Delete for good whatever has been in the Recycle Bin longer than the
+    /// retention period in the settings. Only collections with such items are saved.
+    pub async fn purge_expired() -> Result<(), String> {
+        let days = Self::read(|d| d.settings.trash_retention_days);
+        if days == 0 {
+            return Ok(());
+        }
+        let cutoff = Utc::now() - Duration::days(days as i64);
+        let (patients, records, drugs, expenses, appointments) = Self::read(|d| (
+            has_expired(&d.patients, cutoff),
+            has_expired(&d.records, cutoff),
+            has_expired(&d.drugs, cutoff),
+            has_expired(&d.expenses, cutoff),
+            has_expired(&d.appointments, cutoff),
+        ));
+        Self::write(|tx| {
+            if patients { purge_expired(tx.patients(), cutoff); }
+            if records { purge_expired(tx.records(), cutoff); }
+            if drugs { purge_expired(tx.drugs(), cutoff); }
+            if expenses { purge_expired(tx.expenses(), cutoff); }
+            if appointments { purge_expired(tx.appointments(), cutoff); }
+        }).await
+    }
+
+    // ========== Integrity ==========
+    pub fn check_integrity() -> integrity::Report {
+        Self::read(integrity::scan)
+    }
+
+    /// Give records to another patient, e.g. ones whose patient is gone
+    pub async fn relink_records(record_ids: &[String], patient_id: &str) -> Result<(), String> {
+        Self::transaction(|tx| {
+            live_patient(tx.data(), patient_id)?;
+            for r in tx.records().iter_mut().filter(|r| record_ids.contains(&r.id)) {
+                r.patient_id = patient_id.to_string();
+            }
+            Ok(())
+        }).await
+    }
+
+    pub async fn relink_appointment(appointment_id: &str, patient: &Patient) -> Result<(), String> {
+        Self::transaction(|tx| {
+            let patient = live_patient(tx.data(), &patient.id)?.clone();
+            if let Some(a) = tx.appointments().iter_mut().find(|a| a.id == appointment_id) {
+                a.patient_id = patient.id;
+                a.patient_name = format!("{}{} {}", patient.title, patient.first_name, patient.last_name);
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Move a single record to the Recycle Bin, leaving its patient alone
+    pub async fn delete_record(record_id: &str) -> Result<(), String> {
+        let now = Utc::now();
+        Self::write(|tx| mark_deleted(tx.records(), now, |r| r.id == record_id)).await
+    }
+
+    /// Fold `other_ids` into the patient `keep_id`: their records and appointments
+    /// move over, details the kept patient lacks are filled in from the others,
+    /// allergies and underlying diseases are combined, and the others go to the
+    /// Recycle Bin.
+    pub async fn merge_patients(keep_id: &str, other_ids: &[String]) -> Result<(), String> {
+        let now = Utc::now();
+        Self::transaction(|tx| {
+            let others: Vec<Patient> =
+                tx.data().patients.iter().filter(|p| p.id != keep_id && other_ids.contains(&p.id)).cloned().collect();
+            if others.is_empty() {
+                return Err("ไม่พบผู้ป่วยที่จะรวม".to_string());
+            }
+            let keep = tx
+                .patients()
+                .iter_mut()
+                .find(|p| p.id == keep_id && !p.is_deleted())
+                .ok_or_else(|| "ไม่พบผู้ป่วยที่จะเก็บไว้".to_string())?;
+            for other in &others {
+                merge_patient_details(keep, other);
+            }
+            let keep = keep.clone();
+
+            for r in tx.records().iter_mut().filter(|r| other_ids.contains(&r.patient_id)) {
+                r.patient_id = keep.id.clone();
+            }
+            let name = format!("{}{} {}", keep.title, keep.first_name, keep.last_name);
+            for a in tx.appointments().iter_mut().filter(|a| other_ids.contains(&a.patient_id)) {
+                a.patient_id = keep.id.clone();
+                a.patient_name = name.clone();
+            }
+            mark_deleted(tx.patients(), now, |p| p.id != keep.id && other_ids.contains(&p.id));
+            let actor = tx.data.settings.staff_name.clone();
+            tx.log(audit::merged(&keep, &others, &actor));
+            Ok(())
+        }).await
+    }
+
+    /// Registered patients who may be `patient`, see duplicates.rs
+    pub fn possible_duplicates(patient: &Patient) -> Vec<duplicates::Match> {
+        Self::read(|d| duplicates::matches(patient, &d.patients))
+    }
+
+    /// Every pair of registered patients who may be the same person
+    pub fn duplicate_pairs() -> Vec<duplicates::Pair> {
+        Self::read(|d| duplicates::pairs(&d.patients))
+    }
+
+    /// Change a drug name in the prescriptions of every record, e.g. after the
+    /// drug was renamed in the inventory
+    pub async fn rename_prescribed_drug(from: &str, to: &str) -> Result<(), String> {
+        Self::write(|tx| {
+            for rx in tx.records().iter_mut().flat_map(|r| r.prescriptions.iter_mut()).filter(|rx| rx.name == from) {
+                rx.name = to.to_string();
+            }
+        }).await
+    }
+
     // ========== Records by Date Range ==========
-    pub fn get_records_by_date_range(start: chrono::NaiveDate, end: chrono::NaiveDate) -> Vec<TreatmentRecord> {
-        use chrono::Local;
-        Self::get_records()
-            .into_iter()
-            .filter(|r| {
-                let d = r.date.with_timezone(&Local).date_naive();
-                d >= start && d <= end
-            })
-            .collect()
+    pub fn get_records_by_date_range(start: NaiveDate, end: NaiveDate) -> Vec<TreatmentRecord> {
+        Self::records_between(start, end)
     }
     
     pub fn get_monthly_revenue(year: i32, month: u32) -> f64 {
-        use chrono::Datelike;
-        Self::get_records()
-            .into_iter()
-            .filter(|r| {
-                let d = r.date.with_timezone(&chrono::Local);
-                d.year() == year && d.month() == month
-            })
-            .map(|r| r.price)
-            .sum()
+        let Some((start, end)) = month_bounds(year, month) else { return 0.0 };
+        Self::records_between(start, end).iter().map(|r| r.price).sum()
     }
     
+    #[allow(dead_code)]
     pub fn get_monthly_patient_count(year: i32, month: u32) -> usize {
-        use chrono::Datelike;
-        Self::get_records()
-            .into_iter()
-            .filter(|r| {
-                let d = r.date.with_timezone(&chrono::Local);
-                d.year() == year && d.month() == month
-            })
-            .count()
+        let Some((start, end)) = month_bounds(year, month) else { return 0 };
+        Self::lookup(|_, i| i.records_between(start, end).count())
+    }
+}
+
+/// First and last day of a month
+fn month_bounds(year: i32, month: u32) -> Option<(NaiveDate, NaiveDate)> {
+    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)?
+    };
+    Some((start, next.pred_opt()?))
+}
+
+/// Fill in what `keep` lacks from `other`; text that both have and that could
+/// matter for treatment is combined rather than dropped
+fn merge_patient_details(keep: &mut Patient, other: &Patient) {
+    let fill = |field: &mut String, from: &str| {
+        if field.trim().is_empty() {
+            *field = from.to_string();
+        }
+    };
+    fill(&mut keep.citizen_id, &other.citizen_id);
+    fill(&mut keep.blood_group, &other.blood_group);
+    fill(&mut keep.phone, &other.phone);
+    fill(&mut keep.address, &other.address);
+    keep.birth_date = keep.birth_date.or(other.birth_date);
+    keep.age = keep.age.or(other.age);
+    for allergy in &other.allergies {
+        if !keep.allergies.contains(allergy) {
+            keep.allergies.push(allergy.clone());
+        }
+    }
+
+    let combine = |field: &mut String, from: &str| {
+        let from = from.trim();
+        if !from.is_empty() && !field.contains(from) {
+            *field = if field.trim().is_empty() { from.to_string() } else { format!("{}, {}", field.trim(), from) };
+        }
+    };
+    combine(&mut keep.underlying_disease, &other.underlying_disease);
+}
+
+/// Parse amount from string like "10 เม็ด" or "5 ซอง" and take it off the matching drug
+pub fn reduce_drug_stock(drugs: &mut [DrugItem], drug_name: &str, amount_str: &str) {
+    let amount: u32 = amount_str
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0);
+    
+    if amount == 0 { return; }
+    
+    if let Some(drug) = drugs.iter_mut().find(|d| d.name == drug_name && !d.is_deleted()) {
+        // Use saturating_sub to prevent underflow
+        drug.stock = drug.stock.saturating_sub(amount);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone, Utc};
+    use crate::models::{AllergyOverride, InteractionSeverity};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use crate::storage::{MemoryBackend, Saving};
+
+    fn setup() -> MemoryBackend {
+        let backend = MemoryBackend::default();
+        Store::init_with(Box::new(backend.clone()), ClinicData::default());
+        backend
+    }
+
+    fn poll<T>(write: Pin<&mut impl Future<Output = T>>) -> Poll<T> {
+        write.poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    /// Run a write to the end; the test backends save without waiting
+    fn done<T>(write: impl Future<Output = T>) -> T {
+        match poll(std::pin::pin!(write)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => panic!("the write is still waiting"),
+        }
+    }
+
+    fn patient(id: &str) -> Patient {
+        Patient {
+            id: id.to_string(),
+            hn: format!("HN-{}", id),
+            citizen_id: String::new(),
+            title: "นาย".to_string(),
+            first_name: "สมชาย".to_string(),
+            last_name: "ใจดี".to_string(),
+            birth_date: None,
+            age: None,
+            blood_group: String::new(),
+            underlying_disease: String::new(),
+            allergies: vec![],
+            phone: String::new(),
+            address: String::new(),
+            created_at: Utc::now(),
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn record(id: &str, patient_id: &str, y: i32, m: u32, d: u32, price: f64) -> TreatmentRecord {
+        TreatmentRecord {
+            id: id.to_string(),
+            patient_id: patient_id.to_string(),
+            date: Local.with_ymd_and_hms(y, m, d, 10, 0, 0).unwrap().with_timezone(&Utc),
+            symptoms: String::new(),
+            diagnosis: String::new(),
+            weight: None,
+            pressure: String::new(),
+            prescriptions: Vec::new(),
+            injections: Vec::new(),
+            doctor_note: String::new(),
+            price,
+            allergy_override: None,
+            deleted_at: None,
+            updated_at: None,
+        }
+    }
+
     #[test]
     fn test_parse_drug_amount() {
         // Helper logic check
@@ -340,8 +1251,575 @@ mod tests {
     }
 
     #[test]
-    fn test_hn_format() {
-        let next = 16;
-        assert_eq!(format!("HN-{:05}", next), "HN-00016");
+    fn test_hns_are_generated_and_unique() {
+        setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        assert!(done(Store::save_patient(Patient { id: "p2".to_string(), ..patient("p1") })).is_err());
+
+        let hn = done(Store::register_patient(patient("p2"))).unwrap();
+        assert_eq!(hn, "HN-00001");
+        assert_eq!(Store::next_hn(), "HN-00002");
+        assert_eq!(Store::get_patient("p2").unwrap().hn, "HN-00001");
+
+        // Purged numbers are not handed out again
+        done(Store::delete_patient("p2")).unwrap();
+        done(Store::purge("patient", "p2")).unwrap();
+        assert_eq!(Store::next_hn(), "HN-00002");
+
+        let taken = Patient { hn: "HN-p1".to_string(), ..patient("p3") };
+        done(Store::register_patient(patient("p3"))).unwrap();
+        assert!(done(Store::update_patient(taken)).is_err());
+        assert_eq!(done(Store::renumber_hns()).unwrap(), 1);
+        assert!(Store::check_hns().is_clean());
+        assert_eq!(Store::next_hn(), "HN-00003");
+    }
+
+    #[test]
+    fn test_save_record_deducts_stock() {
+        let backend = setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "Paracetamol".to_string(), stock: 100, ..Default::default() })).unwrap();
+        done(Store::save_drug(DrugItem { id: "d2".to_string(), name: "Amoxicillin".to_string(), stock: 5, ..Default::default() })).unwrap();
+
+        let mut r = record("r1", "p1", 2024, 3, 15, 150.0);
+        r.prescriptions = vec![
+            PrescriptionItem { name: "Paracetamol".to_string(), amount: "20 เม็ด".to_string(), ..Default::default() },
+            PrescriptionItem { name: "Amoxicillin".to_string(), amount: "10 เม็ด".to_string(), ..Default::default() },
+            PrescriptionItem { name: "Unknown".to_string(), amount: "3".to_string(), ..Default::default() },
+        ];
+        done(Store::save_record(r)).unwrap();
+
+        let drugs = Store::get_drugs();
+        assert_eq!(drugs[0].stock, 80);
+        assert_eq!(drugs[1].stock, 0); // saturates instead of underflowing
+        assert_eq!(Store::get_records().len(), 1);
+
+        // The backend got both the stock change and the record
+        let saved = backend.saved();
+        assert_eq!(saved.drugs[0].stock, 80);
+        assert_eq!(saved.records.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_patient_removes_records() {
+        let backend = setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_patient(patient("p2"))).unwrap();
+        done(Store::save_record(record("r1", "p1", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::save_record(record("r2", "p2", 2024, 3, 2, 100.0))).unwrap();
+        done(Store::save_record(record("r3", "p1", 2024, 3, 3, 100.0))).unwrap();
+
+        done(Store::delete_patient("p1")).unwrap();
+
+        let patients = Store::get_patients();
+        assert_eq!(patients.len(), 1);
+        assert_eq!(patients[0].id, "p2");
+        assert!(Store::get_records_by_patient("p1").is_empty());
+        assert!(Store::get_patient("p1").is_none());
+        assert_eq!(Store::get_records().len(), 1);
+        assert_eq!(Store::record_count(), 1);
+
+        // Still saved, marked for the Recycle Bin
+        let saved = backend.saved();
+        assert_eq!(saved.records.len(), 3);
+        assert_eq!(saved.records.iter().filter(|r| r.deleted_at.is_some()).count(), 2);
+    }
+
+    #[test]
+    fn test_recycle_bin_restore_and_purge() {
+        let backend = setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_record(record("r1", "p1", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::save_record(record("r2", "p1", 2024, 3, 2, 100.0))).unwrap();
+        done(Store::save_appointment(Appointment { id: "a1".to_string(), ..Default::default() })).unwrap();
+        done(Store::delete_appointment("a1")).unwrap();
+        done(Store::delete_patient("p1")).unwrap();
+
+        // The records went with the patient, so only the patient is listed
+        let trash = Store::get_trash();
+        assert_eq!(trash.len(), 2);
+        assert_eq!((trash[0].entity, trash[0].id.as_str(), trash[0].related), ("patient", "p1", 2));
+        assert_eq!((trash[1].entity, trash[1].id.as_str()), ("appointment", "a1"));
+
+        done(Store::restore("patient", "p1")).unwrap();
+        assert_eq!(Store::get_records_by_patient("p1").len(), 2);
+        assert_eq!(Store::get_trash().len(), 1);
+
+        done(Store::purge("appointment", "a1")).unwrap();
+        assert!(Store::get_trash().is_empty());
+        assert!(backend.saved().appointments.is_empty());
+
+        let actions: Vec<String> = Store::get_audit_log().into_iter().map(|e| e.action).skip(4).collect();
+        assert_eq!(actions, ["delete", "delete", "delete", "delete", "restore", "restore", "restore", "purge"]);
+    }
+
+    #[test]
+    fn test_merge_patients_moves_everything_to_the_kept_one() {
+        let backend = setup();
+        done(Store::save_patient(patient("keep"))).unwrap();
+        done(Store::save_patient(Patient {
+            phone: "0812345678".to_string(),
+            allergies: allergy::from_text("Penicillin"),
+            ..patient("dup")
+        })).unwrap();
+        done(Store::save_record(record("r1", "dup", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::save_appointment(Appointment { id: "a1".to_string(), patient_id: "dup".to_string(), ..Default::default() })).unwrap();
+
+        done(Store::merge_patients("keep", &["dup".to_string()])).unwrap();
+
+        let kept = Store::get_patient("keep").unwrap();
+        assert_eq!((kept.phone.as_str(), kept.allergies.clone()), ("0812345678", allergy::from_text("Penicillin")));
+        assert_eq!(Store::get_records_by_patient("keep").len(), 1);
+        assert_eq!(Store::get_appointments()[0].patient_id, "keep");
+        assert!(Store::get_patient("dup").is_none());
+        assert!(backend.saved().patients.iter().any(|p| p.id == "dup" && p.deleted_at.is_some()));
+        assert!(Store::check_integrity().is_clean());
+
+        // One entry names the merge, next to the updates it made
+        let log = Store::get_audit_log();
+        let merge = log.iter().find(|e| e.action == audit::ACTION_MERGE).unwrap();
+        assert_eq!(merge.entity_id, "keep");
+        assert_eq!(merge.before.as_ref().unwrap()[0]["id"], "dup");
+        assert!(done(Store::merge_patients("keep", &["keep".to_string()])).is_err());
+    }
+
+    #[test]
+    fn test_relink_needs_a_live_patient() {
+        setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_patient(patient("gone"))).unwrap();
+        done(Store::delete_patient("gone")).unwrap();
+        done(Store::save_record(record("r1", "lost", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::save_appointment(Appointment { id: "a1".to_string(), patient_id: "lost".to_string(), ..Default::default() })).unwrap();
+
+        for target in ["gone", "nobody"] {
+            assert!(done(Store::relink_records(&["r1".to_string()], target)).is_err());
+            assert!(done(Store::relink_appointment("a1", &patient(target))).is_err());
+        }
+        assert_eq!(Store::get_record("r1").unwrap().patient_id, "lost");
+        assert_eq!(Store::get_appointments()[0].patient_id, "lost");
+
+        done(Store::relink_records(&["r1".to_string()], "p1")).unwrap();
+        done(Store::relink_appointment("a1", &patient("p1"))).unwrap();
+        assert_eq!(Store::get_record("r1").unwrap().patient_id, "p1");
+        assert_eq!(Store::get_appointments()[0].patient_name, "นายสมชาย ใจดี");
+    }
+
+    #[test]
+    fn test_allergic_prescription_needs_an_override() {
+        let backend = setup();
+        done(Store::save_patient(Patient { allergies: allergy::from_text("Penicillin"), ..patient("p1") })).unwrap();
+        let amoxy = || TreatmentRecord {
+            prescriptions: vec![PrescriptionItem { name: "Amoxicillin 500mg".to_string(), ..PrescriptionItem::default() }],
+            ..record("r1", "p1", 2024, 3, 1, 100.0)
+        };
+
+        assert!(done(Store::save_record(amoxy())).is_err());
+        let blank = AllergyOverride { drugs: vec!["Amoxicillin 500mg".to_string()], reason: " ".to_string() };
+        assert!(done(Store::save_record(TreatmentRecord { allergy_override: Some(blank), ..amoxy() })).is_err());
+        assert!(backend.saved().records.is_empty());
+
+        let reason = AllergyOverride { drugs: vec!["Amoxicillin 500mg".to_string()], reason: "แพทย์ยืนยันแล้ว".to_string() };
+        done(Store::save_record(TreatmentRecord { allergy_override: Some(reason.clone()), ..amoxy() })).unwrap();
+        assert_eq!(backend.saved().records[0].allergy_override, Some(reason));
+    }
+
+    #[test]
+    fn test_interaction_rules() {
+        let backend = setup();
+        let rules = interactions::from_csv("warfarin,nsaids,รุนแรง,เลือดออกง่าย\nsimvastatin,clarithromycin,,").unwrap();
+        assert_eq!(done(Store::import_interaction_rules(rules)).unwrap(), (2, 0));
+        // The same pair the other way round replaces it
+        let again = interactions::from_csv("NSAIDs,Warfarin,ห้ามใช้ร่วมกัน,หลีกเลี่ยง").unwrap();
+        assert_eq!(done(Store::import_interaction_rules(again)).unwrap(), (0, 1));
+        let saved = backend.saved().interactions;
+        assert_eq!((saved.len(), saved[0].severity), (2, InteractionSeverity::Contraindicated));
+        let duplicate = InteractionRule { id: "new".to_string(), ..saved[1].clone() };
+        assert!(done(Store::save_interaction_rule(duplicate)).is_err());
+
+        // Against what is still being taken from a record two days ago
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_record(TreatmentRecord {
+            date: Utc::now() - Duration::days(2),
+            prescriptions: vec![PrescriptionItem { name: "Warfarin 3mg".to_string(), ..PrescriptionItem::default() }],
+            ..record("r1", "p1", 2024, 3, 1, 100.0)
+        })).unwrap();
+        let brufen = [PrescriptionItem { name: "Ibuprofen 400".to_string(), ..PrescriptionItem::default() }];
+        let found = Store::check_interactions("p1", &brufen);
+        assert_eq!((found.len(), found[0].other.name.as_str()), (1, "Warfarin 3mg"));
+
+        // Removed for good, so sync removes it on the other computer too
+        done(Store::delete_interaction_rule(&saved[0].id)).unwrap();
+        assert!(Store::check_interactions("p1", &brufen).is_empty());
+        assert_eq!(backend.saved().tombstones[0].entity, "interaction");
+    }
+
+    #[test]
+    fn test_purge_expired_follows_retention() {
+        let backend = setup();
+        done(Store::save_drug(DrugItem { id: "old".to_string(), name: "A".to_string(), ..Default::default() })).unwrap();
+        done(Store::save_drug(DrugItem { id: "new".to_string(), name: "B".to_string(), ..Default::default() })).unwrap();
+        done(Store::delete_drug("old")).unwrap();
+        done(Store::delete_drug("new")).unwrap();
+        done(Store::transaction(|tx| {
+            tx.drugs()[0].deleted_at = Some(Utc::now() - Duration::days(31));
+            Ok(())
+        })).unwrap();
+
+        done(Store::purge_expired()).unwrap();
+        let ids: Vec<String> = backend.saved().drugs.into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, ["new"]);
+
+        // 0 keeps everything
+        done(Store::save_settings(ClinicSettings { trash_retention_days: 0, ..Default::default() })).unwrap();
+        done(Store::transaction(|tx| {
+            tx.drugs()[0].deleted_at = Some(Utc::now() - Duration::days(365));
+            Ok(())
+        })).unwrap();
+        done(Store::purge_expired()).unwrap();
+        assert_eq!(backend.saved().drugs.len(), 1);
+    }
+
+    #[test]
+    fn test_writes_are_audited() {
+        let backend = setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_record(record("r1", "p1", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::update_patient(Patient { phone: "0812345678".to_string(), ..patient("p1") })).unwrap();
+        done(Store::delete_patient("p1")).unwrap();
+
+        let log: Vec<(String, String, String)> = Store::get_audit_log()
+            .into_iter()
+            .map(|e| (e.action, e.entity, e.entity_id))
+            .collect();
+        let entry = |action: &str, entity: &str, id: &str| (action.to_string(), entity.to_string(), id.to_string());
+        assert_eq!(log, [
+            entry("create", "patient", "p1"),
+            entry("create", "record", "r1"),
+            entry("update", "patient", "p1"),
+            entry("delete", "patient", "p1"),
+            entry("delete", "record", "r1"),
+        ]);
+
+        // Moving the record to the Recycle Bin logs just that, saved with the deletion
+        let saved = backend.saved().audit_log;
+        assert_eq!(saved.len(), 5);
+        assert_eq!(saved[4].before, Some(serde_json::json!({ "deleted_at": null })));
+        assert!(saved[4].after.as_ref().unwrap()["deleted_at"].is_string());
+        assert_eq!(saved[4].actor, ClinicSettings::default().staff_name);
+    }
+
+    #[test]
+    fn test_monthly_queries() {
+        setup();
+        done(Store::save_record(record("r1", "p1", 2024, 3, 1, 100.0))).unwrap();
+        done(Store::save_record(record("r2", "p1", 2024, 3, 31, 250.0))).unwrap();
+        done(Store::save_record(record("r3", "p1", 2024, 4, 1, 999.0))).unwrap();
+        done(Store::save_record(record("r4", "p1", 2023, 3, 15, 50.0))).unwrap();
+
+        assert_eq!(Store::get_monthly_revenue(2024, 3), 350.0);
+        assert_eq!(Store::get_monthly_patient_count(2024, 3), 2);
+        assert_eq!(Store::get_monthly_revenue(2024, 5), 0.0);
+
+        let start = chrono::NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        let end = chrono::NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+        let ids: Vec<String> = Store::get_records_by_date_range(start, end).into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["r2", "r3"]);
+    }
+
+    struct FailingBackend;
+
+    impl StorageBackend for FailingBackend {
+        fn save(&self, _data: &ClinicData, _keys: &[&str], _logged: &[AuditEntry]) -> Saving {
+            Box::pin(std::future::ready(Err("quota exceeded".to_string())))
+        }
+    }
+
+    /// Saves that finish when the test says so, like the Tauri file
+    #[derive(Clone, Default)]
+    struct SlowBackend {
+        started: Rc<Cell<usize>>,
+        outcome: Rc<RefCell<Option<Result<(), String>>>>,
+    }
+
+    impl SlowBackend {
+        fn finish(&self, outcome: Result<(), String>) {
+            *self.outcome.borrow_mut() = Some(outcome);
+        }
+    }
+
+    impl StorageBackend for SlowBackend {
+        fn save(&self, _data: &ClinicData, _keys: &[&str], _logged: &[AuditEntry]) -> Saving {
+            self.started.set(self.started.get() + 1);
+            let outcome = self.outcome.clone();
+            Box::pin(std::future::poll_fn(move |_| match outcome.borrow_mut().take() {
+                Some(outcome) => Poll::Ready(outcome),
+                None => Poll::Pending,
+            }))
+        }
+    }
+
+    #[test]
+    fn test_transaction_commits_all_collections() {
+        let backend = setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() })).unwrap();
+
+        let count = done(Store::transaction(|tx| {
+            tx.drugs()[0].stock -= 4;
+            tx.records().push(record("r1", "p1", 2024, 3, 1, 100.0));
+            Ok(tx.data().records.len())
+        }));
+
+        assert_eq!(count, Ok(1));
+        assert_eq!(Store::get_drugs()[0].stock, 6);
+        assert_eq!(backend.saved().records.len(), 1);
+    }
+
+    #[test]
+    fn test_transaction_error_changes_nothing() {
+        let backend = setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() })).unwrap();
+
+        let result: Result<(), String> = done(Store::transaction(|tx| {
+            tx.drugs()[0].stock = 0;
+            Err("ยาไม่พอ".to_string())
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(Store::get_drugs()[0].stock, 10);
+        assert_eq!(backend.saved().drugs[0].stock, 10);
+    }
+
+    #[test]
+    fn test_failed_save_keeps_memory_consistent() {
+        setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() })).unwrap();
+        let data = Store::read(ClinicData::clone);
+        let logged = data.audit_log.len();
+        Store::init_with(Box::new(FailingBackend), data);
+
+        let result = done(Store::transaction(|tx| {
+            reduce_drug_stock(tx.drugs(), "ORS", "2 ซอง");
+            tx.records().push(record("r1", "p1", 2024, 3, 1, 100.0));
+            Ok(())
+        }));
+
+        // Neither the stock deduction nor the record went through
+        assert_eq!(result, Err("quota exceeded".to_string()));
+        assert_eq!(Store::get_drugs()[0].stock, 10);
+        assert!(Store::get_records().is_empty());
+        assert_eq!(Store::get_audit_log().len(), logged);
+        assert!(Store::snapshot().tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_changes_show_once_saved() {
+        setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() })).unwrap();
+        let logged = Store::get_audit_log().len();
+        let backend = SlowBackend::default();
+        Store::init_with(Box::new(backend.clone()), Store::snapshot());
+
+        let mut write = std::pin::pin!(Store::transaction(|tx| {
+            reduce_drug_stock(tx.drugs(), "ORS", "2 ซอง");
+            Ok(())
+        }));
+        assert!(poll(write.as_mut()).is_pending());
+        assert_eq!(Store::get_drugs()[0].stock, 10);
+
+        backend.finish(Err("disk full".to_string()));
+        assert_eq!(poll(write.as_mut()), Poll::Ready(Err("disk full".to_string())));
+        assert_eq!(Store::get_drugs()[0].stock, 10);
+        assert_eq!(Store::get_audit_log().len(), logged);
+
+        let mut write = std::pin::pin!(Store::transaction(|tx| {
+            reduce_drug_stock(tx.drugs(), "ORS", "2 ซอง");
+            Ok(())
+        }));
+        assert!(poll(write.as_mut()).is_pending());
+        backend.finish(Ok(()));
+        assert_eq!(poll(write.as_mut()), Poll::Ready(Ok(())));
+        assert_eq!(Store::get_drugs()[0].stock, 8);
+        assert_eq!(Store::get_audit_log().len(), logged + 1);
+    }
+
+    #[test]
+    fn test_transactions_wait_for_the_save_before() {
+        let backend = SlowBackend::default();
+        Store::init_with(Box::new(backend.clone()), ClinicData::default());
+
+        let mut first = std::pin::pin!(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() }));
+        let mut second = std::pin::pin!(Store::transaction(|tx| {
+            let drug = tx.drug("d1").ok_or("ไม่พบยา")?;
+            drug.stock -= 4;
+            Ok(())
+        }));
+        assert!(poll(first.as_mut()).is_pending());
+        assert!(poll(second.as_mut()).is_pending());
+        assert_eq!(backend.started.get(), 1);
+
+        // The second starts from what the first saved
+        backend.finish(Ok(()));
+        assert_eq!(poll(first.as_mut()), Poll::Ready(Ok(())));
+        assert!(poll(second.as_mut()).is_pending());
+        assert_eq!(backend.started.get(), 2);
+        backend.finish(Ok(()));
+        assert_eq!(poll(second.as_mut()), Poll::Ready(Ok(())));
+        assert_eq!(Store::get_drugs()[0].stock, 6);
+    }
+
+    #[test]
+    fn test_failed_load_refuses_every_save() {
+        setup();
+        Store::init_read_only("ไฟล์ข้อมูลเสียหาย".to_string());
+        assert_eq!(Store::take_load_notice().as_deref(), Some("ไฟล์ข้อมูลเสียหาย"));
+
+        for _ in 0..2 {
+            let err = done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), ..Default::default() })).unwrap_err();
+            assert!(err.contains("ไฟล์ข้อมูลเสียหาย"));
+        }
+        assert!(Store::get_drugs().is_empty());
+    }
+
+    #[test]
+    fn test_indexes_follow_writes() {
+        setup();
+        done(Store::save_patient(patient("p1"))).unwrap();
+        done(Store::save_record(record("r1", "p1", 2024, 3, 1, 100.0))).unwrap();
+        assert_eq!(Store::get_patient_by_hn("HN-p1").unwrap().id, "p1");
+        assert_eq!(Store::get_record("r1").unwrap().price, 100.0);
+
+        let mut renamed = patient("p1");
+        renamed.hn = "HN-00009".to_string();
+        done(Store::update_patient(renamed)).unwrap();
+        assert!(Store::get_patient_by_hn("HN-p1").is_none());
+        assert_eq!(Store::get_patient_by_hn("HN-00009").unwrap().id, "p1");
+
+        done(Store::save_record(record("r2", "p1", 2024, 5, 2, 50.0))).unwrap();
+        assert_eq!(Store::get_record_months(), vec![(2024, 5), (2024, 3)]);
+
+        done(Store::delete_patient("p1")).unwrap();
+        assert!(Store::get_patient("p1").is_none());
+        assert!(Store::get_record("r1").is_none());
+        assert!(Store::get_records_by_patient("p1").is_empty());
+        assert_eq!(Store::get_monthly_revenue(2024, 3), 0.0);
+
+        done(Store::restore(Patient::ENTITY, "p1")).unwrap();
+        assert_eq!(Store::get_records_by_patient("p1").len(), 2);
+        assert_eq!(Store::get_patient_by_hn("HN-00009").unwrap().id, "p1");
+
+        // Taking an item out for good moves everything after it
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), ..Default::default() })).unwrap();
+        done(Store::save_drug(DrugItem { id: "d2".to_string(), name: "Paracetamol".to_string(), stock: 1, ..Default::default() })).unwrap();
+        done(Store::delete_drug("d1")).unwrap();
+        done(Store::purge(DrugItem::ENTITY, "d1")).unwrap();
+        assert!(Store::get_drug_by_name("ORS").is_none());
+        done(Store::update_drug(DrugItem { id: "d2".to_string(), name: "Paracetamol".to_string(), stock: 20, ..Default::default() })).unwrap();
+        assert_eq!(Store::get_drug_by_name("Paracetamol").unwrap().stock, 20);
+    }
+
+    #[test]
+    fn test_archiving_logs_one_entry_per_year() {
+        let backend = setup();
+        done(Store::transaction(|tx| {
+            tx.records().extend([record("r1", "p1", 2022, 3, 1, 100.0), record("r2", "p1", 2024, 3, 1, 100.0)]);
+            Ok(())
+        })).unwrap();
+        let logged = Store::get_audit_log().len();
+
+        let archives = crate::archive::split(&Store::snapshot(), 2023);
+        done(Store::remove_archived(&archives)).unwrap();
+
+        let ids: Vec<String> = backend.saved().records.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, ["r2"]);
+        let log = Store::get_audit_log();
+        assert_eq!(log.len(), logged + 1);
+        assert_eq!((log[logged].action.as_str(), log[logged].entity_id.as_str()), (audit::ACTION_ARCHIVE, "2022"));
+    }
+
+    #[test]
+    fn test_archived_items_stay_archived_after_sync() {
+        setup();
+        done(Store::transaction(|tx| {
+            tx.records().extend([record("r1", "p1", 2022, 3, 1, 100.0), record("r2", "p1", 2024, 3, 1, 100.0)]);
+            Ok(())
+        })).unwrap();
+        // The other computer has synced both records before they were archived here
+        let other = Store::snapshot();
+
+        done(Store::remove_archived(&crate::archive::split(&Store::snapshot(), 2023))).unwrap();
+        let here = Store::snapshot();
+        assert_eq!(here.tombstones[0].archived, Some(2022));
+
+        // There r1 goes into its own 2022 archive and leaves the active data
+        let to_file = sync::to_archive(&other, &here);
+        assert_eq!(to_file.len(), 1);
+        assert_eq!((to_file[0].year, to_file[0].records[0].id.as_str()), (2022, "r1"));
+        let (there, _) = sync::apply(&other, here);
+        assert_eq!(there.records.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), ["r2"]);
+
+        // And its old copy doesn't bring r1 back here
+        done(Store::apply_sync(other)).unwrap();
+        let ids: Vec<String> = Store::get_records().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, ["r2"]);
+        assert!(Store::get_archived_by_peer(&there).is_empty());
+    }
+
+    #[test]
+    fn test_sync_reports_hn_clashes() {
+        setup();
+        let hn = done(Store::register_patient(patient("p1"))).unwrap();
+        // The other computer registered someone else under the same number before syncing
+        let mut theirs = patient("p2");
+        theirs.hn = hn.clone();
+        theirs.updated_at = Some(Utc::now());
+        let incoming = ClinicData { patients: vec![theirs], ..ClinicData::default() };
+
+        let applied = done(Store::apply_sync(incoming.clone())).unwrap();
+        assert_eq!(applied.changed, 1);
+        assert_eq!(applied.hn_clashes, vec![hn.clone()]);
+        assert!(applied.clash_message().unwrap().contains(&hn));
+        assert_eq!(Store::check_integrity().duplicate_hns.len(), 1);
+        // Already known, so not reported again
+        assert!(done(Store::apply_sync(incoming)).unwrap().hn_clashes.is_empty());
+    }
+
+    #[test]
+    fn test_log_overflow_leaves_the_newest() {
+        let backend = setup();
+        for n in 1..=3 {
+            done(Store::save_drug(DrugItem { id: format!("d{}", n), name: "ORS".to_string(), ..Default::default() })).unwrap();
+        }
+        let overflow = Store::get_log_overflow(2);
+        assert_eq!(overflow.len(), 1);
+        assert_eq!(overflow[0].entity_id, "d1");
+        assert!(Store::get_log_overflow(5).is_empty());
+
+        done(Store::remove_logged(&overflow.iter().map(|e| e.id.clone()).collect())).unwrap();
+        let left: Vec<String> = backend.saved().audit_log.into_iter().map(|e| e.entity_id).collect();
+        assert_eq!(left, ["d2", "d3"]);
+    }
+
+    #[test]
+    fn test_saves_are_stamped_and_sync_is_not_logged() {
+        let backend = setup();
+        done(Store::save_drug(DrugItem { id: "d1".to_string(), name: "ORS".to_string(), stock: 10, ..Default::default() })).unwrap();
+        done(Store::delete_drug("d1")).unwrap();
+        done(Store::purge("drug", "d1")).unwrap();
+        let saved = backend.saved();
+        assert_eq!(saved.tombstones.len(), 1);
+        assert_eq!(saved.tombstones[0].id, "d1");
+        assert!(saved.tombstones[0].deleted_at >= saved.audit_log[0].timestamp);
+
+        // Changes from the other computer keep their stamps and aren't logged again here
+        let logged = Store::get_audit_log().len();
+        let stamped = "2024-03-01T09:00:00Z".parse().ok();
+        let incoming = ClinicData {
+            drugs: vec![DrugItem { id: "d2".to_string(), name: "Para".to_string(), updated_at: stamped, ..Default::default() }],
+            ..ClinicData::default()
+        };
+        assert_eq!(done(Store::apply_sync(incoming.clone())).unwrap().changed, 1);
+        assert_eq!(done(Store::apply_sync(incoming)).unwrap().changed, 0);
+        assert_eq!(backend.saved().drugs[0].updated_at, stamped);
+        assert_eq!(Store::get_audit_log().len(), logged);
     }
 }
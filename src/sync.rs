@@ -0,0 +1,322 @@
+// Sync between two clinic computers on the same network
+// The desktop app (src-tauri/src/sync.rs) sends the other computer what changed
+// since they last synced and leaves what it gets back in the sync inbox, which
+// `receive` applies. Every item carries `updated_at`, stamped by the Store on each
+// save, and a copy only replaces ours when it is newer; on a tie the larger JSON
+// wins, so both computers settle on the same copy. Items removed for good leave a
+// tombstone so the other side removes them too instead of sending them back.
+// Archiving leaves one marked with the year, and the other side moves its own
+// copies into that year's archive before they leave its active data.
+// Each computer issues HNs from its own counter, so two patients registered on
+// both sides between syncs can get the same HN; `apply` can't tell which is
+// right, so the clash is reported and left to the HN check in Settings.
+// Settings stay per computer.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use crate::archive::{self, Archive};
+use crate::audit::Audited;
+use crate::backup;
+use crate::models::{AuditEntry, SoftDelete, Tombstone, Versioned};
+use crate::storage::{
+    ClinicData, KEY_PATIENTS, KEY_RECORDS, KEY_DRUGS, KEY_EXPENSES, KEY_DRUG_PURCHASES, KEY_APPOINTMENTS,
+    KEY_INTERACTIONS,
+};
+use crate::store::Store;
+use crate::tauri_bridge;
+
+/// Stamp every item in the touched collections that is new or differs from
+/// `before` with `at`. Items that didn't change keep their stamp.
+pub fn stamp(before: &ClinicData, after: &mut ClinicData, keys: &[&str], at: DateTime<Utc>) {
+    fn list<T: Versioned + Audited + Clone>(before: &[T], after: &mut [T], at: DateTime<Utc>) {
+        let old: HashMap<&str, &T> = before.iter().map(|item| (item.id(), item)).collect();
+        for item in after.iter_mut() {
+            let prev = old.get(item.id()).copied();
+            if let Some(prev) = prev {
+                item.set_updated_at(prev.updated_at());
+            }
+            if prev != Some(&*item) {
+                item.set_updated_at(Some(at));
+            }
+        }
+    }
+
+    for key in keys {
+        match *key {
+            KEY_PATIENTS => list(&before.patients, &mut after.patients, at),
+            KEY_RECORDS => list(&before.records, &mut after.records, at),
+            KEY_DRUGS => list(&before.drugs, &mut after.drugs, at),
+            KEY_EXPENSES => list(&before.expenses, &mut after.expenses, at),
+            KEY_DRUG_PURCHASES => list(&before.drug_purchases, &mut after.drug_purchases, at),
+            KEY_APPOINTMENTS => list(&before.appointments, &mut after.appointments, at),
+            KEY_INTERACTIONS => list(&before.interactions, &mut after.interactions, at),
+            _ => {}
+        }
+    }
+}
+
+/// Tombstones for the items `entries` removed outright
+pub fn tombstones(entries: &[AuditEntry]) -> Vec<Tombstone> {
+    entries
+        .iter()
+        .filter(|e| e.before.is_some() && e.after.is_none())
+        .map(|e| Tombstone { entity: e.entity.clone(), id: e.entity_id.clone(), deleted_at: e.timestamp, archived: None })
+        .collect()
+}
+
+/// Tombstones for the items `archive` took out of the active data
+pub fn archived(archive: &Archive, at: DateTime<Utc>) -> Vec<Tombstone> {
+    fn graves<T: Audited>(items: &[T], year: i32, at: DateTime<Utc>) -> impl Iterator<Item = Tombstone> + '_ {
+        items.iter().map(move |item| Tombstone {
+            entity: T::ENTITY.to_string(),
+            id: item.id().to_string(),
+            deleted_at: at,
+            archived: Some(year),
+        })
+    }
+    graves(&archive.records, archive.year, at)
+        .chain(graves(&archive.expenses, archive.year, at))
+        .chain(graves(&archive.drug_purchases, archive.year, at))
+        .collect()
+}
+
+/// When each item was removed, and the year it was archived into if it was
+type Graves = HashMap<(String, String), (DateTime<Utc>, Option<i32>)>;
+
+fn graves(current: &ClinicData, incoming: &ClinicData) -> Graves {
+    let mut graves = Graves::new();
+    for t in current.tombstones.iter().chain(&incoming.tombstones) {
+        let grave = graves.entry((t.entity.clone(), t.id.clone())).or_insert((t.deleted_at, t.archived));
+        grave.0 = grave.0.max(t.deleted_at);
+        grave.1 = grave.1.or(t.archived);
+    }
+    graves
+}
+
+/// The tombstone an item lies under, if it was removed at or after its last change
+fn grave_of<'a, T: Versioned + Audited>(graves: &'a Graves, item: &T) -> Option<&'a (DateTime<Utc>, Option<i32>)> {
+    graves
+        .get(&(T::ENTITY.to_string(), item.id().to_string()))
+        .filter(|(at, _)| item.updated_at().is_none_or(|updated| updated <= *at))
+}
+
+/// An item removed at or after its last change stays removed
+fn buried<T: Versioned + Audited>(graves: &Graves, item: &T) -> bool {
+    grave_of(graves, item).is_some()
+}
+
+/// The items, ours or theirs, that the other computer archived, one archive per
+/// year. They have to be in our archives before `apply` takes them out.
+pub fn to_archive(current: &ClinicData, incoming: &ClinicData) -> Vec<Archive> {
+    fn file<T: Versioned + Audited + Clone>(
+        graves: &Graves,
+        lists: [&[T]; 2],
+        years: &mut BTreeMap<i32, Archive>,
+        list: fn(&mut Archive) -> &mut Vec<T>,
+    ) {
+        let mut seen = HashSet::new();
+        for item in lists.into_iter().flatten() {
+            let Some(&(_, Some(year))) = grave_of(graves, item) else { continue };
+            if seen.insert(item.id()) {
+                list(years.entry(year).or_insert_with(|| Archive { year, ..Archive::default() })).push(item.clone());
+            }
+        }
+    }
+
+    let graves = graves(current, incoming);
+    let mut years = BTreeMap::new();
+    file(&graves, [&current.records, &incoming.records], &mut years, |a| &mut a.records);
+    file(&graves, [&current.expenses, &incoming.expenses], &mut years, |a| &mut a.expenses);
+    file(&graves, [&current.drug_purchases, &incoming.drug_purchases], &mut years, |a| &mut a.drug_purchases);
+    years.into_values().collect()
+}
+
+/// The later change wins; the same stamp on two different copies is settled by
+/// their JSON so both computers pick the same one
+fn is_newer<T: Versioned + serde::Serialize>(incoming: &T, current: &T) -> bool {
+    let key = |item: &T| (item.updated_at(), serde_json::to_string(item).unwrap_or_default());
+    key(incoming) > key(current)
+}
+
+fn merge_list<T: Versioned + Audited + Clone>(items: &mut Vec<T>, incoming: Vec<T>, graves: &Graves) -> usize {
+    let mut changed = 0;
+    let mut positions: HashMap<String, usize> =
+        items.iter().enumerate().map(|(pos, item)| (item.id().to_string(), pos)).collect();
+    for item in incoming.into_iter().filter(|item| !buried(graves, item)) {
+        match positions.get(item.id()) {
+            Some(&pos) if is_newer(&item, &items[pos]) => {
+                items[pos] = item;
+                changed += 1;
+            }
+            Some(_) => {}
+            None => {
+                positions.insert(item.id().to_string(), items.len());
+                items.push(item);
+                changed += 1;
+            }
+        }
+    }
+    let before = items.len();
+    items.retain(|item| !buried(graves, item));
+    changed + before - items.len()
+}
+
+/// `current` with the changes from the other computer applied, and how many
+/// items were added, replaced or removed
+pub fn apply(current: &ClinicData, incoming: ClinicData) -> (ClinicData, usize) {
+    let graves = graves(current, &incoming);
+    let mut data = current.clone();
+    let changed = merge_list(&mut data.patients, incoming.patients, &graves)
+        + merge_list(&mut data.records, incoming.records, &graves)
+        + merge_list(&mut data.drugs, incoming.drugs, &graves)
+        + merge_list(&mut data.expenses, incoming.expenses, &graves)
+        + merge_list(&mut data.drug_purchases, incoming.drug_purchases, &graves)
+        + merge_list(&mut data.appointments, incoming.appointments, &graves)
+        + merge_list(&mut data.interactions, incoming.interactions, &graves);
+
+    let mut tombstones: Vec<Tombstone> = graves
+        .into_iter()
+        .map(|((entity, id), (deleted_at, archived))| Tombstone { entity, id, deleted_at, archived })
+        .collect();
+    tombstones.sort_by(|a, b| (a.deleted_at, &a.entity, &a.id).cmp(&(b.deleted_at, &b.entity, &b.id)));
+    data.tombstones = tombstones;
+    data.audit_log = backup::merge_logs(&current.audit_log, &incoming.audit_log);
+    data.last_hn = current.last_hn.max(incoming.last_hn);
+    (data, changed)
+}
+
+/// HNs that more than one active patient has in `merged` but not in `current`
+pub fn hn_clashes(current: &ClinicData, merged: &ClinicData) -> Vec<String> {
+    fn sharing(data: &ClinicData) -> HashMap<&str, HashSet<&str>> {
+        let mut by_hn: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for p in data.patients.iter().filter(|p| !p.is_deleted() && !p.hn.trim().is_empty()) {
+            by_hn.entry(p.hn.trim()).or_default().insert(p.id.as_str());
+        }
+        by_hn
+    }
+    let before = sharing(current);
+    let mut clashes: Vec<String> = sharing(merged)
+        .into_iter()
+        .filter(|(hn, ids)| ids.len() > 1 && before.get(hn).is_none_or(|had| had.len() < 2))
+        .map(|(hn, _)| hn.to_string())
+        .collect();
+    clashes.sort();
+    clashes
+}
+
+/// What applying changes from the other computer did
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Applied {
+    /// Items added, replaced or removed
+    pub changed: usize,
+    /// HNs that a patient from the other computer now shares with one of ours
+    pub hn_clashes: Vec<String>,
+}
+
+impl Applied {
+    /// The warning to show about `hn_clashes`, if there are any
+    pub fn clash_message(&self) -> Option<String> {
+        (!self.hn_clashes.is_empty()).then(|| {
+            format!(
+                "⚠️ ผู้ป่วยจากเครื่องอื่นได้ HN ซ้ำกับผู้ป่วยในเครื่องนี้: {} กรุณาแก้ไขที่ ตั้งค่า > ตรวจสอบเลข HN",
+                self.hn_clashes.join(", ")
+            )
+        })
+    }
+}
+
+/// Apply everything waiting in the sync inbox (Tauri only). The inbox is only
+/// cleared once the changes are saved, and applying the same changes twice
+/// does nothing the second time.
+pub async fn receive() -> Result<Applied, String> {
+    let inbox = tauri_bridge::read_sync_inbox().await?;
+    let mut applied = Applied::default();
+    if inbox.is_empty() {
+        return Ok(applied);
+    }
+    for changes in &inbox {
+        let doc: Map<String, Value> =
+            serde_json::from_str(changes).map_err(|e| format!("ข้อมูลที่ซิงก์มาอ่านไม่ได้: {}", e))?;
+        let incoming = ClinicData::from_document(doc)?.data;
+        archive::restore(&Store::get_archived_by_peer(&incoming)).await?;
+        let Applied { changed, hn_clashes } = Store::apply_sync(incoming).await?;
+        applied.changed += changed;
+        applied.hn_clashes.extend(hn_clashes);
+    }
+    tauri_bridge::clear_sync_inbox(inbox.len()).await?;
+    applied.hn_clashes.sort();
+    applied.hn_clashes.dedup();
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::models::{DrugItem, Expense};
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, 0, 0).unwrap()
+    }
+
+    fn drug(id: &str, stock: u32, updated: Option<u32>) -> DrugItem {
+        DrugItem { id: id.to_string(), name: id.to_string(), stock, updated_at: updated.map(at), ..DrugItem::default() }
+    }
+
+    #[test]
+    fn test_stamp_only_changed_items() {
+        let before = ClinicData { drugs: vec![drug("d1", 5, Some(1)), drug("d2", 5, Some(1))], ..ClinicData::default() };
+        let mut after = before.clone();
+        after.drugs[1].stock = 4;
+        after.drugs.push(drug("d3", 1, None));
+        stamp(&before, &mut after, &[KEY_DRUGS], at(9));
+
+        let stamps: Vec<Option<DateTime<Utc>>> = after.drugs.iter().map(|d| d.updated_at).collect();
+        assert_eq!(stamps, vec![Some(at(1)), Some(at(9)), Some(at(9))]);
+    }
+
+    #[test]
+    fn test_newer_copy_wins_on_both_sides() {
+        let a = ClinicData { drugs: vec![drug("d1", 5, Some(2)), drug("d2", 5, Some(1))], ..ClinicData::default() };
+        let b = ClinicData { drugs: vec![drug("d1", 3, Some(1)), drug("d2", 7, Some(3))], ..ClinicData::default() };
+
+        let (on_a, changed) = apply(&a, b.clone());
+        let (on_b, _) = apply(&b, a.clone());
+        assert_eq!(changed, 1);
+        assert_eq!(on_a.drugs, on_b.drugs);
+        assert_eq!(on_a.drugs.iter().map(|d| d.stock).collect::<Vec<_>>(), vec![5, 7]);
+
+        // A tie is settled the same way whichever side applies it
+        let left = ClinicData { drugs: vec![drug("d1", 5, Some(2))], ..ClinicData::default() };
+        let right = ClinicData { drugs: vec![drug("d1", 6, Some(2))], ..ClinicData::default() };
+        assert_eq!(apply(&left, right.clone()).0.drugs, apply(&right, left).0.drugs);
+
+        // Applying the same changes again changes nothing
+        assert_eq!(apply(&on_a, b).1, 0);
+    }
+
+    #[test]
+    fn test_tombstones_remove_older_copies() {
+        let grave = |id: &str, hour| Tombstone { entity: "drug".to_string(), id: id.to_string(), deleted_at: at(hour), archived: None };
+        let current = ClinicData {
+            drugs: vec![drug("d1", 5, Some(1)), drug("d2", 5, Some(6))],
+            expenses: vec![Expense { id: "e1".to_string(), ..Expense::default() }],
+            ..ClinicData::default()
+        };
+        let incoming = ClinicData {
+            drugs: vec![drug("d3", 1, Some(1))],
+            tombstones: vec![grave("d1", 4), grave("d2", 4), grave("d3", 4)],
+            last_hn: 12,
+            ..ClinicData::default()
+        };
+
+        let (data, changed) = apply(&current, incoming);
+        // d2 was edited after the other computer removed it, so it stays
+        assert_eq!(data.drugs.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(), vec!["d2"]);
+        assert_eq!(changed, 1);
+        assert_eq!(data.tombstones.len(), 3);
+        assert_eq!(data.expenses.len(), 1);
+        assert_eq!(data.last_hn, 12);
+    }
+}
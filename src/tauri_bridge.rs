@@ -3,13 +3,17 @@
 // or fallback to localStorage when running in browser
 
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::JsFuture;
 use web_sys::window;
 
 #[wasm_bindgen]
 extern "C" {
-    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"])]
-    async fn invoke(cmd: &str, args: JsValue) -> JsValue;
+    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"], catch)]
+    async fn invoke(cmd: &str, args: JsValue) -> Result<JsValue, JsValue>;
+}
+
+/// Tauri rejects with the command's `Err(String)`, anything else is debug-printed
+fn error_message(err: JsValue) -> String {
+    err.as_string().unwrap_or_else(|| format!("{:?}", err))
 }
 
 /// Check if running inside Tauri
@@ -21,17 +25,25 @@ pub fn is_tauri() -> bool {
     }
 }
 
+/// Data loaded at startup. `warning` is set when the desktop app had to fall
+/// back to the previous save because clinic_data.json was damaged.
+pub struct LoadedData {
+    pub data: String,
+    pub warning: Option<String>,
+}
+
 /// Load clinic data - from file (Tauri) or localStorage (browser)
-pub async fn load_data() -> Result<String, String> {
+pub async fn load_data() -> Result<LoadedData, String> {
     if is_tauri() {
-        match invoke("load_clinic_data", JsValue::NULL).await.as_string() {
-            Some(data) => Ok(data),
-            None => Err("Failed to load data from Tauri".to_string())
-        }
+        let result = invoke("load_clinic_data", JsValue::NULL).await.map_err(error_message)?;
+        let field = |name: &str| js_sys::Reflect::get(&result, &name.into()).ok().and_then(|v| v.as_string());
+        let data = field("data").ok_or_else(|| "Failed to load data from Tauri".to_string())?;
+        Ok(LoadedData { data, warning: field("warning") })
     } else {
         // Fallback to localStorage
         if let Some(storage) = window().and_then(|w| w.local_storage().ok().flatten()) {
-            Ok(storage.get_item("clinic_all_data").ok().flatten().unwrap_or_default())
+            let data = storage.get_item("clinic_all_data").ok().flatten().unwrap_or_default();
+            Ok(LoadedData { data, warning: None })
         } else {
             Err("localStorage not available".to_string())
         }
@@ -43,7 +55,7 @@ pub async fn save_data(data: &str) -> Result<(), String> {
     if is_tauri() {
         let args = js_sys::Object::new();
         js_sys::Reflect::set(&args, &"data".into(), &data.into()).unwrap();
-        invoke("save_clinic_data", args.into()).await;
+        invoke("save_clinic_data", args.into()).await.map_err(error_message)?;
         Ok(())
     } else {
         // Fallback to localStorage
@@ -56,32 +68,269 @@ pub async fn save_data(data: &str) -> Result<(), String> {
 }
 
 /// Get the data file path (Tauri only)
+#[allow(dead_code)]
 pub async fn get_data_path() -> Option<String> {
     if is_tauri() {
-        invoke("get_data_path", JsValue::NULL).await.as_string()
+        invoke("get_data_path", JsValue::NULL).await.ok().and_then(|v| v.as_string())
     } else {
         None
     }
 }
 
 /// Create a backup (Tauri only)
+#[allow(dead_code)]
 pub async fn create_backup() -> Result<String, String> {
     if is_tauri() {
-        match invoke("create_backup", JsValue::NULL).await.as_string() {
-            Some(path) => Ok(path),
-            None => Err("Failed to create backup".to_string())
+        match invoke("create_backup", JsValue::NULL).await {
+            Ok(path) => path.as_string().ok_or_else(|| "Failed to create backup".to_string()),
+            Err(err) => Err(error_message(err)),
         }
     } else {
         Err("Backup only available in desktop app".to_string())
     }
 }
 
+/// Take the automatic backup that goes before a restore (Tauri only).
+/// Returns None when the backup schedule has it turned off.
+pub async fn backup_before_restore() -> Result<Option<String>, String> {
+    let path = invoke("backup_before_restore", JsValue::NULL).await.map_err(error_message)?;
+    Ok(path.as_string())
+}
+
+/// How the desktop app's backups stand
+#[derive(Clone, PartialEq, serde::Deserialize)]
+pub struct BackupStatus {
+    /// When the newest backup in the backups folder was taken
+    pub last_backup: Option<chrono::NaiveDateTime>,
+    /// Why the external backup folder has no good copy of the newest backup
+    pub mirror_warning: Option<String>,
+}
+
+/// Backup status (Tauri only)
+pub async fn backup_status() -> Result<BackupStatus, String> {
+    let result = invoke("get_backup_status", JsValue::NULL).await.map_err(error_message)?;
+    let json = js_sys::JSON::stringify(&result).map_err(error_message)?;
+    serde_json::from_str(&String::from(json)).map_err(|e| e.to_string())
+}
+
+/// Copy the backups to `mirror_dir` and check every copy (Tauri only).
+/// Returns how many files were copied.
+pub async fn mirror_backups(mirror_dir: &str) -> Result<usize, String> {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"mirrorDir".into(), &mirror_dir.into()).unwrap();
+    let result = invoke("mirror_backups", args.into()).await.map_err(error_message)?;
+    Ok(result.as_f64().unwrap_or(0.0) as usize)
+}
+
+/// How many items each collection of a backup holds
+#[derive(Clone, PartialEq, serde::Deserialize)]
+pub struct BackupCounts {
+    pub patients: usize,
+    pub records: usize,
+    pub drugs: usize,
+    pub expenses: usize,
+    pub drug_purchases: usize,
+    pub appointments: usize,
+}
+
+/// A file in the desktop app's backups folder
+#[derive(Clone, PartialEq, serde::Deserialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub taken_at: Option<chrono::NaiveDateTime>,
+    /// close, daily, restore or update; None for backups made by hand
+    pub reason: Option<String>,
+    pub size: u64,
+    pub counts: Option<BackupCounts>,
+    /// Why the backup could not be read
+    pub error: Option<String>,
+}
+
+/// The backups on disk, most recent first (Tauri only)
+pub async fn list_backups() -> Result<Vec<BackupInfo>, String> {
+    let result = invoke("list_backups", JsValue::NULL).await.map_err(error_message)?;
+    let json = js_sys::JSON::stringify(&result).map_err(error_message)?;
+    serde_json::from_str(&String::from(json)).map_err(|e| e.to_string())
+}
+
+fn backup_name_args(name: &str) -> JsValue {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"backupName".into(), &name.into()).unwrap();
+    args.into()
+}
+
+/// A backup's contents as plain JSON (Tauri only)
+pub async fn read_backup(name: &str) -> Result<String, String> {
+    let result = invoke("read_backup", backup_name_args(name)).await.map_err(error_message)?;
+    result.as_string().ok_or_else(|| "Failed to read backup".to_string())
+}
+
+/// Delete a backup file (Tauri only)
+pub async fn delete_backup(name: &str) -> Result<(), String> {
+    invoke("delete_backup", backup_name_args(name)).await.map_err(error_message)?;
+    Ok(())
+}
+
 /// Open data folder (Tauri only)
+#[allow(dead_code)]
 pub async fn open_data_folder() -> Result<(), String> {
     if is_tauri() {
-        invoke("open_data_folder", JsValue::NULL).await;
+        invoke("open_data_folder", JsValue::NULL).await.map_err(error_message)?;
         Ok(())
     } else {
         Err("Only available in desktop app".to_string())
     }
 }
+
+/// Whether the desktop data file is encrypted, and whether it has been unlocked yet
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+/// Encryption status (Tauri only; the browser build keeps no data file)
+pub async fn encryption_status() -> Result<EncryptionStatus, String> {
+    if is_tauri() {
+        let result = invoke("get_encryption_status", JsValue::NULL).await.map_err(error_message)?;
+        let field = |name: &str| js_sys::Reflect::get(&result, &name.into()).ok().and_then(|v| v.as_bool()).unwrap_or(false);
+        Ok(EncryptionStatus { enabled: field("enabled"), unlocked: field("unlocked") })
+    } else {
+        Ok(EncryptionStatus { enabled: false, unlocked: false })
+    }
+}
+
+/// Unlock the encrypted data file with its passphrase (Tauri only)
+pub async fn unlock_data(passphrase: &str) -> Result<(), String> {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"passphrase".into(), &passphrase.into()).unwrap();
+    invoke("unlock_data", args.into()).await.map_err(error_message)?;
+    Ok(())
+}
+
+/// Set, change or remove (empty `new_passphrase`) the data file passphrase (Tauri only).
+/// Returns the backup files that could not be re-encrypted.
+pub async fn set_passphrase(current_passphrase: &str, new_passphrase: &str) -> Result<Vec<String>, String> {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"currentPassphrase".into(), &current_passphrase.into()).unwrap();
+    js_sys::Reflect::set(&args, &"newPassphrase".into(), &new_passphrase.into()).unwrap();
+    let result = invoke("set_passphrase", args.into()).await.map_err(error_message)?;
+    Ok(js_sys::Array::from(&result).iter().filter_map(|v| v.as_string()).collect())
+}
+
+/// Years with an archive file, oldest first (Tauri only)
+pub async fn list_archives() -> Result<Vec<i32>, String> {
+    let result = invoke("list_archives", JsValue::NULL).await.map_err(error_message)?;
+    Ok(js_sys::Array::from(&result).iter().filter_map(|v| v.as_f64()).map(|year| year as i32).collect())
+}
+
+fn year_args(year: i32) -> js_sys::Object {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"year".into(), &year.into()).unwrap();
+    args
+}
+
+/// An archive's plain JSON, None when there is none for `year` (Tauri only)
+pub async fn load_archive(year: i32) -> Result<Option<String>, String> {
+    let result = invoke("load_archive", year_args(year).into()).await.map_err(error_message)?;
+    Ok(result.as_string())
+}
+
+/// Write the archive for `year` (Tauri only)
+pub async fn save_archive(year: i32, data: &str) -> Result<(), String> {
+    let args = year_args(year);
+    js_sys::Reflect::set(&args, &"data".into(), &data.into()).unwrap();
+    invoke("save_archive", args.into()).await.map_err(error_message)?;
+    Ok(())
+}
+
+/// The audit log from audit_log.jsonl as one JSON list (Tauri only)
+pub async fn load_audit_log() -> Result<String, String> {
+    let result = invoke("load_audit_log", JsValue::NULL).await.map_err(error_message)?;
+    result.as_string().ok_or_else(|| "Failed to load the audit log from Tauri".to_string())
+}
+
+fn entries_args(entries: &str) -> js_sys::Object {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"entries".into(), &entries.into()).unwrap();
+    args
+}
+
+/// Add a JSON list of audit entries to the end of the log (Tauri only)
+pub async fn append_audit_log(entries: &str) -> Result<(), String> {
+    invoke("append_audit_log", entries_args(entries).into()).await.map_err(error_message)?;
+    Ok(())
+}
+
+/// Replace the whole audit log with a JSON list of entries (Tauri only)
+pub async fn save_audit_log(entries: &str) -> Result<(), String> {
+    invoke("save_audit_log", entries_args(entries).into()).await.map_err(error_message)?;
+    Ok(())
+}
+
+/// How sync with the other clinic computer went the last time
+#[derive(Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SyncStatus {
+    pub last_sync: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// The desktop app's LAN sync settings, kept in sync.json next to the data file
+#[derive(Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub enabled: bool,
+    /// Address this computer listens on; empty for every network it is on
+    pub bind: String,
+    /// Port this computer listens on
+    pub port: u16,
+    /// "host:port" of the other computer; empty to only answer it
+    pub peer: String,
+    /// Shared key, the same on both computers
+    pub key: String,
+    /// Minutes between automatic syncs, 0 for only on demand
+    pub interval_minutes: u32,
+    #[serde(skip_serializing)]
+    pub status: SyncStatus,
+}
+
+/// LAN sync settings (Tauri only)
+pub async fn get_sync_config() -> Result<SyncConfig, String> {
+    let result = invoke("get_sync_config", JsValue::NULL).await.map_err(error_message)?;
+    let json = js_sys::JSON::stringify(&result).map_err(error_message)?;
+    serde_json::from_str(&String::from(json)).map_err(|e| e.to_string())
+}
+
+/// Save the LAN sync settings and restart the sync server with them (Tauri only)
+pub async fn save_sync_config(config: &SyncConfig) -> Result<(), String> {
+    let json = serde_json::to_string(config).map_err(|e| e.to_string())?;
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"config".into(), &js_sys::JSON::parse(&json).map_err(error_message)?).unwrap();
+    invoke("save_sync_config", args.into()).await.map_err(error_message)?;
+    Ok(())
+}
+
+/// Sync with the other computer now (Tauri only). What comes back lands in
+/// the sync inbox; returns how many items it holds.
+pub async fn sync_now() -> Result<usize, String> {
+    let result = invoke("sync_now", JsValue::NULL).await.map_err(error_message)?;
+    Ok(result.as_f64().unwrap_or(0.0) as usize)
+}
+
+/// Changes from the other computer waiting to be applied, oldest first, as
+/// clinic_data.json documents (Tauri only; the browser build has no inbox)
+pub async fn read_sync_inbox() -> Result<Vec<String>, String> {
+    if !is_tauri() {
+        return Ok(Vec::new());
+    }
+    let result = invoke("read_sync_inbox", JsValue::NULL).await.map_err(error_message)?;
+    Ok(js_sys::Array::from(&result).iter().filter_map(|v| v.as_string()).collect())
+}
+
+/// Drop the first `count` inbox entries once they are applied (Tauri only)
+pub async fn clear_sync_inbox(count: usize) -> Result<(), String> {
+    let args = js_sys::Object::new();
+    js_sys::Reflect::set(&args, &"count".into(), &(count as u32).into()).unwrap();
+    invoke("clear_sync_inbox", args.into()).await.map_err(error_message)?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// Thai national ID numbers
+// Citizen IDs and tax IDs are 13 digits, the last one a check digit: each of
+// the first 12 digits is multiplied by 13 down to 2, and the check digit is
+// (11 - sum mod 11) mod 10. That catches any single mistyped digit and most
+// swapped neighbours, which is what typos at the front desk usually are.
+
+/// The digits of `id`, dropping the dashes and spaces of a formatted ID
+pub fn digits(id: &str) -> String {
+    id.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+fn check_digit(first_12: &[u32]) -> u32 {
+    let sum: u32 = first_12.iter().zip((2..=13).rev()).map(|(d, weight)| d * weight).sum();
+    (11 - sum % 11) % 10
+}
+
+/// Why `id` is not a valid ID, or None if it is. Only digits are read, so a
+/// formatted ID checks the same as the bare digits.
+pub fn problem(id: &str) -> Option<String> {
+    let digits: Vec<u32> = id.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != 13 {
+        return Some(format!("ต้องมี 13 หลัก (มี {} หลัก)", digits.len()));
+    }
+    if check_digit(&digits[..12]) != digits[12] {
+        return Some("เลขไม่ถูกต้อง กรุณาตรวจสอบว่าพิมพ์ถูกทุกหลัก".to_string());
+    }
+    None
+}
+
+pub fn is_valid(id: &str) -> bool {
+    problem(id).is_none()
+}
+
+/// `id` as x-xxxx-xxxxx-xx-x for display. Anything but 13 digits is shown as it is.
+pub fn format(id: &str) -> String {
+    let digits = digits(id);
+    if digits.len() != 13 {
+        return id.to_string();
+    }
+    format!("{}-{}-{}-{}-{}", &digits[..1], &digits[1..5], &digits[5..10], &digits[10..12], &digits[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_digit() {
+        assert!(is_valid("1101700207030"));
+        assert!(is_valid("1-1017-00207-03-0"));
+        assert!(is_valid("3100600445554"));
+        // One digit off, and two neighbours swapped
+        assert!(!is_valid("1101700207031"));
+        assert!(!is_valid("1107100207030"));
+        assert_eq!(problem("110170020703"), Some("ต้องมี 13 หลัก (มี 12 หลัก)".to_string()));
+        assert!(!is_valid(""));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("1101700207030"), "1-1017-00207-03-0");
+        assert_eq!(format("1-1017-00207-03-0"), "1-1017-00207-03-0");
+        assert_eq!(format("12345"), "12345");
+    }
+}